@@ -13,6 +13,15 @@ defmodule Xqlite do
   # Connection options (validated via NimbleOptions)
   # ---------------------------------------------------------------------------
 
+  @slow_query_log_keys [
+    threshold_ms: [type: :non_neg_integer, required: true],
+    query_plan: [type: {:or, [:boolean, :pos_integer]}, default: false],
+    capacity: [type: :pos_integer, default: 100],
+    pid: [type: :pid]
+  ]
+
+  @slow_query_log_schema NimbleOptions.new!(@slow_query_log_keys)
+
   @open_opts_schema NimbleOptions.new!(
                       journal_mode: [
                         type: {:in, [:wal, :delete, :truncate, :memory, :off]},
@@ -64,6 +73,18 @@ defmodule Xqlite do
                         type: {:in, [:none, :full, :incremental]},
                         default: :none,
                         doc: "Auto-vacuum mode. Must be set before creating any tables."
+                      ],
+                      slow_query_log: [
+                        type: :keyword_list,
+                        keys: @slow_query_log_keys,
+                        doc:
+                          "Record statements running at least `:threshold_ms`; see `set_slow_query_log/2`. Not set by default."
+                      ],
+                      statement_cache_size: [
+                        type: :non_neg_integer,
+                        default: 16,
+                        doc:
+                          "Prepared statements cached for reuse by `query` / `execute`. 0 disables the cache; see `set_statement_cache_size/2`."
                       ]
                     )
 
@@ -95,6 +116,37 @@ defmodule Xqlite do
           num_rows: non_neg_integer()
         }
 
+  @type slow_query :: %{
+          sql: String.t(),
+          param_count: non_neg_integer(),
+          duration_ns: non_neg_integer(),
+          rows: non_neg_integer(),
+          query_plan: [%{id: integer(), parent: integer(), detail: String.t()}] | nil
+        }
+
+  @type query_plan_row :: %{id: integer(), parent: integer(), detail: String.t()}
+
+  @type index_advice :: %{
+          indexes: [String.t()],
+          statements: [
+            %{
+              sql: String.t(),
+              indexes: [String.t()],
+              plan_before: [query_plan_row()],
+              plan_after: [query_plan_row()]
+            }
+          ]
+        }
+
+  @type statement_dependencies :: %{
+          optional(String.t()) => %{
+            reads: [String.t()],
+            writes: [String.t()],
+            index_reads: [String.t()],
+            index_writes: [String.t()]
+          }
+        }
+
   # ---------------------------------------------------------------------------
   # Error reason types (the inner value of {:error, reason})
   # ---------------------------------------------------------------------------
@@ -142,13 +194,16 @@ defmodule Xqlite do
           | :multiple_statements
           | :null_byte_in_string
           | :operation_cancelled
+          | :preupdate_hook_conflict
           | :statement_finalized
           | {:authorization_denied, integer(), String.t()}
           | {:cannot_convert_atom_to_string, String.t()}
           | {:cannot_convert_to_sqlite_value, String.t(), String.t()}
           | {:cannot_execute, String.t()}
           | {:cannot_execute_pragma, String.t(), String.t()}
+          | {:cannot_open_file, String.t(), String.t()}
           | {:cannot_open_database, String.t(), integer(), String.t()}
+          | {:changeset_handler_failed, user_function_failure(), String.t()}
           | {:constraint_violation, constraint_kind(), constraint_details()}
           | {:database_busy_or_locked, integer(), String.t()}
           | {:expected_keyword_list, String.t()}
@@ -159,9 +214,15 @@ defmodule Xqlite do
           | {:integral_value_out_of_range, non_neg_integer(), integer()}
           | {:internal_encoding_error, String.t()}
           | {:invalid_authorizer_action, atom()}
+          | {:invalid_authorizer_rule, String.t()}
+          | {:invalid_builtin_aggregate, atom()}
+          | {:invalid_changeset_apply_option, atom()}
           | {:invalid_column_index, non_neg_integer()}
           | {:invalid_column_name, String.t()}
           | {:invalid_column_type, non_neg_integer(), String.t(), atom()}
+          | {:invalid_explain_mode, atom()}
+          | {:invalid_function_flag, atom()}
+          | {:invalid_native_collation, atom()}
           | {:invalid_on_error, term()}
           | {:invalid_open_option,
              %{key: atom(), reason: :unknown_key, allowed: [atom()], value: nil}
@@ -169,13 +230,19 @@ defmodule Xqlite do
           | {:invalid_pages_per_step, integer()}
           | {:invalid_parameter_count,
              %{provided: non_neg_integer(), expected: non_neg_integer()}}
+          | {:invalid_parameter_index, integer()}
           | {:invalid_parameter_name, String.t()}
           | {:invalid_pragma_name, String.t()}
+          | {:invalid_slow_query_log_option,
+             %{key: atom() | [atom()], value: term(), message: String.t()}}
           | {:invalid_stream_handle, String.t()}
+          | {:invalid_trace_event, atom()}
+          | {:invalid_update_hook_option, atom()}
           | {:lock_error, String.t()}
           | {:no_such_index, String.t()}
           | {:no_such_table, String.t()}
           | {:read_only_database, integer(), String.t()}
+          | {:row_failed, non_neg_integer(), error_reason()}
           | {:schema_changed, integer(), String.t()}
           | {:schema_parsing_error, String.t(), {:unexpected_value, String.t()}}
           | {:sql_input_error, sql_input_error()}
@@ -184,10 +251,22 @@ defmodule Xqlite do
           | {:to_sql_conversion_failure, String.t()}
           | {:unsupported_atom, String.t()}
           | {:unsupported_data_type, atom()}
+          | {:user_function_failed, String.t(), user_function_failure(), String.t()}
           | {:utf8_error, non_neg_integer(), String.t()}
 
   @type error :: {:error, error_reason()}
 
+  @typedoc """
+  Why an Elixir-implemented SQL function failed; see `Xqlite.Function`.
+  """
+  @type user_function_failure :: :error | :timeout | :noproc | :invalid_result
+
+  @type function_flag :: :deterministic | :direct_only | :innocuous
+
+  @type builtin_aggregate :: :weighted_median
+
+  @type native_collation :: :unicode_nocase | :natural | :no_accents
+
   @typedoc """
   Controls how `stream/4` reacts to a mid-fetch error; see its `:on_error`
   option for the per-mode element shapes.
@@ -222,7 +301,9 @@ defmodule Xqlite do
       result =
         with {:ok, validated} <- validate_open_opts(opts),
              {:ok, conn} <- XqliteNIF.open(path),
-             :ok <- apply_pragmas(conn, validated) do
+             :ok <- apply_pragmas(conn, validated),
+             :ok <- apply_slow_query_log(conn, validated[:slow_query_log]),
+             :ok <- set_statement_cache_size(conn, validated[:statement_cache_size]) do
           {:ok, conn}
         end
 
@@ -243,7 +324,9 @@ defmodule Xqlite do
       result =
         with {:ok, validated} <- validate_open_opts(opts),
              {:ok, conn} <- XqliteNIF.open_in_memory(":memory:"),
-             :ok <- apply_pragmas(conn, validated) do
+             :ok <- apply_pragmas(conn, validated),
+             :ok <- apply_slow_query_log(conn, validated[:slow_query_log]),
+             :ok <- set_statement_cache_size(conn, validated[:statement_cache_size]) do
           {:ok, conn}
         end
 
@@ -399,6 +482,9 @@ defmodule Xqlite do
     end)
   end
 
+  defp apply_slow_query_log(_conn, nil), do: :ok
+  defp apply_slow_query_log(conn, opts), do: set_slow_query_log(conn, opts)
+
   defp set_pragma_value(conn, :busy_timeout, :infinity),
     do: XqliteNIF.set_pragma(conn, "busy_timeout", 2_147_483_647)
 
@@ -774,6 +860,149 @@ defmodule Xqlite do
     end
   end
 
+  @doc """
+  Runs a multi-statement script and returns one result per statement.
+
+  Each statement's outcome is `{:ok, %Xqlite.Result{}}` or
+  `{:error, reason}`, in script order. Meant for migration tooling and
+  admin consoles that need every statement's rows, unlike
+  `execute_batch/2`. No parameter binding. Wraps
+  `XqliteNIF.query_batch/3` and emits `[:xqlite, :query_batch, :*]`
+  telemetry.
+
+  ## Options
+
+    * `:on_error` (`:stop` or `:continue`, default `:stop`) — with
+      `:stop` the failing statement's `{:error, reason}` is the last
+      entry; with `:continue` the remaining statements still run.
+      Statements already run are kept either way.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> script = "CREATE TABLE t(id INTEGER PRIMARY KEY, name TEXT); INSERT INTO t(name) VALUES ('a'), ('b'); SELECT name FROM t ORDER BY id;"
+      iex> {:ok, [created, inserted, {:ok, selected}]} = Xqlite.query_batch(conn, script)
+      iex> {created, inserted}
+      {{:ok, %Xqlite.Result{columns: [], rows: [], num_rows: 0, changes: 0}},
+       {:ok, %Xqlite.Result{columns: [], rows: [], num_rows: 0, changes: 2}}}
+      iex> selected.rows
+      [["a"], ["b"]]
+      iex> {:ok, [{:error, {:no_such_table, _}}, {:ok, one}]} =
+      ...>   Xqlite.query_batch(conn, "SELECT * FROM nope; SELECT 1;", on_error: :continue)
+      iex> one.rows
+      [[1]]
+  """
+  @spec query_batch(conn(), String.t(), keyword()) ::
+          {:ok, [{:ok, Xqlite.Result.t()} | error()]} | error()
+  def query_batch(conn, sql, opts \\ []) when is_binary(sql) do
+    continue_on_error =
+      case Keyword.get(opts, :on_error, :stop) do
+        :stop -> false
+        :continue -> true
+      end
+
+    start_md = %{conn: conn, sql_batch_size_bytes: byte_size(sql), cancellable?: false}
+
+    span_with_stop_metadata [:xqlite, :query_batch], start_md do
+      case XqliteNIF.query_batch(conn, sql, continue_on_error) do
+        {:ok, outcomes} ->
+          results =
+            Enum.map(outcomes, fn
+              {:ok, map} -> {:ok, Xqlite.Result.from_map(map)}
+              {:error, _} = err -> err
+            end)
+
+          {{:ok, results},
+           Map.merge(start_md, %{
+             result_class: :ok,
+             error_reason: nil,
+             statements: length(results)
+           })}
+
+        {:error, reason} = err ->
+          {err,
+           Map.merge(start_md, %{result_class: :error, error_reason: reason, statements: nil})}
+      end
+    end
+  end
+
+  @doc """
+  Executes one statement against many parameter sets, preparing it once.
+
+  Every set is bound and stepped natively within a single NIF call, so
+  bulk inserts avoid a scheduler hop, lock and prepare per row. Returns
+  `{:ok, changes}` with the total rows changed.
+
+  A failing set returns `{:error, {:row_failed, index, reason}}`, where
+  `index` is the set's zero-based position and `reason` the usual error
+  (for example a `:constraint_violation`). Errors before the first row,
+  such as invalid SQL, are returned unwrapped.
+
+  ## Options
+
+    * `:transaction` (boolean, default `true`) — run all sets inside a
+      savepoint, committing them together and rolling all of them back
+      on failure. Nests inside an already open transaction. With
+      `false`, rows before a failing one stay applied.
+    * `:cancel` — a cancel token or list of tokens (see
+      `create_cancel_token/0`). A cancelled run returns
+      `{:error, :operation_cancelled}`.
+    * `:type_extensions` — as for `execute/4`, applied to every set.
+
+  Emits `[:xqlite, :execute_many, :*]` telemetry.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> :ok = XqliteNIF.execute_batch(conn, "CREATE TABLE t(id INTEGER PRIMARY KEY, name TEXT UNIQUE);")
+      iex> Xqlite.execute_many(conn, "INSERT INTO t(name) VALUES (?1)", [["a"], ["b"], ["c"]])
+      {:ok, 3}
+      iex> {:error, {:row_failed, 1, {:constraint_violation, :constraint_unique, _}}} =
+      ...>   Xqlite.execute_many(conn, "INSERT INTO t(name) VALUES (?1)", [["d"], ["a"]])
+      iex> XqliteNIF.query(conn, "SELECT count(*) FROM t", [])
+      {:ok, %{columns: ["count(*)"], rows: [[3]], num_rows: 1}}
+  """
+  @spec execute_many(conn(), String.t(), [list() | keyword()], keyword()) ::
+          {:ok, non_neg_integer()} | error()
+  def execute_many(conn, sql, param_sets, opts \\ []) when is_list(param_sets) do
+    extensions = Keyword.get(opts, :type_extensions, [])
+    tokens = opts |> Keyword.get(:cancel, []) |> List.wrap()
+    bound_sets = Enum.map(param_sets, &Xqlite.TypeExtension.encode_params(&1, extensions))
+
+    start_md = %{
+      conn: conn,
+      sql: sql,
+      param_sets_count: length(bound_sets),
+      cancellable?: tokens != []
+    }
+
+    span_with_stop_metadata [:xqlite, :execute_many], start_md do
+      case XqliteNIF.execute_many(
+             conn,
+             sql,
+             bound_sets,
+             Keyword.get(opts, :transaction, true),
+             tokens
+           ) do
+        {:ok, changes} = ok ->
+          {ok, Map.merge(start_md, %{result_class: :ok, error_reason: nil, changes: changes})}
+
+        {:error, :operation_cancelled} = err ->
+          emit_cancel_honored(conn, :execute_many, tokens)
+
+          {err,
+           Map.merge(start_md, %{
+             result_class: :error,
+             error_reason: :operation_cancelled,
+             changes: nil
+           })}
+
+        {:error, reason} = err ->
+          {err, Map.merge(start_md, %{result_class: :error, error_reason: reason, changes: nil})}
+      end
+    end
+  end
+
   defp decode_result_rows(%Xqlite.Result{} = result, []), do: result
 
   defp decode_result_rows(%Xqlite.Result{rows: rows} = result, extensions) do
@@ -855,6 +1084,91 @@ defmodule Xqlite do
     end
   end
 
+  @doc """
+  Runs a SQL statement and returns its bytecode program with runtime counts.
+
+  The statement is executed in full (rows are fetched and discarded), then
+  its VDBE program is listed as `%Xqlite.BytecodeOp{}` structs: address,
+  opcode, operands, how many times each instruction ran and the CPU cycles
+  it took. `Explain` instructions are merged with the scan statistics
+  `explain_analyze/3` reports, so a plan step's row counts sit next to the
+  instructions that produced them. See `Xqlite.BytecodeOp` for the fields.
+
+  No telemetry is emitted.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> XqliteNIF.execute_batch(conn, "CREATE TABLE t(id INTEGER PRIMARY KEY, name TEXT); INSERT INTO t(name) VALUES ('a'), ('b'), ('c');")
+      :ok
+      iex> {:ok, ops} = Xqlite.explain_bytecode(conn, "SELECT name FROM t WHERE name > ?", ["a"])
+      iex> %Xqlite.BytecodeOp{opcode: "Init", nexec: 1} = hd(ops)
+      iex> Enum.find(ops, &(&1.opcode == "ResultRow")).nexec
+      2
+      iex> Enum.find(ops, &(&1.opcode == "Explain")).scan.rows_visited
+      3
+  """
+  @spec explain_bytecode(conn(), String.t(), list() | keyword()) ::
+          {:ok, [Xqlite.BytecodeOp.t()]} | error()
+  def explain_bytecode(conn, sql, params \\ []) do
+    XqliteNIF.explain_bytecode(conn, sql, params)
+  end
+
+  @doc """
+  Proposes `CREATE INDEX` statements for a workload of SQL statements.
+
+  Each suggestion comes with the statement's `EXPLAIN QUERY PLAN` rows
+  before and after. The connection itself is never modified.
+
+  The advice comes from SQLite's own advisor, `ext/expert`
+  (sqlite3expert), which the NIF compiles in. See
+  `XqliteNIF.suggest_indexes/2` for how the plans are taken.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> XqliteNIF.execute_batch(conn, "CREATE TABLE users(id INTEGER PRIMARY KEY, email TEXT);")
+      :ok
+      iex> {:ok, advice} = Xqlite.suggest_indexes(conn, ["SELECT id FROM users WHERE email = ?"])
+      iex> length(advice.indexes)
+      1
+      iex> [%{plan_before: [%{detail: before}], plan_after: [%{detail: after_}]}] = advice.statements
+      iex> {before, after_ =~ "SEARCH users USING COVERING INDEX"}
+      {"SCAN users", true}
+  """
+  @spec suggest_indexes(conn(), [String.t()]) :: {:ok, index_advice()} | error()
+  def suggest_indexes(conn, sqls) when is_list(sqls) do
+    XqliteNIF.suggest_indexes(conn, sqls)
+  end
+
+  @doc """
+  Returns the tables and indexes a statement reads and writes, per schema.
+
+  Meant for query-result caches: key a cached result on the `:reads` of
+  its query and drop it when the update hook reports a change to one of
+  those tables. The statement is only prepared, never run. Views are
+  resolved to their underlying tables, and tables touched by triggers or
+  foreign-key actions are included.
+
+  Only the first statement in `sql` is analysed.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> XqliteNIF.execute_batch(conn, "CREATE TABLE t(id INTEGER PRIMARY KEY, v TEXT); CREATE TABLE log(id); CREATE VIEW tv AS SELECT v FROM t; CREATE TRIGGER t_log AFTER INSERT ON t BEGIN INSERT INTO log VALUES (new.id); END;")
+      :ok
+      iex> Xqlite.statement_dependencies(conn, "SELECT * FROM tv")
+      {:ok, %{"main" => %{reads: ["t"], writes: [], index_reads: [], index_writes: []}}}
+      iex> {:ok, %{"main" => %{writes: writes}}} = Xqlite.statement_dependencies(conn, "INSERT INTO t(v) VALUES ('x')")
+      iex> writes
+      ["log", "t"]
+  """
+  @spec statement_dependencies(conn(), String.t()) ::
+          {:ok, statement_dependencies()} | error()
+  def statement_dependencies(conn, sql) do
+    XqliteNIF.statement_dependencies(conn, sql)
+  end
+
   @doc """
   Creates a stream that executes a query and emits rows as string-keyed maps.
 
@@ -991,15 +1305,69 @@ defmodule Xqlite do
 
   Accepts a plain list for positional placeholders (`?1`, `?2`, …; the
   count must match, otherwise `{:error, {:invalid_parameter_count,
-  %{provided: _, expected: _}}}`) or a keyword list for named placeholders.
-  Once stepping has started, call `reset/1` before rebinding — SQLite
-  rejects mid-run rebinds.
-  """
-  @spec bind(stmt(), list()) :: :ok | error()
-  def bind(stmt, params) when is_list(params) do
+  %{provided: _, expected: _}}}`), a keyword list for named placeholders,
+  or a map with string keys — `"id"` matches `:id`, `@id` or `$id`,
+  whichever the SQL uses, while `":id"` matches only `:id`. Once stepping
+  has started, call `reset/1` before rebinding — SQLite rejects mid-run
+  rebinds.
+  """
+  @spec bind(stmt(), list() | %{String.t() => term()}) :: :ok | error()
+  def bind(stmt, params) when is_list(params) or is_map(params) do
     XqliteNIF.stmt_bind(stmt, params)
   end
 
+  @doc """
+  Binds a single parameter, leaving the others as they are.
+
+  `key` is a 1-based index, an atom (`:id` for `:id`, as in keyword
+  lists) or a string name resolved like `bind/2` map keys. An unknown name
+  returns `{:error, {:invalid_parameter_name, name}}`, an out-of-range index
+  `{:error, {:invalid_parameter_index, index}}`. Useful for rebinding one
+  value per iteration of a loop; `reset/1` first if the statement has been
+  stepped.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> {:ok, stmt} = Xqlite.prepare(conn, "SELECT :a + @b")
+      iex> Xqlite.bind(stmt, %{"a" => 1, "b" => 2})
+      :ok
+      iex> Xqlite.step(stmt)
+      {:row, [3]}
+      iex> Xqlite.reset(stmt)
+      :ok
+      iex> Xqlite.bind_one(stmt, "@b", 40)
+      :ok
+      iex> Xqlite.step(stmt)
+      {:row, [41]}
+      iex> Xqlite.bind_one(stmt, 3, 0)
+      {:error, {:invalid_parameter_index, 3}}
+  """
+  @spec bind_one(stmt(), pos_integer() | atom() | String.t(), term()) :: :ok | error()
+  def bind_one(stmt, key, value)
+      when is_integer(key) or is_atom(key) or is_binary(key) do
+    XqliteNIF.stmt_bind_one(stmt, key, value)
+  end
+
+  @doc """
+  Describes a prepared statement's parameters.
+
+  Returns `{:ok, %{count: count, names: names}}`, where `names` lists each
+  parameter by index with its prefix (`":id"`, `"@id"`, `"$id"`, `"?2"`)
+  and `nil` for a nameless `?`. Lets query builders check their parameters
+  before binding.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> {:ok, stmt} = Xqlite.prepare(conn, "SELECT :id, ?, @name, :id")
+      iex> Xqlite.parameters(stmt)
+      {:ok, %{count: 3, names: [":id", nil, "@name"]}}
+  """
+  @spec parameters(stmt()) ::
+          {:ok, %{count: non_neg_integer(), names: [String.t() | nil]}} | error()
+  def parameters(stmt), do: XqliteNIF.stmt_parameters(stmt)
+
   @doc """
   Advances a prepared statement one row.
 
@@ -1067,6 +1435,81 @@ defmodule Xqlite do
   @spec column_names(stmt()) :: {:ok, [String.t()]} | error()
   def column_names(stmt), do: XqliteNIF.stmt_column_names(stmt)
 
+  @doc """
+  Returns metadata about a prepared statement.
+
+  The map holds:
+
+    * `:readonly` - `true` if the statement does not write the database
+      directly, which makes it safe to route to a read replica.
+    * `:explain` - `:none`, `:explain` or `:query_plan`. This reflects the
+      SQL's own `EXPLAIN` prefix or `set_explain_mode/2`.
+    * `:busy` - `true` between the first `step/1` and `:done` or `reset/1`.
+    * `:sql` - the SQL text as prepared.
+    * `:expanded_sql` - the SQL with the current bindings inlined as
+      literals, for logging. It is `nil` if SQLite cannot render it.
+    * `:declared_types` - the declared type of each result column, or
+      `nil` for expressions and for columns declared without a type.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> :ok = Xqlite.execute_batch(conn, "CREATE TABLE t(id INTEGER PRIMARY KEY, name TEXT);")
+      iex> {:ok, stmt} = Xqlite.prepare(conn, "SELECT id, upper(name) FROM t WHERE name = ?1")
+      iex> :ok = Xqlite.bind(stmt, ["it's"])
+      iex> Xqlite.statement_info(stmt)
+      {:ok,
+       %{
+         readonly: true,
+         explain: :none,
+         busy: false,
+         sql: "SELECT id, upper(name) FROM t WHERE name = ?1",
+         expanded_sql: "SELECT id, upper(name) FROM t WHERE name = 'it''s'",
+         declared_types: ["INTEGER", nil]
+       }}
+  """
+  @spec statement_info(stmt()) ::
+          {:ok,
+           %{
+             readonly: boolean(),
+             explain: :none | :explain | :query_plan,
+             busy: boolean(),
+             sql: String.t(),
+             expanded_sql: String.t() | nil,
+             declared_types: [String.t() | nil]
+           }}
+          | error()
+  def statement_info(stmt), do: XqliteNIF.stmt_info(stmt)
+
+  @doc """
+  Switches a prepared statement between normal execution (`:none`),
+  `:explain` (bytecode listing) and `:query_plan` (EXPLAIN QUERY PLAN)
+  without preparing it again.
+
+  Stepping a statement in `:query_plan` mode yields rows of
+  `[id, parent, notused, detail]`. Bindings are kept. A statement that has
+  been stepped must be `reset/1` first.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> :ok = Xqlite.execute_batch(conn, "CREATE TABLE t(id INTEGER PRIMARY KEY, name TEXT);")
+      iex> {:ok, stmt} = Xqlite.prepare(conn, "SELECT * FROM t")
+      iex> Xqlite.set_explain_mode(stmt, :query_plan)
+      :ok
+      iex> {:row, [_id, _parent, _notused, detail]} = Xqlite.step(stmt)
+      iex> detail
+      "SCAN t"
+      iex> Xqlite.reset(stmt)
+      :ok
+      iex> Xqlite.set_explain_mode(stmt, :none)
+      :ok
+      iex> Xqlite.step(stmt)
+      :done
+  """
+  @spec set_explain_mode(stmt(), :none | :explain | :query_plan) :: :ok | error()
+  def set_explain_mode(stmt, mode) when is_atom(mode), do: XqliteNIF.stmt_explain(stmt, mode)
+
   @doc """
   Finalizes a prepared statement, releasing its SQLite resources.
 
@@ -1077,6 +1520,59 @@ defmodule Xqlite do
   @spec finalize(stmt()) :: :ok | error()
   def finalize(stmt), do: XqliteNIF.stmt_finalize(stmt)
 
+  @doc """
+  Returns the runtime counters of a prepared statement or a stream.
+
+  Unlike `explain_analyze/3`, which measures a single ad-hoc run, these
+  counters (`sqlite3_stmt_status`) accumulate across every execution of a
+  long-lived statement — or across a whole stream — until read with
+  `reset` set to `true`, which zeroes them (`memused_bytes` is a size and
+  is never reset). That makes it easy to profile a statement in windows.
+
+  `handle` is a statement from `prepare/2` or a stream handle from
+  `XqliteNIF.stream_open/4`. A stream finalizes its statement once
+  exhausted; its counters as of that moment are still served afterwards.
+  Counters of a finalized statement or a closed stream are gone:
+  `{:error, :statement_finalized}`.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> :ok = XqliteNIF.execute_batch(conn, "CREATE TABLE t(x INTEGER); INSERT INTO t VALUES (2), (1), (3);")
+      iex> {:ok, stmt} = Xqlite.prepare(conn, "SELECT x FROM t ORDER BY x")
+      iex> for _ <- 1..3 do
+      ...>   {:ok, %{done: true}} = Xqlite.multi_step(stmt, 10)
+      ...>   Xqlite.reset(stmt)
+      ...> end
+      [:ok, :ok, :ok]
+      iex> {:ok, counters} = Xqlite.stmt_stats(stmt, true)
+      iex> {counters.run, counters.sort}
+      {3, 3}
+      iex> {:ok, %{run: 0}} = Xqlite.stmt_stats(stmt)
+      iex> Xqlite.finalize(stmt)
+      :ok
+  """
+  @spec stmt_stats(stmt() | reference(), boolean()) ::
+          {:ok, Xqlite.ExplainAnalyze.stmt_counters()} | error()
+  def stmt_stats(handle, reset \\ false) when is_boolean(reset) do
+    XqliteNIF.stmt_stats(handle, reset)
+  end
+
+  @doc """
+  Returns the per-loop scan counters of a prepared statement or a stream.
+
+  One entry per loop of the query plan (`sqlite3_stmt_scanstatus_v2`) with
+  the loop count, rows visited and the planner's row estimate. Like
+  `stmt_stats/2` the counts accumulate across executions until read with
+  `reset` set to `true`, and an exhausted stream still reports its final
+  counts. See `Xqlite.ExplainAnalyze` for how to read the fields.
+  """
+  @spec stmt_scanstatus(stmt() | reference(), boolean()) ::
+          {:ok, [Xqlite.ExplainAnalyze.scan()]} | error()
+  def stmt_scanstatus(handle, reset \\ false) when is_boolean(reset) do
+    XqliteNIF.stmt_scanstatus(handle, reset)
+  end
+
   # ---------------------------------------------------------------------------
   # Backup / serialize / deserialize
   # ---------------------------------------------------------------------------
@@ -1465,7 +1961,7 @@ defmodule Xqlite do
   end
 
   # ---------------------------------------------------------------------------
-  # Authorizer (deny-list, single slot)
+  # Authorizer (policy slot + audit observers)
   # ---------------------------------------------------------------------------
 
   @doc """
@@ -1491,15 +1987,10 @@ defmodule Xqlite do
   Single slot per connection: a second call replaces the previous list, and
   `remove_authorizer/1` clears it. Both are idempotent.
 
-  ## Limits (v1)
-
-    * **Action-kind granularity only.** The decision is made purely on the
-      action *kind*; the table, column, trigger and database arguments SQLite
-      passes to the authorizer are ignored. You cannot (yet) deny `DELETE` on
-      one table while allowing it on another.
-    * **Deny-only.** An action is allowed or denied (SQLite's `DENY`). The
-      `IGNORE` disposition (silently treat the access as a NULL/no-op) is not
-      exposed.
+  The decision is made on the action *kind* alone. To match on the table,
+  column, database or file an action touches, or to answer with SQLite's
+  `IGNORE`, use `set_authorizer_rules/3`; it shares this slot, so the two
+  replace each other.
 
   ## Caveat — denying `:pragma` disables `get_pragma`/`set_pragma`
 
@@ -1534,17 +2025,247 @@ defmodule Xqlite do
     XqliteNIF.set_authorizer(conn, denied_actions)
   end
 
+  @doc """
+  Installs a rule-based authorizer on the connection.
+
+  Each rule is `{outcome, actions}` or `{outcome, actions, matchers}`. While
+  a statement is prepared, SQLite reports every action it needs; the rules
+  are tried in order and the **first** one that fits decides. When none
+  fits, the `:default` outcome applies. Evaluation happens natively inside
+  the authorizer callback — no Elixir code runs per action.
+
+  `outcome` is one of:
+
+    * `:allow` — permit the action.
+    * `:deny` — fail preparation with
+      `{:error, {:authorization_denied, extended_code, message}}`.
+    * `:ignore` — SQLite's `SQLITE_IGNORE`: a `:read` of a column yields
+      `NULL` instead of the stored value. For other actions the effect is
+      the one SQLite documents for `SQLITE_IGNORE` (often a silent no-op).
+
+  `actions` is one action-kind atom or a list of them, using the atoms of
+  `set_authorizer/2`. `matchers` is a keyword list; every given key must
+  equal the corresponding argument of the action, and an action without
+  that argument never matches:
+
+    * `:table` — the table of `:read`, `:update`, `:insert`, `:delete`,
+      `:alter_table`, `:analyze` and the table-level `create_*`/`drop_*`
+      actions (including the table an index or trigger belongs to).
+    * `:column` — the column of `:read` and `:update`.
+    * `:name` — the index, trigger, view, pragma, function, savepoint or
+      virtual-table module name.
+    * `:database` — the schema the action applies to (`"main"`, `"temp"`
+      or an attached name).
+    * `:accessor` — the innermost trigger or view causing the access.
+    * `:file` — the file name of `:attach`.
+
+  Identifiers compare ASCII case-insensitively, like SQLite's own; `:file`
+  compares exactly. Rules are validated in full before anything changes: a
+  malformed rule returns `{:error, {:invalid_authorizer_rule, inspected}}`
+  and an unknown action atom `{:error, {:invalid_authorizer_action, atom}}`.
+
+  ## Options
+
+    * `:default` — outcome when no rule matches (default `:allow`). With
+      `:deny` every action must be allowed explicitly, `:select` included.
+    * `:dry_run` — when `true`, rules are evaluated and their decision is
+      reported to observers (see `register_authorizer_observer/2`), but
+      every action is allowed. Default `false`.
+
+  Shares the single authorizer slot with `set_authorizer/2`;
+  `remove_authorizer/1` clears either. The `:pragma` caveat of
+  `set_authorizer/2` applies to rules that deny or ignore pragmas.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> :ok = XqliteNIF.execute_batch(conn, "CREATE TABLE users(id INTEGER, password_hash TEXT); INSERT INTO users VALUES (1, 'secret');")
+      iex> Xqlite.set_authorizer_rules(conn, [
+      ...>   {:ignore, :read, table: "users", column: "password_hash"},
+      ...>   {:deny, [:insert, :update, :delete]}
+      ...> ])
+      :ok
+      iex> XqliteNIF.query(conn, "SELECT id, password_hash FROM users", []) |> elem(1) |> Map.get(:rows)
+      [[1, nil]]
+      iex> match?({:error, {:authorization_denied, _, _}}, XqliteNIF.execute(conn, "DELETE FROM users", []))
+      true
+      iex> Xqlite.set_authorizer_rules(conn, [{:maybe, :read}])
+      {:error, {:invalid_authorizer_rule, "{maybe,read}"}}
+  """
+  @spec set_authorizer_rules(conn(), [tuple()], keyword()) :: :ok | error()
+  def set_authorizer_rules(conn, rules, opts \\ []) when is_list(rules) do
+    XqliteNIF.set_authorizer_rules(
+      conn,
+      rules,
+      Keyword.get(opts, :default, :allow),
+      Keyword.get(opts, :dry_run, false)
+    )
+  end
+
   @doc """
   Removes any authorizer installed on the connection.
 
   Safe to call when none is installed (no-op). After removal, statement
-  preparation is unrestricted again.
+  preparation is unrestricted again. Observers registered with
+  `register_authorizer_observer/2` stay registered and now see `:allow`
+  for every request.
 
   No telemetry is emitted.
   """
   @spec remove_authorizer(conn()) :: :ok | error()
   def remove_authorizer(conn), do: XqliteNIF.remove_authorizer(conn)
 
+  @doc """
+  Registers an authorizer audit observer on the connection.
+
+  Every authorization request SQLite makes while preparing a statement
+  sends
+
+      {:xqlite_authorizer, action, arg1, arg2, db, trigger_or_view, decision}
+
+  to `pid`. `action` is an action-kind atom as accepted by
+  `set_authorizer/2`; `arg1` and `arg2` are SQLite's raw arguments for that
+  action (e.g. table and column for `:read`, pragma name and value for
+  `:pragma`) or `nil`; `db` is the schema name and `trigger_or_view` the
+  innermost trigger or view causing the access, each `nil` when not
+  applicable. `decision` is `:allow`, `:deny` or `:ignore` — what the
+  installed policy decided, or `:allow` when none is installed.
+
+  Observers work alongside the single policy slot and any number can be
+  registered, each with its own handle for
+  `unregister_authorizer_observer/2`. Together with the `:dry_run` option of
+  `set_authorizer_rules/3` this supports a log → dry-run → enforce rollout:
+  observe a workload, evaluate a candidate policy without enforcing it, then
+  install it for real.
+
+  Requests are reported when a statement is *prepared* (or re-prepared
+  after a schema change), not on each execution. Messages are
+  fire-and-forget and can be numerous (one `:read` per column referenced).
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> :ok = XqliteNIF.execute_batch(conn, "CREATE TABLE t(id INTEGER);")
+      iex> {:ok, handle} = Xqlite.register_authorizer_observer(conn, self())
+      iex> {:ok, _} = XqliteNIF.execute(conn, "INSERT INTO t VALUES (1)", [])
+      iex> receive do
+      ...>   {:xqlite_authorizer, :insert, table, nil, db, nil, decision} -> {table, db, decision}
+      ...> end
+      {"t", "main", :allow}
+      iex> Xqlite.unregister_authorizer_observer(conn, handle)
+      :ok
+  """
+  @spec register_authorizer_observer(conn(), pid()) :: {:ok, non_neg_integer()} | error()
+  def register_authorizer_observer(conn, pid) when is_pid(pid) do
+    XqliteNIF.register_authorizer_observer(conn, pid)
+  end
+
+  @doc """
+  Unregisters an authorizer audit observer by handle.
+
+  Idempotent — an unknown or already-removed handle is a no-op.
+  """
+  @spec unregister_authorizer_observer(conn(), non_neg_integer()) :: :ok | error()
+  def unregister_authorizer_observer(conn, handle) when is_integer(handle) and handle >= 0 do
+    XqliteNIF.unregister_authorizer_observer(conn, handle)
+  end
+
+  # ---------------------------------------------------------------------------
+  # Slow-query log (rides on the trace_v2 callback)
+  # ---------------------------------------------------------------------------
+
+  @doc """
+  Installs (or replaces) a native slow-query log on the connection.
+
+  Every statement whose run takes at least `:threshold_ms` produces a
+  `t:slow_query/0` record holding its SQL text (parameters unexpanded),
+  bound-parameter count, run time in nanoseconds, result-row count and
+  optionally its `EXPLAIN QUERY PLAN`. Timing comes from SQLite's own
+  profile clock inside the NIF, so statements of `execute_batch` and
+  streams are covered along with plain queries; a trigger's work counts
+  towards the statement that fired it.
+
+  Records are kept in a bounded ring buffer drained with `slow_queries/1`,
+  or sent as `{:xqlite_slow_query, record}` to `:pid` when given. Replacing
+  a log discards its undrained records. The same settings can be passed to
+  `open/2` as the `:slow_query_log` option.
+
+  ## Options
+
+    * `:threshold_ms` (required, non-negative integer) — records runs of at
+      least this many milliseconds; `0` records every statement.
+    * `:query_plan` (`false`, `true` or a positive integer, default
+      `false`) — capture the plan for every slow statement (`true`) or
+      every Nth one. The plan is produced right after the statement
+      finishes, so it costs an extra prepare per captured record.
+    * `:capacity` (positive integer, default `100`) — ring buffer size;
+      the oldest record is dropped when full.
+    * `:pid` — send records to this process instead of buffering them.
+
+  Options are validated before anything is installed; an unknown key or a
+  bad value returns `{:error, {:invalid_slow_query_log_option, %{key: key,
+  value: value, message: message}}}` and leaves any current log in place.
+
+  While a log is installed the connection pays the profile clock around
+  each statement plus a native callback per result row.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> Xqlite.set_slow_query_log(conn, threshold_ms: 0, query_plan: true)
+      :ok
+      iex> {:ok, _} = XqliteNIF.query(conn, "SELECT ?1 + 1", [41])
+      iex> {:ok, [record]} = Xqlite.slow_queries(conn)
+      iex> {record.sql, record.param_count, record.rows, is_list(record.query_plan)}
+      {"SELECT ?1 + 1", 1, 1, true}
+      iex> Xqlite.slow_queries(conn)
+      {:ok, []}
+      iex> {:error, {:invalid_slow_query_log_option, %{key: :capacity, value: 0}}} =
+      ...>   Xqlite.set_slow_query_log(conn, threshold_ms: 0, capacity: 0)
+  """
+  @spec set_slow_query_log(conn(), keyword()) :: :ok | error()
+  def set_slow_query_log(conn, opts) when is_list(opts) do
+    case NimbleOptions.validate(opts, @slow_query_log_schema) do
+      {:ok, validated} ->
+        plan_every =
+          case validated[:query_plan] do
+            false -> 0
+            true -> 1
+            n -> n
+          end
+
+        XqliteNIF.set_slow_query_log(
+          conn,
+          validated[:threshold_ms],
+          plan_every,
+          validated[:capacity],
+          validated[:pid]
+        )
+
+      {:error, %NimbleOptions.ValidationError{} = err} ->
+        {:error,
+         {:invalid_slow_query_log_option,
+          %{key: err.key, value: err.value, message: Exception.message(err)}}}
+    end
+  end
+
+  @doc """
+  Removes the connection's slow-query log, discarding undrained records.
+
+  Idempotent — a no-op when no log is installed.
+  """
+  @spec clear_slow_query_log(conn()) :: :ok | error()
+  def clear_slow_query_log(conn), do: XqliteNIF.clear_slow_query_log(conn)
+
+  @doc """
+  Drains the slow-query ring buffer, oldest record first.
+
+  Returns `{:ok, []}` when no log is installed or its records go to a pid.
+  See `set_slow_query_log/2` for the record shape.
+  """
+  @spec slow_queries(conn()) :: {:ok, [slow_query()]} | error()
+  def slow_queries(conn), do: XqliteNIF.slow_queries(conn)
+
   # ---------------------------------------------------------------------------
   # Progress hook (multi-subscriber on the progress_handler slot)
   # ---------------------------------------------------------------------------
@@ -1602,6 +2323,656 @@ defmodule Xqlite do
     XqliteNIF.unregister_progress_hook(conn, handle)
   end
 
+  # ---------------------------------------------------------------------------
+  # User-defined SQL functions (Elixir-implemented)
+  # ---------------------------------------------------------------------------
+
+  @doc """
+  Registers an Elixir-implemented scalar SQL function on the connection.
+
+  `handler` is a 1-arity fun, an `{mod, fun, extra}` MFA, or a pid; see
+  `Xqlite.Function` for the calling convention, return-value rules and the
+  deadlock caveat (the handler must not use the same connection). The
+  function is callable from any SQL on `conn` — queries, views, triggers,
+  `WHERE` clauses — once this returns.
+
+  `n_args` is the arity (`-1` accepts any number of arguments). `flags` is
+  a list of:
+
+    * `:deterministic` — same inputs always give the same output; lets
+      SQLite use the function in indexes and generated columns and factor
+      calls out of loops.
+    * `:direct_only` — callable only from top-level SQL, never from
+      triggers, views, CHECK constraints or generated columns.
+    * `:innocuous` — safe to call from schema objects even when
+      `trusted_schema` is off.
+
+  Failures inside the function fail the running statement with
+  `{:error, {:user_function_failed, name, kind, message}}`, `kind` being
+  `:error`, `:timeout`, `:noproc` or `:invalid_result`.
+
+  ## Options
+
+    * `:timeout` (positive integer, default `5_000`) — milliseconds to wait
+      for each call's reply.
+
+  No telemetry is emitted.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> Xqlite.register_scalar_function(conn, "double", 1, [:deterministic], fn [x] -> x * 2 end)
+      :ok
+      iex> XqliteNIF.query(conn, "SELECT double(21)", [])
+      {:ok, %{columns: ["double(21)"], rows: [[42]], num_rows: 1}}
+  """
+  @spec register_scalar_function(
+          conn(),
+          String.t(),
+          integer(),
+          [function_flag()],
+          Xqlite.Function.handler(),
+          keyword()
+        ) :: :ok | error()
+  def register_scalar_function(conn, name, n_args, flags, handler, opts \\ [])
+      when is_binary(name) and is_integer(n_args) and is_list(flags) and is_list(opts) do
+    timeout = Keyword.get(opts, :timeout, 5_000)
+    dispatcher = Xqlite.Function.dispatcher(handler)
+    pid = Xqlite.Function.handler_pid(dispatcher)
+
+    case XqliteNIF.register_scalar_function(conn, name, n_args, flags, pid, timeout) do
+      :ok ->
+        :ok
+
+      {:error, _} = error ->
+        Xqlite.Function.stop_dispatcher(dispatcher)
+        error
+    end
+  end
+
+  @doc """
+  Registers an Elixir-implemented aggregate (or window) SQL function.
+
+  `handler` is a module implementing `Xqlite.Aggregate`, or a pid that
+  answers `:xqlite_aggregate_call` messages (see `Xqlite.Function`). The
+  per-group accumulator is held natively by SQLite, so the handler stays
+  stateless and may serve many groups and connections at once. `n_args`,
+  `flags`, failures and the deadlock caveat are as in
+  `register_scalar_function/6`.
+
+  ## Options
+
+    * `:window` (boolean, default `false`) — register as a window function;
+      the module must then also implement `inverse/2` and `value/1`. A
+      window function is usable as a plain aggregate too.
+    * `:timeout` (positive integer, default `5_000`) — milliseconds to wait
+      for each callback's reply.
+
+  For aggregates implemented natively in Rust, see
+  `register_builtin_aggregate/3`.
+
+  No telemetry is emitted.
+  """
+  @spec register_aggregate_function(
+          conn(),
+          String.t(),
+          integer(),
+          [function_flag()],
+          module() | pid(),
+          keyword()
+        ) :: :ok | error()
+  def register_aggregate_function(conn, name, n_args, flags, handler, opts \\ [])
+      when is_binary(name) and is_integer(n_args) and is_list(flags) and is_list(opts) do
+    timeout = Keyword.get(opts, :timeout, 5_000)
+    dispatcher = Xqlite.Function.aggregate_dispatcher(handler)
+    pid = Xqlite.Function.handler_pid(dispatcher)
+
+    result =
+      if Keyword.get(opts, :window, false) do
+        XqliteNIF.register_window_function(conn, name, n_args, flags, pid, timeout)
+      else
+        XqliteNIF.register_aggregate_function(conn, name, n_args, flags, pid, timeout)
+      end
+
+    case result do
+      :ok ->
+        :ok
+
+      {:error, _} = error ->
+        Xqlite.Function.stop_dispatcher(dispatcher)
+        error
+    end
+  end
+
+  @doc """
+  Registers a Rust-implemented aggregate under the SQL name `name`.
+
+  See `XqliteNIF.register_builtin_aggregate/3` for the available built-ins
+  (currently `:weighted_median`). Built-ins are also window functions.
+
+  No telemetry is emitted.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> Xqlite.register_builtin_aggregate(conn, "wmedian", :weighted_median)
+      :ok
+      iex> XqliteNIF.query(conn, "SELECT wmedian(v, w) FROM (SELECT 1 AS v, 1 AS w UNION ALL SELECT 5, 3)", [])
+      {:ok, %{columns: ["wmedian(v, w)"], rows: [[5.0]], num_rows: 1}}
+  """
+  @spec register_builtin_aggregate(conn(), String.t(), builtin_aggregate()) :: :ok | error()
+  def register_builtin_aggregate(conn, name, builtin)
+      when is_binary(name) and is_atom(builtin) do
+    XqliteNIF.register_builtin_aggregate(conn, name, builtin)
+  end
+
+  @doc """
+  Removes the user function `name` with arity `n_args`.
+
+  A dispatcher spawned for the function's fun, MFA or aggregate module
+  stops once SQLite lets go of the function. Safe to call when no such
+  function is registered. No telemetry is emitted.
+  """
+  @spec unregister_function(conn(), String.t(), integer()) :: :ok | error()
+  def unregister_function(conn, name, n_args) when is_binary(name) and is_integer(n_args) do
+    XqliteNIF.unregister_function(conn, name, n_args)
+  end
+
+  # ---------------------------------------------------------------------------
+  # Collations
+  # ---------------------------------------------------------------------------
+
+  @doc """
+  Registers a collation usable as `COLLATE name` in queries, indexes and
+  column definitions.
+
+  `comparator` is a native collation atom (`:unicode_nocase`, `:natural`,
+  `:no_accents` — see `XqliteNIF.register_native_collation/3`), a 2-arity
+  fun, an `{mod, fun, extra}` MFA, or a pid; see `Xqlite.Collation` for
+  the calling convention. Native collations never leave the native
+  thread; the others cost a message round trip per comparison.
+  Registering an existing name replaces it.
+
+  ## Options
+
+    * `:timeout` (positive integer, default `5_000`) — milliseconds to wait
+      for each comparison. Ignored for native collations.
+
+  No telemetry is emitted.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> Xqlite.register_collation(conn, "natural", :natural)
+      :ok
+      iex> XqliteNIF.query(conn, "SELECT v FROM (SELECT 'file10' AS v UNION ALL SELECT 'file2') ORDER BY v COLLATE natural", [])
+      {:ok, %{columns: ["v"], rows: [["file2"], ["file10"]], num_rows: 2}}
+  """
+  @spec register_collation(conn(), String.t(), Xqlite.Collation.comparator(), keyword()) ::
+          :ok | error()
+  def register_collation(conn, name, comparator, opts \\ [])
+
+  def register_collation(conn, name, native, _opts) when is_binary(name) and is_atom(native) do
+    XqliteNIF.register_native_collation(conn, name, native)
+  end
+
+  def register_collation(conn, name, comparator, opts) when is_binary(name) and is_list(opts) do
+    timeout = Keyword.get(opts, :timeout, 5_000)
+    dispatcher = Xqlite.Collation.dispatcher(comparator)
+    pid = Xqlite.Function.handler_pid(dispatcher)
+
+    case XqliteNIF.register_collation(conn, name, pid, timeout) do
+      :ok ->
+        :ok
+
+      {:error, _} = error ->
+        Xqlite.Function.stop_dispatcher(dispatcher)
+        error
+    end
+  end
+
+  @doc """
+  Removes the collation `name`. No telemetry is emitted.
+  """
+  @spec unregister_collation(conn(), String.t()) :: :ok | error()
+  def unregister_collation(conn, name) when is_binary(name) do
+    XqliteNIF.unregister_collation(conn, name)
+  end
+
+  @doc """
+  Resolves unknown collations lazily, when a statement first needs them.
+
+  Without a hook, preparing SQL that references an unregistered collation
+  (including one named in a schema written by another application) fails
+  with `no such collation sequence`. With one, `resolver` is called with
+  the collation's name and returns `nil` to leave it unknown, a native
+  collation atom, or a comparator fun / MFA (see `Xqlite.Collation`). The
+  result is registered on `conn` under that name, so each name is
+  resolved at most once. `resolver` may also be a pid speaking the raw
+  protocol of `XqliteNIF.set_collation_needed_hook/3`.
+
+  Replaces any previous hook. The deadlock caveat of `Xqlite.Function`
+  applies: the resolver must not use `conn`.
+
+  ## Options
+
+    * `:timeout` (positive integer, default `5_000`) — milliseconds to wait
+      for each resolution and each resolved comparison.
+
+  No telemetry is emitted.
+  """
+  @spec set_collation_needed_hook(conn(), Xqlite.Collation.resolver(), keyword()) ::
+          :ok | error()
+  def set_collation_needed_hook(conn, resolver, opts \\ []) when is_list(opts) do
+    timeout = Keyword.get(opts, :timeout, 5_000)
+    dispatcher = Xqlite.Collation.resolver_dispatcher(resolver)
+    pid = Xqlite.Function.handler_pid(dispatcher)
+
+    case XqliteNIF.set_collation_needed_hook(conn, pid, timeout) do
+      :ok ->
+        :ok
+
+      {:error, _} = error ->
+        Xqlite.Function.stop_dispatcher(dispatcher)
+        error
+    end
+  end
+
+  @doc """
+  Removes the `collation_needed` hook. Collations it already resolved
+  stay registered. No telemetry is emitted.
+  """
+  @spec remove_collation_needed_hook(conn()) :: :ok | error()
+  def remove_collation_needed_hook(conn), do: XqliteNIF.remove_collation_needed_hook(conn)
+
+  # ---------------------------------------------------------------------------
+  # Changesets
+  # ---------------------------------------------------------------------------
+
+  @doc """
+  Applies a changeset or patchset to `conn`, deciding conflicts with
+  `conflict`.
+
+  `changeset` is a binary or `{:file, path}`; a file is read
+  incrementally (`XqliteNIF.changeset_apply_file/4`).
+
+  `conflict` is a fixed strategy (`:omit`, `:replace`, `:abort` — see
+  `XqliteNIF.changeset_apply/4`), a 3-arity fun
+  `fn type, change, conflicting -> :omit | :replace | :abort end`, an
+  MFA, or a pid; see `Xqlite.Conflict` for what each conflict carries.
+  Funs and MFAs run in a dispatcher process that lives for the duration
+  of the call, so they must not use `conn`.
+
+  ## Options
+
+    * `:tables` (list of strings) — apply only these tables.
+    * `:exclude_tables` (list of strings) — skip these tables.
+    * `:table_filter` — a 1-arity fun, MFA or pid deciding per table
+      (`true` applies it); asked once per table before anything is
+      applied.
+    * `:timeout` (positive integer, default `5_000`) — milliseconds to
+      wait for each conflict decision or filter answer.
+    * `:rebase` (boolean, default `false`) — return `{:ok, rebase}` with
+      the buffer for `XqliteNIF.rebaser_configure/2` instead of `:ok`.
+
+  A failing handler or filter aborts and rolls back the whole apply with
+  `{:error, {:changeset_handler_failed, kind, message}}`.
+
+  No telemetry is emitted.
+
+  ## Examples
+
+      iex> {:ok, source} = Xqlite.open_in_memory()
+      iex> {:ok, replica} = Xqlite.open_in_memory()
+      iex> schema = "CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT); CREATE TABLE u (id INTEGER PRIMARY KEY)"
+      iex> :ok = XqliteNIF.execute_batch(source, schema)
+      iex> :ok = XqliteNIF.execute_batch(replica, schema <> "; INSERT INTO t VALUES (1, 'mine')")
+      iex> {:ok, session} = XqliteNIF.session_new(source)
+      iex> :ok = XqliteNIF.session_attach(session, nil)
+      iex> :ok = XqliteNIF.execute_batch(source, "INSERT INTO t VALUES (1, 'theirs'); INSERT INTO u VALUES (7)")
+      iex> {:ok, changeset} = XqliteNIF.session_changeset(session)
+      iex> Xqlite.changeset_apply(replica, changeset, fn :conflict, _change, _row -> :replace end,
+      ...>   tables: ["t"])
+      :ok
+      iex> XqliteNIF.query(replica, "SELECT (SELECT v FROM t), (SELECT count(*) FROM u)", [])
+      {:ok, %{columns: ["(SELECT v FROM t)", "(SELECT count(*) FROM u)"], rows: [["theirs", 0]], num_rows: 1}}
+  """
+  @spec changeset_apply(
+          conn(),
+          binary() | {:file, Path.t()},
+          Xqlite.Conflict.handler(),
+          keyword()
+        ) :: :ok | {:ok, binary()} | error()
+  def changeset_apply(conn, changeset, conflict, opts \\ [])
+      when (is_binary(changeset) or is_tuple(changeset)) and is_list(opts) do
+    {table_filter, opts} = Keyword.pop(opts, :table_filter)
+
+    {conflict, table_filter, dispatcher} =
+      Xqlite.Conflict.dispatcher(conflict, table_filter)
+
+    opts = if table_filter, do: Keyword.put(opts, :table_filter, table_filter), else: opts
+
+    try do
+      case changeset do
+        {:file, path} -> XqliteNIF.changeset_apply_file(conn, to_string(path), conflict, opts)
+        binary -> XqliteNIF.changeset_apply(conn, binary, conflict, opts)
+      end
+    after
+      Xqlite.Function.stop_dispatcher(dispatcher)
+    end
+  end
+
+  @doc """
+  Lazily decodes a changeset or patchset into a stream of `Xqlite.Change`
+  structs.
+
+  `source` is a changeset binary or `{:file, path}`. A file is read
+  incrementally, so changesets larger than memory can be processed;
+  changes are fetched natively `:batch_size` (default `500`) at a time.
+  Opening and decoding errors raise `Xqlite.StreamError`. The native
+  iterator is released when the stream finishes or halts.
+
+  No telemetry is emitted.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> :ok = XqliteNIF.execute_batch(conn, "CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT)")
+      iex> {:ok, session} = XqliteNIF.session_new(conn)
+      iex> :ok = XqliteNIF.session_attach(session, nil)
+      iex> {:ok, 1} = XqliteNIF.execute(conn, "INSERT INTO t VALUES (1, 'a')", [])
+      iex> {:ok, changeset} = XqliteNIF.session_changeset(session)
+      iex> Xqlite.changeset_stream(changeset) |> Enum.map(&{&1.op, &1.new})
+      [{:insert, [1, "a"]}]
+  """
+  @spec changeset_stream(binary() | {:file, Path.t()}, keyword()) :: Enumerable.t()
+  def changeset_stream(source, opts \\ []) do
+    batch_size = Keyword.get(opts, :batch_size, 500)
+
+    Stream.resource(
+      fn -> open_changeset_iter(source) end,
+      fn iter ->
+        case XqliteNIF.changeset_iter_next(iter, batch_size) do
+          {:ok, changes} -> {changes, iter}
+          :done -> {:halt, iter}
+          {:error, reason} -> raise Xqlite.StreamError, reason: reason
+        end
+      end,
+      &XqliteNIF.changeset_iter_close/1
+    )
+  end
+
+  @doc """
+  Merges an enumerable of changesets (or of patchsets) into one minimal
+  changeset using a changegroup, in time linear in their total size.
+
+  Each element is a changeset binary or `{:file, path}`; files are read
+  incrementally. Changes to the same row collapse the way they would with
+  `XqliteNIF.changeset_concat/2`.
+
+  ## Options
+
+    * `:schema` (connection) — bind the merge to this connection's schema,
+      so changesets recorded before an `ALTER TABLE ... ADD COLUMN` are
+      widened to the current columns. See `XqliteNIF.changegroup_new/2`.
+    * `:db` (string, default `"main"`) — schema name within `:schema`.
+    * `:into` — `{:file, path}` writes the merged changeset to `path` and
+      returns `:ok` instead of `{:ok, binary}`.
+
+  The changegroup is deleted before returning, also on error.
+
+  No telemetry is emitted.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> :ok = XqliteNIF.execute_batch(conn, "CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT)")
+      iex> record = fn sql ->
+      ...>   {:ok, session} = XqliteNIF.session_new(conn)
+      ...>   :ok = XqliteNIF.session_attach(session, nil)
+      ...>   :ok = XqliteNIF.execute_batch(conn, sql)
+      ...>   {:ok, changeset} = XqliteNIF.session_changeset(session)
+      ...>   :ok = XqliteNIF.session_delete(session)
+      ...>   changeset
+      ...> end
+      iex> changesets = for v <- ~w(a b c), do: record.("REPLACE INTO t VALUES (1, '" <> v <> "')")
+      iex> {:ok, merged} = Xqlite.changeset_merge(changesets)
+      iex> Xqlite.changeset_stream(merged) |> Enum.map(&{&1.op, &1.new})
+      [{:insert, [1, "c"]}]
+  """
+  @spec changeset_merge(Enumerable.t(), keyword()) :: {:ok, binary()} | :ok | error()
+  def changeset_merge(changesets, opts \\ []) when is_list(opts) do
+    schema = Keyword.get(opts, :schema)
+    db = Keyword.get(opts, :db, "main")
+
+    with {:ok, group} <- XqliteNIF.changegroup_new(schema, db) do
+      try do
+        with :ok <- add_changesets(group, changesets) do
+          case Keyword.get(opts, :into) do
+            nil -> XqliteNIF.changegroup_output(group)
+            {:file, path} -> XqliteNIF.changegroup_output_file(group, to_string(path))
+          end
+        end
+      after
+        XqliteNIF.changegroup_delete(group)
+      end
+    end
+  end
+
+  defp add_changesets(group, changesets) do
+    Enum.reduce_while(changesets, :ok, fn changeset, :ok ->
+      result =
+        case changeset do
+          {:file, path} -> XqliteNIF.changegroup_add_file(group, to_string(path))
+          binary when is_binary(binary) -> XqliteNIF.changegroup_add(group, binary)
+        end
+
+      case result do
+        :ok -> {:cont, :ok}
+        error -> {:halt, error}
+      end
+    end)
+  end
+
+  @doc """
+  Writes the changes recorded by `session` to `destination` in chunks,
+  without building the changeset as one binary.
+
+  `destination` is one of:
+
+    * `{:file, path}` — the file is created or truncated;
+    * a 1-arity fun — called with each chunk, in order; its return value
+      is ignored and a raise stops the output;
+    * a pid — receives `{:xqlite_changeset_chunk, call_id, chunk}` and
+      acknowledges each chunk as described in
+      `XqliteNIF.session_changeset_send/4`.
+
+  The next chunk is produced only after the previous one was handled, so
+  memory stays bounded by `:chunk_size` however large the session is.
+  Funs run in a dispatcher process that lives for the duration of the
+  call, so they must not use the session's connection.
+
+  ## Options
+
+    * `:patchset` (boolean, default `false`) — write a patchset instead.
+    * `:chunk_size` (positive integer, default `65_536`) — bytes per chunk.
+    * `:timeout` (positive integer, default `5_000`) — milliseconds to
+      wait for each chunk to be handled.
+
+  A failing fun or receiver stops the output with
+  `{:error, {:changeset_handler_failed, kind, message}}`.
+
+  No telemetry is emitted.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> :ok = XqliteNIF.execute_batch(conn, "CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT)")
+      iex> {:ok, session} = XqliteNIF.session_new(conn)
+      iex> :ok = XqliteNIF.session_attach(session, nil)
+      iex> :ok = XqliteNIF.execute_batch(conn, "INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+      iex> {:ok, agent} = Agent.start_link(fn -> [] end)
+      iex> collect = fn chunk -> Agent.update(agent, &[chunk | &1]) end
+      iex> :ok = Xqlite.session_changeset_into(session, collect, chunk_size: 8)
+      iex> chunks = agent |> Agent.get(& &1) |> Enum.reverse()
+      iex> Enum.all?(chunks, &(byte_size(&1) <= 8))
+      true
+      iex> changeset = IO.iodata_to_binary(chunks)
+      iex> Xqlite.changeset_stream(changeset) |> Enum.map(& &1.new) |> Enum.sort()
+      [[1, "a"], [2, "b"]]
+  """
+  @spec session_changeset_into(
+          reference(),
+          {:file, Path.t()} | pid() | (binary() -> any()),
+          keyword()
+        ) :: :ok | error()
+  def session_changeset_into(session, destination, opts \\ []) when is_list(opts) do
+    patchset? = Keyword.get(opts, :patchset, false)
+
+    case destination do
+      {:file, path} when patchset? ->
+        XqliteNIF.session_patchset_file(session, to_string(path))
+
+      {:file, path} ->
+        XqliteNIF.session_changeset_file(session, to_string(path))
+
+      pid when is_pid(pid) ->
+        send_changeset(session, pid, patchset?, opts)
+
+      fun when is_function(fun, 1) ->
+        dispatcher = spawn_link(fn -> chunk_loop(fun) end)
+
+        try do
+          send_changeset(session, dispatcher, patchset?, opts)
+        after
+          Xqlite.Function.stop_dispatcher({dispatcher, true})
+        end
+    end
+  end
+
+  defp send_changeset(session, pid, patchset?, opts) do
+    chunk_size = Keyword.get(opts, :chunk_size, 65_536)
+    timeout = Keyword.get(opts, :timeout, 5_000)
+
+    if patchset?,
+      do: XqliteNIF.session_patchset_send(session, pid, chunk_size, timeout),
+      else: XqliteNIF.session_changeset_send(session, pid, chunk_size, timeout)
+  end
+
+  defp chunk_loop(fun) do
+    receive do
+      {:xqlite_changeset_chunk, call_id, chunk} ->
+        XqliteNIF.function_reply(call_id, handle_chunk(fun, chunk))
+        chunk_loop(fun)
+    end
+  end
+
+  defp handle_chunk(fun, chunk) do
+    fun.(chunk)
+    {:ok, true}
+  rescue
+    e -> {:error, Exception.message(e)}
+  catch
+    kind, reason -> {:error, Exception.format_banner(kind, reason)}
+  end
+
+  @doc """
+  Returns the changeset that turns the database file at `from_path` into
+  the `main` database of `conn`.
+
+  The file is attached for the duration of the call and every table of
+  `main` is compared with its namesake there (`XqliteNIF.session_diff/3`).
+  Applying the result to the file — for example a restored backup or an
+  old snapshot — brings it in line with `conn`.
+
+  ## Options
+
+    * `:tables` (list of strings) — compare only these tables. Default:
+      every table of `main` except SQLite's internal ones.
+    * `:as` (string, default `"xqlite_diff"`) — schema name to attach the
+      file under.
+    * `:patchset` (boolean, default `false`) — return a patchset instead.
+
+  Tables must have the same columns and primary key in both databases;
+  tables without a primary key are skipped. Fails while a preupdate hook
+  is registered (see `XqliteNIF.session_new/1`) or inside a transaction,
+  where `ATTACH` is not allowed.
+
+  No telemetry is emitted.
+
+  ## Examples
+
+      iex> path = Path.join(System.tmp_dir!(), "xqlite_diff_doctest.db")
+      iex> File.rm(path)
+      iex> {:ok, old} = Xqlite.open(path)
+      iex> :ok = XqliteNIF.execute_batch(old, "CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT); INSERT INTO t VALUES (1, 'a')")
+      iex> :ok = Xqlite.close(old)
+      iex> {:ok, conn} = Xqlite.open_in_memory()
+      iex> :ok = XqliteNIF.execute_batch(conn, "CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT); INSERT INTO t VALUES (1, 'b')")
+      iex> {:ok, changeset} = Xqlite.changeset_diff(conn, path)
+      iex> Xqlite.changeset_stream(changeset) |> Enum.map(&{&1.op, &1.old, &1.new})
+      [{:update, [1, "a"], [:undefined, "b"]}]
+  """
+  @spec changeset_diff(conn(), Path.t(), keyword()) :: {:ok, binary()} | error()
+  def changeset_diff(conn, from_path, opts \\ []) when is_list(opts) do
+    schema = Keyword.get(opts, :as, "xqlite_diff")
+    quoted = "\"" <> String.replace(schema, "\"", "\"\"") <> "\""
+
+    with {:ok, tables} <- diff_tables(conn, Keyword.get(opts, :tables)),
+         {:ok, _} <-
+           XqliteNIF.execute(conn, "ATTACH DATABASE ?1 AS #{quoted}", [to_string(from_path)]) do
+      try do
+        diff_session(conn, schema, tables, Keyword.get(opts, :patchset, false))
+      after
+        XqliteNIF.execute(conn, "DETACH DATABASE #{quoted}", [])
+      end
+    end
+  end
+
+  defp diff_tables(_conn, tables) when is_list(tables), do: {:ok, tables}
+
+  defp diff_tables(conn, nil) do
+    with {:ok, objects} <- XqliteNIF.schema_list_objects(conn, "main") do
+      {:ok,
+       for %{object_type: :table, name: name} <- objects,
+           not String.starts_with?(name, "sqlite_"),
+           do: name}
+    end
+  end
+
+  defp diff_session(conn, schema, tables, patchset?) do
+    with {:ok, session} <- XqliteNIF.session_new(conn) do
+      try do
+        result =
+          Enum.reduce_while(tables, XqliteNIF.session_attach(session, nil), fn
+            table, :ok -> {:cont, XqliteNIF.session_diff(session, schema, table)}
+            _table, error -> {:halt, error}
+          end)
+
+        with :ok <- result do
+          if patchset?,
+            do: XqliteNIF.session_patchset(session),
+            else: XqliteNIF.session_changeset(session)
+        end
+      after
+        XqliteNIF.session_delete(session)
+      end
+    end
+  end
+
+  defp open_changeset_iter(source) do
+    result =
+      case source do
+        {:file, path} -> XqliteNIF.changeset_iter_open_file(to_string(path))
+        binary when is_binary(binary) -> XqliteNIF.changeset_iter_open(binary)
+      end
+
+    case result do
+      {:ok, iter} -> iter
+      {:error, reason} -> raise Xqlite.StreamError, reason: reason
+    end
+  end
+
   # ---------------------------------------------------------------------------
   # Cancellable wrappers — accept either a single token or a list
   # ---------------------------------------------------------------------------
@@ -2105,7 +3476,8 @@ defmodule Xqlite do
   @doc """
   Returns a snapshot of the connection's `sqlite3_db_status` counters
   (lookaside, pager cache, schema and statement memory, cache
-  hit/miss/spill, deferred foreign keys).
+  hit/miss/spill, deferred foreign keys) plus the prepared statement
+  cache's hit/miss/eviction counts.
 
   See `XqliteNIF.connection_stats/1` for the full key list. Call
   repeatedly for time-series monitoring. No telemetry is emitted.
@@ -2113,6 +3485,33 @@ defmodule Xqlite do
   @spec connection_stats(conn()) :: {:ok, map()} | error()
   def connection_stats(conn), do: XqliteNIF.connection_stats(conn)
 
+  @doc """
+  Resizes the connection's prepared statement cache.
+
+  `query` and `execute` reuse compiled statements from a per-connection
+  LRU cache keyed by the SQL text. `size` is the number of statements
+  kept; `0` disables the cache. Shrinking evicts the least recently used
+  statements. The initial size is the `:statement_cache_size` option of
+  `open/2` (default `16`).
+
+  A schema change flushes the cache the next time a cached statement
+  runs; the statement is compiled afresh, so `SELECT *` picks up new
+  columns. Hit, miss and eviction counts are reported by
+  `connection_stats/1`.
+
+  ## Examples
+
+      iex> {:ok, conn} = Xqlite.open_in_memory(statement_cache_size: 4)
+      iex> for _ <- 1..3, do: {:ok, _} = XqliteNIF.query(conn, "SELECT 1", [])
+      iex> {:ok, stats} = Xqlite.connection_stats(conn)
+      iex> {stats.statement_cache_hit, stats.statement_cache_miss}
+      {2, 1}
+      iex> Xqlite.set_statement_cache_size(conn, 0)
+      :ok
+  """
+  @spec set_statement_cache_size(conn(), non_neg_integer()) :: :ok | error()
+  def set_statement_cache_size(conn, size), do: XqliteNIF.set_statement_cache_size(conn, size)
+
   @doc """
   Returns the compile-time options the linked SQLite library was
   built with, as a list of strings (`PRAGMA compile_options`).
@@ -0,0 +1,47 @@
+defmodule Xqlite.Aggregate do
+  @moduledoc """
+  Behaviour for Elixir-implemented aggregate and window SQL functions.
+
+  Register an implementing module with `Xqlite.register_aggregate_function/6`.
+  The callbacks are pure state transitions: the accumulator is held
+  natively by SQLite, per group (or per window frame), as the
+  `:erlang.term_to_binary/1` encoding of whatever `step/2` returned. The
+  handler process keeps no state of its own, so one module serves any
+  number of groups and connections at once.
+
+      defmodule MyApp.Product do
+        @behaviour Xqlite.Aggregate
+
+        @impl true
+        def init, do: 1
+
+        @impl true
+        def step(acc, [x]) when is_number(x), do: acc * x
+        def step(acc, [nil]), do: acc
+
+        @impl true
+        def finalize(acc), do: acc
+      end
+
+  SQLite calls `init/0` lazily — before the first `step/2` of each group,
+  and for `finalize/1` / `value/1` of a group that saw no rows. Window
+  functions (`window: true`) additionally need `inverse/2`, which removes
+  a row leaving the frame, and `value/1`, which reports the current frame's
+  result without consuming the state.
+
+  `finalize/1` and `value/1` follow the scalar return rules in
+  `Xqlite.Function`: `{:ok, value}`, `{:error, reason}`, or a bare SQLite
+  value. The accumulator itself may be any term.
+  """
+
+  @type state :: term()
+  @type args :: [Xqlite.sqlite_value()]
+
+  @callback init() :: state()
+  @callback step(state(), args()) :: state()
+  @callback finalize(state()) :: term()
+  @callback inverse(state(), args()) :: state()
+  @callback value(state()) :: term()
+
+  @optional_callbacks inverse: 2, value: 1
+end
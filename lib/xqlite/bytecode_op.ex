@@ -0,0 +1,84 @@
+defmodule Xqlite.BytecodeOp do
+  @moduledoc """
+  One VDBE instruction from `Xqlite.explain_bytecode/3`.
+
+  SQLite compiles every statement into a program for its virtual machine
+  (the VDBE). The listing is what `EXPLAIN <sql>` prints, with runtime
+  counts from the execution `explain_bytecode/3` performs. See
+  [The SQLite Bytecode Engine](https://sqlite.org/opcode.html) for what each
+  opcode and operand means.
+
+  ## Reading the counts
+
+  `:nexec` is how many times the instruction ran, so the loop bodies of a
+  scan stand out directly. `:ncycle` is the CPU cycles spent in it, read
+  from the processor's cycle counter where SQLite can (it stays `0`
+  elsewhere). It is the closest SQLite gets to per-operator time.
+
+  Each `Explain` instruction marks the start of a plan step. Its `:p1` is
+  the `EXPLAIN QUERY PLAN` id, and `:scan` carries the matching
+  `sqlite3_stmt_scanstatus_v2` entry (see `Xqlite.ExplainAnalyze`) when
+  SQLite recorded one.
+  """
+
+  @typedoc """
+  Struct definition.
+
+  * `:addr` - Instruction address.
+  * `:opcode` - Opcode name, e.g. `"OpenRead"` or `"Next"`.
+  * `:p1`, `:p2`, `:p3`, `:p5` - Integer operands.
+  * `:p4` - Rendered P4 operand, or `nil` when unused.
+  * `:comment` - Explanatory comment; `nil` unless SQLite was built with `SQLITE_ENABLE_EXPLAIN_COMMENTS`.
+  * `:subprog` - Trigger program the instruction belongs to, e.g. `"TRIGGER audit"` (`"(FK)"` for a foreign-key action); `nil` for the main program.
+  * `:nexec` - Times the instruction ran.
+  * `:ncycle` - CPU cycles spent in the instruction.
+  * `:scan` - For an `Explain` instruction, its scan statistics; otherwise `nil`.
+
+  `:subprog`, `:nexec` and `:ncycle` are `nil` when SQLite lacks the
+  `bytecode()` table (`SQLITE_ENABLE_BYTECODE_VTAB`) and the listing falls
+  back to plain `EXPLAIN`. The bundled build has it.
+  """
+  @type t :: %__MODULE__{
+          addr: non_neg_integer(),
+          opcode: String.t(),
+          p1: integer(),
+          p2: integer(),
+          p3: integer(),
+          p4: String.t() | nil,
+          p5: integer(),
+          comment: String.t() | nil,
+          subprog: String.t() | nil,
+          nexec: non_neg_integer() | nil,
+          ncycle: non_neg_integer() | nil,
+          scan: Xqlite.ExplainAnalyze.scan() | nil
+        }
+
+  @enforce_keys [
+    :addr,
+    :opcode,
+    :p1,
+    :p2,
+    :p3,
+    :p4,
+    :p5,
+    :comment,
+    :subprog,
+    :nexec,
+    :ncycle,
+    :scan
+  ]
+  defstruct [
+    :addr,
+    :opcode,
+    :p1,
+    :p2,
+    :p3,
+    :p4,
+    :p5,
+    :comment,
+    :subprog,
+    :nexec,
+    :ncycle,
+    :scan
+  ]
+end
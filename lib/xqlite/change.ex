@@ -0,0 +1,37 @@
+defmodule Xqlite.Change do
+  @moduledoc """
+  One row change decoded from a changeset or patchset by
+  `XqliteNIF.changeset_decode/1`, `XqliteNIF.changeset_iter_next/2` or
+  `Xqlite.changeset_stream/2`.
+
+  A column missing from a change is `:undefined`, which is distinct from
+  SQL `NULL` (`nil`). Changesets record every column of an INSERT or
+  DELETE, but for an UPDATE only the primary key and the modified
+  columns. Patchsets are more compact still: a DELETE carries only its
+  primary key, and an UPDATE only the primary key and the new values.
+  """
+
+  @typedoc """
+  Struct definition.
+
+  * `:table` - Name of the table the change applies to.
+  * `:op` - `:insert`, `:update` or `:delete`.
+  * `:indirect` - `true` if the change was made by a trigger or a foreign-key action, or while the session was marked indirect.
+  * `:pk_columns` - 0-based indexes of the table's primary-key columns.
+  * `:old` - Column values before the change; `nil` for an INSERT.
+  * `:new` - Column values after the change; `nil` for a DELETE.
+  """
+  @type t :: %__MODULE__{
+          table: String.t(),
+          op: :insert | :update | :delete,
+          indirect: boolean(),
+          pk_columns: [non_neg_integer()],
+          old: [value()] | nil,
+          new: [value()] | nil
+        }
+
+  @type value :: Xqlite.sqlite_value() | :undefined
+
+  @enforce_keys [:table, :op, :indirect, :pk_columns, :old, :new]
+  defstruct [:table, :op, :indirect, :pk_columns, :old, :new]
+end
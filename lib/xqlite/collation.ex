@@ -0,0 +1,163 @@
+defmodule Xqlite.Collation do
+  @moduledoc """
+  Handler plumbing for Elixir-implemented collations and the
+  `collation_needed` hook.
+
+  A collation compares two strings. SQLite calls it synchronously while
+  sorting, grouping or comparing `COLLATE name` expressions; the NIF
+  forwards each comparison to a handler process as
+
+      {:xqlite_collation_call, call_id, name, a, b}
+
+  and blocks until the handler answers with `XqliteNIF.function_reply/2`.
+  The same timeout and deadlock rules as `Xqlite.Function` apply.
+
+  `Xqlite.register_collation/4` accepts:
+
+    * a **native collation atom** — `:unicode_nocase`, `:natural` or
+      `:no_accents`, implemented in Rust (see
+      `XqliteNIF.register_native_collation/3`); no process is involved;
+    * a **2-arity fun** — `fn a, b -> ... end`;
+    * an **MFA** — `{mod, fun, extra}`, invoked as
+      `apply(mod, fun, [a, b | extra])`;
+    * a **pid** — a process you run yourself, answering via
+      `handle_compare/4`.
+
+  A comparator returns `:lt`, `:eq` or `:gt` (the `compare/2` convention
+  of `Date`, `Decimal` and friends), or an integer whose sign carries the
+  ordering. It must be a total order and consistent across calls, or
+  SQLite's sorts and indexes misbehave.
+
+  SQLite gives a collation no way to fail. A comparator that raises,
+  times out, or returns anything else makes that one comparison fall
+  back to byte order.
+
+  ## The collation_needed hook
+
+  `Xqlite.set_collation_needed_hook/3` resolves unknown collations on
+  demand, while a statement is being prepared, instead of failing with
+  `no such collation sequence`. The resolver receives the collation name
+  and returns `nil` (leave it unknown), a native collation atom, or a
+  comparator fun / MFA. Resolved comparators are served by the hook's
+  own dispatcher process.
+
+  ## Dispatcher lifetime
+
+  Dispatchers spawned for a fun, MFA or resolver are linked to the
+  registering process and exit with it. They also stop, unlinking first,
+  once nothing can call them any more: a collation's dispatcher when the
+  collation is unregistered or registered again, the resolver's when the
+  hook is removed or replaced and no collation it resolved is left, and
+  both when the connection closes.
+  """
+
+  @type comparison :: :lt | :eq | :gt | integer()
+  @type compare_fun :: (String.t(), String.t() -> comparison())
+  @type compare_mfa :: {module(), atom(), list()}
+  @type comparator :: Xqlite.native_collation() | pid() | compare_mfa() | compare_fun()
+  @type resolution :: nil | Xqlite.native_collation() | compare_mfa() | compare_fun()
+  @type resolver :: pid() | (String.t() -> resolution())
+
+  @native [:unicode_nocase, :natural, :no_accents]
+
+  @doc """
+  Runs `fun.(a, b)` and sends the ordering back as the answer to
+  `call_id`. For use in self-managed handler processes:
+
+      receive do
+        {:xqlite_collation_call, call_id, "reverse", a, b} ->
+          Xqlite.Collation.handle_compare(call_id, a, b, &compare_reversed/2)
+      end
+
+  Always returns `:ok`.
+  """
+  @spec handle_compare(non_neg_integer(), String.t(), String.t(), compare_fun()) :: :ok
+  def handle_compare(call_id, a, b, fun) when is_integer(call_id) and is_function(fun, 2) do
+    XqliteNIF.function_reply(call_id, compare(fun, a, b))
+  end
+
+  @doc false
+  # Returns `{pid, owned?}` like `Xqlite.Function.dispatcher/1`.
+  @spec dispatcher(pid() | compare_mfa() | compare_fun()) :: {pid(), boolean()}
+  def dispatcher(pid) when is_pid(pid), do: {pid, false}
+
+  def dispatcher(comparator) do
+    fun = to_fun(comparator)
+    owner = self()
+    {spawn_link(fn -> compare_loop(fun, owner) end), true}
+  end
+
+  @doc false
+  @spec resolver_dispatcher(resolver()) :: {pid(), boolean()}
+  def resolver_dispatcher(pid) when is_pid(pid), do: {pid, false}
+
+  def resolver_dispatcher(resolver) when is_function(resolver, 1) do
+    owner = self()
+    {spawn_link(fn -> resolver_loop(resolver, %{}, owner) end), true}
+  end
+
+  defp to_fun({mod, fun, extra}) when is_atom(mod) and is_atom(fun) and is_list(extra) do
+    fn a, b -> apply(mod, fun, [a, b | extra]) end
+  end
+
+  defp to_fun(fun) when is_function(fun, 2), do: fun
+
+  defp compare_loop(fun, owner) do
+    receive do
+      {:xqlite_collation_call, call_id, _name, a, b} ->
+        handle_compare(call_id, a, b, fun)
+        compare_loop(fun, owner)
+
+      :xqlite_dispatcher_stop ->
+        Process.unlink(owner)
+    end
+  end
+
+  defp resolver_loop(resolver, comparators, owner) do
+    receive do
+      {:xqlite_collation_needed, call_id, name} ->
+        {reply, comparators} = resolve(resolver, name, comparators)
+        XqliteNIF.function_reply(call_id, reply)
+        resolver_loop(resolver, comparators, owner)
+
+      :xqlite_dispatcher_stop ->
+        Process.unlink(owner)
+
+      {:xqlite_collation_call, call_id, name, a, b} ->
+        reply =
+          case Map.fetch(comparators, name) do
+            {:ok, fun} -> compare(fun, a, b)
+            :error -> {:error, "collation #{inspect(name)} was not resolved by this hook"}
+          end
+
+        XqliteNIF.function_reply(call_id, reply)
+        resolver_loop(resolver, comparators, owner)
+    end
+  end
+
+  defp resolve(resolver, name, comparators) do
+    case resolver.(name) do
+      nil -> {{:ok, nil}, comparators}
+      native when native in @native -> {{:ok, Atom.to_string(native)}, comparators}
+      comparator -> {{:ok, true}, Map.put(comparators, name, to_fun(comparator))}
+    end
+  rescue
+    e -> {{:error, Exception.message(e)}, comparators}
+  catch
+    kind, reason -> {{:error, Exception.format_banner(kind, reason)}, comparators}
+  end
+
+  defp compare(fun, a, b) do
+    case fun.(a, b) do
+      :lt -> {:ok, -1}
+      :eq -> {:ok, 0}
+      :gt -> {:ok, 1}
+      n when is_integer(n) -> {:ok, n}
+      other -> {:error, "expected :lt, :eq, :gt or an integer, got: #{inspect(other)}"}
+    end
+  rescue
+    e -> {:error, Exception.message(e)}
+  catch
+    kind, reason -> {:error, Exception.format_banner(kind, reason)}
+  end
+end
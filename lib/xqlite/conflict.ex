@@ -0,0 +1,175 @@
+defmodule Xqlite.Conflict do
+  @moduledoc """
+  Handler plumbing for conflict handlers and table filters of
+  `Xqlite.changeset_apply/4`.
+
+  While a changeset is applied, SQLite reports every change that does not
+  fit the target database as a conflict. The NIF forwards each one to a
+  handler process as
+
+      {:xqlite_changeset_conflict, call_id, type, change, conflicting}
+
+  and blocks the apply until the handler answers with
+  `XqliteNIF.function_reply/2`. `type` is one of:
+
+    * `:data` — the row exists but its current values differ from the
+      change's old values (UPDATE, DELETE);
+    * `:notfound` — the row to update or delete does not exist;
+    * `:conflict` — an INSERT collides with an existing primary key;
+    * `:constraint` — applying the change violates another constraint
+      (UNIQUE, CHECK, NOT NULL);
+    * `:foreign_key` — raised once, after all changes, when the apply
+      would leave foreign-key violations.
+
+  `change` is the `Xqlite.Change` being applied and `conflicting` the row
+  currently in the database, for `:data` and `:conflict`. For
+  `:foreign_key`, `change` is `nil` and `conflicting` is the number of
+  violations.
+
+  `Xqlite.changeset_apply/4` accepts, besides the fixed strategies
+  `:omit`, `:replace` and `:abort`:
+
+    * a **3-arity fun** — `fn type, change, conflicting -> decision end`;
+    * an **MFA** — `{mod, fun, extra}`, invoked as
+      `apply(mod, fun, [type, change, conflicting | extra])`;
+    * a **pid** — a process you run yourself, answering via
+      `handle_conflict/5`.
+
+  A decision is `:omit` (skip the change), `:replace` (overwrite the
+  database row; `:data` and `:conflict` only) or `:abort` (roll the whole
+  apply back). Anything else, an exception, or a missed timeout aborts
+  the apply with `{:error, {:changeset_handler_failed, kind, message}}`.
+
+  ## Table filters
+
+  The `:table_filter` option takes a 1-arity fun, an MFA (called as
+  `apply(mod, fun, [table | extra])`) or a pid answering
+
+      {:xqlite_changeset_filter, call_id, table}
+
+  via `handle_filter/3`. It returns `true` to apply the table's changes
+  and `false` to skip them. Each table is asked about once, before any
+  change is applied.
+
+  Funs and MFAs are served by a dispatcher process spawned for the
+  duration of the apply. Handlers must not use the connection being
+  applied to — it stays locked until the apply finishes.
+  """
+
+  @type conflict_type :: :data | :notfound | :conflict | :constraint | :foreign_key
+  @type decision :: :omit | :replace | :abort
+  @type conflict_fun :: (conflict_type(), Xqlite.Change.t() | nil, term() -> decision())
+  @type handler :: decision() | pid() | {module(), atom(), list()} | conflict_fun()
+  @type table_filter :: pid() | {module(), atom(), list()} | (String.t() -> boolean())
+
+  @decisions [:omit, :replace, :abort]
+
+  @doc """
+  Runs `fun.(type, change, conflicting)` and sends the decision back as
+  the answer to `call_id`. For use in self-managed handler processes:
+
+      receive do
+        {:xqlite_changeset_conflict, call_id, type, change, conflicting} ->
+          Xqlite.Conflict.handle_conflict(call_id, type, change, conflicting, &resolve/3)
+      end
+
+  Always returns `:ok`.
+  """
+  @spec handle_conflict(
+          non_neg_integer(),
+          conflict_type(),
+          Xqlite.Change.t() | nil,
+          term(),
+          conflict_fun()
+        ) :: :ok
+  def handle_conflict(call_id, type, change, conflicting, fun)
+      when is_integer(call_id) and is_function(fun, 3) do
+    XqliteNIF.function_reply(call_id, decide(fun, type, change, conflicting))
+  end
+
+  @doc """
+  Runs `fun.(table)` and sends the answer back for `call_id`. For use in
+  self-managed filter processes:
+
+      receive do
+        {:xqlite_changeset_filter, call_id, table} ->
+          Xqlite.Conflict.handle_filter(call_id, table, &(&1 in owned_tables))
+      end
+
+  Always returns `:ok`.
+  """
+  @spec handle_filter(non_neg_integer(), String.t(), (String.t() -> boolean())) :: :ok
+  def handle_filter(call_id, table, fun) when is_integer(call_id) and is_function(fun, 1) do
+    XqliteNIF.function_reply(call_id, filter(fun, table))
+  end
+
+  @doc false
+  # Returns `{conflict, table_filter, dispatcher}`: the arguments to pass to
+  # the NIF, with funs and MFAs replaced by one shared dispatcher pid, and
+  # the `{pid, owned?}` pair to hand to `Xqlite.Function.stop_dispatcher/1`.
+  @spec dispatcher(handler(), table_filter() | nil) ::
+          {decision() | pid(), pid() | nil, {pid() | nil, boolean()}}
+  def dispatcher(conflict, table_filter) do
+    conflict_fun = to_fun(conflict, 3)
+    filter_fun = to_fun(table_filter, 1)
+
+    if conflict_fun || filter_fun do
+      pid = spawn_link(fn -> loop(conflict_fun, filter_fun) end)
+      conflict = if conflict_fun, do: pid, else: conflict
+      table_filter = if filter_fun, do: pid, else: table_filter
+      {conflict, table_filter, {pid, true}}
+    else
+      {conflict, table_filter, {nil, false}}
+    end
+  end
+
+  defp to_fun({mod, fun, extra}, 3) when is_atom(mod) and is_atom(fun) and is_list(extra) do
+    fn type, change, conflicting -> apply(mod, fun, [type, change, conflicting | extra]) end
+  end
+
+  defp to_fun({mod, fun, extra}, 1) when is_atom(mod) and is_atom(fun) and is_list(extra) do
+    fn table -> apply(mod, fun, [table | extra]) end
+  end
+
+  defp to_fun(fun, arity) when is_function(fun, arity), do: fun
+  defp to_fun(_other, _arity), do: nil
+
+  defp loop(conflict_fun, filter_fun) do
+    receive do
+      {:xqlite_changeset_conflict, call_id, type, change, conflicting} ->
+        XqliteNIF.function_reply(call_id, decide(conflict_fun, type, change, conflicting))
+        loop(conflict_fun, filter_fun)
+
+      {:xqlite_changeset_filter, call_id, table} ->
+        XqliteNIF.function_reply(call_id, filter(filter_fun, table))
+        loop(conflict_fun, filter_fun)
+    end
+  end
+
+  defp decide(nil, _type, _change, _conflicting),
+    do: {:error, "no conflict handler was given to this dispatcher"}
+
+  defp decide(fun, type, change, conflicting) do
+    case fun.(type, change, conflicting) do
+      decision when decision in @decisions -> {:ok, Atom.to_string(decision)}
+      other -> {:error, "expected :omit, :replace or :abort, got: #{inspect(other)}"}
+    end
+  rescue
+    e -> {:error, Exception.message(e)}
+  catch
+    kind, reason -> {:error, Exception.format_banner(kind, reason)}
+  end
+
+  defp filter(nil, _table), do: {:error, "no table filter was given to this dispatcher"}
+
+  defp filter(fun, table) do
+    case fun.(table) do
+      apply? when is_boolean(apply?) -> {:ok, apply?}
+      other -> {:error, "expected a boolean, got: #{inspect(other)}"}
+    end
+  rescue
+    e -> {:error, Exception.message(e)}
+  catch
+    kind, reason -> {:error, Exception.format_banner(kind, reason)}
+  end
+end
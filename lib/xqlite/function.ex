@@ -0,0 +1,196 @@
+defmodule Xqlite.Function do
+  @moduledoc """
+  Handler plumbing for Elixir-implemented SQL functions.
+
+  SQLite calls a user function synchronously while it steps a statement.
+  The NIF forwards each call to a handler process as
+
+      {:xqlite_function_call, call_id, name, args}
+
+  and blocks the stepping query until the handler answers with
+  `XqliteNIF.function_reply/2` (or the registration's timeout elapses).
+  `args` is a list of SQLite values (`integer | float | binary | nil`).
+
+  `Xqlite.register_scalar_function/6` accepts three handler shapes:
+
+    * a **1-arity fun** — `fn args -> ... end`;
+    * an **MFA** — `{mod, fun, extra}`, invoked as `apply(mod, fun, [args | extra])`;
+    * a **pid** — a process you run yourself; it must answer every
+      `:xqlite_function_call` message, typically via `handle_call/3`.
+
+  For the first two, a dispatcher process is spawned and linked to the
+  caller of `register_scalar_function/6`. It answers calls one at a time
+  and exits with its owner; calls arriving after that fail with
+  `{:user_function_failed, name, :noproc, _}`. It also stops, unlinking
+  first, once SQLite drops the registration: when the function is
+  unregistered or registered again, or when the connection closes.
+
+  ## Aggregates
+
+  Aggregate and window functions registered with
+  `Xqlite.register_aggregate_function/6` use a second message shape,
+
+      {:xqlite_aggregate_call, call_id, name, phase, state, args}
+
+  where `phase` is `:step`, `:inverse`, `:value` or `:finalize` and
+  `state` is `nil` (no step yet) or the serialized accumulator. The
+  handler is an `Xqlite.Aggregate` module, served by a spawned dispatcher,
+  or a pid that answers via `handle_aggregate_call/5`.
+
+  ## Return values
+
+  The handler's return value is normalised:
+
+    * `{:ok, value}` — `value` becomes the SQL result;
+    * `{:error, reason}` — the statement fails with
+      `{:user_function_failed, name, :error, message}`;
+    * any other term — treated as `{:ok, term}`.
+
+  `value` must map to SQLite: integer, float, binary, `nil`, or a
+  boolean (stored as 1/0). Anything else fails the statement with kind
+  `:invalid_result`. Exceptions, throws and exits raised by the handler
+  are caught and reported as `:error` with a formatted message.
+
+  ## Deadlocks
+
+  The stepping query holds the connection while it waits. A handler must
+  therefore never be the process running that query, and must not use
+  the same connection — such a call waits for the connection until the
+  registration's timeout fails the SQL function with kind `:timeout`.
+  """
+
+  @type handler :: pid() | {module(), atom(), list()} | (list() -> term())
+
+  @doc """
+  Runs `fun.(args)` and sends the normalised result back as the answer to
+  `call_id`. For use in self-managed handler processes:
+
+      receive do
+        {:xqlite_function_call, call_id, "slugify", args} ->
+          Xqlite.Function.handle_call(call_id, args, &MyApp.slugify/1)
+      end
+
+  Always returns `:ok`.
+  """
+  @spec handle_call(non_neg_integer(), [Xqlite.sqlite_value()], (list() -> term())) :: :ok
+  def handle_call(call_id, args, fun) when is_integer(call_id) and is_function(fun, 1) do
+    XqliteNIF.function_reply(call_id, run(fun, args))
+  end
+
+  @doc """
+  Runs the `phase` callback of the `Xqlite.Aggregate` implementation
+  `module` and sends the result back as the answer to `call_id`. For use in
+  self-managed handler processes:
+
+      receive do
+        {:xqlite_aggregate_call, call_id, _name, phase, state, args} ->
+          Xqlite.Function.handle_aggregate_call(call_id, phase, state, args, MyApp.Product)
+      end
+
+  Always returns `:ok`.
+  """
+  @spec handle_aggregate_call(
+          non_neg_integer(),
+          :step | :inverse | :value | :finalize,
+          binary() | nil,
+          [Xqlite.sqlite_value()],
+          module()
+        ) :: :ok
+  def handle_aggregate_call(call_id, phase, state, args, module)
+      when is_integer(call_id) and is_atom(module) do
+    XqliteNIF.function_reply(call_id, run_aggregate(module, phase, state, args))
+  end
+
+  @doc false
+  # Returns `{pid, owned?}`; `owned?` is true when a dispatcher was spawned
+  # for `handler` and must be stopped if the registration fails.
+  @spec dispatcher(handler()) :: {pid(), boolean()}
+  def dispatcher(pid) when is_pid(pid), do: {pid, false}
+
+  def dispatcher({mod, fun, extra}) when is_atom(mod) and is_atom(fun) and is_list(extra) do
+    {spawn_dispatcher(fn args -> apply(mod, fun, [args | extra]) end), true}
+  end
+
+  def dispatcher(fun) when is_function(fun, 1), do: {spawn_dispatcher(fun), true}
+
+  @doc false
+  @spec aggregate_dispatcher(pid() | module()) :: {pid(), boolean()}
+  def aggregate_dispatcher(pid) when is_pid(pid), do: {pid, false}
+
+  def aggregate_dispatcher(module) when is_atom(module) do
+    owner = self()
+    {spawn_link(fn -> aggregate_loop(module, owner) end), true}
+  end
+
+  @doc false
+  # What the registration NIFs take: an owned dispatcher is tagged so the
+  # NIF sends it `:xqlite_dispatcher_stop` once the registration is gone.
+  @spec handler_pid({pid(), boolean()}) :: pid() | {:dispatcher, pid()}
+  def handler_pid({pid, true}), do: {:dispatcher, pid}
+  def handler_pid({pid, false}), do: pid
+
+  @doc false
+  @spec stop_dispatcher({pid(), boolean()}) :: :ok
+  def stop_dispatcher({pid, true}) do
+    Process.unlink(pid)
+    Process.exit(pid, :kill)
+    :ok
+  end
+
+  def stop_dispatcher({_pid, false}), do: :ok
+
+  defp spawn_dispatcher(fun) do
+    owner = self()
+    spawn_link(fn -> loop(fun, owner) end)
+  end
+
+  defp loop(fun, owner) do
+    receive do
+      {:xqlite_function_call, call_id, _name, args} ->
+        handle_call(call_id, args, fun)
+        loop(fun, owner)
+
+      :xqlite_dispatcher_stop ->
+        Process.unlink(owner)
+    end
+  end
+
+  defp aggregate_loop(module, owner) do
+    receive do
+      {:xqlite_aggregate_call, call_id, _name, phase, state, args} ->
+        handle_aggregate_call(call_id, phase, state, args, module)
+        aggregate_loop(module, owner)
+
+      :xqlite_dispatcher_stop ->
+        Process.unlink(owner)
+    end
+  end
+
+  defp run_aggregate(module, phase, state_bin, args) do
+    state = if is_nil(state_bin), do: module.init(), else: :erlang.binary_to_term(state_bin)
+
+    case phase do
+      :step -> {:ok, :erlang.term_to_binary(module.step(state, args))}
+      :inverse -> {:ok, :erlang.term_to_binary(module.inverse(state, args))}
+      :value -> normalize(module.value(state))
+      :finalize -> normalize(module.finalize(state))
+    end
+  rescue
+    e -> {:error, Exception.message(e)}
+  catch
+    kind, reason -> {:error, Exception.format_banner(kind, reason)}
+  end
+
+  defp run(fun, args) do
+    normalize(fun.(args))
+  rescue
+    e -> {:error, Exception.message(e)}
+  catch
+    kind, reason -> {:error, Exception.format_banner(kind, reason)}
+  end
+
+  defp normalize({:ok, _value} = ok), do: ok
+  defp normalize({:error, reason}) when is_binary(reason), do: {:error, reason}
+  defp normalize({:error, reason}), do: {:error, inspect(reason)}
+  defp normalize(value), do: {:ok, value}
+end
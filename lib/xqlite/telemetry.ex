@@ -75,6 +75,14 @@ defmodule Xqlite.Telemetry do
         measurements: %{monotonic_time, duration}
         metadata:     %{conn, sql_batch_size_bytes, cancellable?, result_class, error_reason}
 
+      [:xqlite, :query_batch, :start | :stop | :exception]
+        measurements: %{monotonic_time, duration, statements (on :stop)}
+        metadata:     %{conn, sql_batch_size_bytes, cancellable?, result_class, error_reason}
+
+      [:xqlite, :execute_many, :start | :stop | :exception]
+        measurements: %{monotonic_time, duration, changes (on :stop)}
+        metadata:     %{conn, sql, param_sets_count, cancellable?, result_class, error_reason}
+
       [:xqlite, :query_with_changes, :start | :stop | :exception]
         measurements: %{monotonic_time, duration, num_rows, changes}
         metadata:     %{conn, sql, params_count, cancellable?, result_class, error_reason}
@@ -215,8 +223,8 @@ defmodule Xqlite.Telemetry do
   `:lag` is the duration in nanoseconds between
   `[:xqlite, :cancel, :signalled]` and `[:xqlite, :cancel, :honored]`
   for the same token. `:operation` is the operation that the cancel
-  signal interrupted: `:query`, `:execute`, `:execute_batch`, or
-  `:backup_with_progress`.
+  signal interrupted: `:query`, `:execute`, `:execute_batch`,
+  `:execute_many`, or `:backup_with_progress`.
 
   ## Event surface — hook bridge events (opt-in registration)
 
@@ -252,10 +260,18 @@ defmodule Xqlite.Telemetry do
         measurements: %{monotonic_time, retries, elapsed}
         metadata:     %{conn, tag}
 
+      [:xqlite, :hook, :trace]
+        measurements: %{monotonic_time, duration}
+        metadata:     %{conn, event, sql, tag}
+
       [:xqlite, :hook, :log]
         measurements: %{}
         metadata:     %{code, base_code, message}
 
+  `:event` on `[:xqlite, :hook, :trace]` is `:stmt`, `:profile`, `:row`
+  or `:close`; `:duration` (nanoseconds) is only present for `:profile`
+  and `:sql` is `nil` for `:close`.
+
   `:tag` (in the metadata) is the user-supplied tag from `bridge/2`
   for distinguishing connections in dashboards. `:hook_tag` (only
   on `[:xqlite, :hook, :progress]`) is the tag passed to
@@ -440,13 +456,16 @@ defmodule Xqlite.Telemetry do
   ## Options
 
     * `:hooks` — list of hook kinds to subscribe to. Either an explicit
-      list (`[:wal, :commit, :rollback, :update, :progress]`) or `:all`
-      (default) for every per-connection hook.
+      list (`[:wal, :commit, :rollback, :update, :progress, :busy,
+      :trace]`) or `:all` (default) for every per-connection hook.
     * `:tag` — arbitrary term forwarded as `:tag` in every
       `[:xqlite, :hook, :*]` event's metadata. Useful when one
       handler receives bridged events from multiple connections.
     * `:progress` — keyword opts forwarded to
       `register_progress_hook/3` (default `every_n: 1000`).
+    * `:trace` — keyword opts for the trace subscription: `:events`, the
+      trace events to bridge (default `[:profile]`, one event per
+      finished statement; see `XqliteNIF.register_trace_hook/3`).
 
   Returns `{:error, :telemetry_disabled}` when telemetry is
   compile-disabled — the bridge would otherwise install hooks that
@@ -3,7 +3,7 @@ defmodule Xqlite.Telemetry.Bridge do
   Forwards multi-subscriber hook deliveries into `:telemetry` events.
 
   This is the opt-in hook-bridge half of xqlite telemetry. The fan-out hooks
-  (`update`, `wal`, `commit`, `rollback`, `progress`, plus the global
+  (`update`, `wal`, `commit`, `rollback`, `progress`, `trace`, plus the global
   `log` hook) deliver Erlang messages to subscribed pids. The bridge
   is a small GenServer that:
 
@@ -64,7 +64,8 @@ defmodule Xqlite.Telemetry.Bridge do
   defstruct [:pid, :scope, :tag, :hook_handles]
 
   @type scope :: {:conn, reference()} | :log
-  @type hook_kind :: :wal | :commit | :rollback | :update | :progress | :log
+  @type hook_kind ::
+          :wal | :commit | :rollback | :update | :progress | :busy | :trace | :log
   @type t :: %__MODULE__{
           pid: pid(),
           scope: scope(),
@@ -95,18 +96,18 @@ defmodule Xqlite.Telemetry.Bridge do
   @telemetry_enabled Xqlite.Telemetry.enabled?()
 
   if @telemetry_enabled do
-    @per_conn_hooks [:wal, :commit, :rollback, :update, :progress, :busy]
+    @per_conn_hooks [:wal, :commit, :rollback, :update, :progress, :busy, :trace]
     @valid_per_conn_hooks @per_conn_hooks ++ [:all]
 
     @doc false
     def bridge_per_conn(conn, opts) when is_reference(conn) and is_list(opts) do
       hooks = expand_hooks(Keyword.get(opts, :hooks, :all))
       tag = Keyword.get(opts, :tag)
-      progress_opts = Keyword.get(opts, :progress, [])
+      hook_opts = Keyword.take(opts, [:progress, :trace])
 
       with :ok <- validate_hooks(hooks),
            {:ok, pid} <- start_link({:conn, conn}, tag),
-           {:ok, handles} <- register_per_conn_hooks(pid, conn, hooks, progress_opts) do
+           {:ok, handles} <- register_per_conn_hooks(pid, conn, hooks, hook_opts) do
         {:ok,
          %__MODULE__{
            pid: pid,
@@ -150,9 +151,9 @@ defmodule Xqlite.Telemetry.Bridge do
       end
     end
 
-    defp register_per_conn_hooks(pid, conn, hooks, progress_opts) do
+    defp register_per_conn_hooks(pid, conn, hooks, hook_opts) do
       Enum.reduce_while(hooks, {:ok, []}, fn hook, {:ok, acc} ->
-        case register_per_conn_hook(pid, conn, hook, progress_opts) do
+        case register_per_conn_hook(pid, conn, hook, Keyword.get(hook_opts, hook, [])) do
           {:ok, handle} ->
             {:cont, {:ok, [{hook, handle} | acc]}}
 
@@ -183,6 +184,9 @@ defmodule Xqlite.Telemetry.Bridge do
     defp register_per_conn_hook(pid, conn, :busy, _opts),
       do: NIF.register_busy_observer(conn, pid)
 
+    defp register_per_conn_hook(pid, conn, :trace, opts),
+      do: NIF.register_trace_hook(conn, pid, Keyword.get(opts, :events, [:profile]))
+
     defp register_per_conn_hook(pid, conn, :progress, opts) do
       every_n = Keyword.get(opts, :every_n, 1000)
       tag = Keyword.get(opts, :tag)
@@ -238,6 +242,9 @@ defmodule Xqlite.Telemetry.Bridge do
   defp unregister_hook({:conn, conn}, :progress, handle),
     do: NIF.unregister_progress_hook(conn, handle)
 
+  defp unregister_hook({:conn, conn}, :trace, handle),
+    do: NIF.unregister_trace_hook(conn, handle)
+
   defp unregister_hook(:log, :log, handle), do: NIF.unregister_log_hook(handle)
 
   # --- Hook message → telemetry ---------------------------------------------
@@ -315,6 +322,18 @@ defmodule Xqlite.Telemetry.Bridge do
     )
   end
 
+  defp handle_hook_message({:xqlite_trace, :profile, sql, nanos}, state) do
+    emit_trace(:profile, sql, %{duration: nanos}, state)
+  end
+
+  defp handle_hook_message({:xqlite_trace, event, sql}, state) do
+    emit_trace(event, sql, %{}, state)
+  end
+
+  defp handle_hook_message({:xqlite_trace, :close}, state) do
+    emit_trace(:close, nil, %{}, state)
+  end
+
   defp handle_hook_message({:xqlite_log, code, message}, state) do
     base_code = Bitwise.band(code, 0xFF)
 
@@ -331,6 +350,14 @@ defmodule Xqlite.Telemetry.Bridge do
 
   defp handle_hook_message(_other, _state), do: :ok
 
+  defp emit_trace(event, sql, measurements, state) do
+    Xqlite.Telemetry.emit(
+      [:xqlite, :hook, :trace],
+      Map.put(measurements, :monotonic_time, Xqlite.Telemetry.monotonic_time()),
+      Map.merge(scope_metadata(state), %{event: event, sql: sql})
+    )
+  end
+
   defp scope_metadata(%{scope: {:conn, conn}, tag: tag}), do: %{conn: conn, tag: tag}
   defp scope_metadata(%{scope: :log, tag: tag}), do: %{tag: tag}
 end
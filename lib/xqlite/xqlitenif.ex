@@ -275,6 +275,66 @@ defmodule XqliteNIF do
         ) :: {:ok, map()} | Xqlite.error()
   def explain_analyze(_conn, _sql, _params \\ []), do: err()
 
+  @doc """
+  Runs a SQL statement and returns its VDBE program as a list of
+  `%Xqlite.BytecodeOp{}` structs.
+
+  Like `explain_analyze/3`, the statement is executed in full and its rows
+  are discarded. The program is then read from SQLite's `bytecode()`
+  table-valued function, so each instruction carries how often it ran
+  (`:nexec`) and the CPU cycles spent in it (`:ncycle`); trigger programs
+  are listed too, tagged with `:subprog`. `Explain` instructions carry the
+  matching `sqlite3_stmt_scanstatus_v2` entry under `:scan`.
+
+  Without `SQLITE_ENABLE_BYTECODE_VTAB` (the bundled build enables it) the
+  listing falls back to plain `EXPLAIN`: same instructions, `nil` counts,
+  no trigger programs. Whitespace- or comment-only SQL yields `{:ok, []}`.
+  """
+  @spec explain_bytecode(
+          conn :: Xqlite.conn(),
+          sql :: String.t(),
+          params :: list() | keyword()
+        ) :: {:ok, [Xqlite.BytecodeOp.t()]} | Xqlite.error()
+  def explain_bytecode(_conn, _sql, _params \\ []), do: err()
+
+  @doc """
+  Proposes indexes for a workload of SQL statements.
+
+  The advice comes from SQLite's own advisor, `ext/expert`
+  (sqlite3expert), compiled into the NIF. It works on private in-memory
+  copies of the schema and samples no data, so candidates are judged by
+  the query planner's default size estimates. Collations and application
+  functions the schema or workload use are stood in for on the copies.
+  Nothing is created on `conn`.
+
+  Returns `%{indexes: [create_sql], statements: [advice]}` where each
+  advice is `%{sql:, indexes:, plan_before:, plan_after:}` and the plans
+  are `EXPLAIN QUERY PLAN` rows as in `explain_analyze/3`. Both plans of a
+  statement come from the same schema-only copy, before and after
+  creating its suggested indexes on it, so they differ only by those
+  indexes. A statement that does not prepare fails the whole call.
+  """
+  @spec suggest_indexes(conn :: Xqlite.conn(), sqls :: [String.t()]) ::
+          {:ok, Xqlite.index_advice()} | Xqlite.error()
+  def suggest_indexes(_conn, _sqls), do: err()
+
+  @doc """
+  Lists the tables and indexes the first statement in `sql` reads and writes.
+
+  The statement is prepared but not run, and its compiled program is read
+  through SQLite's `tables_used()` table-valued function. Tables reached
+  through views, triggers and foreign-key actions are included. Returns a
+  map keyed by schema name (`"main"`, `"temp"`, attached names), each value
+  holding sorted `:reads`, `:writes`, `:index_reads` and `:index_writes`
+  name lists; schemas the statement never touches are absent. A table
+  read or written only through one of its indexes (e.g. a covering index)
+  is still listed under `:reads` / `:writes`.
+  Whitespace- or comment-only SQL yields `{:ok, %{}}`.
+  """
+  @spec statement_dependencies(conn :: Xqlite.conn(), sql :: String.t()) ::
+          {:ok, Xqlite.statement_dependencies()} | Xqlite.error()
+  def statement_dependencies(_conn, _sql), do: err()
+
   @doc """
   Executes a SQL statement that does not return rows (e.g., `INSERT`, `UPDATE`, `DELETE`, DDL).
 
@@ -322,6 +382,40 @@ defmodule XqliteNIF do
           {:ok, non_neg_integer()} | Xqlite.error()
   def execute_cancellable(_conn, _sql, _params, _cancel_tokens), do: err()
 
+  @doc """
+  Executes one statement once per parameter set, preparing it only once.
+
+  `param_sets` is a list of parameter lists, each positional or keyword
+  as for `execute/3`. All sets are bound and stepped inside a single NIF
+  call holding the connection lock, so a bulk insert costs one
+  scheduler hop instead of one per row.
+
+  When `transaction` is `true` the run is wrapped in a savepoint: it is
+  its own transaction in autocommit mode and nests inside an open
+  transaction. Any failure rolls back every row of the run. When
+  `false`, each row commits on its own (unless a transaction is open)
+  and the rows before a failure stay applied.
+
+  `cancel_tokens` works as in `execute_cancellable/4`; an empty list
+  means no cancellation.
+
+  Returns `{:ok, changes}`, the total rows changed across all sets
+  (excluding trigger changes, as for `execute/3`). A failing set
+  returns `{:error, {:row_failed, index, reason}}` with its zero-based
+  `index` and the usual error reason, e.g. a `:constraint_violation`.
+  A cancelled run returns `{:error, :operation_cancelled}`. Errors that
+  precede any row, such as a SQL syntax error, are returned as is.
+  """
+  @spec execute_many(
+          conn :: Xqlite.conn(),
+          sql :: String.t(),
+          param_sets :: [list() | keyword()],
+          transaction :: boolean(),
+          cancel_tokens :: [reference()]
+        ) ::
+          {:ok, non_neg_integer()} | Xqlite.error()
+  def execute_many(_conn, _sql, _param_sets, _transaction, _cancel_tokens), do: err()
+
   @doc """
   Executes one or more SQL statements separated by semicolons.
 
@@ -344,6 +438,30 @@ defmodule XqliteNIF do
           :ok | Xqlite.error()
   def execute_batch(_conn, _sql), do: err()
 
+  @doc """
+  Runs a multi-statement script and returns each statement's result set.
+
+  Unlike `execute_batch/2`, which discards rows, and `query/3`, which
+  rejects trailing statements with `:multiple_statements`, every
+  statement is prepared and run in order, and its outcome is kept. No
+  parameter binding. Whitespace and comments produce no entry.
+
+  Returns `{:ok, outcomes}` with one entry per statement:
+  `{:ok, %{columns: columns, rows: rows, num_rows: num_rows, changes: changes}}`
+  or `{:error, reason}`. `changes` follows `query_with_changes/3`.
+
+  When `continue_on_error` is `false` the script stops at the first
+  failing statement, whose `{:error, reason}` is the last entry. When
+  `true` the remaining statements still run. Statements already run are
+  not rolled back either way; wrap the script in a transaction for that.
+
+  Returns `{:error, reason}` only for failures outside any statement,
+  such as a closed connection or a NUL byte in `sql`.
+  """
+  @spec query_batch(conn :: Xqlite.conn(), sql :: String.t(), continue_on_error :: boolean()) ::
+          {:ok, [{:ok, map()} | Xqlite.error()]} | Xqlite.error()
+  def query_batch(_conn, _sql, _continue_on_error), do: err()
+
   @doc """
   Executes one or more SQL statements separated by semicolons, with support for cancellation.
 
@@ -927,13 +1045,39 @@ defmodule XqliteNIF do
       attributable to this connection.
     * `:cache_spill` — count of dirty-cache spills to disk.
     * `:tempbuf_spill` — count of `tempdb` spill events.
+    * `:statement_cache_hit` — `query` / `execute` calls that reused a
+      cached prepared statement.
+    * `:statement_cache_miss` — calls that compiled their statement,
+      including cached statements SQLite re-prepared after a schema
+      change.
+    * `:statement_cache_eviction` — statements dropped from the cache to
+      stay within its size.
 
   All counters are "current" values; high-water marks are not exposed
-  yet. Call repeatedly for time-series monitoring.
+  yet. The statement cache counters accumulate from open. Call
+  repeatedly for time-series monitoring.
   """
   @spec connection_stats(Xqlite.conn()) :: {:ok, map()} | Xqlite.error()
   def connection_stats(_conn), do: err()
 
+  @doc """
+  Resizes the connection's prepared statement cache (raw NIF).
+
+  `query`, `execute` and their `_with_changes` / `_cancellable` variants
+  prepare through a per-connection LRU cache keyed by the SQL text, so
+  repeating a statement skips compiling it. `size` is the number of
+  statements kept; `0` disables the cache. Shrinking evicts the least
+  recently used statements. New connections start at 16.
+
+  A schema change flushes the whole cache the next time a cached
+  statement runs. Hit, miss and eviction counts are reported by
+  `connection_stats/1`.
+
+  Returns `:ok`.
+  """
+  @spec set_statement_cache_size(Xqlite.conn(), non_neg_integer()) :: :ok | Xqlite.error()
+  def set_statement_cache_size(_conn, _size), do: err()
+
   @doc """
   Sets the busy retry POLICY on the connection (raw NIF).
 
@@ -1039,6 +1183,35 @@ defmodule XqliteNIF do
           :ok | Xqlite.error()
   def set_authorizer(_conn, _denied_actions), do: err()
 
+  @doc """
+  Installs a rule-based authorizer on the connection (raw NIF).
+
+  Most users want `Xqlite.set_authorizer_rules/3`.
+
+  `rules` is a list of `{outcome, actions}` or `{outcome, actions, matchers}`
+  tuples, tried in order; the first match decides and `default` (`:allow`,
+  `:deny` or `:ignore`) applies when none does. With `dry_run` set, every
+  action is allowed and the decision is only reported to authorizer
+  observers. `outcome` is `:allow`,
+  `:deny` or `:ignore`, `actions` an action-kind atom or list of them, and
+  `matchers` a keyword list of `:table`, `:column`, `:name`, `:database`,
+  `:accessor` and `:file` strings.
+
+  The rules are validated in full before anything is installed: a malformed
+  rule or default returns `{:error, {:invalid_authorizer_rule, inspected}}`
+  and an unknown action `{:error, {:invalid_authorizer_action, atom}}`.
+  Shares the single slot with `set_authorizer/2`.
+
+  Returns `:ok`.
+  """
+  @spec set_authorizer_rules(
+          conn :: Xqlite.conn(),
+          rules :: [tuple()],
+          default :: atom(),
+          dry_run :: boolean()
+        ) :: :ok | Xqlite.error()
+  def set_authorizer_rules(_conn, _rules, _default, _dry_run), do: err()
+
   @doc """
   Removes any authorizer from the connection (raw NIF).
 
@@ -1050,6 +1223,34 @@ defmodule XqliteNIF do
   @spec remove_authorizer(Xqlite.conn()) :: :ok | Xqlite.error()
   def remove_authorizer(_conn), do: err()
 
+  @doc """
+  Registers an authorizer audit observer on the connection (raw NIF).
+
+  Most users want `Xqlite.register_authorizer_observer/2`.
+
+  Every authorization request sends
+  `{:xqlite_authorizer, action, arg1, arg2, db, trigger_or_view, decision}`
+  to `pid`, where `decision` is what the installed policy produced
+  (`:allow` with none). Independent of the policy slot; any number of
+  observers can be registered.
+
+  Returns `{:ok, handle}`.
+  """
+  @spec register_authorizer_observer(conn :: Xqlite.conn(), pid :: pid()) ::
+          {:ok, non_neg_integer()} | Xqlite.error()
+  def register_authorizer_observer(_conn, _pid), do: err()
+
+  @doc """
+  Unregisters an authorizer audit observer by handle.
+
+  Idempotent — an unknown or already-removed handle is a no-op.
+
+  Returns `:ok`.
+  """
+  @spec unregister_authorizer_observer(conn :: Xqlite.conn(), handle :: non_neg_integer()) ::
+          :ok | Xqlite.error()
+  def unregister_authorizer_observer(_conn, _handle), do: err()
+
   @doc """
   Signals an intent to cancel operations associated with a given cancellation token.
 
@@ -1169,13 +1370,44 @@ defmodule XqliteNIF do
 
   Most users want `Xqlite.bind/2`. Accepts a plain list (positional `?1`,
   `?2`, … — the count must match or `{:error, {:invalid_parameter_count,
-  %{provided: _, expected: _}}}` is returned) or a keyword list (named
-  parameters). After stepping has started, `stmt_reset/1` must run before
-  rebinding (SQLite lifecycle).
+  %{provided: _, expected: _}}}` is returned), a keyword list (named
+  parameters) or a map with string keys (`"id"` matches `:id`, `@id` or
+  `$id`; `":id"` only `:id`). After stepping has started, `stmt_reset/1`
+  must run before rebinding (SQLite lifecycle).
   """
-  @spec stmt_bind(stmt :: Xqlite.stmt(), params :: list()) :: :ok | Xqlite.error()
+  @spec stmt_bind(stmt :: Xqlite.stmt(), params :: list() | %{String.t() => term()}) ::
+          :ok | Xqlite.error()
   def stmt_bind(_stmt, _params), do: err()
 
+  @doc """
+  Binds a single parameter of a prepared statement (raw NIF).
+
+  Most users want `Xqlite.bind_one/3`. `key` is a 1-based index
+  (`{:error, {:invalid_parameter_index, index}}` when out of range), an
+  atom (`:id` binds `:id`, as in keyword lists) or a string name resolved
+  like `stmt_bind/2` map keys. Other parameters keep their bindings.
+  """
+  @spec stmt_bind_one(
+          stmt :: Xqlite.stmt(),
+          key :: pos_integer() | atom() | String.t(),
+          value :: term()
+        ) :: :ok | Xqlite.error()
+  def stmt_bind_one(_stmt, _key, _value), do: err()
+
+  @doc """
+  Returns a prepared statement's parameters (raw NIF).
+
+  Most users want `Xqlite.parameters/1`. Returns `{:ok, %{count: n,
+  names: names}}` from `sqlite3_bind_parameter_count/name`: `names[i]` is
+  the name of parameter `i + 1` with its prefix (`":id"`, `"@id"`,
+  `"$id"`, `"?3"`), or `nil` for a nameless `?`. `count` is the largest
+  index, which numbered parameters (`?5`) can push past the number of
+  distinct placeholders.
+  """
+  @spec stmt_parameters(stmt :: Xqlite.stmt()) ::
+          {:ok, %{count: non_neg_integer(), names: [String.t() | nil]}} | Xqlite.error()
+  def stmt_parameters(_stmt), do: err()
+
   @doc """
   Advances a prepared statement one row (raw NIF).
 
@@ -1242,6 +1474,42 @@ defmodule XqliteNIF do
   @spec stmt_column_names(stmt :: Xqlite.stmt()) :: {:ok, [String.t()]} | Xqlite.error()
   def stmt_column_names(_stmt), do: err()
 
+  @doc """
+  Returns metadata about a prepared statement (raw NIF).
+
+  Most users want `Xqlite.statement_info/1`. Returns `{:ok, map}` with
+  `:readonly` (`sqlite3_stmt_readonly`), `:explain` (`:none`, `:explain`
+  or `:query_plan`, from `sqlite3_stmt_isexplain`), `:busy`
+  (`sqlite3_stmt_busy`), `:sql` (`sqlite3_sql`), `:expanded_sql`
+  (`sqlite3_expanded_sql`, bound values inlined; `nil` if SQLite cannot
+  render it) and `:declared_types` (`sqlite3_column_decltype` per result
+  column, `nil` for expressions and untyped columns).
+  """
+  @spec stmt_info(stmt :: Xqlite.stmt()) ::
+          {:ok,
+           %{
+             readonly: boolean(),
+             explain: :none | :explain | :query_plan,
+             busy: boolean(),
+             sql: String.t(),
+             expanded_sql: String.t() | nil,
+             declared_types: [String.t() | nil]
+           }}
+          | Xqlite.error()
+  def stmt_info(_stmt), do: err()
+
+  @doc """
+  Switches a prepared statement's explain mode (raw NIF).
+
+  Most users want `Xqlite.set_explain_mode/2`. `mode` is `:none`,
+  `:explain` or `:query_plan` (`sqlite3_stmt_explain`); anything else is
+  `{:error, {:invalid_explain_mode, mode}}`. A statement that has been
+  stepped must be reset first, otherwise `{:error, {:cannot_execute, _}}`.
+  """
+  @spec stmt_explain(stmt :: Xqlite.stmt(), mode :: :none | :explain | :query_plan) ::
+          :ok | Xqlite.error()
+  def stmt_explain(_stmt, _mode), do: err()
+
   @doc """
   Finalizes a prepared statement, releasing its SQLite resources (raw NIF).
 
@@ -1254,6 +1522,32 @@ defmodule XqliteNIF do
   @spec stmt_finalize(stmt :: Xqlite.stmt()) :: :ok | Xqlite.error()
   def stmt_finalize(_stmt), do: err()
 
+  @doc """
+  Reads the `sqlite3_stmt_status` counters of a prepared statement or a
+  stream (raw NIF).
+
+  Most users want `Xqlite.stmt_stats/2`. Counters accumulate across every
+  execution of the statement; with `reset` set they are zeroed after
+  reading. An exhausted stream answers with the counters captured just
+  before it finalized its statement. Returns `{:ok, counters}` with the
+  `t:Xqlite.ExplainAnalyze.stmt_counters/0` shape, or `{:error, reason}`.
+  """
+  @spec stmt_stats(handle :: Xqlite.stmt() | reference(), reset :: boolean()) ::
+          {:ok, Xqlite.ExplainAnalyze.stmt_counters()} | Xqlite.error()
+  def stmt_stats(_handle, _reset \\ false), do: err()
+
+  @doc """
+  Reads the per-loop `sqlite3_stmt_scanstatus_v2` counters of a prepared
+  statement or a stream (raw NIF).
+
+  Most users want `Xqlite.stmt_scanstatus/2`. Same accumulation, `reset`
+  and exhausted-stream rules as `stmt_stats/2`; returns
+  `{:ok, [scan]}` with the `t:Xqlite.ExplainAnalyze.scan/0` shape.
+  """
+  @spec stmt_scanstatus(handle :: Xqlite.stmt() | reference(), reset :: boolean()) ::
+          {:ok, [Xqlite.ExplainAnalyze.scan()]} | Xqlite.error()
+  def stmt_scanstatus(_handle, _reset \\ false), do: err()
+
   @doc """
   Retrieves the compile-time options the linked SQLite C library was built with.
 
@@ -1326,22 +1620,96 @@ defmodule XqliteNIF do
 
   The callback fires before the change is committed — if the enclosing
   transaction rolls back, every subscriber will have already received
-  the notification.
+  the notification. Pass `batch: true` to skip rolled-back changes.
+
+  ## Options
+
+    * `:batch` (boolean, default `false`) — buffer this subscriber's row
+      events natively for the current transaction. They are delivered
+      when COMMIT starts, as a single `{:xqlite_update_batch, events}`
+      message, where `events` is a list of
+      `{action, db_name, table_name, rowid}` tuples in statement order; on
+      rollback they are discarded. Delivery happens in SQLite's commit
+      hook, before the commit is durable, so a COMMIT that then fails
+      (e.g. `SQLITE_BUSY` or a full disk) may still have delivered them.
+      A transaction that produced no matching events sends nothing.
+      Work undone inside the transaction is dropped from the buffer too:
+      events since a savepoint on `ROLLBACK TO` it (including the one
+      `execute_many/5` opens), and a statement's own events when it
+      fails and SQLite rolls it back. An `OR IGNORE` / `OR FAIL`
+      statement that changed none of its own rows counts as rolled back,
+      so rows its triggers wrote are dropped as well. The
+      buffer is not capped: it holds one event per matching row change
+      until the transaction ends, so a 100k-row import keeps 100k events
+      in native memory and then sends them in one message. Narrow such
+      subscribers with `:tables` / `:actions`, or use per-row mode.
+    * `:tables` (list of strings) — only report changes to these tables
+      (compared case-insensitively). Default: all tables.
+    * `:actions` (list of `:insert`, `:update`, `:delete`) — only report
+      these actions. Default: all actions.
+
+  Filters apply in both modes and are evaluated natively, so a bulk
+  import into an unwatched table costs no messages at all. An unknown
+  option or malformed value fails with
+  `{:error, {:invalid_update_hook_option, key}}`.
 
   Returns `{:ok, handle}` on success or `{:error, reason}` on failure.
   """
-  @spec register_update_hook(conn :: Xqlite.conn(), pid :: pid()) ::
+  @spec register_update_hook(conn :: Xqlite.conn(), pid :: pid(), opts :: keyword()) ::
           {:ok, non_neg_integer()} | Xqlite.error()
-  def register_update_hook(_conn, _pid), do: err()
+  def register_update_hook(_conn, _pid, _opts \\ []), do: err()
 
   @doc """
   Unregisters an update subscriber by handle. Idempotent — unknown
-  handles are no-ops.
+  handles are no-ops. Events a batch-mode subscriber still had buffered
+  are dropped.
   """
   @spec unregister_update_hook(conn :: Xqlite.conn(), handle :: non_neg_integer()) ::
           :ok | Xqlite.error()
   def unregister_update_hook(_conn, _handle), do: err()
 
+  @doc """
+  Registers a PID to receive preupdate notifications — row changes with
+  the old and new column values — for this connection. Multi-subscriber.
+
+  Each registered PID receives messages in the form:
+  `{:xqlite_preupdate, action, db_name, table_name, details}` where
+  `action`, `db_name` and `table_name` are as in `register_update_hook/2`
+  and `details` is a map:
+  - `:column_count` — number of columns in the affected row
+  - `:depth` — `0` for a direct change, `1` for a change made by a
+    top-level trigger, and so on
+  - `:old_rowid` / `:new_rowid` — rowid before / after the change
+    (`nil` for an INSERT / DELETE respectively)
+  - `:old` / `:new` — list of column values before / after the change,
+    decoded like query results (`nil` for an INSERT / DELETE respectively)
+
+  Unlike the update hook, deletes carry the deleted row's values, so
+  change-data-capture consumers need not re-query. The callback fires
+  before the change is written; as with the update hook, a later
+  rollback does not retract notifications already sent.
+
+  SQLite's preupdate slot is shared with the session extension, so
+  registration fails with `{:error, :preupdate_hook_conflict}` while a
+  session from `session_new/1` is alive on the connection, and
+  `session_new/1` fails the same way while any preupdate subscriber is
+  registered.
+
+  Returns `{:ok, handle}` on success or `{:error, reason}` on failure.
+  """
+  @spec register_preupdate_hook(conn :: Xqlite.conn(), pid :: pid()) ::
+          {:ok, non_neg_integer()} | Xqlite.error()
+  def register_preupdate_hook(_conn, _pid), do: err()
+
+  @doc """
+  Unregisters a preupdate subscriber by handle. Idempotent — unknown
+  handles are no-ops. Removing the last subscriber frees the preupdate
+  slot for sessions.
+  """
+  @spec unregister_preupdate_hook(conn :: Xqlite.conn(), handle :: non_neg_integer()) ::
+          :ok | Xqlite.error()
+  def unregister_preupdate_hook(_conn, _handle), do: err()
+
   @doc """
   Registers a PID to receive WAL events on the connection. Multi-subscriber.
 
@@ -1431,6 +1799,90 @@ defmodule XqliteNIF do
           :ok | Xqlite.error()
   def unregister_rollback_hook(_conn, _handle), do: err()
 
+  @doc """
+  Registers a PID to receive SQL trace events on the connection
+  (`sqlite3_trace_v2`). Multi-subscriber.
+
+  `events` selects which events this subscriber receives:
+
+      {:xqlite_trace, :stmt, sql}              # :stmt
+      {:xqlite_trace, :profile, sql, nanos}    # :profile
+      {:xqlite_trace, :row, sql}               # :row
+      {:xqlite_trace, :close}                  # :close
+
+  - `:stmt` fires when a statement starts running, and again at the start
+    of each trigger it fires. `sql` is the statement text with its current
+    bindings expanded in, or an SQL comment naming the trigger.
+  - `:profile` fires when a statement finishes; `sql` is the statement
+    text as prepared (parameters unexpanded) and `nanos` its run time in
+    nanoseconds.
+  - `:row` fires for every result row a statement produces.
+  - `:close` fires when the connection closes.
+
+  Each subscriber has its own event set; SQLite is only asked for the
+  union of what subscribers want, so an untraced connection pays no
+  tracing overhead. `:row` sends one message per result row — subscribe
+  to it deliberately. An unknown event atom fails with
+  `{:error, {:invalid_trace_event, atom}}`.
+
+  `Xqlite.Telemetry.bridge/2` can subscribe with `hooks: [:trace]` to
+  re-emit deliveries as `[:xqlite, :hook, :trace]` telemetry.
+
+  Returns `{:ok, handle}` on success or `{:error, reason}` on failure.
+  """
+  @spec register_trace_hook(
+          conn :: Xqlite.conn(),
+          pid :: pid(),
+          events :: [:stmt | :profile | :row | :close]
+        ) :: {:ok, non_neg_integer()} | Xqlite.error()
+  def register_trace_hook(_conn, _pid, _events \\ [:stmt, :profile, :row, :close]), do: err()
+
+  @doc """
+  Unregisters a trace subscriber by handle. Idempotent.
+  """
+  @spec unregister_trace_hook(conn :: Xqlite.conn(), handle :: non_neg_integer()) ::
+          :ok | Xqlite.error()
+  def unregister_trace_hook(_conn, _handle), do: err()
+
+  @doc """
+  Installs (or replaces) the connection's slow-query log.
+
+  Every statement whose run takes at least `threshold_ms` milliseconds —
+  as measured by SQLite's profile clock — produces a record:
+
+      %{sql: sql, param_count: n, duration_ns: ns, rows: rows, query_plan: plan}
+
+  `rows` counts result rows; `query_plan` is a list of
+  `%{id:, parent:, detail:}` maps from `EXPLAIN QUERY PLAN`, captured for
+  every `plan_every`-th slow statement (`nil` otherwise; `0` never
+  captures). With a `pid`, each record is sent as
+  `{:xqlite_slow_query, record}`; with `nil`, records are kept in a ring
+  buffer of at most `capacity` entries (oldest dropped first) that
+  `slow_queries/1` drains. Replacing a log discards undrained records.
+  """
+  @spec set_slow_query_log(
+          conn :: Xqlite.conn(),
+          threshold_ms :: non_neg_integer(),
+          plan_every :: non_neg_integer(),
+          capacity :: non_neg_integer(),
+          pid :: pid() | nil
+        ) :: :ok | Xqlite.error()
+  def set_slow_query_log(_conn, _threshold_ms, _plan_every, _capacity, _pid), do: err()
+
+  @doc """
+  Removes the connection's slow-query log, discarding undrained records.
+  Idempotent.
+  """
+  @spec clear_slow_query_log(conn :: Xqlite.conn()) :: :ok | Xqlite.error()
+  def clear_slow_query_log(_conn), do: err()
+
+  @doc """
+  Drains the slow-query ring buffer, oldest record first. Returns
+  `{:ok, []}` when no log is installed or records go to a pid.
+  """
+  @spec slow_queries(conn :: Xqlite.conn()) :: {:ok, [map()]} | Xqlite.error()
+  def slow_queries(_conn), do: err()
+
   @doc """
   Registers a progress-tick subscriber on the connection.
 
@@ -1518,6 +1970,248 @@ defmodule XqliteNIF do
         ) :: :ok | Xqlite.error()
   def deserialize(_conn, _schema, _data, _read_only), do: err()
 
+  # ---------------------------------------------------------------------------
+  # User-defined SQL functions
+  # ---------------------------------------------------------------------------
+
+  @doc """
+  Registers a scalar SQL function answered by an Elixir process (raw NIF).
+
+  Most users want `Xqlite.register_scalar_function/6`, which also accepts
+  a fun or MFA and spawns the handler process for you.
+
+  Each call of `name` with `n_args` arguments (`-1` for any number) sends
+  `{:xqlite_function_call, call_id, name, args}` to `pid` and blocks the
+  executing statement until `function_reply/2` answers `call_id`. If no
+  answer arrives within `timeout_ms`, or `pid` is not alive, the statement
+  fails with `{:error, {:user_function_failed, name, kind, message}}` where
+  `kind` is `:timeout` or `:noproc`; a handler error surfaces as `:error`
+  and an unconvertible result as `:invalid_result`.
+
+  `flags` is a list of `:deterministic`, `:direct_only` and `:innocuous`
+  (SQLite's `SQLITE_DETERMINISTIC` / `SQLITE_DIRECTONLY` /
+  `SQLITE_INNOCUOUS`). An unrecognized atom returns
+  `{:error, {:invalid_function_flag, atom}}` and registers nothing.
+  Registering an existing name and arity replaces the previous definition.
+
+  `pid` may also be given as `{:dispatcher, pid}`, marking a process that
+  exists only to serve this registration: it is sent
+  `:xqlite_dispatcher_stop` once SQLite drops the registration (replaced,
+  unregistered, or the connection closed). The Elixir wrappers tag the
+  dispatchers they spawn this way. The same applies to the other
+  registration NIFs.
+
+  Returns `:ok`.
+  """
+  @spec register_scalar_function(
+          conn :: Xqlite.conn(),
+          name :: String.t(),
+          n_args :: integer(),
+          flags :: [Xqlite.function_flag()],
+          pid :: pid() | {:dispatcher, pid()},
+          timeout_ms :: pos_integer()
+        ) :: :ok | Xqlite.error()
+  def register_scalar_function(_conn, _name, _n_args, _flags, _pid, _timeout_ms), do: err()
+
+  @doc """
+  Registers an aggregate SQL function answered by an Elixir process (raw NIF).
+
+  Most users want `Xqlite.register_aggregate_function/6`, which takes an
+  `Xqlite.Aggregate` module and spawns the handler for you.
+
+  Each step sends `{:xqlite_aggregate_call, call_id, name, :step, state,
+  args}` to `pid`; the reply (`{:ok, binary}`) becomes the group's new
+  native state. `state` is `nil` before the first step. At the end of the
+  group `:finalize` is sent with the current state and `[]`, and its reply
+  is the SQL result. Failure reporting, flags and timeout behave as in
+  `register_scalar_function/6`.
+
+  Returns `:ok`.
+  """
+  @spec register_aggregate_function(
+          conn :: Xqlite.conn(),
+          name :: String.t(),
+          n_args :: integer(),
+          flags :: [Xqlite.function_flag()],
+          pid :: pid() | {:dispatcher, pid()},
+          timeout_ms :: pos_integer()
+        ) :: :ok | Xqlite.error()
+  def register_aggregate_function(_conn, _name, _n_args, _flags, _pid, _timeout_ms),
+    do: err()
+
+  @doc """
+  Registers a window SQL function answered by an Elixir process (raw NIF).
+
+  As `register_aggregate_function/6`, plus the `:inverse` phase (a row
+  leaves the frame; reply with the new state) and the `:value` phase (the
+  current frame's result; the state is unchanged). The function is also
+  usable as a plain aggregate.
+
+  Returns `:ok`.
+  """
+  @spec register_window_function(
+          conn :: Xqlite.conn(),
+          name :: String.t(),
+          n_args :: integer(),
+          flags :: [Xqlite.function_flag()],
+          pid :: pid() | {:dispatcher, pid()},
+          timeout_ms :: pos_integer()
+        ) :: :ok | Xqlite.error()
+  def register_window_function(_conn, _name, _n_args, _flags, _pid, _timeout_ms), do: err()
+
+  @doc """
+  Registers one of the Rust-implemented aggregates under the SQL name `name`.
+
+  `builtin` selects the implementation:
+
+    * `:weighted_median` — `name(value, weight)`: the smallest `value` at
+      which the running weight reaches half the total weight. Rows with a
+      NULL or non-numeric value or weight, or a weight `<= 0`, are skipped;
+      an empty group yields NULL. Usable as an aggregate and as a window
+      function.
+
+  Built-ins run without leaving the native thread and are registered as
+  deterministic and innocuous. An unknown atom returns
+  `{:error, {:invalid_builtin_aggregate, atom}}`.
+
+  Returns `:ok`.
+  """
+  @spec register_builtin_aggregate(
+          conn :: Xqlite.conn(),
+          name :: String.t(),
+          builtin :: Xqlite.builtin_aggregate()
+        ) :: :ok | Xqlite.error()
+  def register_builtin_aggregate(_conn, _name, _builtin), do: err()
+
+  @doc """
+  Removes the user function `name` with arity `n_args` from the connection.
+
+  SQLite reports success even when no such function is registered.
+
+  Returns `:ok`.
+  """
+  @spec unregister_function(conn :: Xqlite.conn(), name :: String.t(), n_args :: integer()) ::
+          :ok | Xqlite.error()
+  def unregister_function(_conn, _name, _n_args), do: err()
+
+  @doc """
+  Answers a pending user-function call.
+
+  `result` is `{:ok, value}` (an integer, float, binary, `nil` or boolean)
+  or `{:error, message}`. Anything else fails the SQL call with
+  `:invalid_result`. Replies to unknown or already timed-out calls are
+  dropped.
+
+  Returns `:ok`.
+  """
+  @spec function_reply(
+          call_id :: non_neg_integer(),
+          result :: {:ok, Xqlite.sqlite_value() | boolean()} | {:error, String.t()}
+        ) :: :ok
+  def function_reply(_call_id, _result), do: err()
+
+  # ---------------------------------------------------------------------------
+  # Collations
+  # ---------------------------------------------------------------------------
+
+  @doc """
+  Registers a collation answered by an Elixir process (raw NIF).
+
+  Most users want `Xqlite.register_collation/4`, which also accepts a
+  2-arity fun, an MFA, or a native collation atom.
+
+  Each comparison sends `{:xqlite_collation_call, call_id, name, a, b}` to
+  `pid` and blocks the executing statement until `function_reply/2`
+  answers `call_id` with `{:ok, integer}` — negative, zero or positive as
+  `a` sorts before, equal to, or after `b`. SQLite gives collations no
+  way to fail, so an error reply, a timeout, a dead `pid` or a
+  non-integer reply compares that pair in byte order instead.
+  Registering an existing name replaces the previous collation. `pid` may
+  be tagged `{:dispatcher, pid}` as in `register_scalar_function/6`.
+
+  Returns `:ok`.
+  """
+  @spec register_collation(
+          conn :: Xqlite.conn(),
+          name :: String.t(),
+          pid :: pid() | {:dispatcher, pid()},
+          timeout_ms :: pos_integer()
+        ) :: :ok | Xqlite.error()
+  def register_collation(_conn, _name, _pid, _timeout_ms), do: err()
+
+  @doc """
+  Registers one of the Rust-implemented collations under the name `name`.
+
+  `native` selects the comparator:
+
+    * `:unicode_nocase` — case-insensitive across all of Unicode (SQLite's
+      `NOCASE` folds ASCII only);
+    * `:natural` — digit runs compare numerically, so `"file2"` sorts
+      before `"file10"`;
+    * `:no_accents` — Latin diacritics are ignored (`"é"` equals `"e"`),
+      ligatures expand (`"æ"` equals `"ae"`); case still matters.
+
+  An unknown atom returns `{:error, {:invalid_native_collation, atom}}`.
+
+  Returns `:ok`.
+  """
+  @spec register_native_collation(
+          conn :: Xqlite.conn(),
+          name :: String.t(),
+          native :: Xqlite.native_collation()
+        ) :: :ok | Xqlite.error()
+  def register_native_collation(_conn, _name, _native), do: err()
+
+  @doc """
+  Removes the collation `name` from the connection.
+
+  Statements already prepared against it keep working; preparing new ones
+  fails unless a `collation_needed` hook resolves it again.
+
+  Returns `:ok`.
+  """
+  @spec unregister_collation(conn :: Xqlite.conn(), name :: String.t()) ::
+          :ok | Xqlite.error()
+  def unregister_collation(_conn, _name), do: err()
+
+  @doc """
+  Installs a `collation_needed` hook answered by an Elixir process (raw NIF).
+
+  Most users want `Xqlite.set_collation_needed_hook/3`.
+
+  When a statement references a collation the connection does not know,
+  `{:xqlite_collation_needed, call_id, name}` is sent to `pid` and
+  preparation blocks until `function_reply/2` answers `call_id` with:
+
+    * `{:ok, nil}` — leave it unknown; preparation fails as usual;
+    * `{:ok, native}` — a native collation's name as a string (for example
+      `"natural"`), registered under `name`;
+    * `{:ok, true}` — `pid` serves the collation itself and must answer
+      `:xqlite_collation_call` messages for `name` (see
+      `register_collation/4`).
+
+  Failures and timeouts leave the collation unknown. Replaces any
+  previous hook. `pid` may be tagged `{:dispatcher, pid}` as in
+  `register_scalar_function/6`; it is stopped once the hook and every
+  collation it served are gone.
+
+  Returns `:ok`.
+  """
+  @spec set_collation_needed_hook(
+          conn :: Xqlite.conn(),
+          pid :: pid() | {:dispatcher, pid()},
+          timeout_ms :: pos_integer()
+        ) :: :ok | Xqlite.error()
+  def set_collation_needed_hook(_conn, _pid, _timeout_ms), do: err()
+
+  @doc """
+  Removes the `collation_needed` hook. Idempotent.
+
+  Returns `:ok`.
+  """
+  @spec remove_collation_needed_hook(conn :: Xqlite.conn()) :: :ok | Xqlite.error()
+  def remove_collation_needed_hook(_conn), do: err()
+
   # ---------------------------------------------------------------------------
   # Extension Loading
   # ---------------------------------------------------------------------------
@@ -1614,6 +2308,9 @@ defmodule XqliteNIF do
 
   Returns an opaque session handle. Attach tables to track with
   `session_attach/2` before making changes.
+
+  Fails with `{:error, :preupdate_hook_conflict}` while a preupdate
+  subscriber is registered; see `register_preupdate_hook/2`.
   """
   @spec session_new(conn :: Xqlite.conn()) :: {:ok, reference()} | Xqlite.error()
   def session_new(_conn), do: err()
@@ -1645,12 +2342,142 @@ defmodule XqliteNIF do
   @spec session_patchset(session :: reference()) :: {:ok, binary()} | Xqlite.error()
   def session_patchset(_session), do: err()
 
+  @doc """
+  Writes the session's changeset to the file at `path` (created or
+  truncated) in chunks, without building it as one binary.
+
+  Fails with `{:error, {:cannot_open_file, path, reason}}` if the file
+  cannot be created.
+  """
+  @spec session_changeset_file(session :: reference(), path :: String.t()) ::
+          :ok | Xqlite.error()
+  def session_changeset_file(_session, _path), do: err()
+
+  @doc """
+  Like `session_changeset_file/2`, but writes a patchset.
+  """
+  @spec session_patchset_file(session :: reference(), path :: String.t()) ::
+          :ok | Xqlite.error()
+  def session_patchset_file(_session, _path), do: err()
+
+  @doc """
+  Sends the session's changeset to `pid` in binaries of `chunk_size`
+  bytes (the last one shorter), one message per chunk:
+
+      {:xqlite_changeset_chunk, call_id, chunk}
+
+  The receiver acknowledges each chunk with
+  `function_reply(call_id, {:ok, true})` before the next one is produced,
+  so at most one chunk is in flight. Replying `{:error, message}`, not
+  replying within `timeout_ms`, or a dead receiver stops the output with
+  `{:error, {:changeset_handler_failed, kind, message}}`. Returns `:ok`
+  once every chunk has been acknowledged.
+
+  The connection stays locked while chunks are sent, so the receiver must
+  not use it. See `Xqlite.session_changeset_into/3` for a wrapper that
+  takes a fun.
+  """
+  @spec session_changeset_send(
+          session :: reference(),
+          pid :: pid(),
+          chunk_size :: pos_integer(),
+          timeout_ms :: pos_integer()
+        ) :: :ok | Xqlite.error()
+  def session_changeset_send(_session, _pid, _chunk_size, _timeout_ms), do: err()
+
+  @doc """
+  Like `session_changeset_send/4`, but sends a patchset.
+  """
+  @spec session_patchset_send(
+          session :: reference(),
+          pid :: pid(),
+          chunk_size :: pos_integer(),
+          timeout_ms :: pos_integer()
+        ) :: :ok | Xqlite.error()
+  def session_patchset_send(_session, _pid, _chunk_size, _timeout_ms), do: err()
+
   @doc """
   Returns true if the session has recorded no changes.
   """
   @spec session_is_empty(session :: reference()) :: boolean()
   def session_is_empty(_session), do: err()
 
+  @doc """
+  Tracks every table except those in `tables`.
+
+  Like `session_attach(session, nil)`, but tables whose names match an
+  entry of `tables` (case-insensitively) are never attached. Replaces any
+  earlier exclusion list; tables the session already attached stay
+  attached.
+  """
+  @spec session_exclude_tables(session :: reference(), tables :: [String.t()]) ::
+          :ok | Xqlite.error()
+  def session_exclude_tables(_session, _tables), do: err()
+
+  @doc """
+  Pauses (`false`) or resumes (`true`) recording.
+
+  Changes made while the session is disabled are not recorded, e.g.
+  maintenance writes that must not be replicated. Sessions start enabled.
+  """
+  @spec session_enable(session :: reference(), enabled :: boolean()) :: :ok | Xqlite.error()
+  def session_enable(_session, _enabled), do: err()
+
+  @doc """
+  Returns whether the session is currently recording.
+  """
+  @spec session_is_enabled(session :: reference()) :: {:ok, boolean()} | Xqlite.error()
+  def session_is_enabled(_session), do: err()
+
+  @doc """
+  Sets the indirect flag for changes recorded from now on.
+
+  Indirect changes — typically those made by triggers or foreign key
+  actions on behalf of the application — are decoded with
+  `indirect: true` (see `Xqlite.Change`). Sessions start direct.
+  """
+  @spec session_indirect(session :: reference(), indirect :: boolean()) ::
+          :ok | Xqlite.error()
+  def session_indirect(_session, _indirect), do: err()
+
+  @doc """
+  Returns whether changes are currently recorded as indirect.
+  """
+  @spec session_is_indirect(session :: reference()) :: {:ok, boolean()} | Xqlite.error()
+  def session_is_indirect(_session), do: err()
+
+  @doc """
+  Records tables without an explicit PRIMARY KEY, keyed by their rowid.
+
+  By default the session ignores such tables. Must be called before any
+  table is attached, otherwise `{:error, {:sqlite_failure, ...}}` with
+  the `SQLITE_MISUSE` code is returned.
+  """
+  @spec session_rowid(session :: reference(), rowid :: boolean()) :: :ok | Xqlite.error()
+  def session_rowid(_session, _rowid), do: err()
+
+  @doc """
+  Returns the heap memory used by the session, in bytes.
+  """
+  @spec session_memory_used(session :: reference()) ::
+          {:ok, non_neg_integer()} | Xqlite.error()
+  def session_memory_used(_session), do: err()
+
+  @doc """
+  Records into the session the changes that would turn `table` in the
+  attached database `from_schema` into `table` in `main`, as if they had
+  been made on `main` while the session was recording.
+
+  The next `session_changeset/1` therefore includes them: applying it to
+  the `from_schema` copy brings that table up to date with `main`. Both
+  tables must have the same columns and primary key, otherwise
+  `{:error, {:schema_changed, ...}}` is returned. A table that is missing
+  from `main` or has no primary key contributes nothing.
+  """
+  @spec session_diff(session :: reference(), from_schema :: String.t(), table :: String.t()) ::
+          :ok | Xqlite.error()
+  def session_diff(_session, _from_schema, _table), do: err()
+
   @doc """
   Deletes the session, releasing its resources.
 
@@ -1662,7 +2489,7 @@ defmodule XqliteNIF do
   @doc """
   Applies a changeset binary to a connection.
 
-  `conflict_strategy` determines behavior on conflicts:
+  `conflict` is a fixed strategy or a handler pid:
   - `:omit` — skip conflicting changes
   - `:replace` — overwrite with the changeset's values. SQLite only permits
     replacement for `DATA` and `CONFLICT` conflicts; for a `NOTFOUND`,
@@ -1670,13 +2497,72 @@ defmodule XqliteNIF do
     the entire apply is aborted and rolled back, returning an error. The
     offending change is not silently skipped — that is `:omit`, not `:replace`.
   - `:abort` — abort the entire apply operation
+  - a pid — receives one message per conflict and decides it:
+
+        {:xqlite_changeset_conflict, call_id, type, change, conflicting}
+
+    `type` is `:data`, `:notfound`, `:conflict`, `:constraint` or
+    `:foreign_key`; `change` is the `Xqlite.Change` being applied and
+    `conflicting` the row currently in the database (`:data` and
+    `:conflict` only, otherwise `nil`). For `:foreign_key`, raised once
+    at the end when the apply would leave foreign-key violations,
+    `change` is `nil` and `conflicting` is the number of violations. The
+    handler answers with `function_reply/2` and `{:ok, "omit"}`,
+    `{:ok, "replace"}` or `{:ok, "abort"}`, typically through
+    `Xqlite.Conflict.handle_conflict/5`. Replacing is only valid for
+    `:data` and `:conflict`.
+
+  The apply runs in a savepoint. A handler that fails — replies
+  `{:error, message}`, an unknown or invalid decision, does not reply
+  within `:timeout`, or is dead — aborts and rolls back the whole apply
+  with `{:error, {:changeset_handler_failed, kind, message}}`. The
+  connection stays locked while the handler decides, so the handler must
+  not use `conn`.
+
+  ## Options
+
+    * `:tables` (list of strings) — apply only changes to these tables
+      (compared case-insensitively). Default: all tables.
+    * `:exclude_tables` (list of strings) — skip changes to these tables.
+    * `:table_filter` (pid) — asked `{:xqlite_changeset_filter, call_id, table}`
+      once per table in the changeset, before anything is applied, and
+      answering `{:ok, boolean}` via `function_reply/2`. Consulted only
+      for tables the lists above let through. A failing filter fails the
+      call with nothing applied.
+    * `:timeout` (positive integer, default `5_000`) — milliseconds to wait
+      for each handler or filter reply.
+    * `:rebase` (boolean, default `false`) — return `{:ok, rebase}` instead
+      of `:ok`, where `rebase` records how conflicts were resolved with
+      `:omit` or `:replace`. Feed it to `rebaser_configure/2` to rebase
+      local changesets over the changes just applied.
+
+  Skipped tables are skipped entirely: none of their changes are applied,
+  so none of them conflict. An unknown option or malformed value fails
+  with `{:error, {:invalid_changeset_apply_option, key}}`.
   """
   @spec changeset_apply(
           conn :: Xqlite.conn(),
           changeset :: binary(),
-          conflict_strategy :: :omit | :replace | :abort
-        ) :: :ok | Xqlite.error()
-  def changeset_apply(_conn, _changeset, _conflict_strategy), do: err()
+          conflict :: :omit | :replace | :abort | pid(),
+          opts :: keyword()
+        ) :: :ok | {:ok, binary()} | Xqlite.error()
+  def changeset_apply(_conn, _changeset, _conflict, _opts \\ []), do: err()
+
+  @doc """
+  Like `changeset_apply/4`, but reads the changeset from the file at
+  `path` incrementally instead of from a binary.
+
+  With a table filter the file is read twice: once to list its tables,
+  once to apply it. A missing or unreadable file fails with
+  `{:error, {:cannot_open_file, path, reason}}`.
+  """
+  @spec changeset_apply_file(
+          conn :: Xqlite.conn(),
+          path :: String.t(),
+          conflict :: :omit | :replace | :abort | pid(),
+          opts :: keyword()
+        ) :: :ok | {:ok, binary()} | Xqlite.error()
+  def changeset_apply_file(_conn, _path, _conflict, _opts \\ []), do: err()
 
   @doc """
   Inverts a changeset binary.
@@ -1686,12 +2572,170 @@ defmodule XqliteNIF do
   @spec changeset_invert(changeset :: binary()) :: {:ok, binary()} | Xqlite.error()
   def changeset_invert(_changeset), do: err()
 
+  @doc """
+  Inverts the changeset in the file at `input_path`, streaming the result
+  into the file at `output_path` (created or truncated). Neither is held
+  in memory as a whole.
+  """
+  @spec changeset_invert_file(input_path :: String.t(), output_path :: String.t()) ::
+          :ok | Xqlite.error()
+  def changeset_invert_file(_input_path, _output_path), do: err()
+
   @doc """
   Concatenates two changeset binaries into one.
   """
   @spec changeset_concat(a :: binary(), b :: binary()) :: {:ok, binary()} | Xqlite.error()
   def changeset_concat(_a, _b), do: err()
 
+  @doc """
+  Decodes a changeset or patchset binary into a list of `Xqlite.Change`
+  structs, in changeset order.
+
+  Column values are typed like query results; a column the change does
+  not record is `:undefined` (see `Xqlite.Change`). A corrupt or
+  truncated binary fails with the SQLite error (typically `SQLITE_CORRUPT`).
+  For changesets too large to hold as one binary, use
+  `changeset_iter_open_file/1`.
+  """
+  @spec changeset_decode(changeset :: binary()) :: {:ok, [Xqlite.Change.t()]} | Xqlite.error()
+  def changeset_decode(_changeset), do: err()
+
+  @doc """
+  Opens a streaming iterator over a changeset or patchset binary.
+
+  Fetch changes with `changeset_iter_next/2`. The binary is copied, so
+  the caller may drop it. See `changeset_iter_open_file/1` to read from
+  disk instead.
+  """
+  @spec changeset_iter_open(changeset :: binary()) :: {:ok, reference()} | Xqlite.error()
+  def changeset_iter_open(_changeset), do: err()
+
+  @doc """
+  Opens a streaming iterator over a changeset or patchset stored in a file.
+
+  The file is read incrementally as changes are fetched, so changesets
+  larger than memory can be decoded. Fails with
+  `{:error, {:cannot_open_file, path, reason}}` if the file cannot be
+  opened.
+  """
+  @spec changeset_iter_open_file(path :: String.t()) :: {:ok, reference()} | Xqlite.error()
+  def changeset_iter_open_file(_path), do: err()
+
+  @doc """
+  Fetches up to `batch_size` further changes from a changeset iterator.
+
+  Returns `{:ok, [Xqlite.Change.t()]}`, then `:done` once the changeset is
+  exhausted. A decoding error ends the iteration; later calls return
+  `:done`.
+  """
+  @spec changeset_iter_next(iter :: reference(), batch_size :: pos_integer()) ::
+          {:ok, [Xqlite.Change.t()]} | :done | Xqlite.error()
+  def changeset_iter_next(_iter, _batch_size), do: err()
+
+  @doc """
+  Releases a changeset iterator and its input. Idempotent; iterators are
+  also released when garbage collected.
+  """
+  @spec changeset_iter_close(iter :: reference()) :: :ok
+  def changeset_iter_close(_iter), do: err()
+
+  @doc """
+  Creates a changegroup: an accumulator that merges many changesets (or
+  many patchsets — the two cannot be mixed) into one minimal changeset.
+
+  Unlike folding with `changeset_concat/2`, which re-reads its growing
+  result on every call, each `changegroup_add/2` costs only the size of
+  the changeset being added.
+
+  With `schema_conn`, the group is bound to the schema of database
+  `db_name` of that connection. Changesets recorded against older
+  versions of a table — before an `ALTER TABLE ... ADD COLUMN` — are
+  then widened to the current columns, using the columns' defaults,
+  instead of failing with `SQLITE_SCHEMA`. A schema-bound group locks the
+  connection during each call and cannot be used once it is closed.
+
+  Returns `{:ok, group}`.
+  """
+  @spec changegroup_new(schema_conn :: Xqlite.conn() | nil, db_name :: String.t()) ::
+          {:ok, reference()} | Xqlite.error()
+  def changegroup_new(_schema_conn \\ nil, _db_name \\ "main"), do: err()
+
+  @doc """
+  Merges a changeset or patchset binary into the group.
+  """
+  @spec changegroup_add(group :: reference(), changeset :: binary()) :: :ok | Xqlite.error()
+  def changegroup_add(_group, _changeset), do: err()
+
+  @doc """
+  Merges a changeset stored in the file at `path`, read incrementally.
+  A missing or unreadable file fails with
+  `{:error, {:cannot_open_file, path, reason}}`.
+  """
+  @spec changegroup_add_file(group :: reference(), path :: String.t()) :: :ok | Xqlite.error()
+  def changegroup_add_file(_group, _path), do: err()
+
+  @doc """
+  Returns the merged changeset of everything added so far. The group is
+  unchanged and can keep accepting changesets.
+  """
+  @spec changegroup_output(group :: reference()) :: {:ok, binary()} | Xqlite.error()
+  def changegroup_output(_group), do: err()
+
+  @doc """
+  Writes the merged changeset to the file at `path` (created or
+  truncated) in chunks, without building it as one binary.
+  """
+  @spec changegroup_output_file(group :: reference(), path :: String.t()) ::
+          :ok | Xqlite.error()
+  def changegroup_output_file(_group, _path), do: err()
+
+  @doc """
+  Deletes the changegroup, releasing its memory. Idempotent; groups are
+  also deleted when garbage collected. Later calls on the group fail.
+  """
+  @spec changegroup_delete(group :: reference()) :: :ok
+  def changegroup_delete(_group), do: err()
+
+  @doc """
+  Creates a rebaser, for offline-first sync where local changesets must
+  be pushed on top of remote changes that were applied first.
+
+  The flow: apply the remote changesets locally with
+  `changeset_apply(conn, remote, conflict, rebase: true)`, hand each
+  returned rebase buffer to `rebaser_configure/2`, then pass every
+  unsynced local changeset through `rebaser_rebase/2` before sending it
+  upstream. The rebased changesets carry the local conflict decisions:
+  a local INSERT whose remote twin was kept with `:omit` becomes an
+  UPDATE of the remote row, local changes that lost to `:replace` are
+  dropped, and so on.
+
+  Returns `{:ok, rebaser}`. A rebaser is not tied to any connection.
+  """
+  @spec rebaser_new() :: {:ok, reference()} | Xqlite.error()
+  def rebaser_new, do: err()
+
+  @doc """
+  Adds a rebase buffer returned by `changeset_apply/4` with
+  `rebase: true`. Buffers from several applies accumulate.
+  """
+  @spec rebaser_configure(rebaser :: reference(), rebase :: binary()) :: :ok | Xqlite.error()
+  def rebaser_configure(_rebaser, _rebase), do: err()
+
+  @doc """
+  Rebases a changeset against every buffer configured so far and
+  returns `{:ok, rebased}`.
+  """
+  @spec rebaser_rebase(rebaser :: reference(), changeset :: binary()) ::
+          {:ok, binary()} | Xqlite.error()
+  def rebaser_rebase(_rebaser, _changeset), do: err()
+
+  @doc """
+  Deletes the rebaser. Idempotent; rebasers are also deleted when
+  garbage collected. Later calls on the rebaser fail.
+  """
+  @spec rebaser_delete(rebaser :: reference()) :: :ok
+  def rebaser_delete(_rebaser), do: err()
+
   # ---------------------------------------------------------------------------
   # Incremental Blob I/O
   # ---------------------------------------------------------------------------
@@ -147,6 +147,8 @@ defmodule Xqlite.MixProject do
         "lib",
         "native/xqlitenif/.cargo",
         "native/xqlitenif/src",
+        "native/xqlitenif/vendor",
+        "native/xqlitenif/build.rs",
         "native/xqlitenif/Cargo.toml",
         "native/xqlitenif/Cargo.lock",
         "checksum-*.exs",
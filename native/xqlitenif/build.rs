@@ -0,0 +1,29 @@
+//! Builds SQLite's index advisor, `ext/expert` (sqlite3expert). It is not
+//! part of the amalgamation libsqlite3-sys bundles, so its two source files
+//! are vendored separately (see vendor/sqlite3expert/README.md) and compiled
+//! against the bundled `sqlite3.h`; the symbols it calls resolve against
+//! the bundled library at link time.
+//!
+//! Its `sqlite3_open` calls are renamed to `xqlite_expert_open` (see
+//! `expert.rs`) rather than editing the vendored source.
+
+const DIR: &str = "vendor/sqlite3expert";
+
+fn main() {
+    let source = format!("{DIR}/sqlite3expert.c");
+    let header = format!("{DIR}/sqlite3expert.h");
+    println!("cargo:rerun-if-changed={source}");
+    println!("cargo:rerun-if-changed={header}");
+
+    let Ok(sqlite_include) = std::env::var("DEP_SQLITE3_INCLUDE") else {
+        panic!("DEP_SQLITE3_INCLUDE is unset; sqlite3expert needs the bundled SQLite");
+    };
+
+    cc::Build::new()
+        .file(&source)
+        .include(DIR)
+        .include(sqlite_include)
+        .define("sqlite3_open", "xqlite_expert_open")
+        .warnings(false)
+        .compile("sqlite3expert");
+}
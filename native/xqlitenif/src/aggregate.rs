@@ -0,0 +1,298 @@
+//! User-defined aggregate and window functions.
+//!
+//! Two implementations share SQLite's aggregate slot:
+//!
+//! * `ElixirAggregate` — every step / inverse / value / finalize call is
+//!   a blocking round trip to an Elixir handler (see `function` for the
+//!   call mechanism). The accumulator lives natively, in SQLite's
+//!   per-group aggregate context, as the opaque `:erlang.term_to_binary`
+//!   bytes the handler returned from its last step; the handler itself
+//!   stays stateless, so one handler process serves any number of
+//!   concurrent groups, window frames and connections.
+//! * `Builtin` — aggregates implemented in Rust, answered without
+//!   leaving the stepping thread.
+//!
+//! Both are registered through `create_window_function` when they
+//! support `inverse`, which makes them usable as plain aggregates too.
+
+use crate::atoms;
+use crate::error::XqliteError;
+use crate::function::{self, FailureKind, Handler, Reply};
+use crate::util::encode_val;
+use rusqlite::Connection;
+use rusqlite::functions::{Aggregate, Context, FunctionFlags, WindowAggregate};
+use rusqlite::types::{Value, ValueRef};
+use rustler::{Atom, Encoder};
+
+/// Accumulator for an Elixir aggregate: `None` until the handler's
+/// first step reply, then its serialized state.
+type ElixirState = Option<Vec<u8>>;
+
+/// Which aggregate callback a round trip is for; sent as the fourth
+/// element of `{:xqlite_aggregate_call, call_id, name, phase, state, args}`.
+#[derive(Debug, Clone, Copy)]
+enum Phase {
+    Step,
+    Inverse,
+    Value,
+    Finalize,
+}
+
+impl Phase {
+    fn to_atom(self) -> Atom {
+        match self {
+            Self::Step => atoms::step(),
+            Self::Inverse => atoms::inverse(),
+            Self::Value => atoms::value(),
+            Self::Finalize => atoms::finalize(),
+        }
+    }
+}
+
+/// An aggregate whose callbacks run in an Elixir handler process.
+#[derive(Debug)]
+pub(crate) struct ElixirAggregate {
+    name: String,
+    handler: Handler,
+}
+
+impl ElixirAggregate {
+    fn round_trip(&self, phase: Phase, state: Option<&[u8]>, args: Vec<Value>) -> Reply {
+        let state = state.map(|bytes| Value::Blob(bytes.to_vec()));
+        function::call(&self.handler, &self.name, |env, call_id| {
+            let state_term = match state {
+                Some(v) => encode_val(env, v)?,
+                None => rustler::types::atom::nil().encode(env),
+            };
+            let mut encoded = Vec::with_capacity(args.len());
+            for arg in args {
+                encoded.push(encode_val(env, arg)?);
+            }
+            Ok((
+                atoms::xqlite_aggregate_call(),
+                call_id,
+                self.name.as_str(),
+                phase.to_atom(),
+                state_term,
+                encoded,
+            )
+                .encode(env))
+        })
+    }
+
+    /// Step or inverse: the reply is the handler's next serialized state.
+    fn advance(
+        &self,
+        phase: Phase,
+        ctx: &mut Context<'_>,
+        acc: &mut ElixirState,
+    ) -> rusqlite::Result<()> {
+        let args = function::context_args(ctx)?;
+        let next = match self.round_trip(phase, acc.as_deref(), args) {
+            Ok(Value::Blob(bytes)) => bytes,
+            Ok(Value::Text(s)) => s.into_bytes(),
+            Ok(other) => {
+                return Err(self.failure(
+                    FailureKind::InvalidResult,
+                    &format!("expected serialized state, got: {other:?}"),
+                ));
+            }
+            Err((kind, message)) => return Err(self.failure(kind, &message)),
+        };
+        *acc = Some(next);
+        Ok(())
+    }
+
+    /// Value or finalize: the reply is the SQL result.
+    fn result(&self, phase: Phase, state: Option<&[u8]>) -> rusqlite::Result<Value> {
+        self.round_trip(phase, state, Vec::new())
+            .map_err(|(kind, message)| self.failure(kind, &message))
+    }
+
+    fn failure(&self, kind: FailureKind, message: &str) -> rusqlite::Error {
+        function::failure_to_sqlite(&self.name, kind, message)
+    }
+}
+
+impl Aggregate<ElixirState, Value> for ElixirAggregate {
+    fn init(&self, _ctx: &mut Context<'_>) -> rusqlite::Result<ElixirState> {
+        // The handler's own init runs lazily, on the first step reply.
+        Ok(None)
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut ElixirState) -> rusqlite::Result<()> {
+        self.advance(Phase::Step, ctx, acc)
+    }
+
+    fn finalize(
+        &self,
+        _ctx: &mut Context<'_>,
+        acc: Option<ElixirState>,
+    ) -> rusqlite::Result<Value> {
+        self.result(Phase::Finalize, acc.flatten().as_deref())
+    }
+}
+
+impl WindowAggregate<ElixirState, Value> for ElixirAggregate {
+    fn value(&self, acc: Option<&mut ElixirState>) -> rusqlite::Result<Value> {
+        self.result(Phase::Value, acc.and_then(|a| a.as_deref()))
+    }
+
+    fn inverse(&self, ctx: &mut Context<'_>, acc: &mut ElixirState) -> rusqlite::Result<()> {
+        self.advance(Phase::Inverse, ctx, acc)
+    }
+}
+
+/// Register an Elixir-backed aggregate. With `window` set it is
+/// registered via `sqlite3_create_window_function` and the handler must
+/// also answer `:inverse` and `:value`. Callers must hold the
+/// connection Mutex.
+pub(crate) fn register_elixir(
+    conn: &Connection,
+    name: &str,
+    n_args: i32,
+    flags: FunctionFlags,
+    handler: Handler,
+    window: bool,
+) -> Result<(), XqliteError> {
+    let aggregate = ElixirAggregate {
+        name: name.to_string(),
+        handler,
+    };
+    if window {
+        conn.create_window_function(name, n_args, flags, aggregate)?;
+    } else {
+        conn.create_aggregate_function(name, n_args, flags, aggregate)?;
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Rust built-ins
+// ---------------------------------------------------------------------------
+
+/// Aggregates implemented natively. Selected by atom at registration;
+/// the SQL name is the caller's choice.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Builtin {
+    /// `weighted_median(value, weight)` — the smallest `value` at which
+    /// the running weight reaches half the total. Rows with a NULL value
+    /// or weight, or a non-positive weight, are skipped. NULL for an
+    /// empty (or all-skipped) group. Window-capable.
+    WeightedMedian,
+}
+
+impl Builtin {
+    pub(crate) fn parse(builtin: Atom) -> Result<Self, XqliteError> {
+        if builtin == atoms::weighted_median() {
+            Ok(Self::WeightedMedian)
+        } else {
+            Err(XqliteError::InvalidBuiltinAggregate { builtin })
+        }
+    }
+}
+
+/// Register a built-in aggregate under `name`. Built-ins are pure, so
+/// they are flagged deterministic and innocuous. Callers must hold the
+/// connection Mutex.
+pub(crate) fn register_builtin(
+    conn: &Connection,
+    name: &str,
+    builtin: Builtin,
+) -> Result<(), XqliteError> {
+    let flags = FunctionFlags::SQLITE_UTF8
+        | FunctionFlags::SQLITE_DETERMINISTIC
+        | FunctionFlags::SQLITE_INNOCUOUS;
+    match builtin {
+        Builtin::WeightedMedian => {
+            conn.create_window_function(name, 2, flags, WeightedMedian)?;
+        }
+    }
+    Ok(())
+}
+
+/// `(value, weight)` pairs currently in the group / window frame.
+type WeightedSamples = Vec<(f64, f64)>;
+
+struct WeightedMedian;
+
+impl WeightedMedian {
+    /// The row's sample, or `None` if the row does not contribute.
+    fn sample(ctx: &Context<'_>) -> rusqlite::Result<Option<(f64, f64)>> {
+        let value = as_f64(ctx.get_raw(0));
+        let weight = as_f64(ctx.get_raw(1));
+        Ok(match (value, weight) {
+            (Some(v), Some(w)) if w > 0.0 && v.is_finite() && w.is_finite() => Some((v, w)),
+            _ => None,
+        })
+    }
+
+    fn median(samples: &WeightedSamples) -> Value {
+        let total: f64 = samples.iter().map(|(_, w)| w).sum();
+        if samples.is_empty() || total <= 0.0 {
+            return Value::Null;
+        }
+        let mut sorted = samples.clone();
+        sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let half = total / 2.0;
+        let mut running = 0.0;
+        for (v, w) in &sorted {
+            running += w;
+            if running >= half {
+                return Value::Real(*v);
+            }
+        }
+        // Float rounding can leave `running` a hair short of `half`.
+        sorted.last().map_or(Value::Null, |(v, _)| Value::Real(*v))
+    }
+}
+
+fn as_f64(value: ValueRef<'_>) -> Option<f64> {
+    match value {
+        ValueRef::Integer(i) => Some(i as f64),
+        ValueRef::Real(f) => Some(f),
+        _ => None,
+    }
+}
+
+impl Aggregate<WeightedSamples, Value> for WeightedMedian {
+    fn init(&self, _ctx: &mut Context<'_>) -> rusqlite::Result<WeightedSamples> {
+        Ok(Vec::new())
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut WeightedSamples) -> rusqlite::Result<()> {
+        if let Some(sample) = Self::sample(ctx)? {
+            acc.push(sample);
+        }
+        Ok(())
+    }
+
+    fn finalize(
+        &self,
+        _ctx: &mut Context<'_>,
+        acc: Option<WeightedSamples>,
+    ) -> rusqlite::Result<Value> {
+        Ok(acc.as_ref().map_or(Value::Null, Self::median))
+    }
+}
+
+impl WindowAggregate<WeightedSamples, Value> for WeightedMedian {
+    fn value(&self, acc: Option<&mut WeightedSamples>) -> rusqlite::Result<Value> {
+        Ok(acc.map_or(Value::Null, |samples| Self::median(samples)))
+    }
+
+    fn inverse(
+        &self,
+        ctx: &mut Context<'_>,
+        acc: &mut WeightedSamples,
+    ) -> rusqlite::Result<()> {
+        // SQLite removes rows from a frame in the order it added them, so
+        // the leaving sample is the oldest equal one.
+        if let Some(sample) = Self::sample(ctx)?
+            && let Some(pos) = acc.iter().position(|s| *s == sample)
+        {
+            acc.remove(pos);
+        }
+        Ok(())
+    }
+}
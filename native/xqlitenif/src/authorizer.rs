@@ -1,16 +1,33 @@
+//! Authorizer policies: a deny-list of action kinds (`set_authorizer/2`)
+//! or an ordered rule list (`set_authorizer_rules/3`).
+//!
+//! Both compile to a `Policy` evaluated natively inside SQLite's
+//! authorizer callback, so no Elixir code runs while a statement is
+//! prepared. Rules are tried in order and the first one whose action kind
+//! and argument matchers all fit decides `Allow`, `Deny` or `Ignore`;
+//! when none matches, the policy default applies.
+//!
+//! The slot also carries a `HookList` of audit observers. Each one is sent
+//! every authorization request together with the decision the policy
+//! produced (`Allow` when none is installed). A dry-run policy is evaluated
+//! and reported but never enforced. The rusqlite closure is reinstalled
+//! whenever either half changes and removed once both are empty.
+
 use crate::atoms;
 use crate::error::XqliteError;
+use crate::hook_util::{self, HookList};
 use rusqlite::Connection;
-use rusqlite::hooks::{AuthAction, AuthContext, Authorization};
-use rustler::Atom;
+use rusqlite::hooks::{AuthAction, AuthContext, Authorization, TransactionOperation};
+use rustler::types::LocalPid;
+use rustler::{Atom, Encoder, Term};
 use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
 
 /// A single authorizer action *kind*.
 ///
-/// v1 granularity is the action kind only — the table / column / trigger /
-/// database arguments rusqlite carries on each `AuthAction` are intentionally
-/// discarded. Exhaustive over the rusqlite 0.40 `AuthAction` enum; `Unknown`
-/// also absorbs any future (`#[non_exhaustive]`) variant.
+/// Exhaustive over the rusqlite 0.40 `AuthAction` enum; `Unknown` also
+/// absorbs any future (`#[non_exhaustive]`) variant. The arguments of an
+/// action are read separately, by `Args::of`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub(crate) enum ActionKind {
     CreateIndex,
@@ -50,7 +67,7 @@ pub(crate) enum ActionKind {
 }
 
 impl ActionKind {
-    /// Kind of an incoming authorizer action (all arguments discarded).
+    /// Kind of an incoming authorizer action.
     #[inline]
     fn of(action: &AuthAction<'_>) -> Self {
         match action {
@@ -96,7 +113,22 @@ impl ActionKind {
     /// is a structured error so the whole list can be rejected atomically
     /// before any authorizer is installed.
     fn from_atom(atom: Atom) -> Result<Self, XqliteError> {
-        let table: [(Atom, Self); 34] = [
+        Self::atom_table()
+            .into_iter()
+            .find_map(|(a, kind)| (a == atom).then_some(kind))
+            .ok_or(XqliteError::InvalidAuthorizerAction { action: atom })
+    }
+
+    /// The atom `from_atom` accepts for this kind.
+    fn atom(self) -> Atom {
+        Self::atom_table()
+            .into_iter()
+            .find_map(|(atom, kind)| (kind == self).then_some(atom))
+            .unwrap_or_else(atoms::unknown)
+    }
+
+    fn atom_table() -> [(Atom, Self); 34] {
+        [
             (atoms::create_index(), Self::CreateIndex),
             (atoms::create_table(), Self::CreateTable),
             (atoms::create_temp_index(), Self::CreateTempIndex),
@@ -131,11 +163,7 @@ impl ActionKind {
             (atoms::savepoint(), Self::Savepoint),
             (atoms::recursive(), Self::Recursive),
             (atoms::unknown(), Self::Unknown),
-        ];
-        table
-            .into_iter()
-            .find_map(|(a, kind)| (a == atom).then_some(kind))
-            .ok_or(XqliteError::InvalidAuthorizerAction { action: atom })
+        ]
     }
 }
 
@@ -145,25 +173,516 @@ pub(crate) fn parse_denied(actions: Vec<Atom>) -> Result<HashSet<ActionKind>, Xq
     actions.into_iter().map(ActionKind::from_atom).collect()
 }
 
-/// Install a deny-list authorizer, replacing any previous one (single slot).
+/// The named arguments of one authorizer action, as rules match them.
+#[derive(Debug, Default)]
+struct Args<'c> {
+    table: Option<&'c str>,
+    column: Option<&'c str>,
+    /// Index, trigger, view, pragma, function, savepoint or module name.
+    name: Option<&'c str>,
+    /// `ATTACH` file name.
+    file: Option<&'c str>,
+}
+
+impl<'c> Args<'c> {
+    fn of(action: &AuthAction<'c>) -> Self {
+        let table = |table| Self {
+            table: Some(table),
+            ..Self::default()
+        };
+        let named = |name, table| Self {
+            table,
+            name: Some(name),
+            ..Self::default()
+        };
+        match *action {
+            AuthAction::CreateIndex {
+                index_name,
+                table_name,
+            }
+            | AuthAction::CreateTempIndex {
+                index_name,
+                table_name,
+            }
+            | AuthAction::DropIndex {
+                index_name,
+                table_name,
+            }
+            | AuthAction::DropTempIndex {
+                index_name,
+                table_name,
+            } => named(index_name, Some(table_name)),
+            AuthAction::CreateTrigger {
+                trigger_name,
+                table_name,
+            }
+            | AuthAction::CreateTempTrigger {
+                trigger_name,
+                table_name,
+            }
+            | AuthAction::DropTrigger {
+                trigger_name,
+                table_name,
+            }
+            | AuthAction::DropTempTrigger {
+                trigger_name,
+                table_name,
+            } => named(trigger_name, Some(table_name)),
+            AuthAction::CreateVtable {
+                table_name,
+                module_name,
+            }
+            | AuthAction::DropVtable {
+                table_name,
+                module_name,
+            } => named(module_name, Some(table_name)),
+            AuthAction::CreateTable { table_name }
+            | AuthAction::CreateTempTable { table_name }
+            | AuthAction::DropTable { table_name }
+            | AuthAction::DropTempTable { table_name }
+            | AuthAction::Delete { table_name }
+            | AuthAction::Insert { table_name }
+            | AuthAction::Analyze { table_name }
+            | AuthAction::AlterTable { table_name, .. } => table(table_name),
+            AuthAction::Read {
+                table_name,
+                column_name,
+            }
+            | AuthAction::Update {
+                table_name,
+                column_name,
+            } => Self {
+                table: Some(table_name),
+                column: Some(column_name),
+                ..Self::default()
+            },
+            AuthAction::CreateView { view_name }
+            | AuthAction::CreateTempView { view_name }
+            | AuthAction::DropView { view_name }
+            | AuthAction::DropTempView { view_name } => named(view_name, None),
+            AuthAction::Pragma { pragma_name, .. } => named(pragma_name, None),
+            AuthAction::Function { function_name } => named(function_name, None),
+            AuthAction::Savepoint { savepoint_name, .. } => named(savepoint_name, None),
+            AuthAction::Reindex { index_name } => named(index_name, None),
+            AuthAction::Attach { filename } => Self {
+                file: Some(filename),
+                ..Self::default()
+            },
+            _ => Self::default(),
+        }
+    }
+}
+
+/// One `{outcome, actions, matchers}` rule. A `None` matcher matches
+/// anything; a set one must equal the action's argument, which an action
+/// without that argument never does.
+#[derive(Debug)]
+struct Rule {
+    outcome: Authorization,
+    kinds: Vec<ActionKind>,
+    table: Option<String>,
+    column: Option<String>,
+    name: Option<String>,
+    database: Option<String>,
+    accessor: Option<String>,
+    file: Option<String>,
+}
+
+impl Rule {
+    fn matches(&self, kind: ActionKind, args: &Args<'_>, ctx: &AuthContext<'_>) -> bool {
+        // Identifiers compare like SQLite's (ASCII case-insensitive); the
+        // ATTACH file name is a path and compares exactly.
+        let ident = |want: &Option<String>, got: Option<&str>| {
+            want.as_deref()
+                .is_none_or(|w| got.is_some_and(|g| g.eq_ignore_ascii_case(w)))
+        };
+        self.kinds.contains(&kind)
+            && ident(&self.table, args.table)
+            && ident(&self.column, args.column)
+            && ident(&self.name, args.name)
+            && ident(&self.database, ctx.database_name)
+            && ident(&self.accessor, ctx.accessor)
+            && self.file.as_deref().is_none_or(|w| args.file == Some(w))
+    }
+}
+
+/// What an installed authorizer decides.
+#[derive(Debug)]
+pub(crate) struct Policy {
+    rules: Vec<Rule>,
+    default: Authorization,
+    /// Report decisions to observers but allow everything.
+    dry_run: bool,
+}
+
+impl Policy {
+    /// Deny every action whose kind is in `denied`, allow the rest.
+    pub(crate) fn deny_list(denied: HashSet<ActionKind>) -> Self {
+        let rule = Rule {
+            outcome: Authorization::Deny,
+            kinds: denied.into_iter().collect(),
+            table: None,
+            column: None,
+            name: None,
+            database: None,
+            accessor: None,
+            file: None,
+        };
+        Self {
+            rules: vec![rule],
+            default: Authorization::Allow,
+            dry_run: false,
+        }
+    }
+
+    pub(crate) fn decide(&self, ctx: &AuthContext<'_>) -> Authorization {
+        let kind = ActionKind::of(&ctx.action);
+        let args = Args::of(&ctx.action);
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(kind, &args, ctx))
+            .map_or(self.default, |rule| rule.outcome)
+    }
+}
+
+fn parse_outcome(atom: Atom) -> Option<Authorization> {
+    if atom == atoms::allow() {
+        Some(Authorization::Allow)
+    } else if atom == atoms::deny() {
+        Some(Authorization::Deny)
+    } else if atom == atoms::ignore() {
+        Some(Authorization::Ignore)
+    } else {
+        None
+    }
+}
+
+/// Decode `{outcome, action_or_actions}` or `{outcome, action_or_actions,
+/// matchers}`, where matchers is a keyword list of `:table`, `:column`,
+/// `:name`, `:database`, `:accessor` and `:file` strings.
+fn parse_rule(term: Term<'_>) -> Result<Rule, XqliteError> {
+    let invalid = || XqliteError::InvalidAuthorizerRule {
+        rule: format!("{term:?}"),
+    };
+    let (outcome, actions, matchers) =
+        if let Ok((outcome, actions)) = term.decode::<(Atom, Term<'_>)>() {
+            (outcome, actions, Vec::new())
+        } else {
+            term.decode::<(Atom, Term<'_>, Vec<(Atom, String)>)>()
+                .map_err(|_| invalid())?
+        };
+    let kinds = match actions.decode::<Atom>() {
+        Ok(action) => vec![ActionKind::from_atom(action)?],
+        Err(_) => actions
+            .decode::<Vec<Atom>>()
+            .map_err(|_| invalid())?
+            .into_iter()
+            .map(ActionKind::from_atom)
+            .collect::<Result<_, _>>()?,
+    };
+    let mut rule = Rule {
+        outcome: parse_outcome(outcome).ok_or_else(invalid)?,
+        kinds,
+        table: None,
+        column: None,
+        name: None,
+        database: None,
+        accessor: None,
+        file: None,
+    };
+    for (key, value) in matchers {
+        let slot = if key == atoms::table() {
+            &mut rule.table
+        } else if key == atoms::column() {
+            &mut rule.column
+        } else if key == atoms::name() {
+            &mut rule.name
+        } else if key == atoms::database() {
+            &mut rule.database
+        } else if key == atoms::accessor() {
+            &mut rule.accessor
+        } else if key == atoms::file() {
+            &mut rule.file
+        } else {
+            return Err(invalid());
+        };
+        *slot = Some(value);
+    }
+    Ok(rule)
+}
+
+/// Build a rule policy from user terms, rejecting the whole list on the
+/// first malformed rule before an authorizer is touched.
+pub(crate) fn parse_rules<'a>(
+    rules: Vec<Term<'a>>,
+    default: Term<'a>,
+    dry_run: bool,
+) -> Result<Policy, XqliteError> {
+    let default = default
+        .decode::<Atom>()
+        .ok()
+        .and_then(parse_outcome)
+        .ok_or_else(|| XqliteError::InvalidAuthorizerRule {
+            rule: format!("default: {default:?}"),
+        })?;
+    Ok(Policy {
+        rules: rules
+            .into_iter()
+            .map(parse_rule)
+            .collect::<Result<_, _>>()?,
+        default,
+        dry_run,
+    })
+}
+
+/// SQLite's raw `(arg1, arg2)` for an action, rebuilt from rusqlite's
+/// parsed form (see the table in the `sqlite3_set_authorizer` docs).
+fn raw_args<'c>(action: &AuthAction<'c>) -> (Option<&'c str>, Option<&'c str>) {
+    match *action {
+        AuthAction::CreateIndex {
+            index_name,
+            table_name,
+        }
+        | AuthAction::CreateTempIndex {
+            index_name,
+            table_name,
+        }
+        | AuthAction::DropIndex {
+            index_name,
+            table_name,
+        }
+        | AuthAction::DropTempIndex {
+            index_name,
+            table_name,
+        } => (Some(index_name), Some(table_name)),
+        AuthAction::CreateTrigger {
+            trigger_name,
+            table_name,
+        }
+        | AuthAction::CreateTempTrigger {
+            trigger_name,
+            table_name,
+        }
+        | AuthAction::DropTrigger {
+            trigger_name,
+            table_name,
+        }
+        | AuthAction::DropTempTrigger {
+            trigger_name,
+            table_name,
+        } => (Some(trigger_name), Some(table_name)),
+        AuthAction::CreateVtable {
+            table_name,
+            module_name,
+        }
+        | AuthAction::DropVtable {
+            table_name,
+            module_name,
+        } => (Some(table_name), Some(module_name)),
+        AuthAction::Read {
+            table_name,
+            column_name,
+        }
+        | AuthAction::Update {
+            table_name,
+            column_name,
+        } => (Some(table_name), Some(column_name)),
+        AuthAction::CreateTable { table_name }
+        | AuthAction::CreateTempTable { table_name }
+        | AuthAction::DropTable { table_name }
+        | AuthAction::DropTempTable { table_name }
+        | AuthAction::Delete { table_name }
+        | AuthAction::Insert { table_name }
+        | AuthAction::Analyze { table_name } => (Some(table_name), None),
+        AuthAction::CreateView { view_name }
+        | AuthAction::CreateTempView { view_name }
+        | AuthAction::DropView { view_name }
+        | AuthAction::DropTempView { view_name } => (Some(view_name), None),
+        AuthAction::Pragma {
+            pragma_name,
+            pragma_value,
+        } => (Some(pragma_name), pragma_value),
+        AuthAction::Transaction { operation } => {
+            (Some(operation_str(operation, "COMMIT")), None)
+        }
+        AuthAction::Savepoint {
+            operation,
+            savepoint_name,
+        } => (Some(operation_str(operation, "")), Some(savepoint_name)),
+        AuthAction::Attach { filename } => (Some(filename), None),
+        AuthAction::Detach { database_name } => (Some(database_name), None),
+        AuthAction::AlterTable {
+            database_name,
+            table_name,
+        } => (Some(database_name), Some(table_name)),
+        AuthAction::Reindex { index_name } => (Some(index_name), None),
+        AuthAction::Function { function_name } => (None, Some(function_name)),
+        AuthAction::Unknown { arg1, arg2, .. } => (arg1, arg2),
+        _ => (None, None),
+    }
+}
+
+/// rusqlite folds every operation string it does not know into
+/// `Unknown`; for `SQLITE_TRANSACTION` the only such string is `COMMIT`.
+fn operation_str(operation: TransactionOperation, unknown: &'static str) -> &'static str {
+    match operation {
+        TransactionOperation::Begin => "BEGIN",
+        TransactionOperation::Release => "RELEASE",
+        TransactionOperation::Rollback => "ROLLBACK",
+        _ => unknown,
+    }
+}
+
+fn decision_atom(decision: Authorization) -> Atom {
+    match decision {
+        Authorization::Deny => atoms::deny(),
+        Authorization::Ignore => atoms::ignore(),
+        _ => atoms::allow(),
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct AuthorizerObserver {
+    pub(crate) pid: LocalPid,
+}
+
+impl std::fmt::Debug for AuthorizerObserver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthorizerObserver").finish()
+    }
+}
+
+/// Per-connection authorizer slot: the single policy plus the audit
+/// observers. Both halves are read and written under the connection
+/// Mutex; the inner `Mutex` only gives the policy interior mutability.
+#[derive(Debug)]
+pub(crate) struct AuthorizerSlot {
+    policy: Mutex<Option<Arc<Policy>>>,
+    observers: Arc<HookList<AuthorizerObserver>>,
+}
+
+impl AuthorizerSlot {
+    pub(crate) fn new() -> Self {
+        Self {
+            policy: Mutex::new(None),
+            observers: Arc::new(HookList::new()),
+        }
+    }
+
+    fn replace_policy(&self, policy: Option<Policy>) -> Result<(), XqliteError> {
+        let mut guard = self
+            .policy
+            .lock()
+            .map_err(|e| XqliteError::LockError(e.to_string()))?;
+        *guard = policy.map(Arc::new);
+        Ok(())
+    }
+}
+
+/// Send `{:xqlite_authorizer, action, arg1, arg2, db, trigger_or_view,
+/// decision}` to every observer. Fire-and-forget.
+fn report(
+    observers: &HookList<AuthorizerObserver>,
+    ctx: &AuthContext<'_>,
+    decision: Authorization,
+) {
+    let action = ActionKind::of(&ctx.action).atom();
+    let (arg1, arg2) = raw_args(&ctx.action);
+    let decision = decision_atom(decision);
+    // SAFETY: the closure calling us captures Arc<HookList>, so the list
+    // outlives the callback. Each message is built in a fresh msg_env by
+    // `send_with_env`; nothing escapes it.
+    unsafe {
+        observers.for_each_snapshot(|entry| {
+            let _ = hook_util::send_with_env(&entry.state.pid, |env| {
+                Ok((
+                    atoms::xqlite_authorizer(),
+                    action,
+                    arg1,
+                    arg2,
+                    ctx.database_name,
+                    ctx.accessor,
+                    decision,
+                )
+                    .encode(env))
+            });
+        });
+    }
+}
+
+/// Re-derive the installed closure from the slot: none when both halves
+/// are empty, otherwise one that decides, reports and enforces.
 ///
-/// The closure owns the denied set and only reads it, so it is `Fn` (hence
-/// `FnMut`), `Send`, and `'static` — exactly what rusqlite's safe authorizer
-/// API requires. Callers must hold the connection Mutex.
-pub(crate) fn set(conn: &Connection, denied: HashSet<ActionKind>) -> Result<(), XqliteError> {
+/// The closure owns its policy snapshot and a clone of the observer list
+/// and only reads them, so it is `Fn` (hence `FnMut`), `Send`, and
+/// `'static` — exactly what rusqlite's safe authorizer API requires.
+/// Callers must hold the connection Mutex.
+fn sync(conn: &Connection, slot: &AuthorizerSlot) -> Result<(), XqliteError> {
+    let policy = slot
+        .policy
+        .lock()
+        .map_err(|e| XqliteError::LockError(e.to_string()))?
+        .clone();
+    if policy.is_none() && slot.observers.is_empty() {
+        return conn
+            .authorizer(None::<fn(AuthContext<'_>) -> Authorization>)
+            .map_err(XqliteError::from);
+    }
+    let observers = Arc::clone(&slot.observers);
     conn.authorizer(Some(move |ctx: AuthContext<'_>| {
-        if denied.contains(&ActionKind::of(&ctx.action)) {
-            Authorization::Deny
-        } else {
-            Authorization::Allow
+        let decision = policy
+            .as_deref()
+            .map_or(Authorization::Allow, |policy| policy.decide(&ctx));
+        if !observers.is_empty() {
+            report(&observers, &ctx, decision);
+        }
+        match policy.as_deref() {
+            Some(policy) if policy.dry_run => Authorization::Allow,
+            _ => decision,
         }
     }))
     .map_err(XqliteError::from)
 }
 
-/// Clear any installed authorizer. Idempotent. Callers must hold the
-/// connection Mutex.
-pub(crate) fn clear(conn: &Connection) -> Result<(), XqliteError> {
-    conn.authorizer(None::<fn(AuthContext<'_>) -> Authorization>)
-        .map_err(XqliteError::from)
+/// Install `policy`, replacing any previous one (single slot). Observers
+/// are unaffected. Callers must hold the connection Mutex.
+pub(crate) fn set(
+    conn: &Connection,
+    slot: &AuthorizerSlot,
+    policy: Policy,
+) -> Result<(), XqliteError> {
+    slot.replace_policy(Some(policy))?;
+    sync(conn, slot)
+}
+
+/// Remove the policy, keeping any observers. Idempotent. Callers must
+/// hold the connection Mutex.
+pub(crate) fn clear(conn: &Connection, slot: &AuthorizerSlot) -> Result<(), XqliteError> {
+    slot.replace_policy(None)?;
+    sync(conn, slot)
+}
+
+/// Add an audit observer. Returns the handle the caller passes to
+/// `unregister_observer`. Callers must hold the connection Mutex.
+pub(crate) fn register_observer(
+    conn: &Connection,
+    slot: &AuthorizerSlot,
+    pid: LocalPid,
+) -> Result<u64, XqliteError> {
+    let id = slot.observers.register(AuthorizerObserver { pid });
+    sync(conn, slot)?;
+    Ok(id)
+}
+
+/// Remove an audit observer. Idempotent — unknown handles are no-ops.
+/// Callers must hold the connection Mutex.
+pub(crate) fn unregister_observer(
+    conn: &Connection,
+    slot: &AuthorizerSlot,
+    id: u64,
+) -> Result<(), XqliteError> {
+    if slot.observers.unregister(id) {
+        sync(conn, slot)?;
+    }
+    Ok(())
 }
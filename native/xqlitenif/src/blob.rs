@@ -44,11 +44,7 @@ use std::sync::atomic::{AtomicPtr, Ordering};
 /// lifetime-erased wrapper whose `Drop` dereferences an `&Connection` — in a
 /// resource that can outlive an explicit connection close. Always own the raw C
 /// handle and call the `sqlite3_*` C functions directly under the connection
-/// `Mutex`. `session.rs` stores a `Session<'static>` safely ONLY because
-/// rusqlite `Session` holds `PhantomData<&Connection>` (`session.rs:26`), so its
-/// `Drop` touches only the raw `self.s` (`session.rs:207`) and never derefs the
-/// connection. That is a fragile distinction — do NOT "unify" the two resources
-/// by giving `XqliteSession` a real wrapper.
+/// `Mutex`, as `session.rs` (`RawSession`) does.
 ///
 /// The pointer lives in an `AtomicPtr` (null == closed/finalized), mirroring
 /// `XqliteStream`/`XqliteStatement`. Every `sqlite3_blob_*` call holds the
@@ -0,0 +1,180 @@
+//! Changegroups: merging many changesets into one.
+//!
+//! `changeset_concat/2` re-parses its accumulated result on every call,
+//! so folding N changesets with it costs O(N²). A `sqlite3_changegroup`
+//! keeps the merged changes in a hash table instead: each add costs only
+//! the size of the changeset added, and `output` serialises the minimal
+//! combined changeset once.
+//!
+//! A group may be bound to a connection's schema at creation
+//! (`sqlite3changegroup_schema`), which lets it merge changesets recorded
+//! before and after an `ALTER TABLE ... ADD COLUMN`. SQLite then reads
+//! that schema, and may prepare statements against it, during adds and
+//! when the group is deleted — so every call on a schema-bound group
+//! holds the connection Mutex, following the same rule as sessions.
+
+use crate::changeset::{self, sqlite_error};
+use crate::connection::XqliteConn;
+use crate::error::XqliteError;
+use rusqlite::ffi;
+use rustler::{Resource, ResourceArc, resource_impl};
+use std::ffi::CString;
+use std::io::{Read, Write};
+use std::sync::Mutex;
+
+/// Owned `sqlite3_changegroup` pointer.
+struct Group(*mut ffi::sqlite3_changegroup);
+
+impl Group {
+    fn delete(self) {
+        // SAFETY: the pointer came from `sqlite3changegroup_new` and is
+        // deleted exactly once, here; callers hold the connection Mutex
+        // when the group is schema-bound.
+        unsafe { ffi::sqlite3changegroup_delete(self.0) }
+    }
+}
+
+pub(crate) struct XqliteChangegroup {
+    /// `None` once deleted.
+    group: Mutex<Option<Group>>,
+    /// Connection whose schema the group was bound to, if any.
+    schema_conn: Option<ResourceArc<XqliteConn>>,
+}
+
+// SAFETY: the raw group pointer is only used while the `group` Mutex is
+// held (and, for a schema-bound group, the connection Mutex before it).
+unsafe impl Send for XqliteChangegroup {}
+// SAFETY: see the `Send` impl above; access is serialized by the Mutexes.
+unsafe impl Sync for XqliteChangegroup {}
+
+#[resource_impl]
+impl Resource for XqliteChangegroup {}
+
+impl Drop for XqliteChangegroup {
+    fn drop(&mut self) {
+        self.delete();
+    }
+}
+
+impl XqliteChangegroup {
+    /// Create an empty group, optionally bound to database `db_name` of
+    /// `schema_conn`.
+    pub(crate) fn new(
+        schema_conn: Option<ResourceArc<XqliteConn>>,
+        db_name: &str,
+    ) -> Result<Self, XqliteError> {
+        let mut ptr: *mut ffi::sqlite3_changegroup = std::ptr::null_mut();
+        // SAFETY: `ptr` is a valid out-pointer.
+        changeset::check(unsafe { ffi::sqlite3changegroup_new(&mut ptr) })?;
+        let group = Group(ptr);
+        if let Some(conn) = &schema_conn {
+            let bound = CString::new(db_name)
+                .map_err(|_| XqliteError::NulErrorInString)
+                .and_then(|z_db| {
+                    crate::connection::with_conn(conn, |c| {
+                        // SAFETY: connection Mutex held; SQLite copies `z_db`.
+                        changeset::check(unsafe {
+                            ffi::sqlite3changegroup_schema(group.0, c.handle(), z_db.as_ptr())
+                        })
+                    })
+                });
+            if let Err(e) = bound {
+                // Nothing was added, so the group holds no statements yet.
+                group.delete();
+                return Err(e);
+            }
+        }
+        Ok(Self {
+            group: Mutex::new(Some(group)),
+            schema_conn,
+        })
+    }
+
+    /// Run `f` on the live group, holding the connection Mutex first when
+    /// the group is schema-bound.
+    fn with_group<F, R>(&self, f: F) -> Result<R, XqliteError>
+    where
+        F: FnOnce(*mut ffi::sqlite3_changegroup) -> Result<R, XqliteError>,
+    {
+        let run = || {
+            let guard = self
+                .group
+                .lock()
+                .map_err(|e| XqliteError::LockError(e.to_string()))?;
+            match guard.as_ref() {
+                Some(group) => f(group.0),
+                None => Err(XqliteError::CannotExecute(
+                    "changegroup has been deleted".to_string(),
+                )),
+            }
+        };
+        match &self.schema_conn {
+            Some(conn) => crate::connection::with_conn(conn, |_| run()),
+            None => run(),
+        }
+    }
+
+    /// Merge an in-memory changeset into the group.
+    pub(crate) fn add(&self, bytes: &[u8]) -> Result<(), XqliteError> {
+        self.add_from(bytes)
+    }
+
+    /// Merge a changeset read incrementally from `input`.
+    pub(crate) fn add_from<R: Read>(&self, mut input: R) -> Result<(), XqliteError> {
+        self.with_group(|group| {
+            // SAFETY: `group` is live under the held Mutex; `input` outlives
+            // the call, and SQLite reads it only from `x_input`.
+            changeset::check(unsafe {
+                ffi::sqlite3changegroup_add_strm(
+                    group,
+                    Some(changeset::x_input::<R>),
+                    (&mut input as *mut R).cast(),
+                )
+            })
+        })
+    }
+
+    /// Serialise the merged changeset.
+    pub(crate) fn output(&self) -> Result<Vec<u8>, XqliteError> {
+        let mut out = Vec::new();
+        self.output_to(&mut out)?;
+        Ok(out)
+    }
+
+    /// Stream the merged changeset into `output`.
+    pub(crate) fn output_to<W: Write>(&self, mut output: W) -> Result<(), XqliteError> {
+        self.with_group(|group| {
+            // SAFETY: as in `add_from`; SQLite writes only through `x_output`.
+            changeset::check(unsafe {
+                ffi::sqlite3changegroup_output_strm(
+                    group,
+                    Some(changeset::x_output::<W>),
+                    (&mut output as *mut W).cast(),
+                )
+            })
+        })?;
+        output
+            .flush()
+            .map_err(|_| sqlite_error(ffi::SQLITE_IOERR_WRITE))
+    }
+
+    /// Delete the group. Idempotent. A schema-bound group whose
+    /// connection is already closed is leaked rather than deleted: its
+    /// cached statements belong to the freed db.
+    pub(crate) fn delete(&self) {
+        let conn_lock = self.schema_conn.as_ref().map(|conn| conn.conn.lock());
+        let mut guard = match self.group.lock() {
+            Ok(g) => g,
+            Err(p) => p.into_inner(),
+        };
+        let Some(group) = guard.take() else {
+            return;
+        };
+        match conn_lock {
+            None => group.delete(),
+            Some(Ok(ref conn_guard)) if conn_guard.is_some() => group.delete(),
+            // Connection closed or lock poisoned: leak.
+            Some(_) => {}
+        }
+    }
+}
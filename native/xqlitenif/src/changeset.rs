@@ -0,0 +1,614 @@
+//! Decoding changesets and patchsets into `%Xqlite.Change{}` structs.
+//!
+//! Both entry points walk a `sqlite3_changeset_iter`; neither needs a
+//! connection, since a changeset iterator is a standalone SQLite object.
+//!
+//! * `decode` iterates an in-memory changeset (`sqlite3changeset_start`)
+//!   to completion inside one NIF call.
+//! * `XqliteChangesetIter` owns a streaming iterator
+//!   (`sqlite3changeset_start_strm`) fed from a file or a binary through
+//!   the `x_input` callback, and hands changes out a batch at a time, so
+//!   a changeset larger than memory never has to be materialised.
+//!
+//! Changesets and patchsets share one format, distinguished per table
+//! record, so the same code decodes both. A column absent from a change
+//! (an unchanged column of an UPDATE, or a non-PK column of a patchset
+//! DELETE) is reported as `:undefined`, distinct from SQL NULL (`nil`).
+
+use crate::atoms;
+use crate::error::XqliteError;
+use crate::function::{self, Handler};
+use crate::hook_util;
+use crate::session;
+use crate::util::encode_val;
+use rusqlite::ffi;
+use rusqlite::types::Value;
+use rustler::types::LocalPid;
+use rustler::types::atom::nil;
+use rustler::types::map::map_new;
+use rustler::{Encoder, Env, Resource, Term, resource_impl};
+use std::ffi::CStr;
+use std::io::{Read, Write};
+use std::os::raw::{c_char, c_int, c_uchar, c_void};
+use std::sync::Mutex;
+
+/// Byte source of a streaming iterator.
+pub(crate) type Input = Box<dyn Read + Send>;
+
+struct IterState {
+    it: *mut ffi::sqlite3_changeset_iter,
+    /// Boxed a second time so the `*mut Input` handed to SQLite as `pIn`
+    /// keeps its address for as long as `it` may call `x_input`.
+    input: Box<Input>,
+}
+
+impl IterState {
+    fn finalize(self) {
+        // SAFETY: `it` came from a successful `sqlite3changeset_start_strm`
+        // and is finalized exactly once, here, before `input` (which it may
+        // read from) drops at the end of this scope. The return code echoes
+        // the last iteration error, which `next_batch` already surfaced.
+        unsafe {
+            ffi::sqlite3changeset_finalize(self.it);
+        }
+        drop(self.input);
+    }
+}
+
+pub(crate) struct XqliteChangesetIter {
+    /// `None` once exhausted or closed.
+    state: Mutex<Option<IterState>>,
+}
+
+// SAFETY: the raw iterator pointer is only dereferenced by SQLite while the
+// Mutex is held, and SQLite objects may move between threads in the
+// serialized / multi-thread modes this crate is built for.
+unsafe impl Send for XqliteChangesetIter {}
+// SAFETY: see the `Send` impl above; access is serialized by the Mutex.
+unsafe impl Sync for XqliteChangesetIter {}
+
+#[resource_impl]
+impl Resource for XqliteChangesetIter {}
+
+impl Drop for XqliteChangesetIter {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl XqliteChangesetIter {
+    /// Start a streaming iterator over `input`.
+    pub(crate) fn open(input: Input) -> Result<Self, XqliteError> {
+        let mut input = Box::new(input);
+        let p_in: *mut Input = &mut *input;
+        let mut it: *mut ffi::sqlite3_changeset_iter = std::ptr::null_mut();
+        // SAFETY: `p_in` points into the heap allocation owned by `input`,
+        // which `IterState` keeps alive until after the iterator is
+        // finalized. SQLite only reads through it from `x_input`.
+        let rc = unsafe {
+            ffi::sqlite3changeset_start_strm(&mut it, Some(x_input::<Input>), p_in.cast())
+        };
+        if rc != ffi::SQLITE_OK {
+            if !it.is_null() {
+                // SAFETY: a failed start may still hand back an iterator.
+                unsafe {
+                    ffi::sqlite3changeset_finalize(it);
+                }
+            }
+            return Err(sqlite_error(rc));
+        }
+        Ok(Self {
+            state: Mutex::new(Some(IterState { it, input })),
+        })
+    }
+
+    /// Decode up to `max` further changes. `Ok(None)` once the changeset
+    /// is exhausted; the iterator is released at that point.
+    pub(crate) fn next_batch<'a>(
+        &self,
+        env: Env<'a>,
+        max: usize,
+    ) -> Result<Option<Vec<Term<'a>>>, XqliteError> {
+        let mut guard = self
+            .state
+            .lock()
+            .map_err(|e| XqliteError::LockError(e.to_string()))?;
+        let Some(state) = guard.as_ref() else {
+            return Ok(None);
+        };
+        let it = state.it;
+        let mut changes = Vec::with_capacity(max.min(1024));
+        let mut finished = false;
+        while changes.len() < max {
+            // SAFETY: `it` is live while the Mutex is held and `state` is
+            // `Some`; nothing else advances it.
+            match unsafe { step(env, it) } {
+                Ok(Some(change)) => changes.push(change),
+                Ok(None) => {
+                    finished = true;
+                    break;
+                }
+                Err(e) => {
+                    // A corrupt or truncated stream cannot be resumed.
+                    if let Some(state) = guard.take() {
+                        state.finalize();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        if finished {
+            if let Some(state) = guard.take() {
+                state.finalize();
+            }
+            if changes.is_empty() {
+                return Ok(None);
+            }
+        }
+        Ok(Some(changes))
+    }
+
+    /// Release the iterator and its input. Idempotent.
+    pub(crate) fn close(&self) {
+        let mut guard = match self.state.lock() {
+            Ok(g) => g,
+            Err(p) => p.into_inner(),
+        };
+        if let Some(state) = guard.take() {
+            state.finalize();
+        }
+    }
+}
+
+/// `xInput` for the `*_strm` changeset functions: fill up to `*pn_data`
+/// bytes from the reader behind `p_in`, reporting how many were read
+/// (0 at end of input).
+pub(crate) unsafe extern "C" fn x_input<R: Read>(
+    p_in: *mut c_void,
+    p_data: *mut c_void,
+    pn_data: *mut c_int,
+) -> c_int {
+    hook_util::guard_ffi_callback("changeset x_input", ffi::SQLITE_IOERR_READ, || {
+        // SAFETY: `p_in` is the `*mut R` registered alongside this callback,
+        // alive for as long as SQLite may call it; SQLite passes a writable
+        // buffer of `*pn_data` bytes and reads nothing else from it during
+        // the call.
+        unsafe {
+            let input = &mut *p_in.cast::<R>();
+            let wanted = usize::try_from(*pn_data).unwrap_or(0);
+            let buf = std::slice::from_raw_parts_mut(p_data.cast::<u8>(), wanted);
+            loop {
+                match input.read(buf) {
+                    Ok(n) => {
+                        *pn_data = n as c_int;
+                        return ffi::SQLITE_OK;
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(_) => return ffi::SQLITE_IOERR_READ,
+                }
+            }
+        }
+    })
+}
+
+/// `xOutput` for the `*_strm` changeset functions: write the `n_data`
+/// bytes at `p_data` to the writer behind `p_out`.
+pub(crate) unsafe extern "C" fn x_output<W: Write>(
+    p_out: *mut c_void,
+    p_data: *const c_void,
+    n_data: c_int,
+) -> c_int {
+    hook_util::guard_ffi_callback("changeset x_output", ffi::SQLITE_IOERR_WRITE, || {
+        // SAFETY: `p_out` is the `*mut W` registered alongside this callback,
+        // alive for as long as SQLite may call it; `p_data` holds `n_data`
+        // readable bytes for the duration of the call.
+        unsafe {
+            let output = &mut *p_out.cast::<W>();
+            let len = usize::try_from(n_data).unwrap_or(0);
+            let data = std::slice::from_raw_parts(p_data.cast::<u8>(), len);
+            match output.write_all(data) {
+                Ok(()) => ffi::SQLITE_OK,
+                Err(_) => ffi::SQLITE_IOERR_WRITE,
+            }
+        }
+    })
+}
+
+/// Writer for the `*_strm` output functions that forwards the bytes to a
+/// receiver pid as `{:xqlite_changeset_chunk, call_id, chunk}` messages of
+/// `chunk_size` bytes (the last one shorter). Each chunk is a `function`
+/// round trip: SQLite produces nothing more until the receiver answers
+/// through `function_reply/2`, which bounds memory at one chunk.
+pub(crate) struct ChunkSink {
+    handler: Handler,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+    /// First receiver failure; it failed the write.
+    failure: Option<XqliteError>,
+}
+
+impl ChunkSink {
+    pub(crate) fn new(
+        pid: LocalPid,
+        chunk_size: usize,
+        timeout_ms: u64,
+    ) -> Result<Self, XqliteError> {
+        if chunk_size == 0 {
+            return Err(XqliteError::CannotExecute(
+                "changeset chunk_size must be >= 1".to_string(),
+            ));
+        }
+        Ok(Self {
+            handler: Handler::new(pid, timeout_ms)?,
+            chunk_size,
+            buffer: Vec::with_capacity(chunk_size),
+            failure: None,
+        })
+    }
+
+    /// Resolve the outcome of a streaming call that wrote into this sink:
+    /// a receiver failure takes precedence over the generic write error
+    /// SQLite reported for it.
+    pub(crate) fn finish(self, result: Result<(), XqliteError>) -> Result<(), XqliteError> {
+        match self.failure {
+            Some(failure) => Err(failure),
+            None => result,
+        }
+    }
+
+    fn send_buffer(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = &self.buffer;
+        let reply = function::call(&self.handler, "changeset chunk", |env, call_id| {
+            let binary = session::to_owned_binary(chunk, "changeset chunk")?;
+            Ok((
+                atoms::xqlite_changeset_chunk(),
+                call_id,
+                binary.release(env),
+            )
+                .encode(env))
+        });
+        self.buffer.clear();
+        match reply {
+            Ok(_) => Ok(()),
+            Err((kind, message)) => {
+                let failure = XqliteError::ChangesetHandlerFailed { kind, message };
+                let io_error = std::io::Error::other(failure.to_string());
+                self.failure.get_or_insert(failure);
+                Err(io_error)
+            }
+        }
+    }
+}
+
+impl Write for ChunkSink {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let n = (self.chunk_size - self.buffer.len()).min(data.len());
+        self.buffer.extend_from_slice(&data[..n]);
+        if self.buffer.len() == self.chunk_size {
+            self.send_buffer()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.send_buffer()
+    }
+}
+
+/// Decode every change in an in-memory changeset or patchset.
+pub(crate) fn decode<'a>(env: Env<'a>, bytes: &[u8]) -> Result<Vec<Term<'a>>, XqliteError> {
+    let len = c_int::try_from(bytes.len()).map_err(|_| {
+        XqliteError::CannotExecute(
+            "changeset larger than 2 GiB; use changeset_iter_open".into(),
+        )
+    })?;
+    let mut it: *mut ffi::sqlite3_changeset_iter = std::ptr::null_mut();
+    // SAFETY: SQLite only reads `len` bytes from the buffer, which outlives
+    // the iterator (finalized before this function returns).
+    let rc =
+        unsafe { ffi::sqlite3changeset_start(&mut it, len, bytes.as_ptr() as *mut c_void) };
+    if rc != ffi::SQLITE_OK {
+        if !it.is_null() {
+            // SAFETY: a failed start may still hand back an iterator.
+            unsafe {
+                ffi::sqlite3changeset_finalize(it);
+            }
+        }
+        return Err(sqlite_error(rc));
+    }
+    let mut changes = Vec::new();
+    let result = loop {
+        // SAFETY: `it` is live until the finalize below.
+        match unsafe { step(env, it) } {
+            Ok(Some(change)) => changes.push(change),
+            Ok(None) => break Ok(changes),
+            Err(e) => break Err(e),
+        }
+    };
+    // SAFETY: finalized exactly once; its return code repeats any error
+    // already captured in `result`.
+    unsafe {
+        ffi::sqlite3changeset_finalize(it);
+    }
+    result
+}
+
+/// Advance `it` and encode the change it lands on.
+///
+/// # Safety
+///
+/// `it` must be a live changeset iterator not used concurrently.
+unsafe fn step<'a>(
+    env: Env<'a>,
+    it: *mut ffi::sqlite3_changeset_iter,
+) -> Result<Option<Term<'a>>, XqliteError> {
+    // SAFETY: caller contract.
+    unsafe {
+        match ffi::sqlite3changeset_next(it) {
+            ffi::SQLITE_ROW => encode_change(env, it).map(Some),
+            ffi::SQLITE_DONE => Ok(None),
+            rc => Err(sqlite_error(rc)),
+        }
+    }
+}
+
+/// Encode the iterator's current change as an `%Xqlite.Change{}`.
+///
+/// # Safety
+///
+/// `it` must be a live iterator positioned on a change (`SQLITE_ROW`).
+pub(crate) unsafe fn encode_change<'a>(
+    env: Env<'a>,
+    it: *mut ffi::sqlite3_changeset_iter,
+) -> Result<Term<'a>, XqliteError> {
+    let mut table_ptr: *const c_char = std::ptr::null();
+    let mut n_col: c_int = 0;
+    let mut op: c_int = 0;
+    let mut indirect: c_int = 0;
+    let mut pk_ptr: *mut c_uchar = std::ptr::null_mut();
+    let mut pk_len: c_int = 0;
+    // SAFETY: caller contract; the out-pointers are valid locals, and the
+    // table name / PK array stay valid until the iterator moves on.
+    let (table, pk_flags) = unsafe {
+        check(ffi::sqlite3changeset_op(
+            it,
+            &mut table_ptr,
+            &mut n_col,
+            &mut op,
+            &mut indirect,
+        ))?;
+        check(ffi::sqlite3changeset_pk(it, &mut pk_ptr, &mut pk_len))?;
+        let table = CStr::from_ptr(table_ptr).to_str().map_err(|e| {
+            XqliteError::InternalEncodingError {
+                context: format!("changeset table name is not valid UTF-8: {e}"),
+            }
+        })?;
+        let pk_flags =
+            std::slice::from_raw_parts(pk_ptr, usize::try_from(pk_len).unwrap_or(0));
+        (table, pk_flags)
+    };
+
+    let (op_atom, has_old, has_new) = match op {
+        ffi::SQLITE_INSERT => (atoms::insert(), false, true),
+        ffi::SQLITE_UPDATE => (atoms::update(), true, true),
+        ffi::SQLITE_DELETE => (atoms::delete(), true, false),
+        other => {
+            return Err(XqliteError::InternalEncodingError {
+                context: format!("unknown changeset operation code {other}"),
+            });
+        }
+    };
+    let pk_columns: Vec<usize> = pk_flags
+        .iter()
+        .enumerate()
+        .filter(|(_, flag)| **flag != 0)
+        .map(|(i, _)| i)
+        .collect();
+
+    let old = if has_old {
+        // SAFETY: caller contract; DELETE and UPDATE carry old.* values.
+        unsafe { encode_row(env, n_col, |i, out| ffi::sqlite3changeset_old(it, i, out))? }
+    } else {
+        nil().encode(env)
+    };
+    let new = if has_new {
+        // SAFETY: caller contract; INSERT and UPDATE carry new.* values.
+        unsafe { encode_row(env, n_col, |i, out| ffi::sqlite3changeset_new(it, i, out))? }
+    } else {
+        nil().encode(env)
+    };
+
+    map_new(env)
+        .map_put(atoms::__struct__(), atoms::change_struct())
+        .and_then(|m| m.map_put(atoms::table(), table))
+        .and_then(|m| m.map_put(atoms::op(), op_atom))
+        .and_then(|m| m.map_put(atoms::indirect(), indirect != 0))
+        .and_then(|m| m.map_put(atoms::pk_columns(), pk_columns))
+        .and_then(|m| m.map_put(atoms::old(), old))
+        .and_then(|m| m.map_put(atoms::new(), new))
+        .map_err(|_| XqliteError::InternalEncodingError {
+            context: "changeset change map_put failed".to_string(),
+        })
+}
+
+/// Encode the database row a DATA or CONFLICT conflict collided with.
+///
+/// # Safety
+///
+/// `it` must be the iterator handed to a conflict callback invoked with
+/// `SQLITE_CHANGESET_DATA` or `SQLITE_CHANGESET_CONFLICT`.
+pub(crate) unsafe fn encode_conflict<'a>(
+    env: Env<'a>,
+    it: *mut ffi::sqlite3_changeset_iter,
+) -> Result<Term<'a>, XqliteError> {
+    let mut table_ptr: *const c_char = std::ptr::null();
+    let mut n_col: c_int = 0;
+    let mut op: c_int = 0;
+    let mut indirect: c_int = 0;
+    // SAFETY: caller contract; `sqlite3changeset_conflict` is valid for
+    // exactly these two conflict types.
+    unsafe {
+        check(ffi::sqlite3changeset_op(
+            it,
+            &mut table_ptr,
+            &mut n_col,
+            &mut op,
+            &mut indirect,
+        ))?;
+        encode_row(env, n_col, |i, out| {
+            ffi::sqlite3changeset_conflict(it, i, out)
+        })
+    }
+}
+
+/// Names of the tables a changeset touches, in changeset order, each
+/// listed once.
+pub(crate) fn tables<R: Read>(mut input: R) -> Result<Vec<String>, XqliteError> {
+    let mut it: *mut ffi::sqlite3_changeset_iter = std::ptr::null_mut();
+    // SAFETY: `input` outlives the iterator, which is finalized before this
+    // function returns.
+    let rc = unsafe {
+        ffi::sqlite3changeset_start_strm(
+            &mut it,
+            Some(x_input::<R>),
+            (&mut input as *mut R).cast(),
+        )
+    };
+    if rc != ffi::SQLITE_OK {
+        if !it.is_null() {
+            // SAFETY: a failed start may still hand back an iterator.
+            unsafe {
+                ffi::sqlite3changeset_finalize(it);
+            }
+        }
+        return Err(sqlite_error(rc));
+    }
+    let mut names: Vec<String> = Vec::new();
+    let result = loop {
+        // SAFETY: `it` is live until the finalize below; the table name is
+        // copied before the iterator moves on.
+        let step = unsafe {
+            match ffi::sqlite3changeset_next(it) {
+                ffi::SQLITE_ROW => {
+                    let mut table_ptr: *const c_char = std::ptr::null();
+                    let (mut n_col, mut op, mut indirect): (c_int, c_int, c_int) = (0, 0, 0);
+                    check(ffi::sqlite3changeset_op(
+                        it,
+                        &mut table_ptr,
+                        &mut n_col,
+                        &mut op,
+                        &mut indirect,
+                    ))
+                    .map(|()| Some(CStr::from_ptr(table_ptr).to_string_lossy().into_owned()))
+                }
+                ffi::SQLITE_DONE => Ok(None),
+                rc => Err(sqlite_error(rc)),
+            }
+        };
+        match step {
+            Ok(Some(name)) => {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            Ok(None) => break Ok(names),
+            Err(e) => break Err(e),
+        }
+    };
+    // SAFETY: finalized exactly once; its return code repeats any error
+    // already captured in `result`.
+    unsafe {
+        ffi::sqlite3changeset_finalize(it);
+    }
+    result
+}
+
+/// Encode one side of a change as a list of `n_col` values, fetching each
+/// through `get` (`sqlite3changeset_old` or `sqlite3changeset_new`).
+///
+/// # Safety
+///
+/// `get` must return `sqlite3_value` pointers that stay valid until the
+/// iterator advances.
+unsafe fn encode_row<'a, F>(
+    env: Env<'a>,
+    n_col: c_int,
+    get: F,
+) -> Result<Term<'a>, XqliteError>
+where
+    F: Fn(c_int, *mut *mut ffi::sqlite3_value) -> c_int,
+{
+    let mut values = Vec::with_capacity(usize::try_from(n_col).unwrap_or(0));
+    for i in 0..n_col {
+        let mut value: *mut ffi::sqlite3_value = std::ptr::null_mut();
+        check(get(i, &mut value))?;
+        let term = if value.is_null() {
+            atoms::undefined().encode(env)
+        } else {
+            // SAFETY: non-null values from the iterator are valid until it
+            // advances (function contract).
+            encode_val(env, unsafe { read_value(i as usize, value)? })?
+        };
+        values.push(term);
+    }
+    Ok(values.encode(env))
+}
+
+/// Copy a protected `sqlite3_value` into an owned `Value`.
+///
+/// # Safety
+///
+/// `value` must be a valid, non-null `sqlite3_value`.
+unsafe fn read_value(
+    column: usize,
+    value: *mut ffi::sqlite3_value,
+) -> Result<Value, XqliteError> {
+    // SAFETY: caller contract. Text/blob pointers are read before any other
+    // call that could invalidate them, with the length fetched afterwards
+    // as the SQLite docs prescribe.
+    unsafe {
+        Ok(match ffi::sqlite3_value_type(value) {
+            ffi::SQLITE_INTEGER => Value::Integer(ffi::sqlite3_value_int64(value)),
+            ffi::SQLITE_FLOAT => Value::Real(ffi::sqlite3_value_double(value)),
+            ffi::SQLITE_TEXT => {
+                let ptr = ffi::sqlite3_value_text(value);
+                let len = usize::try_from(ffi::sqlite3_value_bytes(value)).unwrap_or(0);
+                let bytes = if ptr.is_null() {
+                    &[][..]
+                } else {
+                    std::slice::from_raw_parts(ptr, len)
+                };
+                let text = std::str::from_utf8(bytes).map_err(|e| XqliteError::Utf8Error {
+                    column,
+                    reason: e.to_string(),
+                })?;
+                Value::Text(text.to_owned())
+            }
+            ffi::SQLITE_BLOB => {
+                let ptr = ffi::sqlite3_value_blob(value);
+                let len = usize::try_from(ffi::sqlite3_value_bytes(value)).unwrap_or(0);
+                if ptr.is_null() || len == 0 {
+                    Value::Blob(Vec::new())
+                } else {
+                    Value::Blob(std::slice::from_raw_parts(ptr.cast::<u8>(), len).to_vec())
+                }
+            }
+            _ => Value::Null,
+        })
+    }
+}
+
+pub(crate) fn check(rc: c_int) -> Result<(), XqliteError> {
+    if rc == ffi::SQLITE_OK {
+        Ok(())
+    } else {
+        Err(sqlite_error(rc))
+    }
+}
+
+pub(crate) fn sqlite_error(rc: c_int) -> XqliteError {
+    XqliteError::from(rusqlite::Error::SqliteFailure(ffi::Error::new(rc), None))
+}
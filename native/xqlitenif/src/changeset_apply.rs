@@ -0,0 +1,415 @@
+//! Applying changesets with per-conflict decisions and table filters.
+//!
+//! `changeset_apply/4` drives `sqlite3changeset_apply_v2_strm` directly
+//! rather than through rusqlite, so the conflict callback can hand the
+//! raw iterator to `changeset::encode_change`.
+//!
+//! A conflict is resolved either by a fixed strategy (`:omit`,
+//! `:replace`, `:abort`) or by a handler pid, which receives
+//!
+//!     {:xqlite_changeset_conflict, call_id, type, change, conflicting}
+//!
+//! and answers through `function_reply/2` with `{:ok, "omit"}`,
+//! `{:ok, "replace"}` or `{:ok, "abort"}` — the round trip is the one
+//! `function::call` performs for user functions.
+//!
+//! Tables are filtered natively by the `:tables` allowlist and the
+//! `:exclude_tables` denylist, and optionally by a `:table_filter` pid
+//! asked `{:xqlite_changeset_filter, call_id, table}` (reply
+//! `{:ok, boolean}`). SQLite's `xFilter` has no error channel, so the
+//! filter pid is asked once per table BEFORE anything is applied: a
+//! failing filter then fails the call with nothing written.
+//!
+//! With `rebase: true`, the rebase buffer SQLite records for conflicts
+//! resolved by omit or replace is returned for a `rebaser` (see
+//! `rebaser.rs`).
+//!
+//! Any handler failure (error, timeout, dead pid, unusable reply) aborts
+//! the apply, which SQLite rolls back, and is reported as
+//! `{:changeset_handler_failed, kind, message}`.
+//!
+//! Deadlock note: the conflict callback runs while this NIF holds the
+//! connection Mutex, so a handler that uses the same connection blocks
+//! until the timeout aborts the apply.
+
+use crate::atoms;
+use crate::changeset;
+use crate::error::XqliteError;
+use crate::function::{self, FailureKind, Handler};
+use crate::hook_util;
+use rusqlite::types::Value;
+use rusqlite::{Connection, ffi};
+use rustler::types::LocalPid;
+use rustler::types::atom::nil;
+use rustler::{Atom, Encoder, Term};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::io::Read;
+use std::os::raw::{c_char, c_int, c_void};
+
+const DEFAULT_TIMEOUT_MS: u64 = 5_000;
+
+/// A fixed answer to every conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Strategy {
+    Omit,
+    Replace,
+    Abort,
+}
+
+impl Strategy {
+    fn action(self, conflict_type: c_int) -> c_int {
+        match self {
+            Self::Omit => ffi::SQLITE_CHANGESET_OMIT,
+            Self::Abort => ffi::SQLITE_CHANGESET_ABORT,
+            // SQLITE_CHANGESET_REPLACE is a legal return ONLY for DATA and
+            // CONFLICT conflicts; returning it for NOTFOUND / CONSTRAINT /
+            // FOREIGN_KEY makes sqlite3changeset_apply fail with
+            // SQLITE_MISUSE. A `:replace` request cannot overwrite in those
+            // cases, so abort the whole apply cleanly (rolled back) rather
+            // than surface an opaque misuse error.
+            Self::Replace if replaceable(conflict_type) => ffi::SQLITE_CHANGESET_REPLACE,
+            Self::Replace => ffi::SQLITE_CHANGESET_ABORT,
+        }
+    }
+}
+
+/// Who decides each conflict.
+pub(crate) enum Resolver {
+    Strategy(Strategy),
+    Handler(LocalPid),
+}
+
+/// Decode the `conflict` argument: a strategy atom or a handler pid.
+pub(crate) fn parse_resolver(term: Term<'_>) -> Result<Resolver, XqliteError> {
+    if let Ok(pid) = term.decode::<LocalPid>() {
+        return Ok(Resolver::Handler(pid));
+    }
+    let strategy = match term.decode::<Atom>() {
+        Ok(a) if a == atoms::omit() => Strategy::Omit,
+        Ok(a) if a == atoms::replace() => Strategy::Replace,
+        Ok(a) if a == atoms::abort() => Strategy::Abort,
+        _ => return Err(XqliteError::InvalidConflictStrategy),
+    };
+    Ok(Resolver::Strategy(strategy))
+}
+
+/// Options accepted by `changeset_apply/4`.
+pub(crate) struct ApplyOptions {
+    tables: Option<Vec<String>>,
+    exclude_tables: Vec<String>,
+    table_filter: Option<LocalPid>,
+    timeout_ms: u64,
+    rebase: bool,
+}
+
+impl ApplyOptions {
+    fn has_filter(&self) -> bool {
+        self.tables.is_some() || !self.exclude_tables.is_empty() || self.table_filter.is_some()
+    }
+
+    fn lists_accept(&self, table: &str) -> bool {
+        let listed = |names: &[String]| names.iter().any(|t| t.eq_ignore_ascii_case(table));
+        self.tables.as_deref().is_none_or(listed) && !listed(&self.exclude_tables)
+    }
+}
+
+/// Decode the `changeset_apply/4` keyword list. Unknown keys and
+/// malformed values are rejected with the offending key.
+pub(crate) fn parse_options(opts: Vec<(Atom, Term<'_>)>) -> Result<ApplyOptions, XqliteError> {
+    let mut out = ApplyOptions {
+        tables: None,
+        exclude_tables: Vec::new(),
+        table_filter: None,
+        timeout_ms: DEFAULT_TIMEOUT_MS,
+        rebase: false,
+    };
+    for (key, value) in opts {
+        let invalid = || XqliteError::InvalidChangesetApplyOption { option: key };
+        if key == atoms::tables() {
+            out.tables = Some(value.decode::<Vec<String>>().map_err(|_| invalid())?);
+        } else if key == atoms::exclude_tables() {
+            out.exclude_tables = value.decode::<Vec<String>>().map_err(|_| invalid())?;
+        } else if key == atoms::table_filter() {
+            out.table_filter = Some(value.decode::<LocalPid>().map_err(|_| invalid())?);
+        } else if key == atoms::timeout() {
+            out.timeout_ms = match value.decode::<u64>() {
+                Ok(ms) if ms > 0 => ms,
+                _ => return Err(invalid()),
+            };
+        } else if key == atoms::rebase() {
+            out.rebase = value.decode::<bool>().map_err(|_| invalid())?;
+        } else {
+            return Err(invalid());
+        }
+    }
+    Ok(out)
+}
+
+/// Everything decided before the connection is locked.
+pub(crate) struct Plan {
+    conflict: Conflict,
+    /// Tables to apply; `None` applies every table.
+    allowed: Option<HashSet<String>>,
+    /// Whether to collect the rebase buffer.
+    rebase: bool,
+}
+
+enum Conflict {
+    Strategy(Strategy),
+    Handler(Handler),
+}
+
+impl Plan {
+    /// Validate the arguments and resolve the table filter, asking the
+    /// filter pid (if any) about each table the changeset touches. `open`
+    /// yields the changeset bytes and is only called when a filter needs
+    /// the table list. Runs without the connection Mutex.
+    pub(crate) fn new<R, F>(
+        open: F,
+        resolver: Resolver,
+        options: ApplyOptions,
+    ) -> Result<Self, XqliteError>
+    where
+        R: Read,
+        F: FnOnce() -> Result<R, XqliteError>,
+    {
+        let conflict = match resolver {
+            Resolver::Strategy(s) => Conflict::Strategy(s),
+            Resolver::Handler(pid) => {
+                Conflict::Handler(Handler::new(pid, options.timeout_ms)?)
+            }
+        };
+        let allowed = if options.has_filter() {
+            let filter = options
+                .table_filter
+                .map(|pid| Handler::new(pid, options.timeout_ms))
+                .transpose()?;
+            let mut allowed = HashSet::new();
+            for table in changeset::tables(open()?)? {
+                if options.lists_accept(&table)
+                    && filter
+                        .as_ref()
+                        .map_or(Ok(true), |h| ask_filter(h, &table))?
+                {
+                    allowed.insert(table);
+                }
+            }
+            Some(allowed)
+        } else {
+            None
+        };
+        Ok(Self {
+            conflict,
+            allowed,
+            rebase: options.rebase,
+        })
+    }
+
+    /// Apply the changeset read from `input` to `conn`, returning the
+    /// rebase buffer if the plan asked for one. Callers must hold the
+    /// connection Mutex.
+    pub(crate) fn apply<R: Read>(
+        &self,
+        conn: &Connection,
+        mut input: R,
+    ) -> Result<Option<Vec<u8>>, XqliteError> {
+        let ctx = ApplyCtx {
+            plan: self,
+            failure: RefCell::new(None),
+        };
+        let mut p_rebase: *mut c_void = std::ptr::null_mut();
+        let mut n_rebase: c_int = 0;
+        let (pp_rebase, pn_rebase) = if self.rebase {
+            (
+                &mut p_rebase as *mut *mut c_void,
+                &mut n_rebase as *mut c_int,
+            )
+        } else {
+            (std::ptr::null_mut(), std::ptr::null_mut())
+        };
+        // SAFETY: caller holds the connection Mutex. `input` and `ctx`
+        // outlive the call, and SQLite only uses them from the callbacks,
+        // which run on this thread before it returns. The rebase
+        // out-pointers are either both valid or both null.
+        let rc = unsafe {
+            ffi::sqlite3changeset_apply_v2_strm(
+                conn.handle(),
+                Some(changeset::x_input::<R>),
+                (&mut input as *mut R).cast(),
+                Some(x_filter),
+                Some(x_conflict),
+                (&ctx as *const ApplyCtx).cast_mut().cast(),
+                pp_rebase,
+                pn_rebase,
+                0,
+            )
+        };
+        // SAFETY: on success or failure SQLite hands over `p_rebase`
+        // (possibly null) holding `n_rebase` bytes, to be freed with
+        // `sqlite3_free`.
+        let rebase = unsafe {
+            let buffer = (!p_rebase.is_null()).then(|| {
+                let len = usize::try_from(n_rebase).unwrap_or(0);
+                std::slice::from_raw_parts(p_rebase.cast::<u8>(), len).to_vec()
+            });
+            ffi::sqlite3_free(p_rebase);
+            buffer
+        };
+        if let Some(failure) = ctx.failure.into_inner() {
+            return Err(failure);
+        }
+        changeset::check(rc)?;
+        Ok(self.rebase.then(|| rebase.unwrap_or_default()))
+    }
+}
+
+/// Context behind the `pCtx` pointer of one apply.
+struct ApplyCtx<'p> {
+    plan: &'p Plan,
+    /// First handler failure; it aborted the apply.
+    failure: RefCell<Option<XqliteError>>,
+}
+
+fn replaceable(conflict_type: c_int) -> bool {
+    matches!(
+        conflict_type,
+        ffi::SQLITE_CHANGESET_DATA | ffi::SQLITE_CHANGESET_CONFLICT
+    )
+}
+
+fn conflict_type_atom(conflict_type: c_int) -> Atom {
+    match conflict_type {
+        ffi::SQLITE_CHANGESET_DATA => atoms::data(),
+        ffi::SQLITE_CHANGESET_NOTFOUND => atoms::notfound(),
+        ffi::SQLITE_CHANGESET_CONFLICT => atoms::conflict(),
+        ffi::SQLITE_CHANGESET_CONSTRAINT => atoms::constraint(),
+        _ => atoms::foreign_key(),
+    }
+}
+
+fn handler_failed((kind, message): (FailureKind, String)) -> XqliteError {
+    XqliteError::ChangesetHandlerFailed { kind, message }
+}
+
+fn invalid_reply(message: String) -> XqliteError {
+    XqliteError::ChangesetHandlerFailed {
+        kind: FailureKind::InvalidResult,
+        message,
+    }
+}
+
+fn ask_filter(handler: &Handler, table: &str) -> Result<bool, XqliteError> {
+    let reply = function::call(handler, "changeset table filter", |env, call_id| {
+        Ok((atoms::xqlite_changeset_filter(), call_id, table).encode(env))
+    });
+    match reply.map_err(handler_failed)? {
+        Value::Integer(1) => Ok(true),
+        Value::Integer(0) => Ok(false),
+        other => Err(invalid_reply(format!(
+            "table filter must return a boolean, got: {other:?}"
+        ))),
+    }
+}
+
+/// Ask the conflict handler about the conflict `it` is positioned on.
+///
+/// # Safety
+///
+/// `it` must be the iterator SQLite passed to the conflict callback,
+/// invoked with `conflict_type`.
+unsafe fn ask_conflict(
+    handler: &Handler,
+    conflict_type: c_int,
+    it: *mut ffi::sqlite3_changeset_iter,
+) -> Result<c_int, XqliteError> {
+    let type_atom = conflict_type_atom(conflict_type);
+    let reply = function::call(handler, "changeset conflict", |env, call_id| {
+        // SAFETY: function contract. A FOREIGN_KEY conflict's iterator only
+        // supports `sqlite3changeset_fk_conflicts`; the conflicting row is
+        // only readable for DATA and CONFLICT.
+        let (change, conflicting) = unsafe {
+            if conflict_type == ffi::SQLITE_CHANGESET_FOREIGN_KEY {
+                let mut count: c_int = 0;
+                changeset::check(ffi::sqlite3changeset_fk_conflicts(it, &mut count))?;
+                (nil().encode(env), count.encode(env))
+            } else if replaceable(conflict_type) {
+                (
+                    changeset::encode_change(env, it)?,
+                    changeset::encode_conflict(env, it)?,
+                )
+            } else {
+                (changeset::encode_change(env, it)?, nil().encode(env))
+            }
+        };
+        Ok((
+            atoms::xqlite_changeset_conflict(),
+            call_id,
+            type_atom,
+            change,
+            conflicting,
+        )
+            .encode(env))
+    });
+    match reply.map_err(handler_failed)? {
+        Value::Text(decision) => match decision.as_str() {
+            "omit" => Ok(ffi::SQLITE_CHANGESET_OMIT),
+            "abort" => Ok(ffi::SQLITE_CHANGESET_ABORT),
+            "replace" if replaceable(conflict_type) => Ok(ffi::SQLITE_CHANGESET_REPLACE),
+            "replace" => Err(invalid_reply(format!(
+                ":replace is only valid for :data and :conflict conflicts, not {type_atom:?}"
+            ))),
+            other => Err(invalid_reply(format!(
+                "expected :omit, :replace or :abort, got: {other:?}"
+            ))),
+        },
+        other => Err(invalid_reply(format!(
+            "expected :omit, :replace or :abort, got: {other:?}"
+        ))),
+    }
+}
+
+/// `xFilter`: apply a table's changes only if the plan allows it.
+unsafe extern "C" fn x_filter(p_ctx: *mut c_void, table: *const c_char) -> c_int {
+    hook_util::guard_ffi_callback("changeset x_filter", 0, || {
+        // SAFETY: `p_ctx` is the `ApplyCtx` registered by `Plan::apply`,
+        // alive for the whole apply; `table` is a NUL-terminated string
+        // owned by SQLite for this call.
+        let (ctx, table) = unsafe {
+            (
+                &*(p_ctx as *const ApplyCtx),
+                CStr::from_ptr(table).to_string_lossy(),
+            )
+        };
+        let allowed = ctx
+            .plan
+            .allowed
+            .as_ref()
+            .is_none_or(|set| set.contains(table.as_ref()));
+        c_int::from(allowed)
+    })
+}
+
+/// `xConflict`: resolve one conflict by strategy or by handler.
+unsafe extern "C" fn x_conflict(
+    p_ctx: *mut c_void,
+    conflict_type: c_int,
+    it: *mut ffi::sqlite3_changeset_iter,
+) -> c_int {
+    hook_util::guard_ffi_callback("changeset x_conflict", ffi::SQLITE_CHANGESET_ABORT, || {
+        // SAFETY: see `x_filter`.
+        let ctx = unsafe { &*(p_ctx as *const ApplyCtx) };
+        match &ctx.plan.conflict {
+            Conflict::Strategy(s) => s.action(conflict_type),
+            // SAFETY: `it` is the iterator SQLite passed for this conflict.
+            Conflict::Handler(h) => match unsafe { ask_conflict(h, conflict_type, it) } {
+                Ok(action) => action,
+                Err(e) => {
+                    ctx.failure.borrow_mut().get_or_insert(e);
+                    ffi::SQLITE_CHANGESET_ABORT
+                }
+            },
+        }
+    })
+}
@@ -0,0 +1,344 @@
+//! User-defined collations and the `collation_needed` hook.
+//!
+//! Two comparator sources share `Connection::create_collation`:
+//!
+//! * `Native` — comparators implemented in Rust (Unicode case folding,
+//!   natural sort, accent folding), answered on the stepping thread.
+//! * Elixir — every comparison is a blocking round trip to a handler
+//!   pid via the `function` call mechanism, as
+//!   `{:xqlite_collation_call, call_id, name, a, b}`.
+//!
+//! A collation cannot report an error to SQLite: the C comparator
+//! returns only an ordering. An Elixir comparison that fails (error,
+//! timeout, dead handler, non-integer reply) therefore falls back to
+//! byte order for that one pair, which keeps sorts total and stable
+//! but may misplace the rows involved.
+//!
+//! The `collation_needed` hook lets SQLite ask for an unknown collation
+//! while it prepares a statement. rusqlite's `Connection::collation_needed`
+//! accepts a bare `fn` (no captured state), so we register the C
+//! callback directly, with the resolver state in an `AtomicPtr` slot on
+//! `XqliteConn` (same lifecycle as `busy_handler`).
+
+use crate::atoms;
+use crate::error::XqliteError;
+use crate::function::{self, Handler};
+use crate::hook_util;
+use rusqlite::types::Value;
+use rusqlite::{Connection, ffi};
+use rustler::{Atom, Encoder};
+use std::cmp::Ordering;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_void};
+use std::sync::atomic::AtomicPtr;
+
+/// Comparators implemented natively. Selected by atom at registration;
+/// the SQL name is the caller's choice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Native {
+    /// Case-insensitive over all of Unicode (full lowercase mapping),
+    /// where SQLite's own `NOCASE` folds ASCII only.
+    UnicodeNocase,
+    /// Digit runs compare by numeric value, so `file2` sorts before
+    /// `file10`. Leading zeros only break ties.
+    Natural,
+    /// Accent-insensitive for Latin script: `é`, `É`, `ē` compare equal
+    /// to `e`, `E`, `e`. Case is preserved; ligatures such as `æ` and
+    /// `ß` expand to their letters. Combining marks are ignored.
+    NoAccents,
+}
+
+impl Native {
+    pub(crate) fn parse(collation: Atom) -> Result<Self, XqliteError> {
+        if collation == atoms::unicode_nocase() {
+            Ok(Self::UnicodeNocase)
+        } else if collation == atoms::natural() {
+            Ok(Self::Natural)
+        } else if collation == atoms::no_accents() {
+            Ok(Self::NoAccents)
+        } else {
+            Err(XqliteError::InvalidNativeCollation { collation })
+        }
+    }
+
+    /// Parse the string form a `collation_needed` handler replies with.
+    fn parse_name(name: &str) -> Option<Self> {
+        match name {
+            "unicode_nocase" => Some(Self::UnicodeNocase),
+            "natural" => Some(Self::Natural),
+            "no_accents" => Some(Self::NoAccents),
+            _ => None,
+        }
+    }
+
+    fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            Self::UnicodeNocase => a
+                .chars()
+                .flat_map(char::to_lowercase)
+                .cmp(b.chars().flat_map(char::to_lowercase)),
+            Self::Natural => natural_cmp(a, b),
+            Self::NoAccents => fold_accents(a).cmp(&fold_accents(b)),
+        }
+    }
+}
+
+/// Register a native collation under `name`, replacing any previous
+/// collation of that name. Callers must hold the connection Mutex.
+pub(crate) fn register_native(
+    conn: &Connection,
+    name: &str,
+    native: Native,
+) -> Result<(), XqliteError> {
+    conn.create_collation(name, move |a, b| native.compare(a, b))?;
+    Ok(())
+}
+
+/// Register a collation answered by an Elixir handler. Callers must
+/// hold the connection Mutex.
+pub(crate) fn register_elixir(
+    conn: &Connection,
+    name: &str,
+    handler: Handler,
+) -> Result<(), XqliteError> {
+    let coll_name = name.to_string();
+    conn.create_collation(name, move |a, b| {
+        let reply = function::call(&handler, &coll_name, |env, call_id| {
+            Ok((
+                atoms::xqlite_collation_call(),
+                call_id,
+                coll_name.as_str(),
+                a,
+                b,
+            )
+                .encode(env))
+        });
+        match reply {
+            Ok(Value::Integer(n)) => n.cmp(&0),
+            // See the module doc: a comparator has no error channel.
+            _ => a.cmp(b),
+        }
+    })?;
+    Ok(())
+}
+
+/// Remove a collation. Statements already prepared against it keep
+/// working; new ones fail with "no such collation sequence".
+pub(crate) fn unregister(conn: &Connection, name: &str) -> Result<(), XqliteError> {
+    conn.remove_collation(name)?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// collation_needed hook
+// ---------------------------------------------------------------------------
+
+/// State behind the `collation_needed` C callback: where to ask, and
+/// how long to wait.
+#[derive(Debug)]
+pub(crate) struct CollationResolver {
+    handler: Handler,
+}
+
+/// Install (or replace) the `collation_needed` hook. Callers must hold
+/// the connection Mutex.
+pub(crate) fn set_needed_hook(
+    conn: &Connection,
+    slot: &AtomicPtr<CollationResolver>,
+    handler: Handler,
+) -> Result<(), XqliteError> {
+    hook_util::install_hook(slot, CollationResolver { handler }, |new_ptr| {
+        // SAFETY: caller holds the connection Mutex; `new_ptr` stays valid
+        // until a later install/uninstall swaps it out after re-registering.
+        let rc = unsafe {
+            ffi::sqlite3_collation_needed(
+                conn.handle(),
+                new_ptr as *mut c_void,
+                Some(collation_needed_callback),
+            )
+        };
+        rc_to_result(conn, rc)
+    })
+}
+
+/// Remove the `collation_needed` hook. Idempotent. Callers must hold
+/// the connection Mutex.
+pub(crate) fn remove_needed_hook(
+    conn: &Connection,
+    slot: &AtomicPtr<CollationResolver>,
+) -> Result<(), XqliteError> {
+    hook_util::uninstall_hook(slot, || {
+        // SAFETY: caller holds the connection Mutex. A None callback clears
+        // the registration; calling it with none installed is valid.
+        let rc = unsafe {
+            ffi::sqlite3_collation_needed(conn.handle(), std::ptr::null_mut(), None)
+        };
+        rc_to_result(conn, rc)
+    })
+}
+
+fn rc_to_result(conn: &Connection, rc: c_int) -> Result<(), XqliteError> {
+    if rc == ffi::SQLITE_OK {
+        return Ok(());
+    }
+    // SAFETY: callers hold the connection Mutex; the errmsg pointer is
+    // read before any other call on this handle.
+    let msg = unsafe {
+        let ptr = ffi::sqlite3_errmsg(conn.handle());
+        if ptr.is_null() {
+            format!("sqlite3_collation_needed failed (code {rc})")
+        } else {
+            CStr::from_ptr(ptr).to_string_lossy().into_owned()
+        }
+    };
+    Err(XqliteError::from(rusqlite::Error::SqliteFailure(
+        ffi::Error::new(rc),
+        Some(msg),
+    )))
+}
+
+/// C callback invoked while a statement is prepared against a collation
+/// the connection does not know.
+///
+/// Sends `{:xqlite_collation_needed, call_id, name}` and waits for the
+/// handler's reply: `nil` leaves the collation unknown (the prepare
+/// then fails as usual), a native collation's name registers that
+/// comparator, and `true` registers an Elixir collation served by the
+/// hook's own handler. Any failure leaves the collation unknown.
+///
+/// # Safety
+///
+/// `user_data` is the `*const CollationResolver` registered by
+/// `set_needed_hook`; the slot keeps it alive until the hook is
+/// re-registered or cleared, both of which need the connection Mutex
+/// this callback's caller holds.
+unsafe extern "C" fn collation_needed_callback(
+    user_data: *mut c_void,
+    db: *mut ffi::sqlite3,
+    _e_text_rep: c_int,
+    name: *const c_char,
+) {
+    hook_util::guard_ffi_callback("collation_needed_callback", 0, || {
+        if user_data.is_null() || name.is_null() {
+            return 0;
+        }
+        // SAFETY: see the function-level contract; `name` is a
+        // NUL-terminated string owned by SQLite for this call.
+        let (resolver, name) = unsafe {
+            (
+                &*(user_data as *const CollationResolver),
+                CStr::from_ptr(name).to_string_lossy().into_owned(),
+            )
+        };
+        let reply = function::call(&resolver.handler, &name, |env, call_id| {
+            Ok((atoms::xqlite_collation_needed(), call_id, name.as_str()).encode(env))
+        });
+        // SAFETY: `db` is the live handle SQLite invoked us for; the
+        // borrowed (non-owning) Connection is dropped before returning.
+        let Ok(conn) = (unsafe { Connection::from_handle(db) }) else {
+            return 0;
+        };
+        // Registration failures leave the collation unknown, which SQLite
+        // reports on its own once we return.
+        let _ = match reply {
+            Ok(Value::Text(native)) => match Native::parse_name(&native) {
+                Some(native) => register_native(&conn, &name, native),
+                None => Ok(()),
+            },
+            Ok(Value::Integer(1)) => register_elixir(&conn, &name, resolver.handler.clone()),
+            _ => Ok(()),
+        };
+        0
+    });
+}
+
+// ---------------------------------------------------------------------------
+// Native comparators
+// ---------------------------------------------------------------------------
+
+/// Natural order: maximal ASCII digit runs compare numerically, all other
+/// bytes compare as-is (UTF-8 byte order is code point order). Strings
+/// equal under that rule are ordered bytewise, so `a01` and `a1` stay
+/// distinct and the order stays total.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (x, y) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+    while i < x.len() && j < y.len() {
+        if x[i].is_ascii_digit() && y[j].is_ascii_digit() {
+            let (run_x, next_i) = digit_run(x, i);
+            let (run_y, next_j) = digit_run(y, j);
+            let ord = run_x.len().cmp(&run_y.len()).then_with(|| run_x.cmp(run_y));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+            (i, j) = (next_i, next_j);
+        } else {
+            let ord = x[i].cmp(&y[j]);
+            if ord != Ordering::Equal {
+                return ord;
+            }
+            (i, j) = (i + 1, j + 1);
+        }
+    }
+    (x.len() - i).cmp(&(y.len() - j)).then_with(|| x.cmp(y))
+}
+
+/// The digit run starting at `start` with leading zeros stripped, and the
+/// index just past it.
+fn digit_run(s: &[u8], start: usize) -> (&[u8], usize) {
+    let end = s[start..]
+        .iter()
+        .position(|c| !c.is_ascii_digit())
+        .map_or(s.len(), |n| start + n);
+    let first_significant = s[start..end]
+        .iter()
+        .position(|&c| c != b'0')
+        .map_or(end, |n| start + n);
+    (&s[first_significant..end], end)
+}
+
+/// Base letters for U+00C0..=U+00FF. `?` marks code points expanded by
+/// `fold_ligature`; `×` and `÷` are not letters and map to themselves.
+const LATIN_1: &str = "AAAAAA?CEEEEIIIIDNOOOOO×OUUUUY??aaaaaa?ceeeeiiiidnooooo÷ouuuuy?y";
+
+/// Base letters for Latin Extended-A, U+0100..=U+017F.
+const LATIN_EXT_A: &str = concat!(
+    "AaAaAaCcCcCcCcDdDdEeEeEeEeEeGgGg",
+    "GgGgHhHhIiIiIiIiIi??JjKkkLlLlLlL",
+    "lLlNnNnNnnNnOoOoOo??RrRrRrSsSsSs",
+    "SsTtTtTtUuUuUuUuUuUuWwYyYZzZzZzs",
+);
+
+fn fold_ligature(c: char) -> Option<&'static str> {
+    Some(match c {
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Þ' => "TH",
+        'þ' => "th",
+        'ß' => "ss",
+        'Ĳ' => "IJ",
+        'ĳ' => "ij",
+        'Œ' => "OE",
+        'œ' => "oe",
+        _ => return None,
+    })
+}
+
+fn fold_accents(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if let Some(expanded) = fold_ligature(c) {
+            out.push_str(expanded);
+            continue;
+        }
+        let base = match c as u32 {
+            // Combining diacritical marks (decomposed input).
+            0x0300..=0x036F => continue,
+            cp @ 0x00C0..=0x00FF => LATIN_1.chars().nth((cp - 0x00C0) as usize),
+            cp @ 0x0100..=0x017F => LATIN_EXT_A.chars().nth((cp - 0x0100) as usize),
+            _ => None,
+        };
+        out.push(base.unwrap_or(c));
+    }
+    out
+}
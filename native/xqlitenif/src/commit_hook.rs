@@ -9,6 +9,7 @@
 
 use crate::error::XqliteError;
 use crate::hook_util::{self, HookList};
+use crate::update_hook::{self, UpdateHooks};
 use rustler::sys::{enif_alloc_env, enif_free_env, enif_make_tuple_from_array, enif_send};
 use rustler::types::LocalPid;
 use std::sync::Arc;
@@ -30,11 +31,19 @@ impl CommitSubscriber {
     }
 }
 
+/// Install the master commit-hook closure. It also ends the
+/// transaction for batch-mode update subscribers: their buffered row
+/// events are delivered to them (see `update_hook::flush_batches`).
+/// The commit hook runs when COMMIT starts, before the commit is
+/// durable, so a COMMIT that fails afterwards may still have delivered
+/// them.
 pub(crate) fn install_callback(
     conn: &rusqlite::Connection,
     list: Arc<HookList<CommitSubscriber>>,
+    updates: Arc<UpdateHooks>,
 ) -> Result<(), XqliteError> {
     conn.commit_hook(Some(move || {
+        update_hook::flush_batches(&updates);
         // SAFETY: closure-captured Arc keeps the list alive across calls.
         unsafe {
             list.for_each_snapshot(|entry| {
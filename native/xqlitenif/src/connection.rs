@@ -1,11 +1,16 @@
 use crate::atoms;
+use crate::authorizer::AuthorizerSlot;
 use crate::busy_handler::BusySlotState;
+use crate::collation::CollationResolver;
 use crate::commit_hook::{self, CommitSubscriber};
 use crate::error::XqliteError;
 use crate::hook_util::{self, HookList};
+use crate::preupdate_hook::PreupdateSubscriber;
 use crate::progress_dispatch::{self, ProgressDispatch};
 use crate::rollback_hook::{self, RollbackSubscriber};
-use crate::update_hook::{self, UpdateSubscriber};
+use crate::statement_cache::StatementCache;
+use crate::trace_hook::{self, TraceDispatch};
+use crate::update_hook::{self, UpdateHooks};
 use crate::util::encode_text;
 use crate::wal_hook::{self, WalDispatch};
 use rusqlite::{Connection, Error as RusqliteError};
@@ -14,6 +19,7 @@ use std::sync::Arc;
 use std::sync::Mutex;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::AtomicUsize;
 
 #[derive(Debug)]
 pub(crate) struct XqliteConn {
@@ -25,6 +31,10 @@ pub(crate) struct XqliteConn {
     // serving both halves. Installed lazily, removed when both empty.
     pub(crate) busy_handler: AtomicPtr<BusySlotState>,
 
+    // Single-slot resolver for SQLite's `collation_needed` callback;
+    // same AtomicPtr lifecycle as `busy_handler`.
+    pub(crate) collation_needed: AtomicPtr<CollationResolver>,
+
     // Multi-subscriber per-connection hook lists. Each holds N
     // `HookEntry<T>`s, one per registered subscriber. A master closure
     // (or C callback for FFI hooks) is installed exactly once at open
@@ -39,10 +49,30 @@ pub(crate) struct XqliteConn {
     // The master callback is re-installed by the `set_pragma` NIF when
     // the `wal_autocheckpoint` PRAGMA steals the slot.
     pub(crate) wal_hook: WalDispatch,
-    pub(crate) update_hook: Arc<HookList<UpdateSubscriber>>,
     pub(crate) commit_hook: Arc<HookList<CommitSubscriber>>,
     pub(crate) rollback_hook: Arc<HookList<RollbackSubscriber>>,
 
+    // `sqlite3_trace_v2` takes a bare C callback, so like `wal_hook` the
+    // dispatch is owned directly and its address is the callback context.
+    // SQLite is armed with the union of the subscriber event masks. It
+    // also holds the update hook's subscribers (`update_hooks`), whose
+    // batch mode needs trace events armed; the update, commit and
+    // rollback closures capture clones of that Arc.
+    pub(crate) trace_hook: TraceDispatch,
+
+    // Unlike the lists above, the preupdate master closure is installed
+    // only while the list is non-empty: SQLite's preupdate slot is shared
+    // with the session extension, so it must stay free whenever a
+    // session is attached. `live_sessions` counts those sessions; both
+    // are read and written under the connection Mutex.
+    pub(crate) preupdate_hook: Arc<HookList<PreupdateSubscriber>>,
+    pub(crate) live_sessions: AtomicUsize,
+
+    // The authorizer slot: a single-slot policy plus audit observers,
+    // served by one rusqlite closure that is reinstalled whenever either
+    // half changes and removed when both are empty (see `authorizer`).
+    pub(crate) authorizer: AuthorizerSlot,
+
     /// Multi-subscriber dispatch on SQLite's single
     /// `sqlite3_progress_handler` slot. Owned directly (no box
     /// indirection); its address is stable for the lifetime of the
@@ -50,6 +80,11 @@ pub(crate) struct XqliteConn {
     /// Holds two `HookList`s — `cancels` (cancellable-query lifetime)
     /// and `ticks` (per-conn, registered via `register_progress_hook`).
     pub(crate) progress_dispatch: ProgressDispatch,
+
+    /// Hit / miss / eviction bookkeeping for rusqlite's prepared
+    /// statement cache, which `query` / `execute` prepare through. Read
+    /// and written under the connection Mutex, except `connection_stats`.
+    pub(crate) statement_cache: StatementCache,
 }
 
 #[resource_impl]
@@ -60,9 +95,10 @@ impl Drop for XqliteConn {
         // Field declaration order ensures `conn` (the SQLite Connection)
         // drops first, so no callback can fire while we reclaim
         // subscriber state below. Each HookList<T> reclaims its own
-        // box via its Drop impl; busy_handler is the only remaining
-        // boxed-pointer slot we manage explicitly.
+        // box via its Drop impl; busy_handler and collation_needed are
+        // the boxed-pointer slots we manage explicitly.
         hook_util::drop_hook(&self.busy_handler);
+        hook_util::drop_hook(&self.collation_needed);
     }
 }
 
@@ -119,7 +155,7 @@ pub(crate) fn handle_open_result(
 ) -> Result<ResourceArc<XqliteConn>, XqliteError> {
     match open_result {
         Ok(conn) => {
-            let update_hook_list = Arc::new(HookList::new());
+            let update_hooks = Arc::new(UpdateHooks::default());
             let commit_hook_list = Arc::new(HookList::new());
             let rollback_hook_list = Arc::new(HookList::new());
 
@@ -127,20 +163,26 @@ pub(crate) fn handle_open_result(
                 conn: Mutex::new(Some(conn)),
                 extensions_enabled: AtomicBool::new(false),
                 busy_handler: AtomicPtr::new(std::ptr::null_mut()),
+                collation_needed: AtomicPtr::new(std::ptr::null_mut()),
                 wal_hook: WalDispatch::new(),
-                update_hook: Arc::clone(&update_hook_list),
                 commit_hook: Arc::clone(&commit_hook_list),
                 rollback_hook: Arc::clone(&rollback_hook_list),
+                trace_hook: TraceDispatch::new(Arc::clone(&update_hooks)),
+                preupdate_hook: Arc::new(HookList::new()),
+                live_sessions: AtomicUsize::new(0),
+                authorizer: AuthorizerSlot::new(),
                 progress_dispatch: ProgressDispatch::new(),
+                statement_cache: StatementCache::new(),
             });
 
             // Install master callbacks for every multi-subscriber hook.
             // Each is registered exactly once for the connection's
             // lifetime; subscriber-level register/unregister never
-            // touches SQLite again.
+            // touches SQLite again, except that the trace hook re-arms
+            // its event mask.
             //
-            // SAFETY for the FFI hooks (wal, progress): the WalDispatch
-            // / ProgressDispatch references are taken from inside the
+            // SAFETY for the FFI hooks (wal, progress, trace): the
+            // WalDispatch / ProgressDispatch / TraceDispatch references are taken from inside the
             // ResourceArc, so they live as long as `handle`. The conn
             // (and any in-flight callback) drops before subscriber
             // state via field declaration order.
@@ -160,10 +202,19 @@ pub(crate) fn handle_open_result(
                             &handle.progress_dispatch,
                         );
                         wal_hook::install_callback(conn_ref, &handle.wal_hook);
+                        trace_hook::install_callback(conn_ref, &handle.trace_hook);
                     }
-                    update_hook::install_callback(conn_ref, update_hook_list)?;
-                    commit_hook::install_callback(conn_ref, commit_hook_list)?;
-                    rollback_hook::install_callback(conn_ref, rollback_hook_list)?;
+                    update_hook::install_callback(conn_ref, Arc::clone(&update_hooks))?;
+                    commit_hook::install_callback(
+                        conn_ref,
+                        commit_hook_list,
+                        Arc::clone(&update_hooks),
+                    )?;
+                    rollback_hook::install_callback(
+                        conn_ref,
+                        rollback_hook_list,
+                        Arc::clone(&update_hooks),
+                    )?;
                 }
             }
             Ok(handle)
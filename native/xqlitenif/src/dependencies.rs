@@ -0,0 +1,166 @@
+//! Static read/write sets of a statement, for cache keys and invalidation.
+//!
+//! The statement is prepared but never run. Its compiled program is handed
+//! to SQLite's `tables_used()` table-valued function (part of
+//! `SQLITE_ENABLE_BYTECODE_VTAB`, which the bundled build enables), which
+//! lists every b-tree the program opens and whether it opens it for
+//! writing. Views are already expanded into their base tables by then, and
+//! trigger and foreign-key action programs are walked as well, so tables
+//! reached through either show up alongside the statement's own.
+//!
+//! A query answered from a covering index never opens its table, yet its
+//! result still depends on the table's rows; every index listed therefore
+//! also counts its owning table as read or written.
+
+use crate::atoms;
+use crate::error::XqliteError;
+use crate::explain_analyze::{self, column_text, ffi_error, prepare_first};
+use rusqlite::{Connection, OptionalExtension, ffi};
+use rustler::types::map::map_new;
+use rustler::{Encoder, Env, Term};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::CStr;
+
+/// Tables and indexes one statement reads and writes, keyed by schema
+/// name (`"main"`, `"temp"`, or an attached database).
+#[derive(Default)]
+pub struct Dependencies {
+    schemas: BTreeMap<String, SchemaDependencies>,
+}
+
+#[derive(Default)]
+struct SchemaDependencies {
+    reads: BTreeSet<String>,
+    writes: BTreeSet<String>,
+    index_reads: BTreeSet<String>,
+    index_writes: BTreeSet<String>,
+}
+
+impl Encoder for SchemaDependencies {
+    fn encode<'b>(&self, env: Env<'b>) -> Term<'b> {
+        let list = |set: &BTreeSet<String>| set.iter().collect::<Vec<_>>().encode(env);
+        let built = map_new(env)
+            .map_put(atoms::reads().encode(env), list(&self.reads))
+            .and_then(|m| m.map_put(atoms::writes().encode(env), list(&self.writes)))
+            .and_then(|m| m.map_put(atoms::index_reads().encode(env), list(&self.index_reads)))
+            .and_then(|m| {
+                m.map_put(atoms::index_writes().encode(env), list(&self.index_writes))
+            });
+
+        explain_analyze::map_or_encoding_error(env, built, "schema dependencies")
+    }
+}
+
+impl Encoder for Dependencies {
+    fn encode<'b>(&self, env: Env<'b>) -> Term<'b> {
+        let built = self
+            .schemas
+            .iter()
+            .try_fold(map_new(env), |m, (schema, deps)| {
+                m.map_put(schema.as_str().encode(env), deps.encode(env))
+            });
+
+        explain_analyze::map_or_encoding_error(env, built, "statement dependencies")
+    }
+}
+
+/// Read and write sets of the first statement in `sql`. Whitespace- or
+/// comment-only SQL depends on nothing.
+pub fn statement_dependencies(
+    conn: &Connection,
+    sql: &str,
+) -> Result<Dependencies, XqliteError> {
+    // SAFETY: `with_conn` at the NIF boundary holds the connection Mutex for
+    // the duration of this call; the statement is finalized before return.
+    unsafe {
+        let db_handle = conn.handle();
+        let Some(stmt_ptr) = prepare_first(db_handle, sql)? else {
+            return Ok(Dependencies::default());
+        };
+        let result = collect_tables_used(stmt_ptr.as_ptr(), db_handle);
+        ffi::sqlite3_finalize(stmt_ptr.as_ptr());
+        let mut deps = result?;
+        add_index_tables(conn, &mut deps)?;
+        Ok(deps)
+    }
+}
+
+/// Count each listed index's table as read or written along with it.
+fn add_index_tables(conn: &Connection, deps: &mut Dependencies) -> Result<(), XqliteError> {
+    for (schema, entry) in &mut deps.schemas {
+        let lookup = format!(
+            "SELECT tbl_name FROM \"{}\".sqlite_schema WHERE type = 'index' AND name = ?1",
+            schema.replace('"', "\"\"")
+        );
+        let mut stmt = conn.prepare(&lookup)?;
+        let mut table_of = |index: &String| -> Result<Option<String>, XqliteError> {
+            Ok(stmt.query_row([index], |row| row.get(0)).optional()?)
+        };
+        for index in &entry.index_reads {
+            if let Some(table) = table_of(index)? {
+                entry.reads.insert(table);
+            }
+        }
+        for index in &entry.index_writes {
+            if let Some(table) = table_of(index)? {
+                entry.writes.insert(table);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// # Safety
+/// `stmt_ptr` must be a live statement of `db_handle`, Mutex held.
+unsafe fn collect_tables_used(
+    stmt_ptr: *mut ffi::sqlite3_stmt,
+    db_handle: *mut ffi::sqlite3,
+) -> Result<Dependencies, XqliteError> {
+    const LISTING: &CStr = c"SELECT type, schema, name, wr FROM tables_used(?1)";
+
+    let mut listing: *mut ffi::sqlite3_stmt = std::ptr::null_mut();
+    // SAFETY: `db_handle` is valid and the Mutex is held (fn contract). The
+    // listing statement is finalized on every path below.
+    unsafe {
+        let rc = ffi::sqlite3_prepare_v2(
+            db_handle,
+            LISTING.as_ptr(),
+            -1,
+            &mut listing,
+            std::ptr::null_mut(),
+        );
+        if rc != ffi::SQLITE_OK {
+            return Err(ffi_error(db_handle, rc));
+        }
+        ffi::sqlite3_bind_pointer(listing, 1, stmt_ptr.cast(), c"stmt-pointer".as_ptr(), None);
+
+        let mut deps = Dependencies::default();
+        loop {
+            match ffi::sqlite3_step(listing) {
+                ffi::SQLITE_ROW => {
+                    let is_index = column_text(listing, 0).as_deref() == Some("index");
+                    let schema = column_text(listing, 1).unwrap_or_default();
+                    let name = column_text(listing, 2).unwrap_or_default();
+                    let write = ffi::sqlite3_column_int64(listing, 3) != 0;
+
+                    let entry = deps.schemas.entry(schema).or_default();
+                    let set = match (is_index, write) {
+                        (false, false) => &mut entry.reads,
+                        (false, true) => &mut entry.writes,
+                        (true, false) => &mut entry.index_reads,
+                        (true, true) => &mut entry.index_writes,
+                    };
+                    set.insert(name);
+                }
+                ffi::SQLITE_DONE => break,
+                rc => {
+                    let err = ffi_error(db_handle, rc);
+                    ffi::sqlite3_finalize(listing);
+                    return Err(err);
+                }
+            }
+        }
+        ffi::sqlite3_finalize(listing);
+        Ok(deps)
+    }
+}
@@ -1,5 +1,6 @@
 use crate::atoms;
 use crate::constraint_parse::{self, ConstraintDetails};
+use crate::function::{self, FailureKind};
 use rusqlite::{Error as RusqliteError, ffi};
 use rustler::{
     Atom, Encoder, Env, Term, TermType,
@@ -117,11 +118,37 @@ pub(crate) enum XqliteError {
         expected: usize,
     },
     InvalidParameterName(String),
+    InvalidParameterIndex(i64),
     InvalidPragmaName(String),
     InvalidTransactionMode,
     InvalidAuthorizerAction {
         action: Atom,
     },
+    InvalidAuthorizerRule {
+        rule: String,
+    },
+    InvalidFunctionFlag {
+        flag: Atom,
+    },
+    InvalidBuiltinAggregate {
+        builtin: Atom,
+    },
+    InvalidNativeCollation {
+        collation: Atom,
+    },
+    InvalidUpdateHookOption {
+        option: Atom,
+    },
+    InvalidTraceEvent {
+        event: Atom,
+    },
+    InvalidConflictStrategy,
+    InvalidExplainMode {
+        mode: Atom,
+    },
+    InvalidChangesetApplyOption {
+        option: Atom,
+    },
     NulErrorInString,
     MultipleStatements,
 
@@ -132,6 +159,10 @@ pub(crate) enum XqliteError {
         message: String,
     },
     LockError(String),
+    CannotOpenFile {
+        path: String,
+        reason: String,
+    },
 
     // Statement / Execution Errors
     SqlInputError {
@@ -182,6 +213,21 @@ pub(crate) enum XqliteError {
         extended_code: i32,
         message: String,
     },
+    UserFunctionFailed {
+        // SQLITE_ERROR raised by an Elixir-backed user function; rebuilt
+        // from the failure its callback recorded (see
+        // `function::failure_to_sqlite`)
+        function: String,
+        kind: FailureKind,
+        message: String,
+    },
+    ChangesetHandlerFailed {
+        // A conflict or table-filter handler of `changeset_apply/4` failed
+        // (the apply was aborted and rolled back), or a changeset chunk
+        // receiver did (the output was cut short)
+        kind: FailureKind,
+        message: String,
+    },
 
     // Row / Column Errors
     InvalidColumnIndex(usize),
@@ -212,6 +258,12 @@ pub(crate) enum XqliteError {
         details: Box<ConstraintDetails>,
     },
 
+    // One parameter set of `execute_many` failed; `index` is zero-based
+    RowFailed {
+        index: usize,
+        error: Box<XqliteError>,
+    },
+
     // Generic Fallback
     SqliteFailure {
         code: i32,
@@ -232,6 +284,9 @@ pub(crate) enum XqliteError {
     // Connection state
     ConnectionClosed,
     StatementFinalized,
+    /// The preupdate hook and the session extension share one SQLite
+    /// slot; the other user currently holds it.
+    PreupdateHookConflict,
 
     // Internal
     InternalEncodingError {
@@ -329,6 +384,17 @@ impl Display for XqliteError {
             } => {
                 write!(f, "Authorization denied: {message}")
             }
+            XqliteError::UserFunctionFailed {
+                function,
+                kind,
+                message,
+            } => write!(f, "User function '{function}' failed ({kind:?}): {message}"),
+            XqliteError::ChangesetHandlerFailed { kind, message } => {
+                write!(f, "Changeset handler failed ({kind:?}): {message}")
+            }
+            XqliteError::RowFailed { index, error } => {
+                write!(f, "Parameter set {index} failed: {error}")
+            }
             XqliteError::CannotOpenDatabase {
                 path,
                 code,
@@ -342,6 +408,9 @@ impl Display for XqliteError {
             XqliteError::LockError(reason) => {
                 write!(f, "Failed to lock connection mutex: {reason}")
             }
+            XqliteError::CannotOpenFile { path, reason } => {
+                write!(f, "Cannot open file '{path}': {reason}")
+            }
             XqliteError::InvalidStreamHandle { reason } => {
                 write!(f, "Invalid stream handle: {reason}")
             }
@@ -351,6 +420,12 @@ impl Display for XqliteError {
             XqliteError::StatementFinalized => {
                 write!(f, "Statement is already finalized")
             }
+            XqliteError::PreupdateHookConflict => {
+                write!(
+                    f,
+                    "Preupdate hooks and sessions cannot be used on the same connection at once"
+                )
+            }
             XqliteError::InternalEncodingError { context } => {
                 write!(f, "Internal error during result encoding: {context}")
             }
@@ -361,6 +436,9 @@ impl Display for XqliteError {
             XqliteError::InvalidParameterName(name) => {
                 write!(f, "Invalid parameter name: '{name}'")
             }
+            XqliteError::InvalidParameterIndex(index) => {
+                write!(f, "Invalid parameter index: {index}")
+            }
             XqliteError::InvalidPragmaName(name) => {
                 write!(f, "Invalid pragma name: '{name}'")
             }
@@ -373,6 +451,57 @@ impl Display for XqliteError {
             XqliteError::InvalidAuthorizerAction { action: _ } => {
                 write!(f, "Invalid authorizer action atom")
             }
+            XqliteError::InvalidAuthorizerRule { rule } => {
+                write!(
+                    f,
+                    "Invalid authorizer rule: {rule}. Expected {{outcome, actions}} or {{outcome, actions, matchers}} with outcome :allow, :deny or :ignore"
+                )
+            }
+            XqliteError::InvalidBuiltinAggregate { builtin: _ } => {
+                write!(f, "Invalid built-in aggregate. Allowed: :weighted_median")
+            }
+            XqliteError::InvalidNativeCollation { collation: _ } => {
+                write!(
+                    f,
+                    "Invalid native collation. Allowed: :unicode_nocase, :natural, :no_accents"
+                )
+            }
+            XqliteError::InvalidFunctionFlag { flag: _ } => {
+                write!(
+                    f,
+                    "Invalid function flag. Allowed: :deterministic, :direct_only, :innocuous"
+                )
+            }
+            XqliteError::InvalidTraceEvent { event: _ } => {
+                write!(
+                    f,
+                    "Invalid trace event. Allowed: :stmt, :profile, :row, :close"
+                )
+            }
+            XqliteError::InvalidUpdateHookOption { option: _ } => {
+                write!(
+                    f,
+                    "Invalid update hook option. Allowed: :batch (boolean), :tables (list of strings), :actions (list of :insert, :update, :delete)"
+                )
+            }
+            XqliteError::InvalidExplainMode { mode: _ } => {
+                write!(
+                    f,
+                    "Invalid explain mode. Allowed: :none, :explain, :query_plan"
+                )
+            }
+            XqliteError::InvalidConflictStrategy => {
+                write!(
+                    f,
+                    "Invalid conflict strategy. Allowed: :omit, :replace, :abort, or a handler pid"
+                )
+            }
+            XqliteError::InvalidChangesetApplyOption { option: _ } => {
+                write!(
+                    f,
+                    "Invalid changeset apply option. Allowed: :tables and :exclude_tables (lists of strings), :table_filter (pid), :timeout (positive integer), :rebase (boolean)"
+                )
+            }
             XqliteError::NulErrorInString => {
                 write!(f, "Input string contains embedded null byte")
             }
@@ -506,6 +635,23 @@ impl Encoder for XqliteError {
                 extended_code,
                 message,
             } => (atoms::authorization_denied(), extended_code, message).encode(env),
+            XqliteError::UserFunctionFailed {
+                function,
+                kind,
+                message,
+            } => (
+                atoms::user_function_failed(),
+                function,
+                kind.to_atom(),
+                message,
+            )
+                .encode(env),
+            XqliteError::ChangesetHandlerFailed { kind, message } => {
+                (atoms::changeset_handler_failed(), kind.to_atom(), message).encode(env)
+            }
+            XqliteError::RowFailed { index, error } => {
+                (atoms::row_failed(), index, error.as_ref()).encode(env)
+            }
             XqliteError::CannotOpenDatabase {
                 path,
                 code,
@@ -515,10 +661,14 @@ impl Encoder for XqliteError {
                 (atoms::cannot_convert_atom_to_string(), reason).encode(env)
             }
             XqliteError::LockError(reason) => (atoms::lock_error(), reason).encode(env),
+            XqliteError::CannotOpenFile { path, reason } => {
+                (atoms::cannot_open_file(), path, reason).encode(env)
+            }
             XqliteError::InvalidStreamHandle { reason } => {
                 (atoms::invalid_stream_handle(), reason).encode(env)
             }
             XqliteError::ConnectionClosed => atoms::connection_closed().encode(env),
+            XqliteError::PreupdateHookConflict => atoms::preupdate_hook_conflict().encode(env),
             XqliteError::StatementFinalized => atoms::statement_finalized().encode(env),
             XqliteError::InternalEncodingError { context } => {
                 (atoms::internal_encoding_error(), context).encode(env)
@@ -540,6 +690,9 @@ impl Encoder for XqliteError {
             XqliteError::InvalidParameterName(name) => {
                 (atoms::invalid_parameter_name(), name).encode(env)
             }
+            XqliteError::InvalidParameterIndex(index) => {
+                (atoms::invalid_parameter_index(), index).encode(env)
+            }
             XqliteError::InvalidPragmaName(name) => {
                 (atoms::invalid_pragma_name(), name).encode(env)
             }
@@ -549,6 +702,33 @@ impl Encoder for XqliteError {
             XqliteError::InvalidAuthorizerAction { action } => {
                 (atoms::invalid_authorizer_action(), *action).encode(env)
             }
+            XqliteError::InvalidAuthorizerRule { rule } => {
+                (atoms::invalid_authorizer_rule(), rule).encode(env)
+            }
+            XqliteError::InvalidBuiltinAggregate { builtin } => {
+                (atoms::invalid_builtin_aggregate(), *builtin).encode(env)
+            }
+            XqliteError::InvalidFunctionFlag { flag } => {
+                (atoms::invalid_function_flag(), *flag).encode(env)
+            }
+            XqliteError::InvalidNativeCollation { collation } => {
+                (atoms::invalid_native_collation(), *collation).encode(env)
+            }
+            XqliteError::InvalidUpdateHookOption { option } => {
+                (atoms::invalid_update_hook_option(), *option).encode(env)
+            }
+            XqliteError::InvalidTraceEvent { event } => {
+                (atoms::invalid_trace_event(), *event).encode(env)
+            }
+            XqliteError::InvalidConflictStrategy => {
+                atoms::invalid_conflict_strategy().encode(env)
+            }
+            XqliteError::InvalidExplainMode { mode } => {
+                (atoms::invalid_explain_mode(), *mode).encode(env)
+            }
+            XqliteError::InvalidChangesetApplyOption { option } => {
+                (atoms::invalid_changeset_apply_option(), *option).encode(env)
+            }
             XqliteError::NulErrorInString => atoms::null_byte_in_string().encode(env),
             XqliteError::MultipleStatements => atoms::multiple_statements().encode(env),
             XqliteError::InvalidColumnIndex(index) => {
@@ -681,9 +861,25 @@ pub(crate) fn is_sqlite_auth(err: &RusqliteError) -> bool {
 }
 
 fn classify_sqlite_error(ffi_err: ffi::Error, message_string: String) -> XqliteError {
-    let lower_msg = message_string.to_lowercase();
     let primary_code = ffi_err.extended_code & 0xFF;
 
+    // An Elixir-backed user function failed. Its callback, run by the step
+    // that raised this SQLITE_ERROR on this thread, recorded the failure
+    // (see `function::failure_to_sqlite`). The record is taken on every
+    // classification, and only an error SQLite raised for it matches, so
+    // SQL that merely says the same thing is not mistaken for one.
+    if let Some(failure) = function::take_failure(&message_string)
+        && primary_code == ffi::SQLITE_ERROR
+    {
+        return XqliteError::UserFunctionFailed {
+            function: failure.function,
+            kind: failure.kind,
+            message: failure.message,
+        };
+    }
+
+    let lower_msg = message_string.to_lowercase();
+
     match primary_code {
         ffi::SQLITE_READONLY => XqliteError::ReadOnlyDatabase {
             extended_code: ffi_err.extended_code,
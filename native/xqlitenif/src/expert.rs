@@ -0,0 +1,371 @@
+//! Bindings to SQLite's index advisor, `ext/expert` (sqlite3expert), which
+//! build.rs compiles in from vendor/sqlite3expert.
+//!
+//! sqlite3expert copies the schema of the connection into private
+//! in-memory databases, so nothing is created on the caller's connection.
+//! Each workload statement is added with `sqlite3_expert_sql`, after which
+//! `sqlite3_expert_analyze` tries candidate indexes against all of them.
+//! No data is sampled (`EXPERT_CONFIG_SAMPLE` is set to 0), so the planner
+//! judges candidates by its default size estimates.
+//!
+//! Only the `CREATE INDEX` list of each statement is taken from its
+//! reports: the plan report keeps just the detail text of each row. Both
+//! plans are instead read from one schema-only copy of the connection
+//! made the way sqlite3expert makes its own (`plan_copy`), before and
+//! after creating the statement's indexes on it, so they compare like for
+//! like and keep their `id` / `parent` tree.
+
+use crate::error::XqliteError;
+use crate::explain_analyze;
+use crate::index_advisor::{self, IndexAdvice, StatementAdvice};
+use rusqlite::{Connection, ffi};
+use std::cmp::Ordering;
+use std::ffi::{CStr, CString, c_char, c_int};
+
+/// Opaque `sqlite3expert` handle.
+#[repr(C)]
+struct Sqlite3Expert {
+    _private: [u8; 0],
+}
+
+// From sqlite3expert.h.
+const EXPERT_CONFIG_SAMPLE: c_int = 1;
+const EXPERT_REPORT_SQL: c_int = 1;
+const EXPERT_REPORT_INDEXES: c_int = 2;
+
+unsafe extern "C" {
+    fn sqlite3_expert_new(
+        db: *mut ffi::sqlite3,
+        pz_err: *mut *mut c_char,
+    ) -> *mut Sqlite3Expert;
+    fn sqlite3_expert_config(p: *mut Sqlite3Expert, op: c_int, ...) -> c_int;
+    fn sqlite3_expert_sql(
+        p: *mut Sqlite3Expert,
+        z_sql: *const c_char,
+        pz_err: *mut *mut c_char,
+    ) -> c_int;
+    fn sqlite3_expert_analyze(p: *mut Sqlite3Expert, pz_err: *mut *mut c_char) -> c_int;
+    fn sqlite3_expert_count(p: *mut Sqlite3Expert) -> c_int;
+    fn sqlite3_expert_report(
+        p: *mut Sqlite3Expert,
+        i_stmt: c_int,
+        e_report: c_int,
+    ) -> *const c_char;
+    fn sqlite3_expert_destroy(p: *mut Sqlite3Expert);
+}
+
+/// What sqlite3expert calls instead of `sqlite3_open` (build.rs renames
+/// it) to open its private databases: the same, with foreign keys off.
+/// The bundled SQLite turns them on by default, and sqlite3expert's copy
+/// of a written table, made alone in `temp`, then fails to prepare writes
+/// for want of the parent table. The sqlite3 shell runs it with them off.
+///
+/// # Safety
+///
+/// Same contract as `sqlite3_open`.
+#[unsafe(no_mangle)]
+unsafe extern "C" fn xqlite_expert_open(
+    filename: *const c_char,
+    db: *mut *mut ffi::sqlite3,
+) -> c_int {
+    // SAFETY: forwarded unchanged per the fn contract.
+    let rc = unsafe { ffi::sqlite3_open(filename, db) };
+    if rc == ffi::SQLITE_OK {
+        // SAFETY: `*db` is the connection just opened; this option takes
+        // an int and an optional int out-pointer.
+        unsafe {
+            ffi::sqlite3_db_config(
+                *db,
+                ffi::SQLITE_DBCONFIG_ENABLE_FKEY,
+                0 as c_int,
+                std::ptr::null_mut::<c_int>(),
+            )
+        };
+    }
+    rc
+}
+
+/// Owns a `sqlite3expert` handle; destroyed on drop.
+struct Expert(*mut Sqlite3Expert);
+
+impl Expert {
+    /// # Safety
+    ///
+    /// `db` must be a valid connection handle that outlives the result,
+    /// used only by this thread (the connection Mutex is held) meanwhile.
+    unsafe fn new(db: *mut ffi::sqlite3) -> Result<Self, XqliteError> {
+        let mut err: *mut c_char = std::ptr::null_mut();
+        // SAFETY: `db` is valid per the fn contract; `err` is a valid
+        // out-pointer that sqlite3expert sets to a sqlite3_malloc'd string.
+        let handle = unsafe { sqlite3_expert_new(db, &mut err) };
+        if handle.is_null() {
+            // SAFETY: `err` is null or a sqlite3_malloc'd string.
+            return Err(unsafe { expert_error(ffi::SQLITE_ERROR, err) });
+        }
+        let expert = Self(handle);
+        // SAFETY: `handle` is live; EXPERT_CONFIG_SAMPLE takes one int.
+        let rc = unsafe { sqlite3_expert_config(expert.0, EXPERT_CONFIG_SAMPLE, 0 as c_int) };
+        if rc != ffi::SQLITE_OK {
+            // SAFETY: a null message is allowed.
+            return Err(unsafe { expert_error(rc, std::ptr::null_mut()) });
+        }
+        Ok(expert)
+    }
+
+    /// Add every statement in `sql` to the workload.
+    fn add(&mut self, sql: &str) -> Result<(), XqliteError> {
+        let c_sql = CString::new(sql).map_err(|_| XqliteError::NulErrorInString)?;
+        let mut err: *mut c_char = std::ptr::null_mut();
+        // SAFETY: `self.0` is a live handle; `c_sql` outlives the call.
+        let rc = unsafe { sqlite3_expert_sql(self.0, c_sql.as_ptr(), &mut err) };
+        if rc != ffi::SQLITE_OK {
+            // SAFETY: `err` is null or a sqlite3_malloc'd string.
+            return Err(unsafe { expert_error(rc, err) });
+        }
+        Ok(())
+    }
+
+    fn analyze(&mut self) -> Result<(), XqliteError> {
+        let mut err: *mut c_char = std::ptr::null_mut();
+        // SAFETY: `self.0` is a live handle.
+        let rc = unsafe { sqlite3_expert_analyze(self.0, &mut err) };
+        if rc != ffi::SQLITE_OK {
+            // SAFETY: `err` is null or a sqlite3_malloc'd string.
+            return Err(unsafe { expert_error(rc, err) });
+        }
+        Ok(())
+    }
+
+    fn count(&self) -> c_int {
+        // SAFETY: `self.0` is a live handle.
+        unsafe { sqlite3_expert_count(self.0) }
+    }
+
+    /// One report for statement `i`, or `None` when sqlite3expert has none.
+    fn report(&self, i: c_int, kind: c_int) -> Option<String> {
+        // SAFETY: `self.0` is a live, analyzed handle and `i` is below
+        // `count()`. The string is owned by the handle; it is copied out
+        // before the handle can be destroyed.
+        let ptr = unsafe { sqlite3_expert_report(self.0, i, kind) };
+        if ptr.is_null() {
+            return None;
+        }
+        // SAFETY: non-null, NUL-terminated, valid until the handle drops.
+        Some(
+            unsafe { CStr::from_ptr(ptr) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+impl Drop for Expert {
+    fn drop(&mut self) {
+        // SAFETY: `self.0` came from `sqlite3_expert_new` and is destroyed once.
+        unsafe { sqlite3_expert_destroy(self.0) };
+    }
+}
+
+/// Turn a failed sqlite3expert call into an `XqliteError`, freeing `err`.
+///
+/// # Safety
+///
+/// `err` must be null or a string allocated with `sqlite3_malloc`.
+unsafe fn expert_error(rc: c_int, err: *mut c_char) -> XqliteError {
+    let message = if err.is_null() {
+        // SAFETY: sqlite3_errstr returns a static string for any code.
+        unsafe { CStr::from_ptr(ffi::sqlite3_errstr(rc)) }
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        // SAFETY: non-null and NUL-terminated per the fn contract.
+        let message = unsafe { CStr::from_ptr(err) }
+            .to_string_lossy()
+            .into_owned();
+        // SAFETY: sqlite3_malloc'd per the fn contract, copied above and
+        // not used again.
+        unsafe { ffi::sqlite3_free(err.cast()) };
+        message
+    };
+    XqliteError::from(rusqlite::Error::SqliteFailure(
+        ffi::Error::new(rc),
+        Some(message),
+    ))
+}
+
+/// Advise indexes for `sqls` with sqlite3expert. Callers must hold the
+/// connection Mutex.
+pub(crate) fn suggest_indexes(
+    conn: &Connection,
+    sqls: &[String],
+) -> Result<IndexAdvice, XqliteError> {
+    // SAFETY: the caller holds the connection Mutex, and `expert` is
+    // dropped before this function returns, while `conn` is still alive.
+    let mut expert = unsafe { Expert::new(conn.handle())? };
+    for sql in sqls {
+        expert.add(sql)?;
+    }
+    expert.analyze()?;
+
+    let mut copy = plan_copy(conn)?;
+    let mut statements = Vec::new();
+    for i in 0..expert.count() {
+        let sql = expert.report(i, EXPERT_REPORT_SQL).unwrap_or_default();
+        let indexes = expert
+            .report(i, EXPERT_REPORT_INDEXES)
+            .map(|report| parse_indexes(&report))
+            .unwrap_or_default();
+        let plan_before = explain_analyze::collect_query_plan(&copy, &sql)?;
+        let plan_after = if indexes.is_empty() {
+            plan_before.clone()
+        } else {
+            // Dropping the transaction rolls the indexes back.
+            let tx = copy.transaction()?;
+            for create_sql in &indexes {
+                tx.execute_batch(create_sql)?;
+            }
+            explain_analyze::collect_query_plan(&tx, &sql)?
+        };
+        statements.push(StatementAdvice {
+            sql,
+            indexes,
+            plan_before,
+            plan_after,
+        });
+    }
+
+    Ok(IndexAdvice {
+        indexes: index_advisor::union_of(&statements),
+        statements,
+    })
+}
+
+/// An in-memory copy of `conn`'s schema, made as sqlite3expert makes the
+/// one it plans on: every object but `sqlite_` internals and virtual
+/// tables, no rows and no `sqlite_stat1`, with stand-ins for the
+/// collations and application functions the schema and workload name.
+/// sqlite3expert has already replayed the same schema, so a failure here
+/// is an error.
+fn plan_copy(conn: &Connection) -> Result<Connection, XqliteError> {
+    let copy = Connection::open_in_memory()?;
+    copy.collation_needed(placeholder_collation)?;
+    copy_functions(conn, &copy)?;
+
+    let mut stmt = conn.prepare(
+        "SELECT sql FROM sqlite_schema \
+         WHERE name NOT LIKE 'sqlite_%' AND sql NOT LIKE 'CREATE VIRTUAL %'",
+    )?;
+    let schema = stmt
+        .query_map([], |row| row.get::<_, Option<String>>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for sql in schema.into_iter().flatten() {
+        copy.execute_batch(&sql)?;
+    }
+    Ok(copy)
+}
+
+/// Register any collation the copied schema names as a binary one: plans
+/// only depend on whether an index's collation matches the query's, which
+/// SQLite decides by name.
+fn placeholder_collation(copy: &Connection, name: &str) -> rusqlite::Result<()> {
+    copy.create_collation(name, |a: &str, b: &str| -> Ordering { a.cmp(b) })
+}
+
+/// Declare every application function of `conn` on `copy`, with the same
+/// arity, encoding and deterministic / direct-only flags, so statements and
+/// index expressions that call one still prepare. The copy holds no rows
+/// and is only ever asked for plans, so the stand-ins never run.
+fn copy_functions(conn: &Connection, copy: &Connection) -> Result<(), XqliteError> {
+    let mut stmt = conn.prepare(
+        "SELECT name, type, enc, narg, flags FROM pragma_function_list WHERE builtin = 0",
+    )?;
+    let functions = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, c_int>(3)?,
+                row.get::<_, c_int>(4)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (name, kind, enc, narg, flags) in functions {
+        let c_name = CString::new(name).map_err(|_| XqliteError::NulErrorInString)?;
+        let text_rep = match enc.as_str() {
+            "utf16le" => ffi::SQLITE_UTF16LE,
+            "utf16be" => ffi::SQLITE_UTF16BE,
+            _ => ffi::SQLITE_UTF8,
+        } | (flags & (ffi::SQLITE_DETERMINISTIC | ffi::SQLITE_DIRECTONLY));
+        // SAFETY: `copy` is open and used by this thread only, and its raw
+        // handle does not outlive it; the callbacks are plain functions
+        // and there is no user data.
+        let rc = unsafe {
+            let db = copy.handle();
+            match kind.as_str() {
+                "s" => ffi::sqlite3_create_function_v2(
+                    db,
+                    c_name.as_ptr(),
+                    narg,
+                    text_rep,
+                    std::ptr::null_mut(),
+                    Some(stand_in_step),
+                    None,
+                    None,
+                    None,
+                ),
+                "a" => ffi::sqlite3_create_function_v2(
+                    db,
+                    c_name.as_ptr(),
+                    narg,
+                    text_rep,
+                    std::ptr::null_mut(),
+                    None,
+                    Some(stand_in_step),
+                    Some(stand_in_final),
+                    None,
+                ),
+                "w" => ffi::sqlite3_create_window_function(
+                    db,
+                    c_name.as_ptr(),
+                    narg,
+                    text_rep,
+                    std::ptr::null_mut(),
+                    Some(stand_in_step),
+                    Some(stand_in_final),
+                    Some(stand_in_final),
+                    Some(stand_in_step),
+                    None,
+                ),
+                _ => ffi::SQLITE_OK,
+            }
+        };
+        if rc != ffi::SQLITE_OK {
+            return Err(rusqlite::Error::SqliteFailure(ffi::Error::new(rc), None).into());
+        }
+    }
+    Ok(())
+}
+
+/// Stand-in for an application function's step on the plan copy.
+unsafe extern "C" fn stand_in_step(
+    _ctx: *mut ffi::sqlite3_context,
+    _argc: c_int,
+    _argv: *mut *mut ffi::sqlite3_value,
+) {
+}
+
+/// Stand-in for an aggregate or window function's final / value callback.
+unsafe extern "C" fn stand_in_final(_ctx: *mut ffi::sqlite3_context) {}
+
+/// The `CREATE INDEX ...;` lines of an indexes report, without the `;`.
+/// A statement that needs no new index has none.
+fn parse_indexes(report: &str) -> Vec<String> {
+    report
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("CREATE "))
+        .map(|line| line.trim_end_matches(';').to_string())
+        .collect()
+}
@@ -23,6 +23,7 @@ pub struct ExplainAnalyze {
 
 /// Statement-level counters from `sqlite3_stmt_status`. Applies to the whole
 /// prepared statement regardless of how many scans it contains.
+#[derive(Clone)]
 pub struct StmtCounters {
     pub fullscan_step: i64,
     pub sort: i64,
@@ -37,6 +38,7 @@ pub struct StmtCounters {
 
 /// One scan entry from `sqlite3_stmt_scanstatus_v2`. Each entry describes a
 /// loop in the query plan (table/index scan, subquery, etc).
+#[derive(Clone)]
 pub struct ScanStatus {
     pub loops: i64,
     pub rows_visited: i64,
@@ -49,12 +51,37 @@ pub struct ScanStatus {
 
 /// One row from `EXPLAIN QUERY PLAN <sql>`. Captures SQLite's static analysis
 /// tree; combined with `scans`, you get the runtime shape of the query.
+#[derive(Clone)]
 pub struct QueryPlanRow {
     pub id: i32,
     pub parent: i32,
     pub detail: String,
 }
 
+/// One VDBE instruction of a statement's compiled program. Comes from the
+/// `bytecode()` table-valued function when SQLite is built with it (the
+/// bundled build is), otherwise from plain `EXPLAIN <sql>`.
+pub struct BytecodeOp {
+    pub addr: i64,
+    pub opcode: String,
+    pub p1: i64,
+    pub p2: i64,
+    pub p3: i64,
+    pub p4: Option<String>,
+    pub p5: i64,
+    pub comment: Option<String>,
+    /// Trigger (or `(FK)` action) the instruction belongs to; `None` for
+    /// the main program. Always `None` under the `EXPLAIN` fallback.
+    pub subprog: Option<String>,
+    /// Times the instruction ran and CPU cycles spent in it; `None` under
+    /// the `EXPLAIN` fallback.
+    pub nexec: Option<i64>,
+    pub ncycle: Option<i64>,
+    /// For an `Explain` instruction, the scan it announces — matched on the
+    /// EXPLAIN QUERY PLAN id (`p1` == `ScanStatus::selectid`).
+    pub scan: Option<ScanStatus>,
+}
+
 pub fn core_explain_analyze<'a>(
     env: Env<'a>,
     conn: &Connection,
@@ -67,24 +94,7 @@ pub fn core_explain_analyze<'a>(
     // no concurrent BEAM thread can step the same connection.
     unsafe {
         let db_handle = conn.handle();
-        let c_sql = CString::new(sql).map_err(|_| XqliteError::NulErrorInString)?;
-        let sql_len = c_int::try_from(c_sql.as_bytes().len())
-            .map_err(|_| XqliteError::CannotExecute("SQL too long".to_string()))?;
-
-        let mut raw_stmt_ptr: *mut ffi::sqlite3_stmt = std::ptr::null_mut();
-        let prepare_rc = ffi::sqlite3_prepare_v2(
-            db_handle,
-            c_sql.as_ptr(),
-            sql_len,
-            &mut raw_stmt_ptr,
-            std::ptr::null_mut(),
-        );
-
-        if prepare_rc != ffi::SQLITE_OK {
-            return Err(ffi_error(db_handle, prepare_rc));
-        }
-
-        let stmt_ptr = match NonNull::new(raw_stmt_ptr) {
+        let stmt_ptr = match prepare_first(db_handle, sql)? {
             Some(p) => p,
             None => {
                 // Whitespace-only / comment-only SQL. Return an empty report
@@ -110,21 +120,75 @@ pub fn core_explain_analyze<'a>(
     }
 }
 
+/// Run `sql` to completion (rows are discarded, like `core_explain_analyze`)
+/// and list its VDBE program, with per-instruction execution counts when
+/// the `bytecode()` table is available.
+pub fn core_explain_bytecode<'a>(
+    env: Env<'a>,
+    conn: &Connection,
+    sql: &str,
+    params_term: Term<'a>,
+) -> Result<Vec<BytecodeOp>, XqliteError> {
+    // SAFETY: as in `core_explain_analyze` — `with_conn` at the NIF boundary
+    // holds the connection Mutex for the duration of this call.
+    unsafe {
+        let db_handle = conn.handle();
+        let Some(stmt_ptr) = prepare_first(db_handle, sql)? else {
+            return Ok(Vec::new());
+        };
+
+        let result =
+            run_and_list_bytecode(env, conn, stmt_ptr.as_ptr(), db_handle, params_term, sql);
+
+        ffi::sqlite3_finalize(stmt_ptr.as_ptr());
+
+        result
+    }
+}
+
 // --- private ---------------------------------------------------------------
 
+/// Prepare the first statement of `sql`; `None` for whitespace/comment-only
+/// input.
+///
 /// # Safety
-/// `stmt_ptr` must be non-null and point to a prepared statement on `db_handle`.
-/// The connection Mutex must be held for the duration of this call.
-unsafe fn run_and_collect<'a>(
-    env: Env<'a>,
-    stmt_ptr: *mut ffi::sqlite3_stmt,
+/// `db_handle` must be a valid connection whose Mutex the caller holds.
+pub(crate) unsafe fn prepare_first(
     db_handle: *mut ffi::sqlite3,
-    params_term: Term<'a>,
-    query_plan: Vec<QueryPlanRow>,
-) -> Result<ExplainAnalyze, XqliteError> {
-    bind_params(env, stmt_ptr, db_handle, params_term)?;
+    sql: &str,
+) -> Result<Option<NonNull<ffi::sqlite3_stmt>>, XqliteError> {
+    let c_sql = CString::new(sql).map_err(|_| XqliteError::NulErrorInString)?;
+    let sql_len = c_int::try_from(c_sql.as_bytes().len())
+        .map_err(|_| XqliteError::CannotExecute("SQL too long".to_string()))?;
+
+    let mut raw_stmt_ptr: *mut ffi::sqlite3_stmt = std::ptr::null_mut();
+    // SAFETY: `db_handle` is valid and the Mutex is held (fn contract).
+    let prepare_rc = unsafe {
+        ffi::sqlite3_prepare_v2(
+            db_handle,
+            c_sql.as_ptr(),
+            sql_len,
+            &mut raw_stmt_ptr,
+            std::ptr::null_mut(),
+        )
+    };
 
-    let start = Instant::now();
+    if prepare_rc != ffi::SQLITE_OK {
+        // SAFETY: as above.
+        return Err(unsafe { ffi_error(db_handle, prepare_rc) });
+    }
+
+    Ok(NonNull::new(raw_stmt_ptr))
+}
+
+/// Step `stmt_ptr` until SQLITE_DONE, returning the number of rows seen.
+///
+/// # Safety
+/// `stmt_ptr` must be a live statement on `db_handle`, Mutex held.
+unsafe fn step_to_done(
+    stmt_ptr: *mut ffi::sqlite3_stmt,
+    db_handle: *mut ffi::sqlite3,
+) -> Result<u64, XqliteError> {
     let mut rows_produced: u64 = 0;
 
     loop {
@@ -133,15 +197,32 @@ unsafe fn run_and_collect<'a>(
         let rc = unsafe { ffi::sqlite3_step(stmt_ptr) };
         match rc {
             ffi::SQLITE_ROW => rows_produced += 1,
-            ffi::SQLITE_DONE => break,
+            ffi::SQLITE_DONE => return Ok(rows_produced),
             // SAFETY: `db_handle` is valid and the Mutex is held (fn contract).
             _ => return Err(unsafe { ffi_error(db_handle, rc) }),
         }
     }
+}
+
+/// # Safety
+/// `stmt_ptr` must be non-null and point to a prepared statement on `db_handle`.
+/// The connection Mutex must be held for the duration of this call.
+unsafe fn run_and_collect<'a>(
+    env: Env<'a>,
+    stmt_ptr: *mut ffi::sqlite3_stmt,
+    db_handle: *mut ffi::sqlite3,
+    params_term: Term<'a>,
+    query_plan: Vec<QueryPlanRow>,
+) -> Result<ExplainAnalyze, XqliteError> {
+    bind_params(env, stmt_ptr, db_handle, params_term)?;
+
+    let start = Instant::now();
+    // SAFETY: forwarded fn contract.
+    let rows_produced = unsafe { step_to_done(stmt_ptr, db_handle)? };
 
     let wall_time_ns = start.elapsed().as_nanos() as u64;
     // SAFETY: `stmt_ptr` is live and the Mutex is held (fn contract).
-    let stmt_counters = unsafe { collect_stmt_counters(stmt_ptr) };
+    let stmt_counters = unsafe { collect_stmt_counters(stmt_ptr, false) };
     // SAFETY: `stmt_ptr` is live and the Mutex is held (fn contract).
     let scans = unsafe { collect_scan_status(stmt_ptr) };
 
@@ -154,6 +235,150 @@ unsafe fn run_and_collect<'a>(
     })
 }
 
+/// # Safety
+/// `stmt_ptr` must be non-null and point to a prepared statement on `db_handle`.
+/// The connection Mutex must be held for the duration of this call.
+unsafe fn run_and_list_bytecode<'a>(
+    env: Env<'a>,
+    conn: &Connection,
+    stmt_ptr: *mut ffi::sqlite3_stmt,
+    db_handle: *mut ffi::sqlite3,
+    params_term: Term<'a>,
+    sql: &str,
+) -> Result<Vec<BytecodeOp>, XqliteError> {
+    bind_params(env, stmt_ptr, db_handle, params_term)?;
+    // SAFETY: forwarded fn contract.
+    unsafe { step_to_done(stmt_ptr, db_handle)? };
+
+    // SAFETY: `stmt_ptr` is live and the Mutex is held (fn contract).
+    let scans = unsafe { collect_scan_status(stmt_ptr) };
+    // SAFETY: compileoption_used only reads a static table.
+    let has_vtab =
+        unsafe { ffi::sqlite3_compileoption_used(c"ENABLE_BYTECODE_VTAB".as_ptr()) } != 0;
+    let mut ops = if has_vtab {
+        // SAFETY: forwarded fn contract.
+        unsafe { collect_bytecode(stmt_ptr, db_handle)? }
+    } else {
+        collect_explain(conn, sql)?
+    };
+
+    for op in ops
+        .iter_mut()
+        .filter(|op| op.opcode == "Explain" && op.subprog.is_none())
+    {
+        op.scan = scans
+            .iter()
+            .find(|scan| i64::from(scan.selectid) == op.p1)
+            .cloned();
+    }
+
+    Ok(ops)
+}
+
+/// Read the program of an already-run statement from `bytecode(?1)`, with
+/// the statement passed by pointer so its execution counts are reported.
+///
+/// # Safety
+/// `stmt_ptr` must be a live statement on `db_handle`, Mutex held.
+unsafe fn collect_bytecode(
+    stmt_ptr: *mut ffi::sqlite3_stmt,
+    db_handle: *mut ffi::sqlite3,
+) -> Result<Vec<BytecodeOp>, XqliteError> {
+    const LISTING: &CStr = c"SELECT addr, opcode, p1, p2, p3, p4, p5, comment, subprog, nexec, ncycle FROM bytecode(?1)";
+
+    let mut listing: *mut ffi::sqlite3_stmt = std::ptr::null_mut();
+    // SAFETY: `db_handle` is valid and the Mutex is held (fn contract). The
+    // listing statement is finalized on every path below.
+    unsafe {
+        let rc = ffi::sqlite3_prepare_v2(
+            db_handle,
+            LISTING.as_ptr(),
+            -1,
+            &mut listing,
+            std::ptr::null_mut(),
+        );
+        if rc != ffi::SQLITE_OK {
+            return Err(ffi_error(db_handle, rc));
+        }
+        ffi::sqlite3_bind_pointer(listing, 1, stmt_ptr.cast(), c"stmt-pointer".as_ptr(), None);
+
+        let mut ops = Vec::new();
+        loop {
+            match ffi::sqlite3_step(listing) {
+                ffi::SQLITE_ROW => ops.push(BytecodeOp {
+                    addr: ffi::sqlite3_column_int64(listing, 0),
+                    opcode: column_text(listing, 1).unwrap_or_default(),
+                    p1: ffi::sqlite3_column_int64(listing, 2),
+                    p2: ffi::sqlite3_column_int64(listing, 3),
+                    p3: ffi::sqlite3_column_int64(listing, 4),
+                    p4: column_text(listing, 5),
+                    p5: ffi::sqlite3_column_int64(listing, 6),
+                    comment: column_text(listing, 7),
+                    subprog: column_text(listing, 8),
+                    nexec: column_int64(listing, 9),
+                    ncycle: column_int64(listing, 10),
+                    scan: None,
+                }),
+                ffi::SQLITE_DONE => break,
+                rc => {
+                    let err = ffi_error(db_handle, rc);
+                    ffi::sqlite3_finalize(listing);
+                    return Err(err);
+                }
+            }
+        }
+        ffi::sqlite3_finalize(listing);
+        Ok(ops)
+    }
+}
+
+/// Fallback listing from `EXPLAIN <sql>`: same columns, no counts and no
+/// trigger subprograms. Like `collect_query_plan`, parameters stay unbound.
+fn collect_explain(conn: &Connection, sql: &str) -> Result<Vec<BytecodeOp>, XqliteError> {
+    let mut stmt = conn.prepare(&format!("EXPLAIN {sql}"))?;
+    let mut rows = stmt.raw_query();
+    let mut out = Vec::new();
+
+    while let Some(row) = rows.next()? {
+        out.push(BytecodeOp {
+            addr: row.get(0)?,
+            opcode: row.get(1)?,
+            p1: row.get(2)?,
+            p2: row.get(3)?,
+            p3: row.get(4)?,
+            p4: row.get(5)?,
+            p5: row.get(6)?,
+            comment: row.get(7)?,
+            subprog: None,
+            nexec: None,
+            ncycle: None,
+            scan: None,
+        });
+    }
+
+    Ok(out)
+}
+
+/// # Safety
+/// `stmt` must be a live statement positioned on a row, Mutex held.
+pub(crate) unsafe fn column_text(stmt: *mut ffi::sqlite3_stmt, idx: c_int) -> Option<String> {
+    // SAFETY: fn contract; the text is copied out before the next step.
+    unsafe {
+        let ptr = ffi::sqlite3_column_text(stmt, idx);
+        (!ptr.is_null()).then(|| cstr_to_string(ptr.cast()))
+    }
+}
+
+/// # Safety
+/// `stmt` must be a live statement positioned on a row, Mutex held.
+unsafe fn column_int64(stmt: *mut ffi::sqlite3_stmt, idx: c_int) -> Option<i64> {
+    // SAFETY: fn contract.
+    unsafe {
+        (ffi::sqlite3_column_type(stmt, idx) != ffi::SQLITE_NULL)
+            .then(|| ffi::sqlite3_column_int64(stmt, idx))
+    }
+}
+
 fn bind_params<'a>(
     env: Env<'a>,
     stmt_ptr: *mut ffi::sqlite3_stmt,
@@ -177,7 +402,10 @@ fn bind_params<'a>(
     }
 }
 
-fn collect_query_plan(conn: &Connection, sql: &str) -> Result<Vec<QueryPlanRow>, XqliteError> {
+pub(crate) fn collect_query_plan(
+    conn: &Connection,
+    sql: &str,
+) -> Result<Vec<QueryPlanRow>, XqliteError> {
     let eqp_sql = format!("EXPLAIN QUERY PLAN {sql}");
     let mut stmt = conn.prepare(&eqp_sql)?;
     let col_count = stmt.column_count();
@@ -208,12 +436,18 @@ fn collect_query_plan(conn: &Connection, sql: &str) -> Result<Vec<QueryPlanRow>,
     Ok(out)
 }
 
+/// With `reset`, each counter is zeroed as it is read (SQLite leaves
+/// `memused_bytes` alone — it is a size, not a counter).
+///
 /// # Safety
 /// `stmt_ptr` must be valid and the connection Mutex must be held.
-unsafe fn collect_stmt_counters(stmt_ptr: *mut ffi::sqlite3_stmt) -> StmtCounters {
+pub(crate) unsafe fn collect_stmt_counters(
+    stmt_ptr: *mut ffi::sqlite3_stmt,
+    reset: bool,
+) -> StmtCounters {
     let get = |op: i32| -> i64 {
         // SAFETY: `stmt_ptr` is valid and the Mutex is held (fn contract).
-        unsafe { ffi::sqlite3_stmt_status(stmt_ptr, op, 0) as i64 }
+        unsafe { ffi::sqlite3_stmt_status(stmt_ptr, op, c_int::from(reset)) as i64 }
     };
 
     StmtCounters {
@@ -233,7 +467,7 @@ unsafe fn collect_stmt_counters(stmt_ptr: *mut ffi::sqlite3_stmt) -> StmtCounter
 /// `stmt_ptr` must be valid and the connection Mutex must be held. The returned
 /// `String`s are copied out of SQLite-owned memory before any use that could
 /// invalidate it.
-unsafe fn collect_scan_status(stmt_ptr: *mut ffi::sqlite3_stmt) -> Vec<ScanStatus> {
+pub(crate) unsafe fn collect_scan_status(stmt_ptr: *mut ffi::sqlite3_stmt) -> Vec<ScanStatus> {
     let mut scans = Vec::new();
     let mut idx: c_int = 0;
 
@@ -350,7 +584,7 @@ unsafe fn cstr_to_string(ptr: *const std::os::raw::c_char) -> String {
 /// # Safety
 /// `db_handle` must point to a valid sqlite3 connection and the caller must
 /// hold its Mutex.
-unsafe fn ffi_error(db_handle: *mut ffi::sqlite3, code: c_int) -> XqliteError {
+pub(crate) unsafe fn ffi_error(db_handle: *mut ffi::sqlite3, code: c_int) -> XqliteError {
     let message = {
         // SAFETY: `db_handle` is valid and the Mutex is held (fn contract).
         let err_msg_ptr = unsafe { ffi::sqlite3_errmsg(db_handle) };
@@ -392,7 +626,7 @@ impl StmtCounters {
 /// `InternalEncodingError` term instead of panicking — matching the crate's
 /// graceful `ok_or_else`/`map_err` convention rather than `unwrap`.
 #[inline]
-fn map_or_encoding_error<'b>(
+pub(crate) fn map_or_encoding_error<'b>(
     env: Env<'b>,
     built: rustler::NifResult<Term<'b>>,
     context: &str,
@@ -500,6 +734,35 @@ impl Encoder for ScanStatus {
     }
 }
 
+impl Encoder for BytecodeOp {
+    fn encode<'b>(&self, env: Env<'b>) -> Term<'b> {
+        let built = map_new(env)
+            .map_put(
+                atoms::__struct__().encode(env),
+                atoms::bytecode_op_struct().encode(env),
+            )
+            .and_then(|m| m.map_put(atoms::addr().encode(env), self.addr.encode(env)))
+            .and_then(|m| {
+                m.map_put(
+                    atoms::opcode().encode(env),
+                    self.opcode.as_str().encode(env),
+                )
+            })
+            .and_then(|m| m.map_put(atoms::p1().encode(env), self.p1.encode(env)))
+            .and_then(|m| m.map_put(atoms::p2().encode(env), self.p2.encode(env)))
+            .and_then(|m| m.map_put(atoms::p3().encode(env), self.p3.encode(env)))
+            .and_then(|m| m.map_put(atoms::p4().encode(env), self.p4.encode(env)))
+            .and_then(|m| m.map_put(atoms::p5().encode(env), self.p5.encode(env)))
+            .and_then(|m| m.map_put(atoms::comment().encode(env), self.comment.encode(env)))
+            .and_then(|m| m.map_put(atoms::subprog().encode(env), self.subprog.encode(env)))
+            .and_then(|m| m.map_put(atoms::nexec().encode(env), self.nexec.encode(env)))
+            .and_then(|m| m.map_put(atoms::ncycle().encode(env), self.ncycle.encode(env)))
+            .and_then(|m| m.map_put(atoms::scan().encode(env), self.scan.encode(env)));
+
+        map_or_encoding_error(env, built, "explain_bytecode op")
+    }
+}
+
 impl Encoder for QueryPlanRow {
     fn encode<'b>(&self, env: Env<'b>) -> Term<'b> {
         let built = map_new(env)
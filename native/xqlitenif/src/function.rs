@@ -0,0 +1,384 @@
+//! User-defined SQL functions whose bodies run in Elixir.
+//!
+//! SQLite invokes a user function synchronously from inside
+//! `sqlite3_step`, on whichever (dirty) scheduler thread is running the
+//! NIF that holds the connection Mutex. The callback cannot run Elixir
+//! code itself, so it performs a blocking round trip instead:
+//!
+//! 1. allocate a `call_id` and park a rendezvous channel under it in the
+//!    process-global `PENDING` table;
+//! 2. send `{:xqlite_function_call, call_id, name, args}` to the handler
+//!    pid;
+//! 3. block on the channel until `function_reply/2` delivers the result,
+//!    or the per-function timeout elapses.
+//!
+//! Every failure mode (handler returned an error, handler is dead, reply
+//! timed out, reply not convertible to a SQLite value) is reported to
+//! SQLite as a plain `SQLITE_ERROR`. The structured failure is recorded
+//! on the Rust side as well, in a slot of the thread that stepped the
+//! query: SQLite runs the callback synchronously inside `sqlite3_step`,
+//! so the NIF that stepped converts the resulting error on that same
+//! thread. `error::classify_sqlite_error` takes the record back and
+//! rebuilds `XqliteError::UserFunctionFailed`, regardless of which path
+//! (rusqlite statement, raw-FFI stream, manual statement) stepped the
+//! query. The message text is never parsed, so SQL that raises an error
+//! reading like a failure (a trigger's `RAISE`) stays a SQLite error.
+//!
+//! Deadlock note: the waiting thread holds the connection Mutex. A
+//! handler that calls back into the SAME connection blocks on that
+//! Mutex until the timeout fires; the function then fails with
+//! `:timeout` and the handler's own call proceeds afterwards. The
+//! timeout is what makes this recoverable rather than a hang.
+
+use crate::atoms;
+use crate::error::XqliteError;
+use crate::hook_util;
+use crate::util::{elixir_term_to_rusqlite_value, encode_val};
+use rusqlite::functions::{Context, FunctionFlags};
+use rusqlite::types::Value;
+use rusqlite::{Connection, ffi};
+use rustler::types::LocalPid;
+use rustler::{Atom, Decoder, Encoder, Env, NifResult, Term};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Why a user-function round trip failed. Surfaced to Elixir as the
+/// third element of `{:user_function_failed, name, kind, message}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FailureKind {
+    /// The handler replied `{:error, reason}` (or raised, via the
+    /// `Xqlite.Function` dispatcher).
+    Error,
+    /// No reply within the registered timeout.
+    Timeout,
+    /// The handler pid was not alive when the call was sent.
+    Noproc,
+    /// The handler replied with a value that has no SQLite mapping.
+    InvalidResult,
+}
+
+impl FailureKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Timeout => "timeout",
+            Self::Noproc => "noproc",
+            Self::InvalidResult => "invalid_result",
+        }
+    }
+
+    pub(crate) fn to_atom(self) -> Atom {
+        match self {
+            Self::Error => rustler::types::atom::error(),
+            Self::Timeout => atoms::timeout(),
+            Self::Noproc => atoms::noproc(),
+            Self::InvalidResult => atoms::invalid_result(),
+        }
+    }
+}
+
+/// What `function_reply/2` hands the waiting callback.
+pub(crate) type Reply = Result<Value, (FailureKind, String)>;
+
+/// Calls awaiting a reply, keyed by `call_id`. Global rather than
+/// per-connection: `function_reply/2` carries only the id, so the reply
+/// path never needs (or contends for) the connection Mutex that the
+/// waiting callback is holding.
+static PENDING: Mutex<BTreeMap<u64, SyncSender<Reply>>> = Mutex::new(BTreeMap::new());
+static NEXT_CALL_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Where a registered function's calls go, and how long to wait.
+#[derive(Clone)]
+pub(crate) struct Handler {
+    pub(crate) pid: LocalPid,
+    pub(crate) timeout: Duration,
+    /// Set when `pid` is a dispatcher `Xqlite` spawned for this
+    /// registration. Clones share it, so it drops with the last
+    /// registration still answered by the dispatcher.
+    dispatcher: Option<Arc<OwnedDispatcher>>,
+}
+
+/// Stops an `Xqlite`-spawned dispatcher once nothing can call it any
+/// more: SQLite drops a registration's state when the function or
+/// collation is replaced or removed, and when the connection closes.
+struct OwnedDispatcher(LocalPid);
+
+impl Drop for OwnedDispatcher {
+    fn drop(&mut self) {
+        // SAFETY: see `hook_util::send_with_env`; the message is a bare
+        // atom built inside the fresh env. An already dead dispatcher is
+        // fine, so the result is ignored.
+        let _ = unsafe {
+            hook_util::send_with_env(&self.0, |env| {
+                Ok(atoms::xqlite_dispatcher_stop().encode(env))
+            })
+        };
+    }
+}
+
+/// A handler pid as the registration NIFs take it: a bare pid the caller
+/// runs itself, or `{:dispatcher, pid}` for a dispatcher `Xqlite` spawned
+/// and wants stopped together with the registration.
+pub(crate) struct HandlerPid {
+    pid: LocalPid,
+    owned: bool,
+}
+
+impl<'a> Decoder<'a> for HandlerPid {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        if let Ok(pid) = term.decode::<LocalPid>() {
+            return Ok(Self { pid, owned: false });
+        }
+        let (tag, pid): (Atom, LocalPid) = term.decode()?;
+        if tag == atoms::dispatcher() {
+            Ok(Self { pid, owned: true })
+        } else {
+            Err(rustler::Error::BadArg)
+        }
+    }
+}
+
+impl HandlerPid {
+    pub(crate) fn into_handler(self, timeout_ms: u64) -> Result<Handler, XqliteError> {
+        let mut handler = Handler::new(self.pid, timeout_ms)?;
+        if self.owned {
+            handler.dispatcher = Some(Arc::new(OwnedDispatcher(self.pid)));
+        }
+        Ok(handler)
+    }
+}
+
+impl std::fmt::Debug for Handler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Handler")
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl Handler {
+    /// `timeout_ms` must be >= 1: a zero timeout would fail every call.
+    pub(crate) fn new(pid: LocalPid, timeout_ms: u64) -> Result<Self, XqliteError> {
+        if timeout_ms == 0 {
+            return Err(XqliteError::CannotExecute(
+                "user function timeout_ms must be >= 1".to_string(),
+            ));
+        }
+        Ok(Self {
+            pid,
+            timeout: Duration::from_millis(timeout_ms),
+            dispatcher: None,
+        })
+    }
+}
+
+/// Translate the Elixir flag atoms into `FunctionFlags`. `SQLITE_UTF8`
+/// is always set; every other flag is opt-in. The list is validated in
+/// full, so an unknown atom registers nothing.
+pub(crate) fn parse_flags(flags: Vec<Atom>) -> Result<FunctionFlags, XqliteError> {
+    let mut out = FunctionFlags::SQLITE_UTF8;
+    for flag in flags {
+        out |= if flag == atoms::deterministic() {
+            FunctionFlags::SQLITE_DETERMINISTIC
+        } else if flag == atoms::direct_only() {
+            FunctionFlags::SQLITE_DIRECTONLY
+        } else if flag == atoms::innocuous() {
+            FunctionFlags::SQLITE_INNOCUOUS
+        } else {
+            return Err(XqliteError::InvalidFunctionFlag { flag });
+        };
+    }
+    Ok(out)
+}
+
+/// Validate the registration arguments shared by every Elixir-backed
+/// function kind.
+pub(crate) fn parse_registration(
+    flags: Vec<Atom>,
+    pid: HandlerPid,
+    timeout_ms: u64,
+) -> Result<(FunctionFlags, Handler), XqliteError> {
+    Ok((parse_flags(flags)?, pid.into_handler(timeout_ms)?))
+}
+
+/// Register `name/n_args` as a scalar function answered by `handler`.
+/// Re-registering the same name and arity replaces the previous
+/// definition (SQLite semantics). Callers must hold the connection Mutex.
+pub(crate) fn register_scalar(
+    conn: &Connection,
+    name: &str,
+    n_args: i32,
+    flags: FunctionFlags,
+    handler: Handler,
+) -> Result<(), XqliteError> {
+    let fn_name = name.to_string();
+    conn.create_scalar_function(name, n_args, flags, move |ctx| {
+        let args = context_args(ctx)?;
+        call(&handler, &fn_name, |env, call_id| {
+            let mut encoded = Vec::with_capacity(args.len());
+            for arg in args {
+                encoded.push(encode_val(env, arg)?);
+            }
+            Ok((
+                atoms::xqlite_function_call(),
+                call_id,
+                fn_name.as_str(),
+                encoded,
+            )
+                .encode(env))
+        })
+        .map_err(|(kind, message)| failure_to_sqlite(&fn_name, kind, &message))
+    })?;
+    Ok(())
+}
+
+/// Remove a user function of the given name and arity. SQLite reports
+/// success even when no such function exists.
+pub(crate) fn unregister(
+    conn: &Connection,
+    name: &str,
+    n_args: i32,
+) -> Result<(), XqliteError> {
+    conn.remove_function(name, n_args)?;
+    Ok(())
+}
+
+/// Copy the call's arguments out of SQLite into owned values.
+pub(crate) fn context_args(ctx: &Context<'_>) -> rusqlite::Result<Vec<Value>> {
+    (0..ctx.len()).map(|i| ctx.get::<Value>(i)).collect()
+}
+
+/// Perform one blocking round trip to `handler`. `build` constructs the
+/// message in a fresh process-independent env, given the call id.
+pub(crate) fn call<F>(handler: &Handler, fn_name: &str, build: F) -> Reply
+where
+    F: for<'a> FnOnce(Env<'a>, u64) -> Result<Term<'a>, XqliteError>,
+{
+    let call_id = NEXT_CALL_ID.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = mpsc::sync_channel(1);
+    lock_pending().insert(call_id, tx);
+
+    // SAFETY: see `hook_util::send_with_env`; the message is fully built inside the
+    // fresh env before the send and nothing is retained afterwards.
+    let sent = unsafe { hook_util::send_with_env(&handler.pid, |env| build(env, call_id)) };
+
+    let reply = match sent {
+        Err(e) => Err((
+            FailureKind::InvalidResult,
+            format!("cannot encode arguments for '{fn_name}': {e}"),
+        )),
+        Ok(false) => Err((
+            FailureKind::Noproc,
+            "handler process is not alive".to_string(),
+        )),
+        Ok(true) => match rx.recv_timeout(handler.timeout) {
+            Ok(reply) => reply,
+            Err(RecvTimeoutError::Timeout) => Err((
+                FailureKind::Timeout,
+                format!("no reply within {} ms", handler.timeout.as_millis()),
+            )),
+            // Unreachable while the entry sits in PENDING (it owns the
+            // sender); kept total rather than panicking in a callback.
+            Err(RecvTimeoutError::Disconnected) => {
+                Err((FailureKind::Error, "reply channel closed".to_string()))
+            }
+        },
+    };
+
+    // A reply racing the timeout finds no entry and is dropped.
+    lock_pending().remove(&call_id);
+    reply
+}
+
+/// Hand `reply` to the callback waiting on `call_id`. Returns false when
+/// nothing is waiting (unknown id, or the call already timed out).
+pub(crate) fn deliver(call_id: u64, reply: Reply) -> bool {
+    match lock_pending().remove(&call_id) {
+        Some(tx) => tx.try_send(reply).is_ok(),
+        None => false,
+    }
+}
+
+fn lock_pending() -> std::sync::MutexGuard<'static, BTreeMap<u64, SyncSender<Reply>>> {
+    // Poisoning cannot leave the map inconsistent (every critical section
+    // is a single insert/remove), so recover rather than propagate.
+    match PENDING.lock() {
+        Ok(g) => g,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// A failed call as `failure_to_sqlite` reported it.
+pub(crate) struct Failure {
+    pub(crate) function: String,
+    pub(crate) kind: FailureKind,
+    pub(crate) message: String,
+    /// The exact message SQLite was handed, and so reports for the step.
+    reported: String,
+}
+
+thread_local! {
+    /// The last failure a user-function callback on this thread reported
+    /// and no error conversion has taken back yet.
+    static LAST_FAILURE: RefCell<Option<Failure>> = const { RefCell::new(None) };
+}
+
+/// Encode a failure as the `SQLITE_ERROR` SQLite reports for the call,
+/// and record it for `take_failure`.
+///
+/// Returning a `SqliteFailure` makes rusqlite pass the code and message
+/// through verbatim; any other error type is reported as
+/// `SQLITE_CONSTRAINT_FUNCTION`, which would misclassify the failure as
+/// a constraint violation.
+pub(crate) fn failure_to_sqlite(
+    fn_name: &str,
+    kind: FailureKind,
+    message: &str,
+) -> rusqlite::Error {
+    let reported = format!(
+        "xqlite user function '{fn_name}' failed ({}): {message}",
+        kind.as_str()
+    );
+    LAST_FAILURE.with_borrow_mut(|slot| {
+        *slot = Some(Failure {
+            function: fn_name.to_string(),
+            kind,
+            message: message.to_string(),
+            reported: reported.clone(),
+        })
+    });
+    rusqlite::Error::SqliteFailure(ffi::Error::new(ffi::SQLITE_ERROR), Some(reported))
+}
+
+/// Take back the failure recorded on this thread, if `reported` is the
+/// error SQLite raised for it. Any record is cleared either way, so one
+/// whose error never reached a conversion cannot outlive the next.
+pub(crate) fn take_failure(reported: &str) -> Option<Failure> {
+    LAST_FAILURE
+        .take()
+        .filter(|failure| failure.reported == reported)
+}
+
+/// Decode a handler's `{:ok, value}` / `{:error, message}` reply term.
+pub(crate) fn decode_reply<'a>(env: Env<'a>, term: Term<'a>) -> Reply {
+    match term.decode::<(Atom, Term<'a>)>() {
+        Ok((tag, value)) if tag == rustler::types::atom::ok() => {
+            elixir_term_to_rusqlite_value(env, value)
+                .map_err(|e| (FailureKind::InvalidResult, e.to_string()))
+        }
+        Ok((tag, reason)) if tag == rustler::types::atom::error() => Err((
+            FailureKind::Error,
+            reason
+                .decode::<String>()
+                .unwrap_or_else(|_| format!("{reason:?}")),
+        )),
+        _ => Err((
+            FailureKind::InvalidResult,
+            format!("expected {{:ok, value}} or {{:error, message}}, got: {term:?}"),
+        )),
+    }
+}
@@ -3,12 +3,13 @@
 //! Four orthogonal concerns live here:
 //!
 //! * Term construction for messages sent back to Elixir (`make_atom`,
-//!   `make_binary`) — used by every hook that forwards events as
+//!   `make_binary`, and `send_with_env` for messages built with rustler's
+//!   encoders) — used by every hook that forwards events as
 //!   `{:xqlite_*, ...}` tuples.
 //! * Single-subscriber atomic-slot lifecycle (`install_hook`,
-//!   `uninstall_hook`, `drop_hook`) — used by `busy_handler`, where
-//!   the callback returns a policy decision and multi-subscriber
-//!   composition is ill-defined.
+//!   `uninstall_hook`, `drop_hook`) — used by `busy_handler` and
+//!   `collation`'s `collation_needed` resolver, where the callback
+//!   makes a decision and multi-subscriber composition is ill-defined.
 //! * Multi-subscriber lists (`HookList<T>`) — used by every fan-out
 //!   hook (`update`, `wal`, `commit`, `rollback`, `log`, `progress`,
 //!   plus the cancel sub-list inside `progress_dispatch`). N
@@ -16,10 +17,10 @@
 //!   handle for unregistration; the C callback walks a snapshot
 //!   without locks.
 //! * Raw-FFI callback unwind guard (`guard_ffi_callback`) — wraps the
-//!   body of the three callbacks we register directly through
-//!   `ffi::sqlite3_*` (progress / wal / busy) in `catch_unwind`, so a
-//!   panic can never unwind across `extern "C"` into SQLite's C stack
-//!   and crash the BEAM.
+//!   body of the four callbacks we register directly through
+//!   `ffi::sqlite3_*` (progress / wal / busy / collation_needed) in
+//!   `catch_unwind`, so a panic can never unwind across `extern "C"`
+//!   into SQLite's C stack and crash the BEAM.
 //!
 //! Both slot styles share the same release-acquire ordering and the
 //! same "caller holds connection Mutex during writes" invariant. The
@@ -28,7 +29,12 @@
 //! the underlying state.
 
 use crate::error::XqliteError;
-use rustler::sys::{ERL_NIF_TERM, ErlNifEnv, enif_make_atom_len, enif_make_new_binary};
+use rustler::sys::{
+    ERL_NIF_TERM, ErlNifEnv, enif_alloc_env, enif_free_env, enif_make_atom_len,
+    enif_make_new_binary, enif_send,
+};
+use rustler::types::LocalPid;
+use rustler::{Env, Term};
 use std::io::Write;
 use std::os::raw::c_int;
 use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
@@ -51,6 +57,39 @@ pub(crate) unsafe fn make_binary(env: *mut ErlNifEnv, data: &[u8]) -> ERL_NIF_TE
     }
 }
 
+/// Build a message with rustler's safe encoders and send it to `pid`.
+/// Returns `Ok(false)` when the pid is not alive.
+///
+/// # Safety
+///
+/// See `busy_handler::send_busy_to_pid` for the OTP 26.1 NULL-env
+/// invariant. The `Env` handed to `build` wraps a freshly allocated,
+/// process-independent msg_env that is freed right after the send, so
+/// no term built in it may escape `build`'s return value.
+pub(crate) unsafe fn send_with_env<F>(pid: &LocalPid, build: F) -> Result<bool, XqliteError>
+where
+    F: for<'a> FnOnce(Env<'a>) -> Result<Term<'a>, XqliteError>,
+{
+    // SAFETY: msg_env is freshly allocated and only used on this thread;
+    // the `Env` wrapper is confined to this block (lifetime tied to the
+    // local `marker`), and the env is freed exactly once.
+    unsafe {
+        let msg_env = enif_alloc_env();
+        let marker = ();
+        let env = Env::new(&marker, msg_env);
+        let result = build(env).map(|msg| {
+            enif_send(
+                std::ptr::null_mut(),
+                pid.as_c_arg(),
+                msg_env,
+                msg.as_c_arg(),
+            ) != 0
+        });
+        enif_free_env(msg_env);
+        result
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Raw-FFI callback unwind guard
 // ---------------------------------------------------------------------------
@@ -58,9 +97,9 @@ pub(crate) unsafe fn make_binary(env: *mut ErlNifEnv, data: &[u8]) -> ERL_NIF_TE
 /// Run the body of a raw-FFI-registered SQLite C callback under
 /// `catch_unwind`, returning `fallback` if it panics.
 ///
-/// xqlite registers three callbacks directly through `ffi::sqlite3_*`
-/// (`progress_dispatch_callback`, `wal_hook_callback`, `busy_callback`)
-/// instead of rusqlite's safe wrappers. rusqlite guards its OWN
+/// xqlite registers four callbacks directly through `ffi::sqlite3_*`
+/// (`progress_dispatch_callback`, `wal_hook_callback`, `busy_callback`,
+/// `collation_needed_callback`) instead of rusqlite's safe wrappers. rusqlite guards its OWN
 /// callbacks with `catch_unwind` trampolines; a raw registration has no
 /// such guard, so a panic escaping the body would unwind across the
 /// `extern "C"` boundary into SQLite's C stack — undefined behavior that
@@ -71,7 +110,8 @@ pub(crate) unsafe fn make_binary(env: *mut ErlNifEnv, data: &[u8]) -> ERL_NIF_TE
 ///
 /// `fallback` must be the callback's safe-on-panic return: a value that
 /// neither changes normal behavior nor corrupts SQLite state (0 for the
-/// progress and busy handlers, `SQLITE_OK` for the WAL hook).
+/// progress and busy handlers, `SQLITE_OK` for the WAL hook; the
+/// `collation_needed` callback returns `void` and ignores it).
 #[inline]
 pub(crate) fn guard_ffi_callback<F>(what: &str, fallback: c_int, body: F) -> c_int
 where
@@ -0,0 +1,77 @@
+//! Index advice for a workload of SQL statements: the result types and the
+//! entry point. The advice itself comes from SQLite's own advisor,
+//! `ext/expert` (sqlite3expert), compiled in by build.rs (see `expert`).
+
+use crate::atoms;
+use crate::error::XqliteError;
+use crate::expert;
+use crate::explain_analyze::{self, QueryPlanRow};
+use rusqlite::Connection;
+use rustler::types::map::map_new;
+use rustler::{Encoder, Env, Term};
+
+/// Advice for a whole workload.
+pub struct IndexAdvice {
+    /// Every suggested `CREATE INDEX`, in first-suggested order.
+    pub indexes: Vec<String>,
+    pub statements: Vec<StatementAdvice>,
+}
+
+/// Advice for one statement of the workload.
+pub struct StatementAdvice {
+    pub sql: String,
+    /// `CREATE INDEX` statements that improve this statement's plan.
+    pub indexes: Vec<String>,
+    pub plan_before: Vec<QueryPlanRow>,
+    pub plan_after: Vec<QueryPlanRow>,
+}
+
+impl Encoder for StatementAdvice {
+    fn encode<'b>(&self, env: Env<'b>) -> Term<'b> {
+        let built = map_new(env)
+            .map_put(atoms::sql().encode(env), self.sql.as_str().encode(env))
+            .and_then(|m| m.map_put(atoms::indexes().encode(env), self.indexes.encode(env)))
+            .and_then(|m| {
+                m.map_put(
+                    atoms::plan_before().encode(env),
+                    self.plan_before.encode(env),
+                )
+            })
+            .and_then(|m| {
+                m.map_put(atoms::plan_after().encode(env), self.plan_after.encode(env))
+            });
+
+        explain_analyze::map_or_encoding_error(env, built, "statement advice")
+    }
+}
+
+impl Encoder for IndexAdvice {
+    fn encode<'b>(&self, env: Env<'b>) -> Term<'b> {
+        let built = map_new(env)
+            .map_put(atoms::indexes().encode(env), self.indexes.encode(env))
+            .and_then(|m| {
+                m.map_put(atoms::statements().encode(env), self.statements.encode(env))
+            });
+
+        explain_analyze::map_or_encoding_error(env, built, "index advice")
+    }
+}
+
+/// Advise indexes for `sqls` against the schema of `conn`'s main database.
+pub fn suggest_indexes(
+    conn: &Connection,
+    sqls: &[String],
+) -> Result<IndexAdvice, XqliteError> {
+    expert::suggest_indexes(conn, sqls)
+}
+
+/// Every statement's suggestions, de-duplicated in first-suggested order.
+pub(crate) fn union_of(statements: &[StatementAdvice]) -> Vec<String> {
+    let mut indexes: Vec<String> = Vec::new();
+    for create_sql in statements.iter().flat_map(|s| &s.indexes) {
+        if !indexes.contains(create_sql) {
+            indexes.push(create_sql.clone());
+        }
+    }
+    indexes
+}
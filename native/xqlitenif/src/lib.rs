@@ -2,6 +2,11 @@
 
 pub(crate) mod atoms {
     rustler::atoms! {
+        __struct__,
+        accessor,
+        actions,
+        addr,
+        allow,
         alter_table,
         analyze,
         asc,
@@ -9,9 +14,11 @@ pub(crate) mod atoms {
         attach,
         authorization_denied,
         autoindex,
+        batch,
         binary,
         blob,
         busy,
+        bytecode_op_struct = "Elixir.Xqlite.BytecodeOp",
         cache_hit,
         cache_miss,
         cache_spill,
@@ -19,6 +26,9 @@ pub(crate) mod atoms {
         cache_used_shared,
         cache_write,
         cannot_convert_atom_to_string,
+        cannot_open_file,
+        change_struct = "Elixir.Xqlite.Change",
+        changeset_handler_failed,
         changes,
         checkpointed_pages,
         cannot_convert_to_sqlite_value,
@@ -26,9 +36,15 @@ pub(crate) mod atoms {
         cannot_execute_pragma,
         cannot_open_database,
         cascade,
+        close,
         code,
+        column,
+        column_count,
         columns,
+        comment,
         connection_closed,
+        conflict,
+        constraint,
         constraint_check,
         constraint_commit_hook,
         constraint_datatype,
@@ -43,6 +59,7 @@ pub(crate) mod atoms {
         constraint_unique,
         constraint_violation,
         constraint_vtab,
+        count,
         create_index,
         create_table,
         create_temp_index,
@@ -53,13 +70,21 @@ pub(crate) mod atoms {
         create_view,
         create_vtable,
         current,
+        database,
         database_busy_or_locked,
+        data,
         date,
+        depth,
         desc,
         detach,
         detail,
+        deterministic,
+        direct_only,
+        dispatcher,
         deferred,
+        declared_types,
         deferred_fks,
+        deny,
         done,
         drop_index,
         drop_table,
@@ -70,43 +95,66 @@ pub(crate) mod atoms {
         drop_trigger,
         drop_view,
         drop_vtable,
+        duration_ns,
         error,
         expr,
         estimated_rows,
         explain,
+        expanded_sql,
         exclusive,
+        exclude_tables,
         execute_returned_results,
         expected,
         extension_loading_disabled,
         expected_keyword_list,
         expected_keyword_tuple,
         expected_list,
+        file,
         filter_hit,
         filter_miss,
+        finalize,
         float,
         from_sql_conversion_failure,
+        foreign_key,
         full,
         fullscan_step,
         function,
         hidden_alias,
         id,
+        ignore,
         immediate,
         index_exists,
         index_name,
+        index_reads,
+        index_writes,
+        indexes,
+        indirect,
+        inverse,
+        innocuous,
         integer,
         integral_value_out_of_range,
         invalid_conflict_strategy,
         internal_encoding_error,
         invalid_authorizer_action,
+        invalid_authorizer_rule,
         invalid_batch_size,
+        invalid_builtin_aggregate,
+        invalid_changeset_apply_option,
         invalid_column_index,
         invalid_column_name,
         invalid_column_type,
+        invalid_explain_mode,
+        invalid_function_flag,
+        invalid_native_collation,
         invalid_pages_per_step,
         invalid_parameter_count,
+        invalid_parameter_index,
         invalid_parameter_name,
         invalid_pragma_name,
+        invalid_result,
+        invalid_trace_event,
         invalid_transaction_mode,
+        invalid_update_hook_option,
         invalid_stream_handle,
         list,
         literal,
@@ -123,34 +171,61 @@ pub(crate) mod atoms {
         minimum,
         multiple_statements,
         name,
+        names,
+        natural,
+        ncycle,
         negative_infinity,
+        new,
+        nexec,
+        new_rowid,
+        no_accents,
         no_action,
         no_such_index,
         no_such_table,
         no_value,
+        noproc,
         none,
+        notfound,
         normal,
         abort,
         null_byte_in_string,
         num_rows,
         numeric,
         offset,
+        old,
+        old_rowid,
         omit,
+        op,
+        opcode,
         operation_cancelled,
+        p1,
+        p2,
+        p3,
+        p4,
+        p5,
+        plan_after,
+        plan_before,
+        param_count,
         parent,
         parentid,
         partial,
         passive,
         pid,
+        pk_columns,
         port,
         positive_infinity,
         pragma,
+        preupdate_hook_conflict,
         primary_key_constraint,
+        profile,
         provided,
         query_plan,
         read_only_database,
         read,
+        reads,
+        readonly,
         real,
+        rebase,
         recursive,
         reference,
         reindex,
@@ -159,11 +234,13 @@ pub(crate) mod atoms {
         restart,
         restrict,
         row,
+        row_failed,
         rows,
         rows_produced,
         rows_visited,
         run,
         savepoint,
+        scan,
         scans,
         schema_changed,
         schema_parsing_error,
@@ -180,67 +257,112 @@ pub(crate) mod atoms {
         sql,
         sql_input_error,
         sqlite_failure,
+        statement_cache_eviction,
+        statement_cache_hit,
+        statement_cache_miss,
         statement_finalized,
+        statements,
+        step,
+        stmt,
         stmt_counters,
         stmt_used,
         stored_generated,
         string,
+        subprog,
         table,
+        table_filter,
         table_exists,
+        tables,
         target_type,
         tempbuf_spill,
         text,
         time,
+        timeout,
         timestamp,
         to_sql_conversion_failure,
         transaction,
         truncate,
         tuple,
+        undefined,
         vm_step,
         wall_time_ns,
+        weighted_median,
         write,
+        writes,
         unexpected_value,
+        unicode_nocase,
         unique_constraint,
         unknown,
         unsupported_atom,
         unsupported_data_type,
+        user_function_failed,
         utf8_error,
+        value,
         r#virtual,
         virtual_generated,
         view,
         delete,
         insert,
         update,
+        xqlite_aggregate_call,
+        xqlite_authorizer,
         xqlite_busy,
+        xqlite_changeset_chunk,
+        xqlite_changeset_conflict,
+        xqlite_changeset_filter,
+        xqlite_collation_call,
+        xqlite_collation_needed,
         xqlite_commit,
+        xqlite_dispatcher_stop,
+        xqlite_function_call,
         xqlite_log,
+        xqlite_preupdate,
         xqlite_progress,
         xqlite_rollback,
+        xqlite_slow_query,
+        xqlite_trace,
         xqlite_update,
+        xqlite_update_batch,
         xqlite_wal
     }
 }
 
+mod aggregate;
 mod authorizer;
 mod blob;
 mod busy_handler;
 mod cancel;
+mod changegroup;
+mod changeset;
+mod changeset_apply;
+mod collation;
 mod commit_hook;
 mod connection;
 mod constraint_parse;
+mod dependencies;
 mod error;
+mod expert;
 mod explain_analyze;
+mod function;
 mod hook_util;
+mod index_advisor;
 mod log_hook;
 mod nif;
 mod pragma;
+mod preupdate_hook;
 mod progress_dispatch;
 mod query;
+mod query_batch;
+mod rebaser;
 mod rollback_hook;
 mod schema;
 mod session;
+mod slow_query;
 mod statement;
+mod statement_cache;
+mod stmt_stats;
 mod stream;
+mod trace_hook;
 mod transaction;
 mod update_hook;
 mod util;
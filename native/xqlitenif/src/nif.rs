@@ -1,33 +1,46 @@
+use crate::aggregate;
 use crate::atoms;
 use crate::authorizer;
 use crate::blob::{self, XqliteBlob};
 use crate::busy_handler;
 use crate::cancel::XqliteCancelToken;
+use crate::changegroup::XqliteChangegroup;
+use crate::changeset::{self, XqliteChangesetIter};
+use crate::changeset_apply;
+use crate::collation;
 use crate::connection::{self, XqliteConn, XqliteQueryResult};
+use crate::dependencies;
 use crate::error::XqliteError;
 use crate::explain_analyze::{self, ExplainAnalyze};
+use crate::function;
+use crate::index_advisor;
 use crate::pragma;
 use crate::query;
+use crate::query_batch;
+use crate::rebaser::XqliteRebaser;
 use crate::schema::{
     ColumnInfo, DatabaseInfo, ForeignKeyInfo, IndexColumnInfo, IndexInfo, SchemaObjectInfo,
 };
-use crate::session::{self, XqliteSession};
-use crate::statement::XqliteStatement;
+use crate::session::{self, RawSession, XqliteSession};
+use crate::statement::{self, StmtInfo, XqliteStatement};
+use crate::stmt_stats::{self, StatsTarget, StmtProfile};
 use crate::stream::XqliteStream;
 use crate::transaction;
 use crate::util::singular_ok_or_error_tuple;
 use rusqlite::Connection;
 use rusqlite::ffi;
-use rusqlite::session::{ConflictAction, ConflictType};
+use rusqlite::functions::FunctionFlags;
 use rustler::{
-    Encoder, Env, ResourceArc, Term, TermType,
+    Encoder, Env, NifResult, ResourceArc, Term, TermType,
     types::{
         atom::{error, ok},
         map::map_new,
     },
 };
-use std::io::Cursor;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Cursor, Write};
 use std::ptr::NonNull;
+use std::sync::Mutex;
 use std::sync::atomic::{AtomicPtr, Ordering};
 
 // ---------------------------------------------------------------------------
@@ -98,7 +111,7 @@ fn query<'a>(
     params_term: Term<'a>,
 ) -> Result<XqliteQueryResult<'a>, XqliteError> {
     connection::with_conn(&handle, |conn| {
-        query::core_query(env, conn, &sql, params_term)
+        query::core_query(env, conn, &handle.statement_cache, &sql, params_term)
     })
 }
 
@@ -110,7 +123,35 @@ fn execute<'a>(
     params_term: Term<'a>,
 ) -> Result<usize, XqliteError> {
     connection::with_conn(&handle, |conn| {
-        query::core_execute(env, conn, &sql, params_term)
+        query::core_execute(env, conn, &handle.statement_cache, &sql, params_term)
+    })
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn execute_many<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<XqliteConn>,
+    sql: String,
+    param_sets: Term<'a>,
+    transaction: bool,
+    tokens: Vec<ResourceArc<XqliteCancelToken>>,
+) -> Result<u64, XqliteError> {
+    let token_bools: Vec<std::sync::Arc<std::sync::atomic::AtomicBool>> =
+        tokens.iter().map(|t| t.0.clone()).collect();
+    connection::with_conn(&handle, |conn| {
+        let _guard = crate::cancel::ProgressHandlerGuard::new(
+            &handle.progress_dispatch,
+            token_bools.clone(),
+        );
+        query::core_execute_many(
+            env,
+            conn,
+            &handle.statement_cache,
+            &sql,
+            param_sets,
+            transaction,
+            &token_bools,
+        )
     })
 }
 
@@ -125,6 +166,26 @@ fn execute_batch(
     singular_ok_or_error_tuple(env, execution_result)
 }
 
+#[rustler::nif(schedule = "DirtyIo")]
+fn query_batch<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<XqliteConn>,
+    sql: String,
+    continue_on_error: bool,
+) -> Result<Vec<Term<'a>>, XqliteError> {
+    let outcomes = connection::with_conn(&handle, |conn| {
+        query_batch::query_batch(env, conn, &sql, continue_on_error)
+    })?;
+
+    Ok(outcomes
+        .iter()
+        .map(|outcome| match outcome {
+            Ok((qr, changes)) => encode_query_result_with_changes(env, qr, *changes),
+            Err(err) => (error(), err).encode(env),
+        })
+        .collect())
+}
+
 #[rustler::nif(schedule = "DirtyIo")]
 fn query_with_changes<'a>(
     env: Env<'a>,
@@ -133,7 +194,7 @@ fn query_with_changes<'a>(
     params_term: Term<'a>,
 ) -> Term<'a> {
     let result = connection::with_conn(&handle, |conn| {
-        query::core_query_with_changes(env, conn, &sql, params_term)
+        query::core_query_with_changes(env, conn, &handle.statement_cache, &sql, params_term)
     });
 
     match result {
@@ -155,7 +216,7 @@ fn query_with_changes_cancellable<'a>(
     let result = connection::with_conn(&handle, |conn| {
         let _guard =
             crate::cancel::ProgressHandlerGuard::new(&handle.progress_dispatch, token_bools);
-        query::core_query_with_changes(env, conn, &sql, params_term)
+        query::core_query_with_changes(env, conn, &handle.statement_cache, &sql, params_term)
     });
 
     match result {
@@ -177,7 +238,7 @@ fn query_cancellable<'a>(
     connection::with_conn(&handle, |conn| {
         let _guard =
             crate::cancel::ProgressHandlerGuard::new(&handle.progress_dispatch, token_bools);
-        query::core_query(env, conn, &sql, params_term)
+        query::core_query(env, conn, &handle.statement_cache, &sql, params_term)
     })
 }
 
@@ -194,7 +255,7 @@ fn execute_cancellable<'a>(
     connection::with_conn(&handle, |conn| {
         let _guard =
             crate::cancel::ProgressHandlerGuard::new(&handle.progress_dispatch, token_bools);
-        query::core_execute(env, conn, &sql, params_term)
+        query::core_execute(env, conn, &handle.statement_cache, &sql, params_term)
     })
 }
 
@@ -231,6 +292,36 @@ fn explain_analyze<'a>(
     })
 }
 
+#[rustler::nif(schedule = "DirtyIo")]
+fn explain_bytecode<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<XqliteConn>,
+    sql: String,
+    params_term: Term<'a>,
+) -> Result<Vec<explain_analyze::BytecodeOp>, XqliteError> {
+    connection::with_conn(&handle, |conn| {
+        explain_analyze::core_explain_bytecode(env, conn, &sql, params_term)
+    })
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn statement_dependencies(
+    handle: ResourceArc<XqliteConn>,
+    sql: String,
+) -> Result<dependencies::Dependencies, XqliteError> {
+    connection::with_conn(&handle, |conn| {
+        dependencies::statement_dependencies(conn, &sql)
+    })
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn suggest_indexes(
+    handle: ResourceArc<XqliteConn>,
+    sqls: Vec<String>,
+) -> Result<index_advisor::IndexAdvice, XqliteError> {
+    connection::with_conn(&handle, |conn| index_advisor::suggest_indexes(conn, &sqls))
+}
+
 // ---------------------------------------------------------------------------
 // Transaction / autocommit introspection
 // ---------------------------------------------------------------------------
@@ -322,7 +413,7 @@ fn unregister_busy_observer(
 }
 
 // ---------------------------------------------------------------------------
-// Authorizer (deny-list, single slot)
+// Authorizer (policy slot + audit observers)
 // ---------------------------------------------------------------------------
 
 #[rustler::nif(schedule = "DirtyIo")]
@@ -337,13 +428,63 @@ fn set_authorizer<'a>(
         Ok(set) => set,
         Err(e) => return (error(), e).encode(env),
     };
-    let result = connection::with_conn(&handle, |conn| authorizer::set(conn, denied));
+    let policy = authorizer::Policy::deny_list(denied);
+    let result = connection::with_conn(&handle, |conn| {
+        authorizer::set(conn, &handle.authorizer, policy)
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn set_authorizer_rules<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<XqliteConn>,
+    rules: Vec<Term<'a>>,
+    default: Term<'a>,
+    dry_run: bool,
+) -> Term<'a> {
+    // Same all-or-nothing validation as set_authorizer/2.
+    let policy = match authorizer::parse_rules(rules, default, dry_run) {
+        Ok(policy) => policy,
+        Err(e) => return (error(), e).encode(env),
+    };
+    let result = connection::with_conn(&handle, |conn| {
+        authorizer::set(conn, &handle.authorizer, policy)
+    });
     singular_ok_or_error_tuple(env, result)
 }
 
 #[rustler::nif(schedule = "DirtyIo")]
 fn remove_authorizer(env: Env<'_>, handle: ResourceArc<XqliteConn>) -> Term<'_> {
-    let result = connection::with_conn(&handle, authorizer::clear);
+    let result =
+        connection::with_conn(&handle, |conn| authorizer::clear(conn, &handle.authorizer));
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn register_authorizer_observer(
+    env: Env<'_>,
+    handle: ResourceArc<XqliteConn>,
+    pid: rustler::LocalPid,
+) -> Term<'_> {
+    let result = connection::with_conn(&handle, |conn| {
+        authorizer::register_observer(conn, &handle.authorizer, pid)
+    });
+    match result {
+        Ok(id) => (ok(), id).encode(env),
+        Err(err) => (error(), err).encode(env),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn unregister_authorizer_observer(
+    env: Env<'_>,
+    handle: ResourceArc<XqliteConn>,
+    observer_handle: u64,
+) -> Term<'_> {
+    let result = connection::with_conn(&handle, |conn| {
+        authorizer::unregister_observer(conn, &handle.authorizer, observer_handle)
+    });
     singular_ok_or_error_tuple(env, result)
 }
 
@@ -510,11 +651,38 @@ fn connection_stats<'a>(
                     })?;
             }
 
+            let cache = handle.statement_cache.stats()?;
+            let counters = [
+                (atoms::statement_cache_hit(), cache.hits),
+                (atoms::statement_cache_miss(), cache.misses),
+                (atoms::statement_cache_eviction(), cache.evictions),
+            ];
+            for (atom, count) in counters {
+                map = map
+                    .map_put(atom.encode(env), count.encode(env))
+                    .map_err(|_| {
+                        XqliteError::CannotExecute(
+                            "connection_stats map_put for statement cache failed".to_string(),
+                        )
+                    })?;
+            }
+
             Ok(map)
         }
     })
 }
 
+#[rustler::nif(schedule = "DirtyIo")]
+fn set_statement_cache_size(
+    handle: ResourceArc<XqliteConn>,
+    size: usize,
+) -> Result<rustler::Atom, XqliteError> {
+    connection::with_conn(&handle, |conn| {
+        handle.statement_cache.set_capacity(conn, size)?;
+        Ok(ok())
+    })
+}
+
 // ---------------------------------------------------------------------------
 // Cancel NIFs
 // ---------------------------------------------------------------------------
@@ -846,7 +1014,9 @@ fn stmt_bind<'a>(
     params_term: Term<'a>,
 ) -> Term<'a> {
     use crate::stream::{bind_named_params_ffi, bind_positional_params_ffi};
-    use crate::util::{decode_exec_keyword_params, decode_plain_list_params, is_keyword};
+    use crate::util::{
+        decode_exec_keyword_params, decode_map_params, decode_plain_list_params, is_keyword,
+    };
 
     let result = stmt_handle.with_live_stmt(|stmt_ptr, db_handle| {
         match params_term.get_type() {
@@ -871,14 +1041,95 @@ fn stmt_bind<'a>(
                     bind_positional_params_ffi(stmt_ptr, &positional, db_handle)
                 }
             }
+            TermType::Map => {
+                let named = decode_map_params(env, params_term)?;
+                bind_named_params_ffi(stmt_ptr, &named, db_handle)
+            }
             _ => Err(XqliteError::ExpectedList {
-                value_str: format!("Parameters term was not a list: {params_term:?}"),
+                value_str: format!("Parameters term was not a list or map: {params_term:?}"),
             }),
         }
     });
     singular_ok_or_error_tuple(env, result)
 }
 
+#[rustler::nif(schedule = "DirtyIo")]
+fn stmt_bind_one<'a>(
+    env: Env<'a>,
+    stmt_handle: ResourceArc<XqliteStatement>,
+    key: Term<'a>,
+    value: Term<'a>,
+) -> Term<'a> {
+    use crate::stream::{bind_value_to_raw_stmt, parameter_index};
+    use crate::util::elixir_term_to_rusqlite_value;
+
+    let result = stmt_handle.with_live_stmt(|stmt_ptr, db_handle| {
+        let bind_idx = match key.get_type() {
+            TermType::Integer => {
+                // Bignums are out of range for any statement.
+                let index: i64 = key.decode().unwrap_or(i64::MAX);
+                // SAFETY: with_live_stmt holds the connection mutex and
+                // proved stmt_ptr live.
+                let count = unsafe { ffi::sqlite3_bind_parameter_count(stmt_ptr) };
+                match std::os::raw::c_int::try_from(index) {
+                    Ok(idx) if (1..=count).contains(&idx) => idx,
+                    _ => return Err(XqliteError::InvalidParameterIndex(index)),
+                }
+            }
+            TermType::Atom => {
+                let name = key
+                    .atom_to_string()
+                    .map_err(|e| XqliteError::CannotConvertAtomToString(format!("{e:?}")))?;
+                parameter_index(stmt_ptr, &format!(":{name}"))?
+            }
+            _ => {
+                let name: String = key
+                    .decode()
+                    .map_err(|_| XqliteError::InvalidParameterName(format!("{key:?}")))?;
+                parameter_index(stmt_ptr, &name)?
+            }
+        };
+        let value = elixir_term_to_rusqlite_value(env, value)?;
+        bind_value_to_raw_stmt(stmt_ptr, bind_idx, &value, db_handle)
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn stmt_parameters(
+    env: Env<'_>,
+    stmt_handle: ResourceArc<XqliteStatement>,
+) -> Result<Term<'_>, XqliteError> {
+    stmt_handle.with_live_stmt(|stmt_ptr, _db_handle| {
+        // SAFETY: with_live_stmt holds the connection mutex and proved
+        // stmt_ptr live.
+        let count = unsafe { ffi::sqlite3_bind_parameter_count(stmt_ptr) };
+        let mut names: Vec<Option<String>> = Vec::with_capacity(count.max(0) as usize);
+        for i in 1..=count {
+            // SAFETY: `i` is within the statement's parameter count; a null
+            // return means a nameless `?`.
+            let name_ptr = unsafe { ffi::sqlite3_bind_parameter_name(stmt_ptr, i) };
+            names.push(if name_ptr.is_null() {
+                None
+            } else {
+                // SAFETY: non-null, NUL-terminated, owned by the statement;
+                // copied out immediately.
+                Some(
+                    unsafe { std::ffi::CStr::from_ptr(name_ptr) }
+                        .to_string_lossy()
+                        .into_owned(),
+                )
+            });
+        }
+        map_new(env)
+            .map_put(atoms::count().encode(env), count.encode(env))
+            .and_then(|m| m.map_put(atoms::names().encode(env), names.encode(env)))
+            .map_err(|_| XqliteError::InternalEncodingError {
+                context: "stmt_parameters map".to_string(),
+            })
+    })
+}
+
 #[rustler::nif(schedule = "DirtyIo")]
 fn stmt_step<'a>(env: Env<'a>, stmt_handle: ResourceArc<XqliteStatement>) -> Term<'a> {
     use crate::stream::process_single_step;
@@ -1054,11 +1305,54 @@ fn stmt_column_names(
     }
 }
 
+#[rustler::nif(schedule = "DirtyIo")]
+fn stmt_info(stmt_handle: ResourceArc<XqliteStatement>) -> Result<StmtInfo, XqliteError> {
+    stmt_handle.with_live_stmt(|stmt_ptr, _db_handle| {
+        // SAFETY: with_live_stmt holds the connection mutex and proved
+        // stmt_ptr live.
+        Ok(unsafe { StmtInfo::read(stmt_ptr) })
+    })
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn stmt_explain(
+    env: Env<'_>,
+    stmt_handle: ResourceArc<XqliteStatement>,
+    mode: rustler::Atom,
+) -> Term<'_> {
+    let result = statement::explain_mode(mode).and_then(|e_mode| {
+        stmt_handle.with_live_stmt(|stmt_ptr, db_handle| {
+            // SAFETY: with_live_stmt holds the connection mutex and proved
+            // stmt_ptr live on db_handle.
+            unsafe { statement::set_explain_mode(stmt_ptr, db_handle, e_mode) }
+        })
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
 #[rustler::nif(schedule = "DirtyIo")]
 fn stmt_finalize(env: Env<'_>, stmt_handle: ResourceArc<XqliteStatement>) -> Term<'_> {
     singular_ok_or_error_tuple(env, stmt_handle.take_and_finalize())
 }
 
+#[rustler::nif(schedule = "DirtyIo")]
+fn stmt_stats<'a>(env: Env<'a>, handle: Term<'a>, reset: bool) -> NifResult<Term<'a>> {
+    let target = StatsTarget::decode(handle)?;
+    Ok(match stmt_stats::stats(&target, reset) {
+        Ok(counters) => (ok(), counters).encode(env),
+        Err(err) => (error(), err).encode(env),
+    })
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn stmt_scanstatus<'a>(env: Env<'a>, handle: Term<'a>, reset: bool) -> NifResult<Term<'a>> {
+    let target = StatsTarget::decode(handle)?;
+    Ok(match stmt_stats::scanstatus(&target, reset) {
+        Ok(scans) => (ok(), scans).encode(env),
+        Err(err) => (error(), err).encode(env),
+    })
+}
+
 #[rustler::nif(schedule = "DirtyIo")]
 fn stream_open<'a>(
     env: Env<'a>,
@@ -1123,6 +1417,7 @@ fn stream_open<'a>(
                         atomic_raw_stmt: AtomicPtr::new(std::ptr::null_mut()),
                         conn_resource_arc: conn_resource_arc_clone,
                         column_names: Vec::new(),
+                        exhausted_profile: Mutex::new(None),
                     });
                 }
             };
@@ -1189,6 +1484,7 @@ fn stream_open<'a>(
                 atomic_raw_stmt: AtomicPtr::new(non_null_raw_stmt.as_ptr()),
                 conn_resource_arc: conn_resource_arc_clone,
                 column_names,
+                exhausted_profile: Mutex::new(None),
             })
         }
     })
@@ -1329,6 +1625,11 @@ fn stream_fetch<'a>(
             }
             Ok(None) => {
                 stream_definitively_exhausted = true;
+                // SAFETY: current_stmt_ptr is live and conn_lock_guard is held.
+                let profile = unsafe { StmtProfile::capture(current_stmt_ptr) };
+                if let Ok(mut slot) = stream_handle.exhausted_profile.lock() {
+                    *slot = Some(profile);
+                }
                 let ptr_to_finalize = stream_handle
                     .atomic_raw_stmt
                     .swap(std::ptr::null_mut(), Ordering::AcqRel);
@@ -1451,13 +1752,18 @@ fn unregister_log_hook(env: Env<'_>, id: u64) -> Term<'_> {
 // ---------------------------------------------------------------------------
 
 #[rustler::nif(schedule = "DirtyIo")]
-fn register_update_hook(
-    env: Env<'_>,
+fn register_update_hook<'a>(
+    env: Env<'a>,
     handle: ResourceArc<XqliteConn>,
     pid: rustler::LocalPid,
-) -> Term<'_> {
-    let result = connection::with_conn(&handle, |_conn| {
-        crate::update_hook::register(&handle.update_hook, pid)
+    opts: Vec<(rustler::Atom, Term<'a>)>,
+) -> Term<'a> {
+    let options = match crate::update_hook::parse_options(opts) {
+        Ok(options) => options,
+        Err(e) => return (error(), e).encode(env),
+    };
+    let result = connection::with_conn(&handle, |conn| {
+        crate::update_hook::register(conn, &handle.trace_hook, pid, options)
     });
     match result {
         Ok(id) => (ok(), id).encode(env),
@@ -1467,13 +1773,41 @@ fn register_update_hook(
 
 #[rustler::nif(schedule = "DirtyIo")]
 fn unregister_update_hook(env: Env<'_>, handle: ResourceArc<XqliteConn>, id: u64) -> Term<'_> {
-    let result = connection::with_conn(&handle, |_conn| {
-        crate::update_hook::unregister(&handle.update_hook, id);
+    let result = connection::with_conn(&handle, |conn| {
+        crate::update_hook::unregister(conn, &handle.trace_hook, id);
         Ok(())
     });
     singular_ok_or_error_tuple(env, result)
 }
 
+#[rustler::nif(schedule = "DirtyIo")]
+fn register_preupdate_hook(
+    env: Env<'_>,
+    handle: ResourceArc<XqliteConn>,
+    pid: rustler::LocalPid,
+) -> Term<'_> {
+    let result = connection::with_conn(&handle, |conn| {
+        let live_sessions = handle.live_sessions.load(Ordering::Relaxed);
+        crate::preupdate_hook::register(conn, &handle.preupdate_hook, live_sessions, pid)
+    });
+    match result {
+        Ok(id) => (ok(), id).encode(env),
+        Err(err) => (error(), err).encode(env),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn unregister_preupdate_hook(
+    env: Env<'_>,
+    handle: ResourceArc<XqliteConn>,
+    id: u64,
+) -> Term<'_> {
+    let result = connection::with_conn(&handle, |conn| {
+        crate::preupdate_hook::unregister(conn, &handle.preupdate_hook, id)
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
 #[rustler::nif(schedule = "DirtyIo")]
 fn register_wal_hook(
     env: Env<'_>,
@@ -1550,6 +1884,70 @@ fn unregister_rollback_hook(
     singular_ok_or_error_tuple(env, result)
 }
 
+#[rustler::nif(schedule = "DirtyIo")]
+fn register_trace_hook(
+    env: Env<'_>,
+    handle: ResourceArc<XqliteConn>,
+    pid: rustler::LocalPid,
+    events: Vec<rustler::Atom>,
+) -> Term<'_> {
+    let mask = match crate::trace_hook::parse_events(events) {
+        Ok(mask) => mask,
+        Err(e) => return (error(), e).encode(env),
+    };
+    let result = connection::with_conn(&handle, |conn| {
+        crate::trace_hook::register(conn, &handle.trace_hook, pid, mask)
+    });
+    match result {
+        Ok(id) => (ok(), id).encode(env),
+        Err(err) => (error(), err).encode(env),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn unregister_trace_hook(env: Env<'_>, handle: ResourceArc<XqliteConn>, id: u64) -> Term<'_> {
+    let result = connection::with_conn(&handle, |conn| {
+        crate::trace_hook::unregister(conn, &handle.trace_hook, id);
+        Ok(())
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn set_slow_query_log(
+    env: Env<'_>,
+    handle: ResourceArc<XqliteConn>,
+    threshold_ms: u64,
+    plan_every: u64,
+    capacity: usize,
+    pid: Option<rustler::LocalPid>,
+) -> Term<'_> {
+    let log = crate::slow_query::SlowQueryLog::new(threshold_ms, plan_every, capacity, pid);
+    let result = connection::with_conn(&handle, |conn| {
+        crate::slow_query::configure(conn, &handle.trace_hook, Some(log))
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn clear_slow_query_log(env: Env<'_>, handle: ResourceArc<XqliteConn>) -> Term<'_> {
+    let result = connection::with_conn(&handle, |conn| {
+        crate::slow_query::configure(conn, &handle.trace_hook, None)
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn slow_queries(env: Env<'_>, handle: ResourceArc<XqliteConn>) -> Term<'_> {
+    let result = connection::with_conn(&handle, |_conn| {
+        crate::slow_query::drain(&handle.trace_hook.slow_queries)
+    });
+    match result {
+        Ok(records) => (ok(), records).encode(env),
+        Err(err) => (error(), err).encode(env),
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Progress hook NIFs (multi-subscriber on the progress_dispatch slot)
 // ---------------------------------------------------------------------------
@@ -1600,31 +1998,214 @@ fn unregister_progress_hook(
 }
 
 // ---------------------------------------------------------------------------
-// Serialize / Deserialize NIFs
+// User-defined SQL functions (Elixir-backed)
 // ---------------------------------------------------------------------------
 
 #[rustler::nif(schedule = "DirtyIo")]
-fn serialize<'a>(
-    env: Env<'a>,
+fn register_scalar_function(
+    env: Env<'_>,
     handle: ResourceArc<XqliteConn>,
-    schema: String,
-) -> Result<rustler::Binary<'a>, XqliteError> {
-    connection::with_conn(&handle, |conn| {
-        let data = conn.serialize(schema.as_str())?;
-        let bytes: &[u8] = &data;
-        let mut binary = rustler::OwnedBinary::new(bytes.len()).ok_or_else(|| {
-            XqliteError::InternalEncodingError {
-                context: "failed to allocate binary for serialized database".to_string(),
-            }
-        })?;
-        binary.as_mut_slice().copy_from_slice(bytes);
-        Ok(binary.release(env))
-    })
+    name: String,
+    n_args: i32,
+    flags: Vec<rustler::Atom>,
+    pid: function::HandlerPid,
+    timeout_ms: u64,
+) -> Term<'_> {
+    let (flags, handler) = match function::parse_registration(flags, pid, timeout_ms) {
+        Ok(parsed) => parsed,
+        Err(e) => return (error(), e).encode(env),
+    };
+    let result = connection::with_conn(&handle, |conn| {
+        function::register_scalar(conn, &name, n_args, flags, handler)
+    });
+    singular_ok_or_error_tuple(env, result)
 }
 
 #[rustler::nif(schedule = "DirtyIo")]
-fn deserialize<'a>(
-    env: Env<'a>,
+fn unregister_function(
+    env: Env<'_>,
+    handle: ResourceArc<XqliteConn>,
+    name: String,
+    n_args: i32,
+) -> Term<'_> {
+    let result =
+        connection::with_conn(&handle, |conn| function::unregister(conn, &name, n_args));
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn register_aggregate_function(
+    env: Env<'_>,
+    handle: ResourceArc<XqliteConn>,
+    name: String,
+    n_args: i32,
+    flags: Vec<rustler::Atom>,
+    pid: function::HandlerPid,
+    timeout_ms: u64,
+) -> Term<'_> {
+    let parsed = function::parse_registration(flags, pid, timeout_ms);
+    register_elixir_aggregate(env, &handle, &name, n_args, parsed, false)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn register_window_function(
+    env: Env<'_>,
+    handle: ResourceArc<XqliteConn>,
+    name: String,
+    n_args: i32,
+    flags: Vec<rustler::Atom>,
+    pid: function::HandlerPid,
+    timeout_ms: u64,
+) -> Term<'_> {
+    let parsed = function::parse_registration(flags, pid, timeout_ms);
+    register_elixir_aggregate(env, &handle, &name, n_args, parsed, true)
+}
+
+fn register_elixir_aggregate<'a>(
+    env: Env<'a>,
+    handle: &ResourceArc<XqliteConn>,
+    name: &str,
+    n_args: i32,
+    parsed: Result<(FunctionFlags, function::Handler), XqliteError>,
+    window: bool,
+) -> Term<'a> {
+    let (flags, handler) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return (error(), e).encode(env),
+    };
+    let result = connection::with_conn(handle, |conn| {
+        aggregate::register_elixir(conn, name, n_args, flags, handler, window)
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn register_builtin_aggregate(
+    env: Env<'_>,
+    handle: ResourceArc<XqliteConn>,
+    name: String,
+    builtin: rustler::Atom,
+) -> Term<'_> {
+    let builtin = match aggregate::Builtin::parse(builtin) {
+        Ok(b) => b,
+        Err(e) => return (error(), e).encode(env),
+    };
+    let result = connection::with_conn(&handle, |conn| {
+        aggregate::register_builtin(conn, &name, builtin)
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
+/// Deliberately a regular (non-dirty) NIF that never touches a
+/// connection: the callback awaiting this reply is holding the
+/// connection Mutex, and delivery is a single channel send.
+#[rustler::nif]
+fn function_reply<'a>(env: Env<'a>, call_id: u64, result: Term<'a>) -> Term<'a> {
+    // Replies for unknown or already timed-out calls are dropped.
+    let _ = function::deliver(call_id, function::decode_reply(env, result));
+    ok().encode(env)
+}
+
+// ---------------------------------------------------------------------------
+// Collations
+// ---------------------------------------------------------------------------
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn register_collation(
+    env: Env<'_>,
+    handle: ResourceArc<XqliteConn>,
+    name: String,
+    pid: function::HandlerPid,
+    timeout_ms: u64,
+) -> Term<'_> {
+    let handler = match pid.into_handler(timeout_ms) {
+        Ok(h) => h,
+        Err(e) => return (error(), e).encode(env),
+    };
+    let result = connection::with_conn(&handle, |conn| {
+        collation::register_elixir(conn, &name, handler)
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn register_native_collation(
+    env: Env<'_>,
+    handle: ResourceArc<XqliteConn>,
+    name: String,
+    native: rustler::Atom,
+) -> Term<'_> {
+    let native = match collation::Native::parse(native) {
+        Ok(n) => n,
+        Err(e) => return (error(), e).encode(env),
+    };
+    let result = connection::with_conn(&handle, |conn| {
+        collation::register_native(conn, &name, native)
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn unregister_collation(
+    env: Env<'_>,
+    handle: ResourceArc<XqliteConn>,
+    name: String,
+) -> Term<'_> {
+    let result = connection::with_conn(&handle, |conn| collation::unregister(conn, &name));
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn set_collation_needed_hook(
+    env: Env<'_>,
+    handle: ResourceArc<XqliteConn>,
+    pid: function::HandlerPid,
+    timeout_ms: u64,
+) -> Term<'_> {
+    let handler = match pid.into_handler(timeout_ms) {
+        Ok(h) => h,
+        Err(e) => return (error(), e).encode(env),
+    };
+    let result = connection::with_conn(&handle, |conn| {
+        collation::set_needed_hook(conn, &handle.collation_needed, handler)
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn remove_collation_needed_hook(env: Env<'_>, handle: ResourceArc<XqliteConn>) -> Term<'_> {
+    let result = connection::with_conn(&handle, |conn| {
+        collation::remove_needed_hook(conn, &handle.collation_needed)
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
+// ---------------------------------------------------------------------------
+// Serialize / Deserialize NIFs
+// ---------------------------------------------------------------------------
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn serialize<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<XqliteConn>,
+    schema: String,
+) -> Result<rustler::Binary<'a>, XqliteError> {
+    connection::with_conn(&handle, |conn| {
+        let data = conn.serialize(schema.as_str())?;
+        let bytes: &[u8] = &data;
+        let mut binary = rustler::OwnedBinary::new(bytes.len()).ok_or_else(|| {
+            XqliteError::InternalEncodingError {
+                context: "failed to allocate binary for serialized database".to_string(),
+            }
+        })?;
+        binary.as_mut_slice().copy_from_slice(bytes);
+        Ok(binary.release(env))
+    })
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn deserialize<'a>(
+    env: Env<'a>,
     handle: ResourceArc<XqliteConn>,
     schema: String,
     data: rustler::Binary<'a>,
@@ -1856,14 +2437,14 @@ fn encode_query_result_with_changes<'a>(
 #[rustler::nif(schedule = "DirtyIo")]
 fn session_new<'a>(env: Env<'a>, handle: ResourceArc<XqliteConn>) -> Term<'a> {
     let result = connection::with_conn(&handle, |conn| {
-        let s = rusqlite::session::Session::new(conn)?;
-        // SAFETY: We erase the connection lifetime. This is safe because
-        // conn_resource_arc (stored in XqliteSession) prevents the connection
-        // from being dropped while the session exists.
-        let static_session: rusqlite::session::Session<'static> =
-            unsafe { std::mem::transmute(s) };
+        // The session would take over the preupdate slot from the hook.
+        if !handle.preupdate_hook.is_empty() {
+            return Err(XqliteError::PreupdateHookConflict);
+        }
+        let raw = RawSession::new(conn, "main")?;
+        handle.live_sessions.fetch_add(1, Ordering::Relaxed);
         Ok(ResourceArc::new(XqliteSession {
-            session: std::sync::Mutex::new(Some(static_session)),
+            session: std::sync::Mutex::new(Some(raw)),
             conn_resource_arc: handle.clone(),
         }))
     });
@@ -1879,13 +2460,7 @@ fn session_attach<'a>(
     session_handle: ResourceArc<XqliteSession>,
     table: Option<String>,
 ) -> Term<'a> {
-    let result = session::with_session_mut(&session_handle, |s| {
-        match &table {
-            Some(name) => s.attach(Some(name.as_str()))?,
-            None => s.attach(None::<&str>)?,
-        }
-        Ok(())
-    });
+    let result = session::with_session_mut(&session_handle, |s| s.attach(table.as_deref()));
     singular_ok_or_error_tuple(env, result)
 }
 
@@ -1896,7 +2471,7 @@ fn session_changeset<'a>(
 ) -> Term<'a> {
     let result = session::with_session_mut(&session_handle, |s| {
         let mut output = Vec::new();
-        s.changeset_strm(&mut output)?;
+        s.changeset_to(&mut output)?;
         session::to_owned_binary(&output, "changeset")
     });
     match result {
@@ -1909,7 +2484,7 @@ fn session_changeset<'a>(
 fn session_patchset<'a>(env: Env<'a>, session_handle: ResourceArc<XqliteSession>) -> Term<'a> {
     let result = session::with_session_mut(&session_handle, |s| {
         let mut output = Vec::new();
-        s.patchset_strm(&mut output)?;
+        s.patchset_to(&mut output)?;
         session::to_owned_binary(&output, "patchset")
     });
     match result {
@@ -1918,11 +2493,167 @@ fn session_patchset<'a>(env: Env<'a>, session_handle: ResourceArc<XqliteSession>
     }
 }
 
+#[rustler::nif(schedule = "DirtyIo")]
+fn session_changeset_file(
+    env: Env<'_>,
+    session_handle: ResourceArc<XqliteSession>,
+    path: String,
+) -> Term<'_> {
+    singular_ok_or_error_tuple(env, session_output_file(&session_handle, path, false))
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn session_patchset_file(
+    env: Env<'_>,
+    session_handle: ResourceArc<XqliteSession>,
+    path: String,
+) -> Term<'_> {
+    singular_ok_or_error_tuple(env, session_output_file(&session_handle, path, true))
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn session_changeset_send(
+    env: Env<'_>,
+    session_handle: ResourceArc<XqliteSession>,
+    pid: rustler::LocalPid,
+    chunk_size: usize,
+    timeout_ms: u64,
+) -> Term<'_> {
+    let result = session_output_send(&session_handle, pid, chunk_size, timeout_ms, false);
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn session_patchset_send(
+    env: Env<'_>,
+    session_handle: ResourceArc<XqliteSession>,
+    pid: rustler::LocalPid,
+    chunk_size: usize,
+    timeout_ms: u64,
+) -> Term<'_> {
+    let result = session_output_send(&session_handle, pid, chunk_size, timeout_ms, true);
+    singular_ok_or_error_tuple(env, result)
+}
+
+fn session_output_file(
+    session_handle: &ResourceArc<XqliteSession>,
+    path: String,
+    patchset: bool,
+) -> Result<(), XqliteError> {
+    let output = create_changeset_file(path)?;
+    session::with_session_mut(session_handle, |s| {
+        if patchset {
+            s.patchset_to(output)
+        } else {
+            s.changeset_to(output)
+        }
+    })
+}
+
+fn session_output_send(
+    session_handle: &ResourceArc<XqliteSession>,
+    pid: rustler::LocalPid,
+    chunk_size: usize,
+    timeout_ms: u64,
+    patchset: bool,
+) -> Result<(), XqliteError> {
+    let mut sink = changeset::ChunkSink::new(pid, chunk_size, timeout_ms)?;
+    let result = session::with_session_mut(session_handle, |s| {
+        if patchset {
+            s.patchset_to(&mut sink)
+        } else {
+            s.changeset_to(&mut sink)
+        }
+    });
+    sink.finish(result)
+}
+
 #[rustler::nif(schedule = "DirtyIo")]
 fn session_is_empty(session_handle: ResourceArc<XqliteSession>) -> Result<bool, XqliteError> {
     session::with_session(&session_handle, |s| Ok(s.is_empty()))
 }
 
+#[rustler::nif(schedule = "DirtyIo")]
+fn session_exclude_tables<'a>(
+    env: Env<'a>,
+    session_handle: ResourceArc<XqliteSession>,
+    tables: Vec<String>,
+) -> Term<'a> {
+    let result = session::with_session_mut(&session_handle, |s| {
+        s.exclude_tables(tables);
+        Ok(())
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn session_enable<'a>(
+    env: Env<'a>,
+    session_handle: ResourceArc<XqliteSession>,
+    enabled: bool,
+) -> Term<'a> {
+    let result = session::with_session_mut(&session_handle, |s| {
+        s.set_enabled(enabled);
+        Ok(())
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn session_is_enabled(
+    session_handle: ResourceArc<XqliteSession>,
+) -> Result<bool, XqliteError> {
+    session::with_session(&session_handle, |s| Ok(s.is_enabled()))
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn session_indirect<'a>(
+    env: Env<'a>,
+    session_handle: ResourceArc<XqliteSession>,
+    indirect: bool,
+) -> Term<'a> {
+    let result = session::with_session_mut(&session_handle, |s| {
+        s.set_indirect(indirect);
+        Ok(())
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn session_is_indirect(
+    session_handle: ResourceArc<XqliteSession>,
+) -> Result<bool, XqliteError> {
+    session::with_session(&session_handle, |s| Ok(s.is_indirect()))
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn session_rowid<'a>(
+    env: Env<'a>,
+    session_handle: ResourceArc<XqliteSession>,
+    rowid: bool,
+) -> Term<'a> {
+    let result = session::with_session_mut(&session_handle, |s| s.set_rowid(rowid));
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn session_memory_used(
+    session_handle: ResourceArc<XqliteSession>,
+) -> Result<i64, XqliteError> {
+    session::with_session(&session_handle, |s| Ok(s.memory_used()))
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn session_diff<'a>(
+    env: Env<'a>,
+    session_handle: ResourceArc<XqliteSession>,
+    from_schema: String,
+    table: String,
+) -> Term<'a> {
+    let result = session::with_session_mut(&session_handle, |s| s.diff(&from_schema, &table));
+    singular_ok_or_error_tuple(env, result)
+}
+
 #[rustler::nif(schedule = "DirtyIo")]
 fn session_delete<'a>(env: Env<'a>, session_handle: ResourceArc<XqliteSession>) -> Term<'a> {
     singular_ok_or_error_tuple(env, session::close(&session_handle))
@@ -1933,50 +2664,54 @@ fn changeset_apply<'a>(
     env: Env<'a>,
     handle: ResourceArc<XqliteConn>,
     changeset_binary: rustler::Binary<'a>,
-    conflict_strategy: rustler::Atom,
+    conflict: Term<'a>,
+    opts: Vec<(rustler::Atom, Term<'a>)>,
 ) -> Term<'a> {
-    let strategy = if conflict_strategy == atoms::omit() {
-        ConflictAction::SQLITE_CHANGESET_OMIT
-    } else if conflict_strategy == atoms::replace() {
-        ConflictAction::SQLITE_CHANGESET_REPLACE
-    } else if conflict_strategy == atoms::abort() {
-        ConflictAction::SQLITE_CHANGESET_ABORT
-    } else {
-        return (atoms::error(), atoms::invalid_conflict_strategy()).encode(env);
-    };
+    let bytes = changeset_binary.as_slice();
+    let result = changeset_apply::parse_resolver(conflict)
+        .and_then(|resolver| {
+            let options = changeset_apply::parse_options(opts)?;
+            changeset_apply::Plan::new(|| Ok(bytes), resolver, options)
+        })
+        .and_then(|plan| connection::with_conn(&handle, |conn| plan.apply(conn, bytes)));
+    encode_apply_result(env, result)
+}
 
-    let result = connection::with_conn(&handle, |conn| {
-        let bytes = changeset_binary.as_slice();
-        let mut cursor = Cursor::new(bytes);
-        let strategy_code = strategy as i32;
-        conn.apply_strm(
-            &mut cursor,
-            None::<fn(&str) -> bool>,
-            move |conflict_type, _item| {
-                if strategy_code == ConflictAction::SQLITE_CHANGESET_ABORT as i32 {
-                    ConflictAction::SQLITE_CHANGESET_ABORT
-                } else if strategy_code == ConflictAction::SQLITE_CHANGESET_REPLACE as i32 {
-                    // SQLITE_CHANGESET_REPLACE is a legal return ONLY for DATA
-                    // and CONFLICT conflicts; returning it for NOTFOUND /
-                    // CONSTRAINT / FOREIGN_KEY makes sqlite3changeset_apply fail
-                    // with SQLITE_MISUSE. A `:replace` request cannot overwrite
-                    // in those cases, so abort the whole apply cleanly (rolled
-                    // back) rather than surface an opaque misuse error.
-                    match conflict_type {
-                        ConflictType::SQLITE_CHANGESET_DATA
-                        | ConflictType::SQLITE_CHANGESET_CONFLICT => {
-                            ConflictAction::SQLITE_CHANGESET_REPLACE
-                        }
-                        _ => ConflictAction::SQLITE_CHANGESET_ABORT,
-                    }
-                } else {
-                    ConflictAction::SQLITE_CHANGESET_OMIT
-                }
-            },
-        )?;
-        Ok(())
+#[rustler::nif(schedule = "DirtyIo")]
+fn changeset_apply_file<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<XqliteConn>,
+    path: String,
+    conflict: Term<'a>,
+    opts: Vec<(rustler::Atom, Term<'a>)>,
+) -> Term<'a> {
+    let open = || open_changeset_file(&path);
+    let result = changeset_apply::parse_resolver(conflict)
+        .and_then(|resolver| {
+            let options = changeset_apply::parse_options(opts)?;
+            changeset_apply::Plan::new(open, resolver, options)
+        })
+        .and_then(|plan| {
+            let input = open()?;
+            connection::with_conn(&handle, |conn| plan.apply(conn, input))
+        });
+    encode_apply_result(env, result)
+}
+
+fn encode_apply_result(
+    env: Env<'_>,
+    result: Result<Option<Vec<u8>>, XqliteError>,
+) -> Term<'_> {
+    let result = result.and_then(|rebase| {
+        rebase
+            .map(|bytes| session::to_owned_binary(&bytes, "rebase buffer"))
+            .transpose()
     });
-    singular_ok_or_error_tuple(env, result)
+    match result {
+        Ok(None) => ok().encode(env),
+        Ok(Some(binary)) => (ok(), binary.release(env)).encode(env),
+        Err(err) => (error(), err).encode(env),
+    }
 }
 
 #[rustler::nif(schedule = "DirtyIo")]
@@ -1994,6 +2729,38 @@ fn changeset_invert<'a>(env: Env<'a>, changeset_binary: rustler::Binary<'a>) ->
     }
 }
 
+#[rustler::nif(schedule = "DirtyIo")]
+fn changeset_invert_file(env: Env<'_>, input_path: String, output_path: String) -> Term<'_> {
+    let result = open_changeset_file(&input_path).and_then(|mut input| {
+        let mut output = create_changeset_file(output_path)?;
+        rusqlite::session::invert_strm(&mut input, &mut output)?;
+        output
+            .flush()
+            .map_err(|_| changeset::sqlite_error(ffi::SQLITE_IOERR_WRITE))
+    });
+    singular_ok_or_error_tuple(env, result)
+}
+
+/// Open a changeset file for incremental reading.
+fn open_changeset_file(path: &str) -> Result<BufReader<File>, XqliteError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| XqliteError::CannotOpenFile {
+            path: path.to_string(),
+            reason: e.to_string(),
+        })
+}
+
+/// Create (or truncate) a file to stream changeset output into.
+fn create_changeset_file(path: String) -> Result<BufWriter<File>, XqliteError> {
+    File::create(&path)
+        .map(BufWriter::new)
+        .map_err(|e| XqliteError::CannotOpenFile {
+            path,
+            reason: e.to_string(),
+        })
+}
+
 #[rustler::nif(schedule = "DirtyIo")]
 fn changeset_concat<'a>(
     env: Env<'a>,
@@ -2013,6 +2780,162 @@ fn changeset_concat<'a>(
     }
 }
 
+#[rustler::nif(schedule = "DirtyIo")]
+fn changeset_decode<'a>(env: Env<'a>, changeset_binary: rustler::Binary<'a>) -> Term<'a> {
+    match changeset::decode(env, changeset_binary.as_slice()) {
+        Ok(changes) => (ok(), changes).encode(env),
+        Err(err) => (error(), err).encode(env),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn changeset_iter_open<'a>(env: Env<'a>, changeset_binary: rustler::Binary<'a>) -> Term<'a> {
+    let input: changeset::Input = Box::new(Cursor::new(changeset_binary.as_slice().to_vec()));
+    match XqliteChangesetIter::open(input) {
+        Ok(iter) => (ok(), ResourceArc::new(iter)).encode(env),
+        Err(err) => (error(), err).encode(env),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn changeset_iter_open_file(env: Env<'_>, path: String) -> Term<'_> {
+    let result = std::fs::File::open(&path)
+        .map_err(|e| XqliteError::CannotOpenFile {
+            path,
+            reason: e.to_string(),
+        })
+        .and_then(|file| XqliteChangesetIter::open(Box::new(std::io::BufReader::new(file))));
+    match result {
+        Ok(iter) => (ok(), ResourceArc::new(iter)).encode(env),
+        Err(err) => (error(), err).encode(env),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn changeset_iter_next(
+    env: Env<'_>,
+    iter: ResourceArc<XqliteChangesetIter>,
+    batch_size: usize,
+) -> Term<'_> {
+    match iter.next_batch(env, batch_size) {
+        Ok(Some(changes)) => (ok(), changes).encode(env),
+        Ok(None) => atoms::done().encode(env),
+        Err(err) => (error(), err).encode(env),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn changeset_iter_close(iter: ResourceArc<XqliteChangesetIter>) -> rustler::Atom {
+    iter.close();
+    ok()
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn changegroup_new(
+    env: Env<'_>,
+    schema_conn: Option<ResourceArc<XqliteConn>>,
+    db_name: String,
+) -> Term<'_> {
+    match XqliteChangegroup::new(schema_conn, &db_name) {
+        Ok(group) => (ok(), ResourceArc::new(group)).encode(env),
+        Err(err) => (error(), err).encode(env),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn changegroup_add<'a>(
+    env: Env<'a>,
+    group: ResourceArc<XqliteChangegroup>,
+    changeset_binary: rustler::Binary<'a>,
+) -> Term<'a> {
+    singular_ok_or_error_tuple(env, group.add(changeset_binary.as_slice()))
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn changegroup_add_file(
+    env: Env<'_>,
+    group: ResourceArc<XqliteChangegroup>,
+    path: String,
+) -> Term<'_> {
+    let result = std::fs::File::open(&path)
+        .map_err(|e| XqliteError::CannotOpenFile {
+            path,
+            reason: e.to_string(),
+        })
+        .and_then(|file| group.add_from(std::io::BufReader::new(file)));
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn changegroup_output(env: Env<'_>, group: ResourceArc<XqliteChangegroup>) -> Term<'_> {
+    let result = group
+        .output()
+        .and_then(|bytes| session::to_owned_binary(&bytes, "changegroup output"));
+    match result {
+        Ok(binary) => (ok(), binary.release(env)).encode(env),
+        Err(err) => (error(), err).encode(env),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn changegroup_output_file(
+    env: Env<'_>,
+    group: ResourceArc<XqliteChangegroup>,
+    path: String,
+) -> Term<'_> {
+    let result = std::fs::File::create(&path)
+        .map_err(|e| XqliteError::CannotOpenFile {
+            path,
+            reason: e.to_string(),
+        })
+        .and_then(|file| group.output_to(std::io::BufWriter::new(file)));
+    singular_ok_or_error_tuple(env, result)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn changegroup_delete(group: ResourceArc<XqliteChangegroup>) -> rustler::Atom {
+    group.delete();
+    ok()
+}
+
+#[rustler::nif]
+fn rebaser_new(env: Env<'_>) -> Term<'_> {
+    match XqliteRebaser::new() {
+        Ok(rebaser) => (ok(), ResourceArc::new(rebaser)).encode(env),
+        Err(err) => (error(), err).encode(env),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn rebaser_configure<'a>(
+    env: Env<'a>,
+    rebaser: ResourceArc<XqliteRebaser>,
+    rebase_binary: rustler::Binary<'a>,
+) -> Term<'a> {
+    singular_ok_or_error_tuple(env, rebaser.configure(rebase_binary.as_slice()))
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn rebaser_rebase<'a>(
+    env: Env<'a>,
+    rebaser: ResourceArc<XqliteRebaser>,
+    changeset_binary: rustler::Binary<'a>,
+) -> Term<'a> {
+    let result = rebaser
+        .rebase(changeset_binary.as_slice())
+        .and_then(|bytes| session::to_owned_binary(&bytes, "rebased changeset"));
+    match result {
+        Ok(binary) => (ok(), binary.release(env)).encode(env),
+        Err(err) => (error(), err).encode(env),
+    }
+}
+
+#[rustler::nif]
+fn rebaser_delete(rebaser: ResourceArc<XqliteRebaser>) -> rustler::Atom {
+    rebaser.delete();
+    ok()
+}
+
 // ---------------------------------------------------------------------------
 // Incremental Blob I/O NIFs
 // ---------------------------------------------------------------------------
@@ -0,0 +1,200 @@
+//! Multi-subscriber dispatch for SQLite's preupdate hook.
+//!
+//! Same fan-out shape as `update_hook`, with one difference: the
+//! preupdate slot is also what the session extension records changes
+//! through (`sqlite3session_create` installs its own preupdate hook),
+//! and SQLite leaves the behavior undefined when both are in use. So
+//! instead of installing a master closure at open time, the closure is
+//! installed when the first subscriber registers and removed when the
+//! last one leaves, and registration is refused while a session is
+//! attached (and vice versa — see `session_new`).
+
+use crate::atoms;
+use crate::error::XqliteError;
+use crate::hook_util::{self, HookList};
+use crate::util::encode_val;
+use rusqlite::Connection;
+use rusqlite::hooks::{Action, PreUpdateCase};
+use rusqlite::types::{Value, ValueRef};
+use rustler::types::LocalPid;
+use rustler::types::atom::nil;
+use rustler::types::map::map_new;
+use rustler::{Encoder, Env, Term};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub(crate) struct PreupdateSubscriber {
+    pub(crate) pid: LocalPid,
+}
+
+impl std::fmt::Debug for PreupdateSubscriber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreupdateSubscriber").finish()
+    }
+}
+
+/// One row change, copied out of SQLite before the hook returns.
+struct RowChange {
+    column_count: i32,
+    depth: i32,
+    old_rowid: Option<i64>,
+    new_rowid: Option<i64>,
+    old: Option<Vec<Value>>,
+    new: Option<Vec<Value>>,
+}
+
+impl RowChange {
+    fn capture(case: &PreUpdateCase) -> Option<Self> {
+        Some(match case {
+            PreUpdateCase::Insert(new) => Self {
+                column_count: new.get_column_count(),
+                depth: new.get_query_depth(),
+                old_rowid: None,
+                new_rowid: Some(new.get_new_row_id()),
+                old: None,
+                new: Some(columns(new.get_column_count(), |i| {
+                    new.get_new_column_value(i)
+                })),
+            },
+            PreUpdateCase::Delete(old) => Self {
+                column_count: old.get_column_count(),
+                depth: old.get_query_depth(),
+                old_rowid: Some(old.get_old_row_id()),
+                new_rowid: None,
+                old: Some(columns(old.get_column_count(), |i| {
+                    old.get_old_column_value(i)
+                })),
+                new: None,
+            },
+            PreUpdateCase::Update {
+                old_value_accessor: old,
+                new_value_accessor: new,
+            } => Self {
+                column_count: old.get_column_count(),
+                depth: old.get_query_depth(),
+                old_rowid: Some(old.get_old_row_id()),
+                new_rowid: Some(new.get_new_row_id()),
+                old: Some(columns(old.get_column_count(), |i| {
+                    old.get_old_column_value(i)
+                })),
+                new: Some(columns(new.get_column_count(), |i| {
+                    new.get_new_column_value(i)
+                })),
+            },
+            PreUpdateCase::Unknown => return None,
+        })
+    }
+
+    fn encode<'a>(&self, env: Env<'a>) -> Result<Term<'a>, XqliteError> {
+        let old = encode_row(env, self.old.as_deref())?;
+        let new = encode_row(env, self.new.as_deref())?;
+        map_new(env)
+            .map_put(atoms::column_count(), self.column_count)
+            .and_then(|m| m.map_put(atoms::depth(), self.depth))
+            .and_then(|m| m.map_put(atoms::old_rowid(), self.old_rowid))
+            .and_then(|m| m.map_put(atoms::new_rowid(), self.new_rowid))
+            .and_then(|m| m.map_put(atoms::old(), old))
+            .and_then(|m| m.map_put(atoms::new(), new))
+            .map_err(|_| XqliteError::InternalEncodingError {
+                context: "preupdate details map_put failed".to_string(),
+            })
+    }
+}
+
+/// Copy `count` column values out through `get`. A value SQLite cannot
+/// hand over (out-of-memory, a column index it rejects, or TEXT that is
+/// not valid UTF-8) becomes NULL rather than dropping the whole event.
+fn columns<'a, F>(count: i32, get: F) -> Vec<Value>
+where
+    F: Fn(i32) -> rusqlite::Result<ValueRef<'a>>,
+{
+    (0..count)
+        .map(|i| {
+            get(i)
+                .ok()
+                .and_then(|v| Value::try_from(v).ok())
+                .unwrap_or(Value::Null)
+        })
+        .collect()
+}
+
+fn encode_row<'a>(env: Env<'a>, row: Option<&[Value]>) -> Result<Term<'a>, XqliteError> {
+    let Some(values) = row else {
+        return Ok(nil().encode(env));
+    };
+    let mut terms = Vec::with_capacity(values.len());
+    for value in values {
+        terms.push(encode_val(env, value.clone())?);
+    }
+    Ok(terms.encode(env))
+}
+
+/// Register a preupdate subscriber, installing the master closure if
+/// this is the first one. Refused while a session is attached to the
+/// connection. Callers must hold the connection Mutex.
+pub(crate) fn register(
+    conn: &Connection,
+    list: &Arc<HookList<PreupdateSubscriber>>,
+    live_sessions: usize,
+    pid: LocalPid,
+) -> Result<u64, XqliteError> {
+    if live_sessions > 0 {
+        return Err(XqliteError::PreupdateHookConflict);
+    }
+    if list.is_empty() {
+        install_callback(conn, Arc::clone(list))?;
+    }
+    Ok(list.register(PreupdateSubscriber { pid }))
+}
+
+/// Remove a preupdate subscriber. Idempotent. Removing the last one
+/// frees the preupdate slot for sessions. Callers must hold the
+/// connection Mutex.
+pub(crate) fn unregister(
+    conn: &Connection,
+    list: &HookList<PreupdateSubscriber>,
+    id: u64,
+) -> Result<(), XqliteError> {
+    if list.unregister(id) && list.is_empty() {
+        conn.preupdate_hook(None::<fn(Action, &str, &str, &PreUpdateCase)>)?;
+    }
+    Ok(())
+}
+
+fn install_callback(
+    conn: &Connection,
+    list: Arc<HookList<PreupdateSubscriber>>,
+) -> Result<(), XqliteError> {
+    conn.preupdate_hook(Some(
+        move |action: Action, db: &str, table: &str, case: &PreUpdateCase| {
+            let action_name = match action {
+                Action::SQLITE_INSERT => atoms::insert(),
+                Action::SQLITE_UPDATE => atoms::update(),
+                Action::SQLITE_DELETE => atoms::delete(),
+                _ => atoms::unknown(),
+            };
+            let Some(change) = RowChange::capture(case) else {
+                return;
+            };
+
+            // SAFETY: the closure captures Arc<HookList>, so the list
+            // outlives every callback. Each message is built in a fresh
+            // msg_env by `send_with_env`; nothing escapes it.
+            unsafe {
+                list.for_each_snapshot(|entry| {
+                    let _ = hook_util::send_with_env(&entry.state.pid, |env| {
+                        Ok((
+                            atoms::xqlite_preupdate(),
+                            action_name,
+                            db,
+                            table,
+                            change.encode(env)?,
+                        )
+                            .encode(env))
+                    });
+                });
+            }
+        },
+    ))?;
+    Ok(())
+}
@@ -1,12 +1,15 @@
 use crate::connection::XqliteQueryResult;
 use crate::error::XqliteError;
+use crate::statement_cache::StatementCache;
 use crate::util::{
     decode_exec_keyword_params, decode_plain_list_params, is_keyword, process_rows,
 };
 use rusqlite::types::Value;
-use rusqlite::{Connection, ToSql};
+use rusqlite::{Connection, Statement, ToSql};
 use rustler::types::atom::nil;
 use rustler::{Env, Term, TermType};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 /// Reject SQL text containing an interior NUL byte before it reaches SQLite.
 ///
@@ -28,14 +31,12 @@ fn reject_interior_nul(sql: &str) -> Result<(), XqliteError> {
 pub(crate) fn core_query<'a>(
     env: Env<'a>,
     conn: &Connection,
+    cache: &StatementCache,
     sql: &str,
     params_term: Term<'a>,
 ) -> Result<XqliteQueryResult<'a>, XqliteError> {
     reject_interior_nul(sql)?;
-    let mut stmt = conn.prepare(sql)?;
-    let column_names: Vec<String> =
-        stmt.column_names().iter().map(|s| s.to_string()).collect();
-    let column_count = column_names.len();
+    let mut stmt = cache.prepare(conn, sql)?;
 
     let rows_result = match params_term.get_type() {
         TermType::List => {
@@ -65,8 +66,12 @@ pub(crate) fn core_query<'a>(
     };
     let rows = rows_result?;
 
-    let results_vec = process_rows(env, rows, column_count)?;
+    let results_vec = process_rows(env, rows)?;
     let num_rows = results_vec.len();
+    // Read after stepping: a cached statement SQLite re-prepared for a new
+    // schema may have gained or lost columns since it was last used.
+    let column_names: Vec<String> =
+        stmt.column_names().iter().map(|s| s.to_string()).collect();
 
     Ok(XqliteQueryResult {
         columns: column_names,
@@ -89,11 +94,12 @@ pub(crate) fn core_query<'a>(
 pub(crate) fn core_query_with_changes<'a>(
     env: Env<'a>,
     conn: &Connection,
+    cache: &StatementCache,
     sql: &str,
     params_term: Term<'a>,
 ) -> Result<(XqliteQueryResult<'a>, u64), XqliteError> {
     let before = conn.total_changes();
-    let qr = core_query(env, conn, sql, params_term)?;
+    let qr = core_query(env, conn, cache, sql, params_term)?;
     let changes = if conn.total_changes() == before {
         0
     } else {
@@ -105,12 +111,22 @@ pub(crate) fn core_query_with_changes<'a>(
 pub(crate) fn core_execute<'a>(
     env: Env<'a>,
     conn: &Connection,
+    cache: &StatementCache,
     sql: &str,
     params_term: Term<'a>,
 ) -> Result<usize, XqliteError> {
     reject_interior_nul(sql)?;
-    let mut stmt = conn.prepare(sql)?;
+    let mut stmt = cache.prepare(conn, sql)?;
+    execute_with_params(env, &mut stmt, params_term)
+}
 
+/// Binds one positional or keyword parameter list (or `nil`) and runs
+/// the statement to completion, returning its change count.
+fn execute_with_params<'a>(
+    env: Env<'a>,
+    stmt: &mut Statement<'_>,
+    params_term: Term<'a>,
+) -> Result<usize, XqliteError> {
     let affected_rows = match params_term.get_type() {
         TermType::List => {
             if params_term.is_empty_list() {
@@ -141,6 +157,79 @@ pub(crate) fn core_execute<'a>(
     Ok(affected_rows)
 }
 
+/// Runs one statement once per parameter set, preparing it a single time.
+///
+/// With `transaction` the whole run sits in a savepoint, so it is one
+/// transaction in autocommit mode and nests inside an open one; any
+/// failure rolls back every row. Without it each row commits on its own
+/// and the rows before a failure stay applied.
+///
+/// A failing row is reported as `RowFailed` carrying its zero-based
+/// index. Cancellation is checked between rows as well as by the
+/// progress handler inside each step, and is reported unwrapped.
+pub(crate) fn core_execute_many<'a>(
+    env: Env<'a>,
+    conn: &Connection,
+    cache: &StatementCache,
+    sql: &str,
+    param_sets: Term<'a>,
+    transaction: bool,
+    cancel_tokens: &[Arc<AtomicBool>],
+) -> Result<u64, XqliteError> {
+    reject_interior_nul(sql)?;
+    let param_sets: Vec<Term<'a>> =
+        param_sets.decode().map_err(|_| XqliteError::ExpectedList {
+            value_str: format!("{param_sets:?}"),
+        })?;
+    let mut stmt = cache.prepare(conn, sql)?;
+
+    if !transaction {
+        return execute_param_sets(env, &mut stmt, &param_sets, cancel_tokens);
+    }
+
+    conn.execute_batch("SAVEPOINT xqlite_execute_many")?;
+    let outcome =
+        execute_param_sets(env, &mut stmt, &param_sets, cancel_tokens).and_then(|changes| {
+            conn.execute_batch("RELEASE xqlite_execute_many")?;
+            Ok(changes)
+        });
+    if outcome.is_err() {
+        // Best effort: an interrupt or an `ON CONFLICT ROLLBACK` may have
+        // already rolled the transaction back, taking the savepoint with it.
+        let _ =
+            conn.execute_batch("ROLLBACK TO xqlite_execute_many; RELEASE xqlite_execute_many");
+    }
+    outcome
+}
+
+fn execute_param_sets<'a>(
+    env: Env<'a>,
+    stmt: &mut Statement<'_>,
+    param_sets: &[Term<'a>],
+    cancel_tokens: &[Arc<AtomicBool>],
+) -> Result<u64, XqliteError> {
+    let mut changes: u64 = 0;
+    for (index, params_term) in param_sets.iter().enumerate() {
+        // OR-semantics: any signalled token cancels the run.
+        if cancel_tokens.iter().any(|t| t.load(Ordering::Acquire)) {
+            return Err(XqliteError::OperationCancelled);
+        }
+        match execute_with_params(env, stmt, *params_term) {
+            Ok(affected) => changes += affected as u64,
+            Err(XqliteError::OperationCancelled) => {
+                return Err(XqliteError::OperationCancelled);
+            }
+            Err(error) => {
+                return Err(XqliteError::RowFailed {
+                    index,
+                    error: Box::new(error),
+                });
+            }
+        }
+    }
+    Ok(changes)
+}
+
 pub(crate) fn core_execute_batch(
     conn: &Connection,
     sql_batch: &str,
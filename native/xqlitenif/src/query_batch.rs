@@ -0,0 +1,232 @@
+//! `query_batch`: runs a multi-statement script and keeps every
+//! statement's result set, in order.
+//!
+//! The script is walked with the `sqlite3_prepare_v2` tail pointer — the
+//! same loop `stmt_prepare` uses to reject trailing SQL, here continued:
+//! prepare one statement, run it to completion, resume at its tail. A
+//! statement that fails to prepare leaves no trustworthy tail, so when
+//! continuing past errors the walk resumes after the shortest prefix that
+//! `sqlite3_complete` accepts as a whole statement.
+
+use crate::connection::XqliteQueryResult;
+use crate::error::XqliteError;
+use crate::explain_analyze::ffi_error;
+use crate::util::sqlite_row_to_elixir_terms;
+use rusqlite::{Connection, ffi};
+use rustler::Env;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+
+/// One statement's outcome: its result set and the rows it changed.
+pub(crate) type StatementOutcome<'a> = Result<(XqliteQueryResult<'a>, u64), XqliteError>;
+
+/// Run every statement of `sql`. With `continue_on_error` a failing
+/// statement is recorded and the rest still run; otherwise the failure is
+/// the last entry. Whitespace and comments between statements produce no
+/// entry. Callers must hold the connection Mutex.
+pub(crate) fn query_batch<'a>(
+    env: Env<'a>,
+    conn: &Connection,
+    sql: &str,
+    continue_on_error: bool,
+) -> Result<Vec<StatementOutcome<'a>>, XqliteError> {
+    let c_sql = CString::new(sql).map_err(|_| XqliteError::NulErrorInString)?;
+    let len = c_sql.as_bytes().len();
+    // SAFETY: the caller holds the connection Mutex (fn contract).
+    let db_handle = unsafe { conn.handle() };
+
+    let mut outcomes = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        let remaining = c_int::try_from(len - offset)
+            .map_err(|_| XqliteError::CannotExecute("SQL too long".to_string()))?;
+        let mut raw_stmt_ptr: *mut ffi::sqlite3_stmt = std::ptr::null_mut();
+        let mut tail_ptr: *const c_char = std::ptr::null();
+        // SAFETY: `offset < len`, so the pointer stays inside `c_sql`, which
+        // outlives the call; the Mutex is held (fn contract).
+        let prepare_rc = unsafe {
+            ffi::sqlite3_prepare_v2(
+                db_handle,
+                c_sql.as_ptr().add(offset),
+                remaining,
+                &mut raw_stmt_ptr,
+                &mut tail_ptr,
+            )
+        };
+
+        if prepare_rc != ffi::SQLITE_OK {
+            let start = offset + leading_separators(&sql[offset..]);
+            let end = statement_end(sql, start)?;
+            // SAFETY: as above; nothing has touched the connection since the
+            // failed prepare.
+            outcomes.push(Err(unsafe {
+                prepare_error(db_handle, prepare_rc, &sql[start..end], start - offset)
+            }));
+            if !continue_on_error {
+                break;
+            }
+            offset = end;
+            continue;
+        }
+
+        // The tail lies within `c_sql`; a null tail means nothing is left.
+        offset = if tail_ptr.is_null() {
+            len
+        } else {
+            (tail_ptr as usize) - (c_sql.as_ptr() as usize)
+        };
+
+        // Null with SQLITE_OK: only whitespace/comments were consumed.
+        if raw_stmt_ptr.is_null() {
+            continue;
+        }
+
+        // SAFETY: `raw_stmt_ptr` was just prepared on `db_handle` and is
+        // finalized right after; the Mutex is held (fn contract).
+        let outcome = unsafe { run_statement(env, db_handle, raw_stmt_ptr) };
+        // SAFETY: finalizing the statement prepared above, exactly once.
+        unsafe { ffi::sqlite3_finalize(raw_stmt_ptr) };
+
+        let failed = outcome.is_err();
+        outcomes.push(outcome);
+        if failed && !continue_on_error {
+            break;
+        }
+    }
+    Ok(outcomes)
+}
+
+/// # Safety
+/// `stmt_ptr` must be a freshly prepared statement on `db_handle`, whose
+/// connection Mutex the caller holds.
+unsafe fn run_statement<'a>(
+    env: Env<'a>,
+    db_handle: *mut ffi::sqlite3,
+    stmt_ptr: *mut ffi::sqlite3_stmt,
+) -> StatementOutcome<'a> {
+    // SAFETY: `stmt_ptr` is live and the Mutex is held (fn contract).
+    let column_count = unsafe { ffi::sqlite3_column_count(stmt_ptr) } as usize;
+    let mut columns = Vec::with_capacity(column_count);
+    for i in 0..column_count {
+        // SAFETY: `i` is below the statement's column count.
+        let name_ptr = unsafe { ffi::sqlite3_column_name(stmt_ptr, i as c_int) };
+        if name_ptr.is_null() {
+            return Err(XqliteError::InternalEncodingError {
+                context: format!("SQLite returned null column name for index {i}"),
+            });
+        }
+        // SAFETY: non-null, NUL-terminated, valid until the statement is
+        // finalized or re-prepared; copied out immediately.
+        columns.push(
+            unsafe { CStr::from_ptr(name_ptr) }
+                .to_string_lossy()
+                .into_owned(),
+        );
+    }
+
+    // SAFETY: `db_handle` is valid and the Mutex is held (fn contract).
+    let changes_before = unsafe { ffi::sqlite3_total_changes64(db_handle) };
+    let mut rows = Vec::new();
+    loop {
+        // SAFETY: `stmt_ptr` is live and the Mutex is held (fn contract).
+        match unsafe { ffi::sqlite3_step(stmt_ptr) } {
+            ffi::SQLITE_ROW => {
+                // SAFETY: the statement has just returned SQLITE_ROW and
+                // `column_count` is its column count.
+                let row = unsafe { sqlite_row_to_elixir_terms(env, stmt_ptr, column_count)? };
+                rows.push(row);
+            }
+            ffi::SQLITE_DONE => break,
+            // SAFETY: `db_handle` is valid and the Mutex is held (fn contract).
+            rc => return Err(unsafe { ffi_error(db_handle, rc) }),
+        }
+    }
+
+    // Same sticky-counter rule as `core_query_with_changes`: only a
+    // statement that moved the total changed anything.
+    // SAFETY: as above.
+    let changes = unsafe {
+        if ffi::sqlite3_total_changes64(db_handle) == changes_before {
+            0
+        } else {
+            ffi::sqlite3_changes64(db_handle) as u64
+        }
+    };
+
+    let num_rows = rows.len();
+    Ok((
+        XqliteQueryResult {
+            columns,
+            rows,
+            num_rows,
+        },
+        changes,
+    ))
+}
+
+/// Like rusqlite's own prepare: a syntax error SQLite can locate becomes
+/// an `SqlInputError` carrying the failing statement and the offset into
+/// it. `skipped` is how far the statement starts into the prepared input.
+///
+/// # Safety
+/// `db_handle` must be valid, its Mutex held, and the failed prepare its
+/// most recent API call.
+unsafe fn prepare_error(
+    db_handle: *mut ffi::sqlite3,
+    code: c_int,
+    stmt_sql: &str,
+    skipped: usize,
+) -> XqliteError {
+    // SAFETY: forwarded fn contract.
+    let offset = unsafe { ffi::sqlite3_error_offset(db_handle) };
+    // SAFETY: forwarded fn contract.
+    let error = unsafe { ffi_error(db_handle, code) };
+    let skipped = c_int::try_from(skipped).unwrap_or(c_int::MAX);
+    match error {
+        XqliteError::SqliteFailure {
+            message: Some(msg), ..
+        } if offset >= skipped => XqliteError::from(rusqlite::Error::SqlInputError {
+            error: ffi::Error::new(code),
+            msg,
+            sql: stmt_sql.to_string(),
+            offset: offset - skipped,
+        }),
+        other => other,
+    }
+}
+
+/// Length of the whitespace, comments and empty statements that
+/// `sqlite3_prepare_v2` passes over before the next statement.
+fn leading_separators(sql: &str) -> usize {
+    let bytes = sql.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b';' | b' ' | b'\t' | b'\n' | b'\r' | b'\x0c' => i += 1,
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = sql[i..].find('\n').map_or(bytes.len(), |n| i + n + 1);
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = sql[i + 2..].find("*/").map_or(bytes.len(), |n| i + n + 4);
+            }
+            _ => break,
+        }
+    }
+    i
+}
+
+/// Byte offset just past the statement starting at `offset`: the first
+/// `;` that completes it per `sqlite3_complete`, or the end of `sql`.
+fn statement_end(sql: &str, offset: usize) -> Result<usize, XqliteError> {
+    let rest = &sql[offset..];
+    for (i, _) in rest.match_indices(';') {
+        let candidate =
+            CString::new(&rest[..=i]).map_err(|_| XqliteError::NulErrorInString)?;
+        // SAFETY: `candidate` is a NUL-terminated string that outlives the
+        // call; `sqlite3_complete` only scans it.
+        if unsafe { ffi::sqlite3_complete(candidate.as_ptr()) } != 0 {
+            return Ok(offset + i + 1);
+        }
+    }
+    Ok(sql.len())
+}
@@ -0,0 +1,115 @@
+//! Rebasing local changesets over remote changes.
+//!
+//! An offline client that pulls remote changesets applies them over its
+//! own unsynced edits, resolving conflicts as it sees fit.
+//! `changeset_apply(..., rebase: true)` returns those decisions as a
+//! rebase buffer. A `sqlite3_rebaser` configured with it rewrites the
+//! client's local changesets so they can be pushed upstream on top of
+//! the remote changes: a local INSERT whose remote twin was omitted
+//! becomes an UPDATE of the remote row, one that lost to `:replace` is
+//! dropped, and so on.
+//!
+//! A rebaser is not tied to a connection: it only transforms changeset
+//! bytes, so a plain Mutex serializes access.
+
+use crate::changeset;
+use crate::error::XqliteError;
+use rusqlite::ffi;
+use rustler::{Resource, resource_impl};
+use std::os::raw::c_int;
+use std::sync::Mutex;
+
+/// Owned `sqlite3_rebaser` pointer.
+struct Rebaser(*mut ffi::sqlite3_rebaser);
+
+impl Drop for Rebaser {
+    fn drop(&mut self) {
+        // SAFETY: the pointer came from `sqlite3rebaser_create` and is
+        // deleted exactly once, here.
+        unsafe { ffi::sqlite3rebaser_delete(self.0) }
+    }
+}
+
+pub(crate) struct XqliteRebaser {
+    /// `None` once deleted.
+    rebaser: Mutex<Option<Rebaser>>,
+}
+
+// SAFETY: the raw rebaser pointer is only used while the Mutex is held.
+unsafe impl Send for XqliteRebaser {}
+// SAFETY: see the `Send` impl above; access is serialized by the Mutex.
+unsafe impl Sync for XqliteRebaser {}
+
+#[resource_impl]
+impl Resource for XqliteRebaser {}
+
+impl XqliteRebaser {
+    pub(crate) fn new() -> Result<Self, XqliteError> {
+        let mut ptr: *mut ffi::sqlite3_rebaser = std::ptr::null_mut();
+        // SAFETY: `ptr` is a valid out-pointer.
+        changeset::check(unsafe { ffi::sqlite3rebaser_create(&mut ptr) })?;
+        Ok(Self {
+            rebaser: Mutex::new(Some(Rebaser(ptr))),
+        })
+    }
+
+    fn with_rebaser<F, R>(&self, f: F) -> Result<R, XqliteError>
+    where
+        F: FnOnce(*mut ffi::sqlite3_rebaser) -> Result<R, XqliteError>,
+    {
+        let guard = self
+            .rebaser
+            .lock()
+            .map_err(|e| XqliteError::LockError(e.to_string()))?;
+        match guard.as_ref() {
+            Some(rebaser) => f(rebaser.0),
+            None => Err(XqliteError::CannotExecute(
+                "rebaser has been deleted".to_string(),
+            )),
+        }
+    }
+
+    /// Add a rebase buffer produced by `changeset_apply`. May be called
+    /// several times; the buffers accumulate.
+    pub(crate) fn configure(&self, rebase: &[u8]) -> Result<(), XqliteError> {
+        let len = c_int::try_from(rebase.len())
+            .map_err(|_| changeset::sqlite_error(ffi::SQLITE_TOOBIG))?;
+        self.with_rebaser(|rebaser| {
+            // SAFETY: `rebaser` is live under the held Mutex; SQLite copies
+            // the buffer before returning.
+            changeset::check(unsafe {
+                ffi::sqlite3rebaser_configure(rebaser, len, rebase.as_ptr().cast())
+            })
+        })
+    }
+
+    /// Rebase `bytes` against every buffer configured so far.
+    pub(crate) fn rebase(&self, bytes: &[u8]) -> Result<Vec<u8>, XqliteError> {
+        self.with_rebaser(|rebaser| {
+            let mut input: &[u8] = bytes;
+            let mut output = Vec::new();
+            // SAFETY: `rebaser` is live under the held Mutex; `input` and
+            // `output` outlive the call and are only used by the stream
+            // callbacks, on this thread.
+            changeset::check(unsafe {
+                ffi::sqlite3rebaser_rebase_strm(
+                    rebaser,
+                    Some(changeset::x_input::<&[u8]>),
+                    (&mut input as *mut &[u8]).cast(),
+                    Some(changeset::x_output::<Vec<u8>>),
+                    (&mut output as *mut Vec<u8>).cast(),
+                )
+            })?;
+            Ok(output)
+        })
+    }
+
+    /// Delete the rebaser. Idempotent.
+    pub(crate) fn delete(&self) {
+        let mut guard = match self.rebaser.lock() {
+            Ok(g) => g,
+            Err(p) => p.into_inner(),
+        };
+        guard.take();
+    }
+}
@@ -5,6 +5,7 @@
 
 use crate::error::XqliteError;
 use crate::hook_util::{self, HookList};
+use crate::update_hook::{self, UpdateHooks};
 use rustler::sys::{enif_alloc_env, enif_free_env, enif_make_tuple_from_array, enif_send};
 use rustler::types::LocalPid;
 use std::sync::Arc;
@@ -26,11 +27,16 @@ impl RollbackSubscriber {
     }
 }
 
+/// Install the master rollback-hook closure. It also ends the
+/// transaction for batch-mode update subscribers: their buffered row
+/// events are dropped for them (see `update_hook::discard_batches`).
 pub(crate) fn install_callback(
     conn: &rusqlite::Connection,
     list: Arc<HookList<RollbackSubscriber>>,
+    updates: Arc<UpdateHooks>,
 ) -> Result<(), XqliteError> {
     conn.rollback_hook(Some(move || {
+        update_hook::discard_batches(&updates);
         // SAFETY: closure-captured Arc keeps the list alive across calls.
         unsafe {
             list.for_each_snapshot(|entry| {
@@ -1,21 +1,219 @@
+use crate::changeset::{self, sqlite_error};
 use crate::connection::XqliteConn;
 use crate::error::XqliteError;
-use rusqlite::session::Session;
+use crate::hook_util;
+use rusqlite::{Connection, ffi};
 use rustler::{Resource, ResourceArc, resource_impl};
+use std::ffi::{CStr, CString};
+use std::io::Write;
+use std::os::raw::{c_char, c_int, c_void};
 use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+
+/// Owned `sqlite3_session` handle.
+///
+/// Wrapping the raw pointer rather than rusqlite's `Session` follows the
+/// rule in `blob.rs`, and gives `sqlite3session_diff` ownership of its
+/// error message: rusqlite's `Session::diff` dereferences it even when
+/// SQLite leaves it null (I/O errors on the other database).
+pub(crate) struct RawSession {
+    ptr: *mut ffi::sqlite3_session,
+    /// Context of `x_table_filter`. Boxed so the address handed to SQLite
+    /// stays put; dropped only after `sqlite3session_delete`.
+    filter: Option<Box<TableFilter>>,
+}
+
+/// Tables `sqlite3session_table_filter` refuses to auto-attach.
+struct TableFilter {
+    excluded: Vec<String>,
+}
+
+impl RawSession {
+    /// Create a session recording changes to database `db_name` of `conn`.
+    pub(crate) fn new(conn: &Connection, db_name: &str) -> Result<Self, XqliteError> {
+        let z_db = CString::new(db_name).map_err(|_| XqliteError::NulErrorInString)?;
+        let mut ptr: *mut ffi::sqlite3_session = std::ptr::null_mut();
+        // SAFETY: caller holds the connection Mutex; `ptr` is a valid
+        // out-pointer and SQLite copies `z_db`.
+        changeset::check(unsafe {
+            ffi::sqlite3session_create(conn.handle(), z_db.as_ptr(), &mut ptr)
+        })?;
+        Ok(Self { ptr, filter: None })
+    }
+
+    /// Attach one table, or every table when `table` is `None`.
+    pub(crate) fn attach(&mut self, table: Option<&str>) -> Result<(), XqliteError> {
+        let z_tab = table
+            .map(CString::new)
+            .transpose()
+            .map_err(|_| XqliteError::NulErrorInString)?;
+        let p_tab = z_tab.as_ref().map_or(std::ptr::null(), |z| z.as_ptr());
+        // SAFETY: the session is live and the connection Mutex held.
+        changeset::check(unsafe { ffi::sqlite3session_attach(self.ptr, p_tab) })
+    }
+
+    /// Auto-attach every table (as `attach(None)`) except those named in
+    /// `exclude`, compared case-insensitively like SQLite table names.
+    /// Replaces any earlier exclusion list; tables already attached stay
+    /// attached.
+    pub(crate) fn exclude_tables(&mut self, exclude: Vec<String>) {
+        let filter = Box::new(TableFilter { excluded: exclude });
+        let ctx = std::ptr::from_ref::<TableFilter>(&filter).cast_mut().cast();
+        // SAFETY: the session is live and the connection Mutex held. The
+        // previous context, if any, is dropped only after SQLite has been
+        // handed the new one, and `filter` lives as long as `self`.
+        unsafe { ffi::sqlite3session_table_filter(self.ptr, Some(x_table_filter), ctx) };
+        self.filter = Some(filter);
+    }
+
+    /// Pause (`false`) or resume (`true`) recording.
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        // SAFETY: the session is live and the connection Mutex held.
+        unsafe { ffi::sqlite3session_enable(self.ptr, c_int::from(enabled)) };
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        // SAFETY: as in `set_enabled`; -1 only queries the flag.
+        unsafe { ffi::sqlite3session_enable(self.ptr, -1) != 0 }
+    }
+
+    /// Mark the changes recorded from now on as indirect (`true`) or
+    /// direct (`false`).
+    pub(crate) fn set_indirect(&mut self, indirect: bool) {
+        // SAFETY: the session is live and the connection Mutex held.
+        unsafe { ffi::sqlite3session_indirect(self.ptr, c_int::from(indirect)) };
+    }
+
+    pub(crate) fn is_indirect(&self) -> bool {
+        // SAFETY: as in `set_indirect`; -1 only queries the flag.
+        unsafe { ffi::sqlite3session_indirect(self.ptr, -1) != 0 }
+    }
+
+    /// Record tables without an explicit PRIMARY KEY as if keyed by
+    /// their rowid (`SQLITE_SESSION_OBJCONFIG_ROWID`). SQLite refuses
+    /// the change with `SQLITE_MISUSE` once a table is attached.
+    pub(crate) fn set_rowid(&mut self, rowid: bool) -> Result<(), XqliteError> {
+        let mut arg = c_int::from(rowid);
+        // SAFETY: the session is live and the connection Mutex held; `arg`
+        // is a valid in/out int for the duration of the call.
+        changeset::check(unsafe {
+            ffi::sqlite3session_object_config(
+                self.ptr,
+                ffi::SQLITE_SESSION_OBJCONFIG_ROWID,
+                (&mut arg as *mut c_int).cast(),
+            )
+        })
+    }
+
+    /// Heap memory currently used by the session, in bytes.
+    pub(crate) fn memory_used(&self) -> i64 {
+        // SAFETY: the session is live and the connection Mutex held.
+        unsafe { ffi::sqlite3session_memory_used(self.ptr) }
+    }
+
+    /// Stream the recorded changes as a changeset into `output`.
+    pub(crate) fn changeset_to<W: Write>(&mut self, mut output: W) -> Result<(), XqliteError> {
+        // SAFETY: the session is live and the connection Mutex held;
+        // `output` outlives the call and is used only by `x_output`.
+        changeset::check(unsafe {
+            ffi::sqlite3session_changeset_strm(
+                self.ptr,
+                Some(changeset::x_output::<W>),
+                (&mut output as *mut W).cast(),
+            )
+        })?;
+        output
+            .flush()
+            .map_err(|_| sqlite_error(ffi::SQLITE_IOERR_WRITE))
+    }
+
+    /// Stream the recorded changes as a patchset into `output`.
+    pub(crate) fn patchset_to<W: Write>(&mut self, mut output: W) -> Result<(), XqliteError> {
+        // SAFETY: as in `changeset_to`.
+        changeset::check(unsafe {
+            ffi::sqlite3session_patchset_strm(
+                self.ptr,
+                Some(changeset::x_output::<W>),
+                (&mut output as *mut W).cast(),
+            )
+        })?;
+        output
+            .flush()
+            .map_err(|_| sqlite_error(ffi::SQLITE_IOERR_WRITE))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        // SAFETY: the session is live and the connection Mutex held.
+        unsafe { ffi::sqlite3session_isempty(self.ptr) != 0 }
+    }
+
+    /// Record, as if made by this session, the changes that turn table
+    /// `table` of database `from` into the session's copy of it.
+    pub(crate) fn diff(&mut self, from: &str, table: &str) -> Result<(), XqliteError> {
+        let z_from = CString::new(from).map_err(|_| XqliteError::NulErrorInString)?;
+        let z_tab = CString::new(table).map_err(|_| XqliteError::NulErrorInString)?;
+        let mut z_err: *mut c_char = std::ptr::null_mut();
+        // SAFETY: the session is live and the connection Mutex held; the
+        // error message, if any, is ours to free.
+        let rc = unsafe {
+            ffi::sqlite3session_diff(self.ptr, z_from.as_ptr(), z_tab.as_ptr(), &mut z_err)
+        };
+        let message = (!z_err.is_null()).then(|| {
+            // SAFETY: non-null `z_err` is a NUL-terminated string from
+            // `sqlite3_mprintf`, freed right after copying.
+            unsafe {
+                let message = CStr::from_ptr(z_err).to_string_lossy().into_owned();
+                ffi::sqlite3_free(z_err.cast());
+                message
+            }
+        });
+        match (rc, message) {
+            (ffi::SQLITE_OK, _) => Ok(()),
+            (rc, None) => Err(sqlite_error(rc)),
+            (rc, Some(message)) => Err(XqliteError::from(rusqlite::Error::SqliteFailure(
+                ffi::Error::new(rc),
+                Some(message),
+            ))),
+        }
+    }
+}
+
+/// `xFilter` for `sqlite3session_table_filter`: accept every table not in
+/// the exclusion list.
+unsafe extern "C" fn x_table_filter(ctx: *mut c_void, z_tab: *const c_char) -> c_int {
+    hook_util::guard_ffi_callback("session x_table_filter", 0, || {
+        // SAFETY: `ctx` is the `TableFilter` boxed in `RawSession::filter`,
+        // alive until the session is deleted; `z_tab` is a NUL-terminated
+        // table name owned by SQLite for the duration of the call.
+        let (filter, table) = unsafe { (&*ctx.cast::<TableFilter>(), CStr::from_ptr(z_tab)) };
+        let table = table.to_string_lossy();
+        let excluded = filter
+            .excluded
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(&table));
+        c_int::from(!excluded)
+    })
+}
+
+impl Drop for RawSession {
+    fn drop(&mut self) {
+        // SAFETY: the pointer came from `sqlite3session_create` and is
+        // deleted exactly once, here, under the connection Mutex (see
+        // `close`).
+        unsafe { ffi::sqlite3session_delete(self.ptr) }
+    }
+}
 
 pub(crate) struct XqliteSession {
-    // SAFETY: the `Session<'static>` is sound because `conn_resource_arc`
-    // keeps the `XqliteConn` *resource* alive for at least as long as this
-    // handle. Session::new() borrows the connection; we erase the lifetime
-    // via transmute at construction.
+    // Lifetime: `conn_resource_arc` keeps the `XqliteConn` *resource* alive
+    // for at least as long as this handle, so its Mutex is always lockable.
     //
     // Lifetime is NOT the same as exclusion. Every `sqlite3session_*` call
     // goes through `with_session`/`with_session_mut`/`close`, which hold the
     // *connection* Mutex for the whole duration of the raw call. The
     // per-session Mutex only provides interior mutability and guards the
     // `Option` for explicit delete.
-    pub(crate) session: Mutex<Option<Session<'static>>>,
+    pub(crate) session: Mutex<Option<RawSession>>,
     pub(crate) conn_resource_arc: ResourceArc<XqliteConn>,
 }
 
@@ -55,6 +253,10 @@ pub(crate) fn close(session_handle: &XqliteSession) -> Result<(), XqliteError> {
     let Some(session) = session_guard.take() else {
         return Ok(());
     };
+    session_handle
+        .conn_resource_arc
+        .live_sessions
+        .fetch_sub(1, Ordering::Relaxed);
     match conn_lock {
         Ok(ref conn_guard) if conn_guard.is_some() => {
             // `sqlite3session_delete` runs under the held connection Mutex.
@@ -76,7 +278,7 @@ pub(crate) fn with_session<F, R>(
     func: F,
 ) -> Result<R, XqliteError>
 where
-    F: FnOnce(&Session<'static>) -> Result<R, XqliteError>,
+    F: FnOnce(&RawSession) -> Result<R, XqliteError>,
 {
     // Raw-handle locking rule: every `sqlite3session_*` call must hold the
     // connection Mutex. Acquire it first (order conn -> session, matching
@@ -105,7 +307,7 @@ pub(crate) fn with_session_mut<F, R>(
     func: F,
 ) -> Result<R, XqliteError>
 where
-    F: FnOnce(&mut Session<'static>) -> Result<R, XqliteError>,
+    F: FnOnce(&mut RawSession) -> Result<R, XqliteError>,
 {
     let conn_guard = session_handle
         .conn_resource_arc
@@ -0,0 +1,272 @@
+//! Native slow-query log, layered on the `trace_hook` dispatch.
+//!
+//! While a log is configured, the trace callback is armed with PROFILE and
+//! ROW on top of whatever the trace subscribers asked for. ROW events bump
+//! a per-statement row counter; the PROFILE event that ends each run
+//! compares the run time against the threshold and turns a slow run into a
+//! `SlowQuery` record. Records go to a subscriber pid when one is
+//! configured, otherwise into a bounded ring buffer drained by the
+//! `slow_queries` NIF.
+//!
+//! Because this sits in the trace callback, it sees every statement the
+//! connection runs — including each statement of an `execute_batch`.
+//! Trigger bodies are not profiled separately by SQLite; their time counts
+//! towards the statement that fired them.
+//!
+//! The optional query plan is captured with
+//! `explain_analyze::collect_query_plan` on a borrowed handle, from inside
+//! the callback. That EXPLAIN statement fires trace events of its own;
+//! `capturing_plan` makes the callback skip them so nothing recurses and
+//! trace subscribers never see the internal statement.
+
+use crate::atoms;
+use crate::error::XqliteError;
+use crate::explain_analyze::{self, QueryPlanRow};
+use crate::hook_util;
+use crate::trace_hook::{self, TraceDispatch};
+use rusqlite::{Connection, ffi};
+use rustler::types::LocalPid;
+use rustler::types::atom::nil;
+use rustler::types::map::map_new;
+use rustler::{Encoder, Env, Term};
+use std::collections::{HashMap, VecDeque};
+use std::os::raw::{c_uint, c_void};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Slow-query state carried by `TraceDispatch`.
+#[derive(Debug)]
+pub(crate) struct SlowQueryState {
+    log: Mutex<Option<SlowQueryLog>>,
+    /// Set while the callback runs its own `EXPLAIN QUERY PLAN`.
+    capturing_plan: AtomicBool,
+}
+
+impl SlowQueryState {
+    pub(crate) fn new() -> Self {
+        Self {
+            log: Mutex::new(None),
+            capturing_plan: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether a log is configured (and so needs PROFILE and ROW armed).
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.log.lock().map(|log| log.is_some()).unwrap_or(false)
+    }
+
+    pub(crate) fn is_capturing_plan(&self) -> bool {
+        self.capturing_plan.load(Ordering::Relaxed)
+    }
+}
+
+/// One configured log: its settings plus the running state.
+pub(crate) struct SlowQueryLog {
+    threshold_ns: i64,
+    /// Capture the plan for every Nth slow statement; 0 never does.
+    plan_every: u64,
+    capacity: usize,
+    pid: Option<LocalPid>,
+    slow_seen: u64,
+    /// Result rows so far per running statement, keyed by its address.
+    rows: HashMap<usize, u64>,
+    records: VecDeque<SlowQuery>,
+}
+
+impl SlowQueryLog {
+    pub(crate) fn new(
+        threshold_ms: u64,
+        plan_every: u64,
+        capacity: usize,
+        pid: Option<LocalPid>,
+    ) -> Self {
+        Self {
+            threshold_ns: i64::try_from(threshold_ms.saturating_mul(1_000_000))
+                .unwrap_or(i64::MAX),
+            plan_every,
+            capacity,
+            pid,
+            slow_seen: 0,
+            rows: HashMap::new(),
+            records: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, record: SlowQuery) {
+        self.records.push_back(record);
+        while self.records.len() > self.capacity {
+            self.records.pop_front();
+        }
+    }
+}
+
+impl std::fmt::Debug for SlowQueryLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SlowQueryLog")
+            .field("threshold_ns", &self.threshold_ns)
+            .field("plan_every", &self.plan_every)
+            .field("capacity", &self.capacity)
+            .field("buffered", &self.records.len())
+            .finish()
+    }
+}
+
+/// One statement run that took at least the threshold.
+pub(crate) struct SlowQuery {
+    sql: String,
+    param_count: i32,
+    duration_ns: i64,
+    rows: u64,
+    query_plan: Option<Vec<QueryPlanRow>>,
+}
+
+impl Encoder for SlowQuery {
+    fn encode<'b>(&self, env: Env<'b>) -> Term<'b> {
+        let plan = match &self.query_plan {
+            Some(rows) => rows.encode(env),
+            None => nil().encode(env),
+        };
+        let built = map_new(env)
+            .map_put(atoms::sql().encode(env), self.sql.as_str().encode(env))
+            .and_then(|m| {
+                m.map_put(
+                    atoms::param_count().encode(env),
+                    self.param_count.encode(env),
+                )
+            })
+            .and_then(|m| {
+                m.map_put(
+                    atoms::duration_ns().encode(env),
+                    self.duration_ns.encode(env),
+                )
+            })
+            .and_then(|m| m.map_put(atoms::rows().encode(env), self.rows.encode(env)))
+            .and_then(|m| m.map_put(atoms::query_plan().encode(env), plan));
+
+        explain_analyze::map_or_encoding_error(env, built, "slow query record")
+    }
+}
+
+/// Feed one trace event to the log. A no-op unless a log is configured
+/// and `event` is ROW or PROFILE.
+///
+/// # Safety
+///
+/// `p` and `x` must be the arguments SQLite passed to the trace callback
+/// for `event`, and the connection Mutex must be held.
+pub(crate) unsafe fn observe(
+    state: &SlowQueryState,
+    event: c_uint,
+    p: *mut c_void,
+    x: *mut c_void,
+) {
+    if event != ffi::SQLITE_TRACE_ROW && event != ffi::SQLITE_TRACE_PROFILE {
+        return;
+    }
+    let Ok(mut guard) = state.log.lock() else {
+        return;
+    };
+    let Some(log) = guard.as_mut() else {
+        return;
+    };
+    let stmt: *mut ffi::sqlite3_stmt = p.cast();
+    if event == ffi::SQLITE_TRACE_ROW {
+        *log.rows.entry(stmt as usize).or_default() += 1;
+        return;
+    }
+
+    // PROFILE ends the run, so the row counter is done either way.
+    let rows = log.rows.remove(&(stmt as usize)).unwrap_or(0);
+    // SAFETY: for PROFILE, `x` points at the run time in nanoseconds.
+    let duration_ns = unsafe { *(x as *const i64) };
+    if duration_ns < log.threshold_ns {
+        return;
+    }
+
+    log.slow_seen += 1;
+    let want_plan = log.plan_every != 0 && (log.slow_seen - 1) % log.plan_every == 0;
+    // SAFETY: for PROFILE, `p` is the statement that just ran.
+    let (sql, param_count) = unsafe {
+        (
+            trace_hook::c_text(ffi::sqlite3_sql(stmt)),
+            ffi::sqlite3_bind_parameter_count(stmt),
+        )
+    };
+    let query_plan = if want_plan && !sql.is_empty() {
+        // SAFETY: `stmt` is live and the connection Mutex is held.
+        unsafe { capture_plan(state, stmt, &sql) }
+    } else {
+        None
+    };
+
+    let record = SlowQuery {
+        sql,
+        param_count,
+        duration_ns,
+        rows,
+        query_plan,
+    };
+    match &log.pid {
+        Some(pid) => {
+            // SAFETY: the message is built in send_with_env's fresh msg_env.
+            let _ = unsafe {
+                hook_util::send_with_env(pid, |env| {
+                    Ok((atoms::xqlite_slow_query(), &record).encode(env))
+                })
+            };
+        }
+        None => log.push(record),
+    }
+}
+
+/// Run `EXPLAIN QUERY PLAN` for `sql` on the connection owning `stmt`.
+/// `None` when the plan cannot be produced (e.g. the statement dropped a
+/// table the plan would need).
+///
+/// # Safety
+///
+/// `stmt` must be a live statement and the connection Mutex must be held.
+unsafe fn capture_plan(
+    state: &SlowQueryState,
+    stmt: *mut ffi::sqlite3_stmt,
+    sql: &str,
+) -> Option<Vec<QueryPlanRow>> {
+    // SAFETY: `db` is the live handle `stmt` belongs to; the borrowed
+    // (non-owning) Connection is dropped before returning.
+    let conn = unsafe { Connection::from_handle(ffi::sqlite3_db_handle(stmt)) }.ok()?;
+    state.capturing_plan.store(true, Ordering::Relaxed);
+    let plan = explain_analyze::collect_query_plan(&conn, sql).ok();
+    state.capturing_plan.store(false, Ordering::Relaxed);
+    plan
+}
+
+/// Install, replace (`Some`) or remove (`None`) the connection's slow-query
+/// log, then re-arm the trace callback. Replacing a log discards its
+/// undrained records. Callers must hold the connection Mutex.
+pub(crate) fn configure(
+    conn: &Connection,
+    dispatch: &TraceDispatch,
+    log: Option<SlowQueryLog>,
+) -> Result<(), XqliteError> {
+    *dispatch
+        .slow_queries
+        .log
+        .lock()
+        .map_err(|e| XqliteError::LockError(e.to_string()))? = log;
+    // SAFETY: `dispatch` lives inside the caller's XqliteConn.
+    unsafe { trace_hook::install_callback(conn, dispatch) };
+    Ok(())
+}
+
+/// Take every buffered record, oldest first. Empty when no log is
+/// configured or records go to a subscriber.
+pub(crate) fn drain(state: &SlowQueryState) -> Result<Vec<SlowQuery>, XqliteError> {
+    let mut guard = state
+        .log
+        .lock()
+        .map_err(|e| XqliteError::LockError(e.to_string()))?;
+    Ok(guard
+        .as_mut()
+        .map(|log| log.records.drain(..).collect())
+        .unwrap_or_default())
+}
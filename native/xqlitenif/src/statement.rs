@@ -1,10 +1,15 @@
+use crate::atoms;
 use crate::connection::XqliteConn;
 use crate::error::XqliteError;
-use crate::stream::take_and_finalize_raw;
+use crate::explain_analyze::{ffi_error, map_or_encoding_error};
+use crate::stream::{take_and_finalize_raw, with_live_raw_stmt};
 use rusqlite::ffi;
-use rustler::{Resource, ResourceArc};
+use rustler::types::map::map_new;
+use rustler::{Atom, Encoder, Env, Resource, ResourceArc, Term};
+use std::ffi::CStr;
 use std::io::Write;
-use std::sync::atomic::{AtomicPtr, Ordering};
+use std::os::raw::{c_char, c_int};
+use std::sync::atomic::AtomicPtr;
 
 /// A manually managed prepared statement: prepare → (bind → step /
 /// multi_step → reset)* → finalize.
@@ -34,34 +39,13 @@ impl XqliteStatement {
     }
 
     /// Runs `f` with the connection Mutex held, the connection proven open,
-    /// and the raw statement pointer proven live.
-    ///
-    /// Lock-then-load ordering makes this sound against a concurrent
-    /// finalize: a finalizer may swap the pointer to null at any moment, but
-    /// it cannot call `sqlite3_finalize` without this same Mutex — so a
-    /// pointer loaded non-null *under the lock* remains valid until the
-    /// guard drops.
+    /// and the raw statement pointer proven live (see
+    /// `stream::with_live_raw_stmt` for why that ordering is sound).
     pub(crate) fn with_live_stmt<F, R>(&self, f: F) -> Result<R, XqliteError>
     where
         F: FnOnce(*mut ffi::sqlite3_stmt, *mut ffi::sqlite3) -> Result<R, XqliteError>,
     {
-        let guard = self
-            .conn_resource_arc
-            .conn
-            .lock()
-            .map_err(|e| XqliteError::LockError(e.to_string()))?;
-        let conn = guard.as_ref().ok_or(XqliteError::ConnectionClosed)?;
-
-        let ptr = self.atomic_raw_stmt.load(Ordering::Acquire);
-        if ptr.is_null() {
-            return Err(XqliteError::StatementFinalized);
-        }
-
-        // SAFETY: handle() only extracts the raw sqlite3*; `guard` keeps the
-        // Connection alive (and the connection exclusively ours) for the
-        // whole duration of `f`.
-        let db = unsafe { conn.handle() };
-        f(ptr, db)
+        with_live_raw_stmt(&self.atomic_raw_stmt, &self.conn_resource_arc, f)
     }
 }
 
@@ -80,3 +64,140 @@ impl Drop for XqliteStatement {
         }
     }
 }
+
+/// What `stmt_info` reports about a live statement.
+pub(crate) struct StmtInfo {
+    readonly: bool,
+    /// `sqlite3_stmt_isexplain`: 0 normal, 1 EXPLAIN, 2 EXPLAIN QUERY PLAN.
+    explain: c_int,
+    busy: bool,
+    sql: Option<String>,
+    /// Bound values inlined; `None` when SQLite cannot render it (out of
+    /// memory, or longer than `SQLITE_LIMIT_LENGTH`).
+    expanded_sql: Option<String>,
+    /// `sqlite3_column_decltype` per result column; `None` for expressions
+    /// and for columns declared without a type.
+    declared_types: Vec<Option<String>>,
+}
+
+impl StmtInfo {
+    /// # Safety
+    /// `stmt_ptr` must be live and its connection Mutex held, as inside
+    /// `XqliteStatement::with_live_stmt`.
+    pub(crate) unsafe fn read(stmt_ptr: *mut ffi::sqlite3_stmt) -> Self {
+        // SAFETY: forwarded fn contract for every call below. The strings
+        // are copied out before the Mutex is released.
+        unsafe {
+            let column_count = ffi::sqlite3_column_count(stmt_ptr);
+            let declared_types = (0..column_count)
+                .map(|i| owned_str(ffi::sqlite3_column_decltype(stmt_ptr, i)))
+                .collect();
+
+            // sqlite3_expanded_sql hands over a buffer that must be freed.
+            let expanded_ptr = ffi::sqlite3_expanded_sql(stmt_ptr);
+            let expanded_sql = owned_str(expanded_ptr);
+            ffi::sqlite3_free(expanded_ptr.cast());
+
+            Self {
+                readonly: ffi::sqlite3_stmt_readonly(stmt_ptr) != 0,
+                explain: ffi::sqlite3_stmt_isexplain(stmt_ptr),
+                busy: ffi::sqlite3_stmt_busy(stmt_ptr) != 0,
+                sql: owned_str(ffi::sqlite3_sql(stmt_ptr)),
+                expanded_sql,
+                declared_types,
+            }
+        }
+    }
+}
+
+impl Encoder for StmtInfo {
+    fn encode<'b>(&self, env: Env<'b>) -> Term<'b> {
+        let built = map_new(env)
+            .map_put(atoms::readonly().encode(env), self.readonly.encode(env))
+            .and_then(|m| {
+                m.map_put(
+                    atoms::explain().encode(env),
+                    explain_mode_atom(self.explain).encode(env),
+                )
+            })
+            .and_then(|m| m.map_put(atoms::busy().encode(env), self.busy.encode(env)))
+            .and_then(|m| m.map_put(atoms::sql().encode(env), self.sql.encode(env)))
+            .and_then(|m| {
+                m.map_put(
+                    atoms::expanded_sql().encode(env),
+                    self.expanded_sql.encode(env),
+                )
+            })
+            .and_then(|m| {
+                m.map_put(
+                    atoms::declared_types().encode(env),
+                    self.declared_types.encode(env),
+                )
+            });
+
+        map_or_encoding_error(env, built, "statement info")
+    }
+}
+
+fn explain_mode_atom(mode: c_int) -> Atom {
+    match mode {
+        1 => atoms::explain(),
+        2 => atoms::query_plan(),
+        _ => atoms::none(),
+    }
+}
+
+/// `sqlite3_stmt_explain` mode for `:none`, `:explain` or `:query_plan`.
+pub(crate) fn explain_mode(mode: Atom) -> Result<c_int, XqliteError> {
+    if mode == atoms::none() {
+        Ok(0)
+    } else if mode == atoms::explain() {
+        Ok(1)
+    } else if mode == atoms::query_plan() {
+        Ok(2)
+    } else {
+        Err(XqliteError::InvalidExplainMode { mode })
+    }
+}
+
+/// Switch a statement between normal execution (0), EXPLAIN (1) and
+/// EXPLAIN QUERY PLAN (2) via `sqlite3_stmt_explain`. The statement must
+/// not be mid-run; SQLite re-prepares it when the mode needs different
+/// bytecode.
+///
+/// # Safety
+/// `stmt_ptr` must be live on `db_handle`, whose Mutex the caller holds.
+pub(crate) unsafe fn set_explain_mode(
+    stmt_ptr: *mut ffi::sqlite3_stmt,
+    db_handle: *mut ffi::sqlite3,
+    e_mode: c_int,
+) -> Result<(), XqliteError> {
+    // SAFETY: forwarded fn contract.
+    match unsafe { ffi::sqlite3_stmt_explain(stmt_ptr, e_mode) } {
+        ffi::SQLITE_OK => Ok(()),
+        // Not an error SQLite records on the connection: the statement has
+        // been stepped and not reset.
+        ffi::SQLITE_BUSY => Err(XqliteError::CannotExecute(
+            "cannot change the explain mode of a running statement; reset it first"
+                .to_string(),
+        )),
+        // SAFETY: forwarded fn contract; a failed re-prepare leaves its
+        // message on the connection.
+        rc => Err(unsafe { ffi_error(db_handle, rc) }),
+    }
+}
+
+/// # Safety
+/// `ptr` must be null or a NUL-terminated string valid for the call.
+unsafe fn owned_str(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        None
+    } else {
+        // SAFETY: non-null and NUL-terminated (fn contract).
+        Some(
+            unsafe { CStr::from_ptr(ptr) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
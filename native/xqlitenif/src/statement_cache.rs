@@ -0,0 +1,458 @@
+//! Per-connection prepared statement cache for `query` / `execute`.
+//!
+//! Storage and LRU order are rusqlite's own (`prepare_cached`, keyed by the
+//! trimmed SQL text): a statement is taken out of rusqlite's LRU while in
+//! use and re-inserted as the most recent entry when dropped, evicting the
+//! least recent one once over capacity. rusqlite keeps no counters, so
+//! `StatementCache` mirrors that key set step for step and counts hits,
+//! misses and evictions as it goes. SQL that compiles to no statement at
+//! all (empty or comment-only) is never cached by rusqlite, so the mirror
+//! skips it too.
+//!
+//! rusqlite prepares the trimmed text, so SQL input errors from the cached
+//! path are re-based onto the caller's string to report the same offsets
+//! as an uncached prepare.
+//!
+//! SQLite re-prepares a stale statement by itself on its next step after a
+//! schema change (`sqlite3_prepare_v2` semantics), bumping the statement's
+//! `SQLITE_STMTSTATUS_REPREPARE` counter. A cached statement that came back
+//! re-prepared is discarded and the whole cache flushed, since every other
+//! entry was compiled against the old schema too; the run counts as a miss.
+//! Callers must read column metadata after stepping, not before.
+
+use crate::error::XqliteError;
+use rusqlite::{CachedStatement, Connection, Statement, StatementStatus};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+/// rusqlite's own default capacity, which every new connection starts with.
+pub(crate) const DEFAULT_CAPACITY: usize = 16;
+
+#[derive(Debug)]
+pub(crate) struct StatementCache {
+    state: Mutex<CacheState>,
+}
+
+/// Hit / miss / eviction counters since the connection was opened.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct CacheStats {
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+    pub(crate) evictions: u64,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    capacity: usize,
+    /// Cached SQL text -> recency tick; `order` is the inverse, oldest first.
+    keys: HashMap<String, u64>,
+    order: BTreeMap<u64, String>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl CacheState {
+    /// Take `key` out of the LRU, as rusqlite does while the statement is in
+    /// use. Returns whether it was cached.
+    fn check_out(&mut self, key: &str) -> bool {
+        match self.keys.remove(key) {
+            Some(tick) => {
+                self.order.remove(&tick);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Put `key` back as the most recent entry. Two copies of one SQL text
+    /// can be checked out at once (the second a miss); rusqlite keeps the
+    /// one returned last, in place of the other, at the back of the LRU.
+    fn check_in(&mut self, key: String) {
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        if let Some(replaced) = self.keys.insert(key, self.tick) {
+            self.order.remove(&replaced);
+        }
+        self.evict_to(self.capacity);
+    }
+
+    fn evict_to(&mut self, capacity: usize) {
+        while self.keys.len() > capacity {
+            let Some((_, key)) = self.order.pop_first() else {
+                break;
+            };
+            self.keys.remove(&key);
+            self.stats.evictions += 1;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.keys.clear();
+        self.order.clear();
+    }
+}
+
+impl StatementCache {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Mutex::new(CacheState {
+                capacity: DEFAULT_CAPACITY,
+                ..CacheState::default()
+            }),
+        }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, CacheState>, XqliteError> {
+        self.state
+            .lock()
+            .map_err(|e| XqliteError::LockError(e.to_string()))
+    }
+
+    /// Resize the cache; 0 disables it. Shrinking evicts the least recently
+    /// used entries. Callers must hold the connection Mutex.
+    pub(crate) fn set_capacity(
+        &self,
+        conn: &Connection,
+        capacity: usize,
+    ) -> Result<(), XqliteError> {
+        let mut state = self.lock()?;
+        conn.set_prepared_statement_cache_capacity(capacity);
+        state.capacity = capacity;
+        state.evict_to(capacity);
+        Ok(())
+    }
+
+    pub(crate) fn stats(&self) -> Result<CacheStats, XqliteError> {
+        Ok(self.lock()?.stats)
+    }
+
+    /// Prepare `sql`, reusing a cached statement when there is one. Callers
+    /// must hold the connection Mutex for the lifetime of the result.
+    pub(crate) fn prepare<'c>(
+        &'c self,
+        conn: &'c Connection,
+        sql: &str,
+    ) -> Result<PreparedStmt<'c>, XqliteError> {
+        let key = sql.trim();
+        let hit;
+        {
+            let mut state = self.lock()?;
+            if state.capacity == 0 {
+                drop(state);
+                return Ok(PreparedStmt {
+                    stmt: Stmt::Uncached(conn.prepare(sql)?),
+                    ticket: None,
+                });
+            }
+            hit = state.check_out(key);
+            if hit {
+                state.stats.hits += 1;
+            } else {
+                state.stats.misses += 1;
+            }
+        }
+
+        let stmt = match conn.prepare_cached(key) {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                // rusqlite caches nothing on failure; neither does the mirror.
+                return Err(untrim_input_error(e.into(), sql));
+            }
+        };
+        // A hit always carries a compiled statement. On a miss, whitespace-
+        // or comment-only SQL yields a null one that rusqlite won't cache
+        // (and whose status counters must not be read); `expanded_sql` is
+        // None exactly for those.
+        let ticket = (hit || stmt.expanded_sql().is_some()).then(|| Ticket {
+            key: key.to_string(),
+            hit,
+            reprepares: stmt.get_status(StatementStatus::RePrepare),
+            stale: false,
+            conn,
+            cache: self,
+        });
+        Ok(PreparedStmt {
+            stmt: Stmt::Cached(stmt),
+            ticket,
+        })
+    }
+}
+
+/// Re-base a SQL input error from preparing `sql.trim()` onto `sql`.
+fn untrim_input_error(err: XqliteError, sql: &str) -> XqliteError {
+    match err {
+        XqliteError::SqlInputError {
+            code,
+            message,
+            offset,
+            ..
+        } => {
+            let lead = i32::try_from(sql.len() - sql.trim_start().len()).unwrap_or(i32::MAX);
+            XqliteError::SqlInputError {
+                code,
+                message,
+                sql: sql.to_string(),
+                offset: if offset >= 0 {
+                    offset.saturating_add(lead)
+                } else {
+                    offset
+                },
+            }
+        }
+        other => other,
+    }
+}
+
+/// A statement from `StatementCache::prepare`; derefs to the rusqlite
+/// `Statement` and goes back to the cache when dropped.
+pub(crate) struct PreparedStmt<'c> {
+    // Field order matters: `stmt` drops first (rusqlite re-caches it), then
+    // `ticket` updates the mirror to match.
+    stmt: Stmt<'c>,
+    ticket: Option<Ticket<'c>>,
+}
+
+enum Stmt<'c> {
+    Uncached(Statement<'c>),
+    Cached(CachedStatement<'c>),
+}
+
+/// Mirror bookkeeping for one checked-out cached statement.
+struct Ticket<'c> {
+    key: String,
+    hit: bool,
+    reprepares: i32,
+    /// Set just before `stmt` drops: SQLite re-prepared it while in use.
+    stale: bool,
+    conn: &'c Connection,
+    cache: &'c StatementCache,
+}
+
+impl<'c> Deref for PreparedStmt<'c> {
+    type Target = Statement<'c>;
+
+    fn deref(&self) -> &Statement<'c> {
+        match &self.stmt {
+            Stmt::Uncached(stmt) => stmt,
+            Stmt::Cached(stmt) => stmt,
+        }
+    }
+}
+
+impl<'c> DerefMut for PreparedStmt<'c> {
+    fn deref_mut(&mut self) -> &mut Statement<'c> {
+        match &mut self.stmt {
+            Stmt::Uncached(stmt) => stmt,
+            Stmt::Cached(stmt) => stmt,
+        }
+    }
+}
+
+impl Drop for PreparedStmt<'_> {
+    fn drop(&mut self) {
+        // Untracked statements may be null; never read their counters.
+        let Some(before) = self.ticket.as_ref().map(|ticket| ticket.reprepares) else {
+            return;
+        };
+        let stale = self.get_status(StatementStatus::RePrepare) != before;
+        if let Some(ticket) = self.ticket.as_mut() {
+            ticket.stale = stale;
+        }
+    }
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        let mut state = match self.cache.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if self.stale {
+            // Drops the statement rusqlite just took back, and all others.
+            self.conn.flush_prepared_statement_cache();
+            state.clear();
+            if self.hit {
+                // Compiled afresh after all: count the run as a miss.
+                state.stats.hits -= 1;
+                state.stats.misses += 1;
+            }
+        } else {
+            state.check_in(std::mem::take(&mut self.key));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::ffi;
+    use std::collections::HashSet;
+
+    /// Every statement alive on `conn`, by address, with its SQL. With
+    /// none checked out, exactly what rusqlite's cache holds.
+    fn live(conn: &Connection) -> HashMap<usize, String> {
+        let mut stmts = HashMap::new();
+        // SAFETY: the test owns `conn` and steps nothing meanwhile.
+        unsafe {
+            let db = conn.handle();
+            let mut stmt = ffi::sqlite3_next_stmt(db, std::ptr::null_mut());
+            while !stmt.is_null() {
+                let sql = std::ffi::CStr::from_ptr(ffi::sqlite3_sql(stmt));
+                stmts.insert(stmt as usize, sql.to_string_lossy().into_owned());
+                stmt = ffi::sqlite3_next_stmt(db, stmt);
+            }
+        }
+        stmts
+    }
+
+    fn live_sql(conn: &Connection) -> HashSet<String> {
+        live(conn).into_values().collect()
+    }
+
+    fn mirrored(cache: &StatementCache) -> HashSet<String> {
+        cache.lock().unwrap().keys.keys().cloned().collect()
+    }
+
+    /// Run `workload` in rounds, each preparing its statements while the
+    /// earlier ones of the round are still checked out, then returning
+    /// them first to last. Every counter step is checked against what
+    /// rusqlite did: a hit hands out a statement that was already alive,
+    /// and an eviction finalizes one, other than the copy of the same SQL
+    /// that a returned statement replaces.
+    fn check_workload(capacity: usize, workload: &[Vec<&str>]) {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t(a, b)").unwrap();
+        let cache = StatementCache::new();
+        cache.set_capacity(&conn, capacity).unwrap();
+
+        for round in workload {
+            let mut held = Vec::new();
+            for sql in round {
+                let before = live(&conn);
+                let stats = cache.stats().unwrap();
+                held.push((sql.trim(), cache.prepare(&conn, sql).unwrap()));
+                let fresh = live(&conn).keys().any(|stmt| !before.contains_key(stmt));
+
+                let now = cache.stats().unwrap();
+                assert_eq!(
+                    now.hits - stats.hits,
+                    u64::from(!fresh),
+                    "{sql:?} in {round:?}"
+                );
+                assert_eq!(
+                    now.misses - stats.misses,
+                    u64::from(fresh),
+                    "{sql:?} in {round:?}"
+                );
+            }
+
+            for (key, stmt) in held {
+                let before = live(&conn);
+                let stats = cache.stats().unwrap();
+                drop(stmt);
+                let after = live(&conn);
+
+                let finalized: Vec<_> =
+                    before.keys().filter(|s| !after.contains_key(s)).collect();
+                let replaced = finalized.iter().filter(|s| before[**s] == key).count();
+                let evictions = cache.stats().unwrap().evictions - stats.evictions;
+                assert_eq!(
+                    evictions,
+                    (finalized.len() - replaced) as u64,
+                    "{key:?} in {round:?}"
+                );
+            }
+
+            assert!(live(&conn).len() <= capacity, "{round:?}");
+            assert_eq!(mirrored(&cache), live_sql(&conn), "{round:?}");
+        }
+    }
+
+    /// A fixed pseudo-random workload over more statements than the
+    /// smaller capacities hold, with repeats inside a round.
+    fn workload() -> Vec<Vec<&'static str>> {
+        const SQLS: [&str; 6] = [
+            "SELECT a FROM t",
+            "  SELECT a FROM t  ",
+            "SELECT b FROM t",
+            "SELECT a, b FROM t WHERE a = ?1",
+            "INSERT INTO t VALUES (?1, ?2)",
+            "DELETE FROM t WHERE b = ?1",
+        ];
+        let mut seed = 7_u32;
+        let mut next = move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 16) as usize
+        };
+        (0..200)
+            .map(|_| {
+                let len = [1, 1, 1, 2, 3][next() % 5];
+                (0..len).map(|_| SQLS[next() % SQLS.len()]).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counts_match_rusqlite_at_capacity_1() {
+        check_workload(1, &workload());
+    }
+
+    #[test]
+    fn counts_match_rusqlite_at_capacity_2() {
+        check_workload(2, &workload());
+    }
+
+    #[test]
+    fn counts_match_rusqlite_at_capacity_16() {
+        check_workload(16, &workload());
+    }
+
+    #[test]
+    fn shrinking_evicts_the_least_recent() {
+        let conn = Connection::open_in_memory().unwrap();
+        let cache = StatementCache::new();
+        for sql in ["SELECT 1", "SELECT 2", "SELECT 3"] {
+            drop(cache.prepare(&conn, sql).unwrap());
+        }
+        drop(cache.prepare(&conn, "SELECT 1").unwrap());
+
+        cache.set_capacity(&conn, 1).unwrap();
+
+        assert_eq!(live_sql(&conn), HashSet::from(["SELECT 1".to_string()]));
+        assert_eq!(mirrored(&cache), live_sql(&conn));
+        assert_eq!(cache.stats().unwrap().evictions, 2);
+    }
+
+    #[test]
+    fn a_reprepared_statement_flushes_the_cache() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t(a)").unwrap();
+        let cache = StatementCache::new();
+        for sql in ["SELECT * FROM t", "SELECT 1"] {
+            let mut stmt = cache.prepare(&conn, sql).unwrap();
+            stmt.raw_query().next().unwrap();
+        }
+        conn.execute_batch("ALTER TABLE t ADD COLUMN b").unwrap();
+
+        {
+            let mut stmt = cache.prepare(&conn, "SELECT * FROM t").unwrap();
+            stmt.raw_query().next().unwrap();
+            assert_eq!(stmt.column_count(), 2);
+        }
+
+        assert!(live(&conn).is_empty());
+        assert!(mirrored(&cache).is_empty());
+        let stats = cache.stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (0, 3, 0));
+
+        // Compiled against the new schema, it is cached and reused again.
+        drop(cache.prepare(&conn, "SELECT * FROM t").unwrap());
+        drop(cache.prepare(&conn, "SELECT * FROM t").unwrap());
+        assert_eq!(
+            live_sql(&conn),
+            HashSet::from(["SELECT * FROM t".to_string()])
+        );
+        assert_eq!(cache.stats().unwrap().hits, 1);
+    }
+}
@@ -0,0 +1,113 @@
+//! Runtime counters for long-lived statements and streams.
+//!
+//! `explain_analyze` reads `sqlite3_stmt_status` and
+//! `sqlite3_stmt_scanstatus_v2` once, for a statement it prepares and
+//! throws away. Here the same collectors run against a live
+//! `XqliteStatement` or `XqliteStream`, whose counters accumulate across
+//! every execution (or the whole stream) until the caller resets them.
+//!
+//! A stream finalizes its statement the moment it is exhausted, which
+//! would take the counters with it; `stream_fetch` therefore snapshots
+//! them into a `StmtProfile` just before that finalize, and the snapshot
+//! answers afterwards.
+
+use crate::error::XqliteError;
+use crate::explain_analyze::{
+    ScanStatus, StmtCounters, collect_scan_status, collect_stmt_counters,
+};
+use crate::statement::XqliteStatement;
+use crate::stream::XqliteStream;
+use rusqlite::ffi;
+use rustler::{NifResult, ResourceArc, Term};
+
+/// Counters and scan loops of one statement, copied out of SQLite.
+#[derive(Clone)]
+pub(crate) struct StmtProfile {
+    pub(crate) counters: StmtCounters,
+    pub(crate) scans: Vec<ScanStatus>,
+}
+
+impl StmtProfile {
+    /// # Safety
+    /// `stmt_ptr` must be valid and the connection Mutex must be held.
+    pub(crate) unsafe fn capture(stmt_ptr: *mut ffi::sqlite3_stmt) -> Self {
+        // SAFETY: forwarded fn contract.
+        unsafe {
+            Self {
+                counters: collect_stmt_counters(stmt_ptr, false),
+                scans: collect_scan_status(stmt_ptr),
+            }
+        }
+    }
+}
+
+/// A resource owning a raw statement: either kind works for the stats NIFs.
+pub(crate) enum StatsTarget {
+    Statement(ResourceArc<XqliteStatement>),
+    Stream(ResourceArc<XqliteStream>),
+}
+
+impl StatsTarget {
+    /// Decode a statement or stream handle; anything else is a badarg.
+    pub(crate) fn decode(term: Term<'_>) -> NifResult<Self> {
+        if let Ok(stmt) = term.decode::<ResourceArc<XqliteStatement>>() {
+            return Ok(Self::Statement(stmt));
+        }
+        term.decode::<ResourceArc<XqliteStream>>().map(Self::Stream)
+    }
+
+    /// Run `read` on the live statement. An exhausted stream answers from
+    /// its final snapshot via `from_snapshot` instead.
+    fn read<R>(
+        &self,
+        read: impl FnOnce(*mut ffi::sqlite3_stmt) -> R,
+        from_snapshot: impl FnOnce(&StmtProfile) -> R,
+    ) -> Result<R, XqliteError> {
+        let live = |stmt_ptr, _db| Ok(read(stmt_ptr));
+        match self {
+            Self::Statement(stmt) => stmt.with_live_stmt(live),
+            Self::Stream(stream) => match stream.with_live_stmt(live) {
+                Err(XqliteError::StatementFinalized) => {
+                    let snapshot = stream
+                        .exhausted_profile
+                        .lock()
+                        .map_err(|e| XqliteError::LockError(e.to_string()))?;
+                    snapshot
+                        .as_ref()
+                        .map(from_snapshot)
+                        .ok_or(XqliteError::StatementFinalized)
+                }
+                other => other,
+            },
+        }
+    }
+}
+
+/// Statement-level counters, zeroed after reading when `reset` is set.
+pub(crate) fn stats(target: &StatsTarget, reset: bool) -> Result<StmtCounters, XqliteError> {
+    target.read(
+        // SAFETY: `read` hands us a live statement under the conn Mutex.
+        |stmt_ptr| unsafe { collect_stmt_counters(stmt_ptr, reset) },
+        |snapshot| snapshot.counters.clone(),
+    )
+}
+
+/// Per-loop scan counters, zeroed after reading when `reset` is set.
+pub(crate) fn scanstatus(
+    target: &StatsTarget,
+    reset: bool,
+) -> Result<Vec<ScanStatus>, XqliteError> {
+    target.read(
+        |stmt_ptr| {
+            // SAFETY: `read` hands us a live statement under the conn Mutex.
+            unsafe {
+                let scans = collect_scan_status(stmt_ptr);
+                if reset {
+                    ffi::sqlite3_stmt_scanstatus_reset(stmt_ptr);
+                }
+                scans
+            }
+        },
+        |snapshot| snapshot.scans.clone(),
+    )
+}
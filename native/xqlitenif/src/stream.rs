@@ -1,11 +1,13 @@
 use crate::connection::XqliteConn;
 use crate::error::XqliteError;
+use crate::stmt_stats::StmtProfile;
 use crate::util::sqlite_row_to_elixir_terms;
 use rusqlite::ffi;
 use rusqlite::types::Value;
 use rustler::{Env, Resource, ResourceArc, Term};
 use std::io::Write;
 use std::os::raw::c_int;
+use std::sync::Mutex;
 use std::sync::atomic::{AtomicPtr, Ordering};
 
 pub(crate) struct XqliteStream {
@@ -16,6 +18,10 @@ pub(crate) struct XqliteStream {
     // These are immutable after stream_open completes
     pub(crate) conn_resource_arc: ResourceArc<XqliteConn>,
     pub(crate) column_names: Vec<String>,
+
+    // Runtime counters captured just before an exhausted stream finalizes
+    // its statement, so `stmt_stats` can still report on the whole run.
+    pub(crate) exhausted_profile: Mutex<Option<StmtProfile>>,
 }
 
 #[rustler::resource_impl]
@@ -28,6 +34,49 @@ impl XqliteStream {
     pub(crate) fn take_and_finalize_atomic_stmt(&self) -> Result<(), XqliteError> {
         take_and_finalize_raw(&self.atomic_raw_stmt, &self.conn_resource_arc)
     }
+
+    /// See `XqliteStatement::with_live_stmt`.
+    pub(crate) fn with_live_stmt<F, R>(&self, f: F) -> Result<R, XqliteError>
+    where
+        F: FnOnce(*mut ffi::sqlite3_stmt, *mut ffi::sqlite3) -> Result<R, XqliteError>,
+    {
+        with_live_raw_stmt(&self.atomic_raw_stmt, &self.conn_resource_arc, f)
+    }
+}
+
+/// Runs `f` with the connection Mutex held, the connection proven open,
+/// and the raw statement pointer proven live. Shared by every resource
+/// that owns a raw `sqlite3_stmt`.
+///
+/// Lock-then-load ordering makes this sound against a concurrent
+/// finalize: a finalizer may swap the pointer to null at any moment, but
+/// it cannot call `sqlite3_finalize` without this same Mutex — so a
+/// pointer loaded non-null *under the lock* remains valid until the
+/// guard drops.
+pub(crate) fn with_live_raw_stmt<F, R>(
+    atomic_raw_stmt: &AtomicPtr<ffi::sqlite3_stmt>,
+    conn_resource_arc: &ResourceArc<XqliteConn>,
+    f: F,
+) -> Result<R, XqliteError>
+where
+    F: FnOnce(*mut ffi::sqlite3_stmt, *mut ffi::sqlite3) -> Result<R, XqliteError>,
+{
+    let guard = conn_resource_arc
+        .conn
+        .lock()
+        .map_err(|e| XqliteError::LockError(e.to_string()))?;
+    let conn = guard.as_ref().ok_or(XqliteError::ConnectionClosed)?;
+
+    let ptr = atomic_raw_stmt.load(Ordering::Acquire);
+    if ptr.is_null() {
+        return Err(XqliteError::StatementFinalized);
+    }
+
+    // SAFETY: handle() only extracts the raw sqlite3*; `guard` keeps the
+    // Connection alive (and the connection exclusively ours) for the
+    // whole duration of `f`.
+    let db = unsafe { conn.handle() };
+    f(ptr, db)
 }
 
 /// Atomically takes a raw statement pointer and finalizes it under the
@@ -140,7 +189,7 @@ pub(crate) unsafe fn process_single_step<'a>(
 }
 
 #[inline]
-fn bind_value_to_raw_stmt(
+pub(crate) fn bind_value_to_raw_stmt(
     raw_stmt_ptr: *mut ffi::sqlite3_stmt,
     bind_idx: c_int,
     value: &Value,
@@ -229,18 +278,39 @@ pub(crate) fn bind_named_params_ffi(
     db_handle: *mut ffi::sqlite3,
 ) -> Result<(), XqliteError> {
     for (name, value) in params {
-        let c_name = std::ffi::CString::new(name.as_str())
-            .map_err(|_| XqliteError::InvalidParameterName(name.clone()))?;
+        let bind_idx = parameter_index(raw_stmt_ptr, name)?;
+        bind_value_to_raw_stmt(raw_stmt_ptr, bind_idx, value, db_handle)?;
+    }
+    Ok(())
+}
 
+/// 1-based index of the parameter called `name`. A bare name (no `:`,
+/// `@`, `$` or `?` prefix) matches `:name`, `@name` or `$name`, whichever
+/// the statement uses.
+pub(crate) fn parameter_index(
+    raw_stmt_ptr: *mut ffi::sqlite3_stmt,
+    name: &str,
+) -> Result<c_int, XqliteError> {
+    let lookup = |candidate: &str| -> Result<c_int, XqliteError> {
+        let c_name = std::ffi::CString::new(candidate)
+            .map_err(|_| XqliteError::InvalidParameterName(name.to_string()))?;
         // SAFETY: raw_stmt_ptr is valid (caller holds mutex). c_name is a valid
         // null-terminated CString. Returns 0 if parameter name not found (not UB).
-        let bind_idx =
-            unsafe { ffi::sqlite3_bind_parameter_index(raw_stmt_ptr, c_name.as_ptr()) };
+        Ok(unsafe { ffi::sqlite3_bind_parameter_index(raw_stmt_ptr, c_name.as_ptr()) })
+    };
 
-        if bind_idx == 0 {
-            return Err(XqliteError::InvalidParameterName(name.clone()));
+    let mut bind_idx = lookup(name)?;
+    if bind_idx == 0 && !name.starts_with([':', '@', '$', '?']) {
+        for prefix in [':', '@', '$'] {
+            bind_idx = lookup(&format!("{prefix}{name}"))?;
+            if bind_idx != 0 {
+                break;
+            }
         }
-        bind_value_to_raw_stmt(raw_stmt_ptr, bind_idx, value, db_handle)?;
     }
-    Ok(())
+
+    if bind_idx == 0 {
+        return Err(XqliteError::InvalidParameterName(name.to_string()));
+    }
+    Ok(bind_idx)
 }
@@ -0,0 +1,277 @@
+//! Multi-subscriber dispatch for SQLite's `sqlite3_trace_v2` hook.
+//!
+//! Same shape as `wal_hook`: one C callback wired up at connection open,
+//! walking a `HookList<TraceSubscriber>` per event. rusqlite's
+//! `Connection::trace_v2` accepts a bare `fn` (no closure capture), so we
+//! drop to FFI.
+//!
+//! Each subscriber carries its own event mask. SQLite is armed with the
+//! union of those masks and re-armed on every register / unregister, so a
+//! connection nobody traces pays nothing — no per-row callback and no
+//! profile clock around each statement. The slow-query log (see
+//! `slow_query`) rides on the same callback and adds PROFILE and ROW to
+//! the union while it is configured. Batch-mode update subscribers do the
+//! same with STMT and PROFILE (see `update_hook::observe`).
+
+use crate::atoms;
+use crate::error::XqliteError;
+use crate::hook_util::{self, HookList};
+use crate::slow_query::{self, SlowQueryState};
+use crate::update_hook::{self, UpdateHooks};
+use rusqlite::{Connection, ffi};
+use rustler::types::LocalPid;
+use rustler::{Atom, Encoder};
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::sync::Arc;
+
+/// Everything the trace callback needs. Owned directly by `XqliteConn`;
+/// its address is what SQLite gets as the callback context.
+#[derive(Debug)]
+pub(crate) struct TraceDispatch {
+    pub(crate) list: HookList<TraceSubscriber>,
+    pub(crate) slow_queries: SlowQueryState,
+    pub(crate) update_hooks: Arc<UpdateHooks>,
+}
+
+impl TraceDispatch {
+    pub(crate) fn new(update_hooks: Arc<UpdateHooks>) -> Self {
+        Self {
+            list: HookList::new(),
+            slow_queries: SlowQueryState::new(),
+            update_hooks,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct TraceSubscriber {
+    pub(crate) pid: LocalPid,
+    /// `SQLITE_TRACE_*` bits this subscriber asked for.
+    pub(crate) mask: c_uint,
+}
+
+impl std::fmt::Debug for TraceSubscriber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TraceSubscriber")
+            .field("mask", &self.mask)
+            .finish()
+    }
+}
+
+/// Turn user event atoms (`:stmt`, `:profile`, `:row`, `:close`) into a
+/// `SQLITE_TRACE_*` mask, rejecting any unknown atom.
+pub(crate) fn parse_events(events: Vec<Atom>) -> Result<c_uint, XqliteError> {
+    events.into_iter().try_fold(0, |mask, event| {
+        let bit = if event == atoms::stmt() {
+            ffi::SQLITE_TRACE_STMT
+        } else if event == atoms::profile() {
+            ffi::SQLITE_TRACE_PROFILE
+        } else if event == atoms::row() {
+            ffi::SQLITE_TRACE_ROW
+        } else if event == atoms::close() {
+            ffi::SQLITE_TRACE_CLOSE
+        } else {
+            return Err(XqliteError::InvalidTraceEvent { event });
+        };
+        Ok(mask | bit)
+    })
+}
+
+/// One trace event, copied out of SQLite before the callback returns.
+enum TraceEvent {
+    Stmt(String),
+    Profile(String, i64),
+    Row(String),
+    Close,
+}
+
+impl TraceEvent {
+    /// # Safety
+    ///
+    /// `p` and `x` must be the arguments SQLite passed to the trace
+    /// callback for `event` (see the `sqlite3_trace_v2` docs).
+    unsafe fn capture(event: c_uint, p: *mut c_void, x: *mut c_void) -> Option<Self> {
+        // SAFETY: per the contract above, `p` is the statement for the
+        // statement events and `x` is a C string / `*const i64`.
+        unsafe {
+            match event {
+                ffi::SQLITE_TRACE_STMT => {
+                    let text = c_text(x as *const c_char);
+                    // A trigger subprogram reports an SQL comment naming the
+                    // trigger; anything else is the statement's own text,
+                    // which we expand with its current bindings.
+                    Some(Self::Stmt(if text.starts_with("--") {
+                        text
+                    } else {
+                        expanded_sql(p.cast()).unwrap_or(text)
+                    }))
+                }
+                ffi::SQLITE_TRACE_PROFILE => Some(Self::Profile(
+                    c_text(ffi::sqlite3_sql(p.cast())),
+                    *(x as *const i64),
+                )),
+                ffi::SQLITE_TRACE_ROW => Some(Self::Row(c_text(ffi::sqlite3_sql(p.cast())))),
+                ffi::SQLITE_TRACE_CLOSE => Some(Self::Close),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// # Safety
+///
+/// `ptr` must be null or a valid null-terminated C string.
+pub(crate) unsafe fn c_text(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    // SAFETY: see the doc comment.
+    unsafe { CStr::from_ptr(ptr) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// # Safety
+///
+/// `stmt` must be a live prepared statement.
+unsafe fn expanded_sql(stmt: *mut ffi::sqlite3_stmt) -> Option<String> {
+    // SAFETY: `stmt` is live; the returned buffer is ours to free.
+    unsafe {
+        let ptr = ffi::sqlite3_expanded_sql(stmt);
+        if ptr.is_null() {
+            return None;
+        }
+        let text = c_text(ptr);
+        ffi::sqlite3_free(ptr.cast());
+        Some(text)
+    }
+}
+
+/// C callback SQLite invokes for every armed trace event.
+///
+/// # Safety
+///
+/// `ctx` is the `*const TraceDispatch` passed by `install_callback`; it
+/// lives as long as the `XqliteConn` (drop order: conn drops first —
+/// firing the CLOSE event — then the TraceDispatch).
+unsafe extern "C" fn trace_callback(
+    event: c_uint,
+    ctx: *mut c_void,
+    p: *mut c_void,
+    x: *mut c_void,
+) -> c_int {
+    // Registered via raw `ffi::sqlite3_trace_v2`, so nothing else catches a
+    // panic before it unwinds into SQLite's C stack. SQLite ignores the
+    // return value; 0 is what the docs ask for.
+    hook_util::guard_ffi_callback("trace_callback", 0, move || {
+        // SAFETY: see the doc comment above.
+        let dispatch = unsafe { &*(ctx as *const TraceDispatch) };
+        // Events of the slow-query log's own EXPLAIN are nobody's business.
+        if dispatch.slow_queries.is_capturing_plan() {
+            return 0;
+        }
+        // SAFETY: `p` / `x` are SQLite's arguments for `event`, and the conn
+        // mutex is held while SQLite runs statements.
+        unsafe { slow_query::observe(&dispatch.slow_queries, event, p, x) };
+        // SAFETY: as above.
+        unsafe { update_hook::observe(&dispatch.update_hooks, event, p, x) };
+
+        // Copy the event out once, and only if some subscriber wants it.
+        let mut captured: Option<Option<TraceEvent>> = None;
+        // SAFETY: snapshot borrow is valid while the callback runs (the conn
+        // mutex is held; no concurrent unregister can free the snapshot).
+        unsafe {
+            dispatch.list.for_each_snapshot(|entry| {
+                if entry.state.mask & event == 0 {
+                    return;
+                }
+                // SAFETY: `p` / `x` are SQLite's arguments for `event`.
+                let captured =
+                    captured.get_or_insert_with(|| TraceEvent::capture(event, p, x));
+                if let Some(trace) = captured {
+                    send_trace_to_pid(&entry.state.pid, trace);
+                }
+            });
+        }
+        0
+    })
+}
+
+/// Send one `{:xqlite_trace, …}` message to `pid`. Fire-and-forget.
+///
+/// # Safety
+///
+/// See `hook_util::send_with_env`.
+unsafe fn send_trace_to_pid(pid: &LocalPid, trace: &TraceEvent) {
+    // SAFETY: the message is built in send_with_env's fresh msg_env.
+    let _ = unsafe {
+        hook_util::send_with_env(pid, |env| {
+            let tag = atoms::xqlite_trace();
+            Ok(match trace {
+                TraceEvent::Stmt(sql) => (tag, atoms::stmt(), sql.as_str()).encode(env),
+                TraceEvent::Profile(sql, nanos) => {
+                    (tag, atoms::profile(), sql.as_str(), *nanos).encode(env)
+                }
+                TraceEvent::Row(sql) => (tag, atoms::row(), sql.as_str()).encode(env),
+                TraceEvent::Close => (tag, atoms::close()).encode(env),
+            })
+        })
+    };
+}
+
+/// Arm SQLite with the union of the subscribers' masks, plus PROFILE and
+/// ROW while a slow-query log is configured and STMT and PROFILE while an
+/// update subscriber batches (disarming it when the union is empty).
+/// Called once at open and again after every register / unregister.
+///
+/// # Safety
+///
+/// `dispatch` must outlive the SQLite Connection (live in the same
+/// `XqliteConn`, whose `Mutex<Connection>` field drops first by
+/// declaration order). Caller holds the connection Mutex.
+pub(crate) unsafe fn install_callback(conn: &Connection, dispatch: &TraceDispatch) {
+    let mut mask: c_uint = 0;
+    // SAFETY: caller holds the conn Mutex, so the snapshot cannot be
+    // reclaimed while we read it.
+    unsafe {
+        dispatch
+            .list
+            .for_each_snapshot(|entry| mask |= entry.state.mask);
+    }
+    if dispatch.slow_queries.is_enabled() {
+        mask |= ffi::SQLITE_TRACE_PROFILE | ffi::SQLITE_TRACE_ROW;
+    }
+    if dispatch.update_hooks.has_batches() {
+        mask |= update_hook::BATCH_TRACE_MASK;
+    }
+    let user_data = dispatch as *const TraceDispatch as *mut c_void;
+    let callback = (mask != 0).then_some(trace_callback as _);
+    // SAFETY: see the doc comment.
+    unsafe {
+        ffi::sqlite3_trace_v2(conn.handle(), mask, callback, user_data);
+    }
+}
+
+/// Add a trace subscriber for the events in `mask`. Returns the handle the
+/// caller passes to `unregister`. Callers must hold the connection Mutex.
+pub(crate) fn register(
+    conn: &Connection,
+    dispatch: &TraceDispatch,
+    pid: LocalPid,
+    mask: c_uint,
+) -> Result<u64, XqliteError> {
+    let id = dispatch.list.register(TraceSubscriber { pid, mask });
+    // SAFETY: `dispatch` lives inside the caller's XqliteConn.
+    unsafe { install_callback(conn, dispatch) };
+    Ok(id)
+}
+
+/// Remove a trace subscriber. Idempotent — unknown handles are no-ops.
+/// Callers must hold the connection Mutex.
+pub(crate) fn unregister(conn: &Connection, dispatch: &TraceDispatch, id: u64) {
+    if dispatch.list.unregister(id) {
+        // SAFETY: see `register`.
+        unsafe { install_callback(conn, dispatch) };
+    }
+}
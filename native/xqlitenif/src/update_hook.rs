@@ -5,19 +5,109 @@
 //! open time. The closure captures `Arc<HookList<UpdateSubscriber>>`
 //! and fans out each event to every subscriber. Register / unregister
 //! NIFs only modify the HookList; they never touch rusqlite.
+//!
+//! Subscribers registered with `batch: true` do not get one message per
+//! row. Their events are buffered per subscriber for the open
+//! transaction; the commit hook master closure flushes every buffer as
+//! a single `{:xqlite_update_batch, events}` message (`flush_batches`)
+//! and the rollback hook master closure discards them
+//! (`discard_batches`). Both run under the connection Mutex, like the
+//! update hook itself, so the buffer locks are never contended.
+//!
+//! SQLite also undoes work without a rollback hook: `ROLLBACK TO` a
+//! savepoint, and a statement that fails inside an open transaction
+//! (its own changes are rolled back, the transaction's are kept). Batch
+//! mode therefore rides on the trace callback (see `observe`), like the
+//! slow-query log does, and cuts the events of undone work from the
+//! buffers. Every buffered event carries a sequence number; `Marks`
+//! records where each open savepoint and each running statement began,
+//! and undoing one drops every buffered event from its mark on.
+//!
+//! The commit hook fires when COMMIT starts, not once it is durable, so
+//! a COMMIT that fails afterwards may still have delivered its batch.
+//! Buffers are uncapped: one `RowEvent` per matching row change stays
+//! in memory until the transaction ends.
 
+use crate::atoms;
 use crate::error::XqliteError;
 use crate::hook_util::{self, HookList};
+use crate::trace_hook::{self, TraceDispatch};
+use rusqlite::ffi;
 use rusqlite::hooks::Action;
 use rustler::sys::{
     enif_alloc_env, enif_free_env, enif_make_int64, enif_make_tuple_from_array, enif_send,
 };
 use rustler::types::LocalPid;
-use std::sync::Arc;
+use rustler::{Atom, Encoder, Term};
+use std::os::raw::{c_char, c_uint, c_void};
+use std::sync::{Arc, Mutex};
+
+/// The update-hook subscribers of one connection, plus the savepoint and
+/// statement marks their batches are cut back to. Shared by the update,
+/// commit, rollback and trace callbacks.
+#[derive(Debug, Default)]
+pub(crate) struct UpdateHooks {
+    pub(crate) list: HookList<UpdateSubscriber>,
+    marks: Mutex<Marks>,
+}
+
+/// Positions in the connection's stream of buffered row events, which
+/// numbers every event it buffers, where undoable work began.
+#[derive(Debug, Default)]
+struct Marks {
+    next_seq: u64,
+    /// Open savepoints, outermost first.
+    savepoints: Vec<(String, u64)>,
+    /// Top-level statements running now, by statement address.
+    statements: Vec<(usize, u64)>,
+    /// The statement that started running last.
+    last_started: usize,
+}
+
+impl UpdateHooks {
+    /// Whether any subscriber buffers its events (and so needs `observe`).
+    pub(crate) fn has_batches(&self) -> bool {
+        let mut any = false;
+        // SAFETY: callers hold the connection Mutex, so the snapshot
+        // cannot be reclaimed while we read it.
+        unsafe {
+            self.list
+                .for_each_snapshot(|entry| any |= entry.state.batch.is_some());
+        }
+        any
+    }
+
+    fn marks(&self) -> std::sync::MutexGuard<'_, Marks> {
+        // Every critical section leaves `Marks` consistent, so recover
+        // from poisoning rather than propagate it.
+        match self.marks.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Drop every buffered event numbered `from` or later.
+    fn cut_back(&self, from: u64) {
+        // SAFETY: called from the trace callback, under the connection Mutex.
+        unsafe {
+            self.list.for_each_snapshot(|entry| {
+                if let Some(buffer) = &entry.state.batch {
+                    let mut events = lock_buffer(buffer);
+                    let keep = events.partition_point(|e| e.seq < from);
+                    events.truncate(keep);
+                }
+            });
+        }
+    }
+}
 
 #[derive(Clone)]
 pub(crate) struct UpdateSubscriber {
     pub(crate) pid: LocalPid,
+    filter: UpdateFilter,
+    /// `Some` in batch mode: row events of the open transaction, shared
+    /// across the entry clones `HookList` makes on register/unregister.
+    batch: Option<Arc<Mutex<Vec<RowEvent>>>>,
 }
 
 impl std::fmt::Debug for UpdateSubscriber {
@@ -27,17 +117,97 @@ impl std::fmt::Debug for UpdateSubscriber {
 }
 
 impl UpdateSubscriber {
-    pub(crate) fn new(pid: LocalPid) -> Self {
-        Self { pid }
+    pub(crate) fn new(pid: LocalPid, options: UpdateHookOptions) -> Self {
+        Self {
+            pid,
+            filter: options.filter,
+            batch: options.batch.then(|| Arc::new(Mutex::new(Vec::new()))),
+        }
+    }
+}
+
+/// Per-subscriber event filter. `None` accepts everything.
+#[derive(Clone, Default)]
+struct UpdateFilter {
+    tables: Option<Vec<String>>,
+    actions: Option<Vec<Action>>,
+}
+
+impl UpdateFilter {
+    fn accepts(&self, action: Action, table: &str) -> bool {
+        let table_ok = self
+            .tables
+            .as_ref()
+            .is_none_or(|tables| tables.iter().any(|t| t.eq_ignore_ascii_case(table)));
+        let action_ok = self
+            .actions
+            .as_ref()
+            .is_none_or(|actions| actions.contains(&action));
+        table_ok && action_ok
+    }
+}
+
+/// Options accepted by `register_update_hook/3`.
+#[derive(Default)]
+pub(crate) struct UpdateHookOptions {
+    batch: bool,
+    filter: UpdateFilter,
+}
+
+/// Decode the `register_update_hook/3` keyword list. Unknown keys and
+/// malformed values are rejected with the offending key.
+pub(crate) fn parse_options(
+    opts: Vec<(Atom, Term<'_>)>,
+) -> Result<UpdateHookOptions, XqliteError> {
+    let mut out = UpdateHookOptions::default();
+    for (key, value) in opts {
+        let invalid = || XqliteError::InvalidUpdateHookOption { option: key };
+        if key == atoms::batch() {
+            out.batch = value.decode::<bool>().map_err(|_| invalid())?;
+        } else if key == atoms::tables() {
+            let tables = value.decode::<Vec<String>>().map_err(|_| invalid())?;
+            out.filter.tables = Some(tables);
+        } else if key == atoms::actions() {
+            let names = value.decode::<Vec<Atom>>().map_err(|_| invalid())?;
+            let actions = names
+                .into_iter()
+                .map(|name| action_from_atom(name).ok_or_else(invalid))
+                .collect::<Result<Vec<_>, _>>()?;
+            out.filter.actions = Some(actions);
+        } else {
+            return Err(invalid());
+        }
+    }
+    Ok(out)
+}
+
+fn action_from_atom(name: Atom) -> Option<Action> {
+    if name == atoms::insert() {
+        Some(Action::SQLITE_INSERT)
+    } else if name == atoms::update() {
+        Some(Action::SQLITE_UPDATE)
+    } else if name == atoms::delete() {
+        Some(Action::SQLITE_DELETE)
+    } else {
+        None
     }
 }
 
+/// One buffered row event, owned so it outlives the hook invocation.
+struct RowEvent {
+    seq: u64,
+    action: Atom,
+    db: String,
+    table: String,
+    rowid: i64,
+}
+
 /// Install the master update-hook closure on a freshly opened
-/// connection. The closure captures `list` so subscriber-level
+/// connection. The closure captures `hooks` so subscriber-level
 /// register / unregister stays cheap (modifies the HookList only).
 pub(crate) fn install_callback(
     conn: &rusqlite::Connection,
-    list: Arc<HookList<UpdateSubscriber>>,
+    hooks: Arc<UpdateHooks>,
 ) -> Result<(), XqliteError> {
     conn.update_hook(Some(
         move |action: Action, db: &str, table: &str, rowid: i64| {
@@ -48,11 +218,31 @@ pub(crate) fn install_callback(
                 _ => b"unknown",
             };
 
-            // SAFETY: the closure captures Arc<HookList>, so the list
+            let mut seq = None;
+            // SAFETY: the closure captures Arc<UpdateHooks>, so the list
             // outlives every callback. Snapshot iteration is wait-free.
             unsafe {
-                list.for_each_snapshot(|entry| {
-                    send_update_to_pid(&entry.state.pid, action_name, db, table, rowid);
+                hooks.list.for_each_snapshot(|entry| {
+                    let sub = &entry.state;
+                    if !sub.filter.accepts(action, table) {
+                        return;
+                    }
+                    match &sub.batch {
+                        Some(buffer) => lock_buffer(buffer).push(RowEvent {
+                            seq: *seq.get_or_insert_with(|| {
+                                let mut marks = hooks.marks();
+                                marks.next_seq += 1;
+                                marks.next_seq - 1
+                            }),
+                            action: action_atom(action),
+                            db: db.to_owned(),
+                            table: table.to_owned(),
+                            rowid,
+                        }),
+                        None => {
+                            send_update_to_pid(&sub.pid, action_name, db, table, rowid);
+                        }
+                    }
                 });
             }
         },
@@ -93,15 +283,297 @@ unsafe fn send_update_to_pid(
     }
 }
 
-/// Add an update subscriber.
+fn action_atom(action: Action) -> Atom {
+    match action {
+        Action::SQLITE_INSERT => atoms::insert(),
+        Action::SQLITE_UPDATE => atoms::update(),
+        Action::SQLITE_DELETE => atoms::delete(),
+        _ => atoms::unknown(),
+    }
+}
+
+/// Recover the buffer even if a previous holder panicked: losing the
+/// events of one transaction beats wedging the subscriber forever.
+fn lock_buffer(buffer: &Mutex<Vec<RowEvent>>) -> std::sync::MutexGuard<'_, Vec<RowEvent>> {
+    match buffer.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Deliver every batch-mode subscriber's buffered events as one
+/// `{:xqlite_update_batch, [{action, db, table, rowid}, ...]}` message.
+/// Subscribers with nothing buffered get no message. Called from the
+/// commit hook master closure.
+pub(crate) fn flush_batches(hooks: &UpdateHooks) {
+    hooks.marks().savepoints.clear();
+    // SAFETY: called from the commit hook closure, which captures an
+    // Arc of `hooks` and runs under the connection Mutex.
+    unsafe {
+        hooks.list.for_each_snapshot(|entry| {
+            let Some(buffer) = &entry.state.batch else {
+                return;
+            };
+            let events = std::mem::take(&mut *lock_buffer(buffer));
+            if events.is_empty() {
+                return;
+            }
+            // SAFETY: the message is fully built inside the fresh env by
+            // `send_with_env`; nothing escapes it.
+            let _ = hook_util::send_with_env(&entry.state.pid, |env| {
+                let terms: Vec<Term<'_>> = events
+                    .iter()
+                    .map(|e| (e.action, e.db.as_str(), e.table.as_str(), e.rowid).encode(env))
+                    .collect();
+                Ok((atoms::xqlite_update_batch(), terms).encode(env))
+            });
+        });
+    }
+}
+
+/// Drop every batch-mode subscriber's buffered events. Called from the
+/// rollback hook master closure.
+pub(crate) fn discard_batches(hooks: &UpdateHooks) {
+    hooks.marks().savepoints.clear();
+    // SAFETY: see `flush_batches`; called from the rollback hook closure.
+    unsafe {
+        hooks.list.for_each_snapshot(|entry| {
+            if let Some(buffer) = &entry.state.batch {
+                lock_buffer(buffer).clear();
+            }
+        });
+    }
+}
+
+/// Trace events `observe` needs while a batch-mode subscriber exists.
+pub(crate) const BATCH_TRACE_MASK: c_uint = ffi::SQLITE_TRACE_STMT | ffi::SQLITE_TRACE_PROFILE;
+
+/// Keep the batch buffers free of undone work. Called from the trace
+/// callback for every armed event.
+///
+/// STMT marks where a top-level statement began; SQLite also reports
+/// each trigger program it runs, with text of its own rather than the
+/// statement's. PROFILE, fired as soon as the statement halts, settles
+/// it:
+///
+/// * `SAVEPOINT`, `RELEASE` and `ROLLBACK TO` update the savepoint
+///   marks, and `ROLLBACK TO` cuts the buffers back to its savepoint's.
+///   A name SQLite does not know fails there and is ignored here.
+/// * A statement that writes and ends with `sqlite3_changes` at 0 was
+///   rolled back (or changed nothing), so the buffers are cut back to
+///   where it began. A statement that ran while another one began is not
+///   judged; a write cannot fail after its first step, so this only
+///   spares the `RETURNING` statement whose rows are still being read.
+///
+/// Known limit: an `OR IGNORE` or `OR FAIL` statement whose own rows
+/// were all skipped or failed first, but whose triggers wrote, also ends
+/// at 0 changes, and those trigger events are dropped.
+///
+/// # Safety
+///
+/// `p` and `x` must be the arguments SQLite passed to the trace callback
+/// for `event`, and the connection Mutex must be held.
+pub(crate) unsafe fn observe(
+    hooks: &UpdateHooks,
+    event: c_uint,
+    p: *mut c_void,
+    x: *mut c_void,
+) {
+    if event & BATCH_TRACE_MASK == 0 || !hooks.has_batches() {
+        return;
+    }
+    let stmt: *mut ffi::sqlite3_stmt = p.cast();
+    // SAFETY: `p` is the live statement the event is about.
+    let (db, sql) = unsafe { (ffi::sqlite3_db_handle(stmt), ffi::sqlite3_sql(stmt)) };
+    let mut marks = hooks.marks();
+
+    if event == ffi::SQLITE_TRACE_STMT {
+        // A trigger program's text is not the statement's own.
+        if x as *const c_char != sql {
+            return;
+        }
+        // SAFETY: `db` is the open connection running `stmt`.
+        if unsafe { ffi::sqlite3_get_autocommit(db) } != 0 {
+            // No transaction, so no savepoint survived (one that ended
+            // without writing fires neither commit nor rollback hook).
+            marks.savepoints.clear();
+        }
+        let began = marks.next_seq;
+        marks.statements.retain(|(s, _)| *s != stmt as usize);
+        marks.statements.push((stmt as usize, began));
+        marks.last_started = stmt as usize;
+        return;
+    }
+
+    let Some(index) = marks
+        .statements
+        .iter()
+        .position(|(s, _)| *s == stmt as usize)
+    else {
+        return;
+    };
+    let (_, began) = marks.statements.swap_remove(index);
+    // SAFETY: `sql` is the statement's own NUL-terminated text.
+    let text = unsafe { crate::trace_hook::c_text(sql) };
+    if let Some(op) = savepoint_op(&text) {
+        match op {
+            SavepointOp::Open(name) => {
+                let at = marks.next_seq;
+                marks.savepoints.push((name, at));
+            }
+            SavepointOp::Release(name) => {
+                if let Some(i) = innermost(&marks.savepoints, &name) {
+                    marks.savepoints.truncate(i);
+                }
+            }
+            SavepointOp::RollbackTo(name) => {
+                if let Some(i) = innermost(&marks.savepoints, &name) {
+                    let from = marks.savepoints[i].1;
+                    marks.savepoints.truncate(i + 1);
+                    drop(marks);
+                    hooks.cut_back(from);
+                }
+            }
+        }
+        return;
+    }
+    // SAFETY: `stmt` is live and `db` is its open connection.
+    let undone =
+        unsafe { ffi::sqlite3_stmt_readonly(stmt) == 0 && ffi::sqlite3_changes64(db) == 0 };
+    if undone && marks.last_started == stmt as usize {
+        drop(marks);
+        hooks.cut_back(began);
+    }
+}
+
+/// The innermost open savepoint called `name` (names ignore ASCII case).
+fn innermost(savepoints: &[(String, u64)], name: &str) -> Option<usize> {
+    savepoints
+        .iter()
+        .rposition(|(open, _)| open.eq_ignore_ascii_case(name))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum SavepointOp {
+    Open(String),
+    Release(String),
+    RollbackTo(String),
+}
+
+/// Recognise `SAVEPOINT name`, `RELEASE [SAVEPOINT] name` and
+/// `ROLLBACK [TRANSACTION] TO [SAVEPOINT] name`.
+fn savepoint_op(sql: &str) -> Option<SavepointOp> {
+    let mut words = SqlWords(sql);
+    let first = words.next()?;
+    if first.eq_ignore_ascii_case("savepoint") {
+        return words.next().map(SavepointOp::Open);
+    }
+    if first.eq_ignore_ascii_case("release") {
+        let name = words.next()?;
+        let name = if name.eq_ignore_ascii_case("savepoint") {
+            words.next()?
+        } else {
+            name
+        };
+        return Some(SavepointOp::Release(name));
+    }
+    if !first.eq_ignore_ascii_case("rollback") {
+        return None;
+    }
+    let mut word = words.next()?;
+    if word.eq_ignore_ascii_case("transaction") {
+        word = words.next()?;
+    }
+    if !word.eq_ignore_ascii_case("to") {
+        return None;
+    }
+    let name = words.next()?;
+    let name = if name.eq_ignore_ascii_case("savepoint") {
+        words.next()?
+    } else {
+        name
+    };
+    Some(SavepointOp::RollbackTo(name))
+}
+
+/// The leading words of one SQL statement: keywords and identifiers,
+/// dequoted, with whitespace and comments skipped. Stops at anything
+/// else.
+struct SqlWords<'a>(&'a str);
+
+impl Iterator for SqlWords<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        loop {
+            self.0 = self.0.trim_start();
+            if let Some(rest) = self.0.strip_prefix("--") {
+                self.0 = rest.split_once('\n').map_or("", |(_, after)| after);
+            } else if let Some(rest) = self.0.strip_prefix("/*") {
+                self.0 = rest.split_once("*/").map_or("", |(_, after)| after);
+            } else {
+                break;
+            }
+        }
+        let close = match self.0.chars().next()? {
+            '"' => '"',
+            '`' => '`',
+            '\'' => '\'',
+            '[' => ']',
+            c if c.is_alphanumeric() || c == '_' => {
+                let end = self
+                    .0
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+                    .unwrap_or(self.0.len());
+                let (word, rest) = self.0.split_at(end);
+                self.0 = rest;
+                return Some(word.to_string());
+            }
+            _ => return None,
+        };
+        // A quoted name; the closing quote doubled stands for itself
+        // (except in `[...]`).
+        let mut name = String::new();
+        let mut chars = self.0[1..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            if c != close {
+                name.push(c);
+                continue;
+            }
+            if close != ']' && self.0[1 + i + 1..].starts_with(close) {
+                name.push(close);
+                chars.next();
+                continue;
+            }
+            self.0 = &self.0[1 + i + 1..];
+            return Some(name);
+        }
+        None
+    }
+}
+
+/// Add an update subscriber, arming the trace callback when it is the
+/// first in batch mode. Callers must hold the connection Mutex.
 pub(crate) fn register(
-    list: &HookList<UpdateSubscriber>,
+    conn: &rusqlite::Connection,
+    trace: &TraceDispatch,
     pid: LocalPid,
+    options: UpdateHookOptions,
 ) -> Result<u64, XqliteError> {
-    Ok(list.register(UpdateSubscriber::new(pid)))
+    let id = trace
+        .update_hooks
+        .list
+        .register(UpdateSubscriber::new(pid, options));
+    // SAFETY: `trace` lives inside the caller's XqliteConn.
+    unsafe { trace_hook::install_callback(conn, trace) };
+    Ok(id)
 }
 
-/// Remove an update subscriber. Idempotent.
-pub(crate) fn unregister(list: &HookList<UpdateSubscriber>, id: u64) {
-    let _ = list.unregister(id);
+/// Remove an update subscriber. Idempotent. Events it still had
+/// buffered are dropped. Callers must hold the connection Mutex.
+pub(crate) fn unregister(conn: &rusqlite::Connection, trace: &TraceDispatch, id: u64) {
+    if trace.update_hooks.list.unregister(id) {
+        // SAFETY: see `register`.
+        unsafe { trace_hook::install_callback(conn, trace) };
+    }
 }
@@ -3,8 +3,8 @@ use crate::error::XqliteError;
 use rusqlite::ffi;
 use rusqlite::{Rows, types::Value};
 use rustler::{
-    Atom, Binary, Encoder, Env, Error as RustlerError, ListIterator, Resource, ResourceArc,
-    Term, TermType, resource_impl,
+    Atom, Binary, Encoder, Env, Error as RustlerError, ListIterator, MapIterator, Resource,
+    ResourceArc, Term, TermType, resource_impl,
     types::{
         atom::{error, false_, nil, ok, true_},
         binary::OwnedBinary,
@@ -170,7 +170,6 @@ pub(crate) fn singular_ok_or_error_tuple<'a>(
 pub(crate) fn process_rows<'a, 'rows>(
     env: Env<'a>,
     mut rows: Rows<'rows>,
-    column_count: usize,
 ) -> Result<Vec<Vec<Term<'a>>>, XqliteError> {
     let mut results: Vec<Vec<Term<'a>>> = Vec::new();
 
@@ -179,6 +178,7 @@ pub(crate) fn process_rows<'a, 'rows>(
 
         match row_option_result {
             Ok(Some(row)) => {
+                let column_count = row.as_ref().column_count();
                 let mut row_values: Vec<Term<'a>> = Vec::with_capacity(column_count);
                 for i in 0..column_count {
                     let val = row.get::<usize, Value>(i)?;
@@ -197,7 +197,7 @@ pub(crate) fn process_rows<'a, 'rows>(
 }
 
 #[inline]
-fn elixir_term_to_rusqlite_value<'a>(
+pub(crate) fn elixir_term_to_rusqlite_value<'a>(
     env: Env<'a>,
     term: Term<'a>,
 ) -> Result<Value, XqliteError> {
@@ -272,6 +272,25 @@ pub(crate) fn decode_exec_keyword_params<'a>(
     Ok(params)
 }
 
+/// Decodes a map with string keys (`%{"id" => 1}`, `%{":id" => 1}`) into
+/// name/value pairs; names are resolved by `stream::parameter_index`.
+pub(crate) fn decode_map_params<'a>(
+    env: Env<'a>,
+    map_term: Term<'a>,
+) -> Result<Vec<(String, Value)>, XqliteError> {
+    let iter = MapIterator::new(map_term).ok_or_else(|| XqliteError::ExpectedList {
+        value_str: format!("{map_term:?}"),
+    })?;
+    let mut params: Vec<(String, Value)> = Vec::new();
+    for (key_term, value_term) in iter {
+        let name: String = key_term
+            .decode()
+            .map_err(|_| XqliteError::InvalidParameterName(format!("{key_term:?}")))?;
+        params.push((name, elixir_term_to_rusqlite_value(env, value_term)?));
+    }
+    Ok(params)
+}
+
 pub(crate) fn decode_plain_list_params<'a>(
     env: Env<'a>,
     list_term: Term<'a>,
@@ -0,0 +1,155 @@
+defmodule Xqlite.NIF.AggregateFunctionTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias XqliteNIF, as: NIF
+
+  defmodule Product do
+    @behaviour Xqlite.Aggregate
+
+    @impl true
+    def init, do: 1
+
+    @impl true
+    def step(acc, [nil]), do: acc
+    def step(acc, [x]), do: acc * x
+
+    @impl true
+    def finalize(acc), do: acc
+  end
+
+  defmodule MovingSum do
+    @behaviour Xqlite.Aggregate
+
+    @impl true
+    def init, do: 0
+
+    @impl true
+    def step(acc, [x]), do: acc + x
+
+    @impl true
+    def inverse(acc, [x]), do: acc - x
+
+    @impl true
+    def value(acc), do: acc
+
+    @impl true
+    def finalize(acc), do: acc
+  end
+
+  defmodule Faulty do
+    @behaviour Xqlite.Aggregate
+
+    @impl true
+    def init, do: []
+
+    @impl true
+    def step(_acc, [13]), do: raise("unlucky")
+    def step(acc, [x]), do: [x | acc]
+
+    @impl true
+    def finalize(acc), do: length(acc)
+  end
+
+  for_each_opener "aggregate functions" do
+    setup %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE t(grp TEXT, x INTEGER, w REAL);
+        INSERT INTO t VALUES ('a', 2, 1.0), ('a', 3, 1.0), ('a', 4, 2.0),
+                             ('b', 5, 1.0), ('b', NULL, 1.0);
+        CREATE TABLE empty(x INTEGER);
+        """)
+
+      :ok
+    end
+
+    test "an Xqlite.Aggregate module aggregates per group", %{conn: conn} do
+      :ok = Xqlite.register_aggregate_function(conn, "product", 1, [:deterministic], Product)
+
+      assert {:ok, %{rows: [["a", 24], ["b", 5]]}} =
+               NIF.query(conn, "SELECT grp, product(x) FROM t GROUP BY grp ORDER BY grp", [])
+    end
+
+    test "an empty group finalizes the init/0 state", %{conn: conn} do
+      :ok = Xqlite.register_aggregate_function(conn, "product", 1, [], Product)
+
+      assert {:ok, %{rows: [[1]]}} = NIF.query(conn, "SELECT product(x) FROM empty", [])
+    end
+
+    test "window functions use inverse/2 and value/1", %{conn: conn} do
+      :ok = Xqlite.register_aggregate_function(conn, "msum", 1, [], MovingSum, window: true)
+
+      sql = """
+      SELECT x, msum(x) OVER (ORDER BY x ROWS BETWEEN 1 PRECEDING AND CURRENT ROW)
+      FROM t WHERE grp = 'a' ORDER BY x
+      """
+
+      assert {:ok, %{rows: [[2, 2], [3, 5], [4, 7]]}} = NIF.query(conn, sql, [])
+
+      # A window function is a plain aggregate too.
+      assert {:ok, %{rows: [[9]]}} =
+               NIF.query(conn, "SELECT msum(x) FROM t WHERE grp = 'a'", [])
+    end
+
+    test "a raising callback fails the statement", %{conn: conn} do
+      :ok = Xqlite.register_aggregate_function(conn, "faulty", 1, [], Faulty)
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO t VALUES ('c', 13, 1.0)", [])
+
+      assert {:ok, %{rows: [[3]]}} =
+               NIF.query(conn, "SELECT faulty(x) FROM t WHERE grp = 'a'", [])
+
+      assert {:error, {:user_function_failed, "faulty", :error, "unlucky"}} =
+               NIF.query(conn, "SELECT faulty(x) FROM t WHERE grp = 'c'", [])
+    end
+
+    test "a self-managed pid answers via handle_aggregate_call/5", %{conn: conn} do
+      handler =
+        spawn_link(fn ->
+          Enum.each(1..4, fn _ ->
+            receive do
+              {:xqlite_aggregate_call, call_id, "prod", phase, state, args} ->
+                Xqlite.Function.handle_aggregate_call(call_id, phase, state, args, Product)
+            end
+          end)
+        end)
+
+      # Three steps for group 'a' plus one finalize.
+      :ok = NIF.register_aggregate_function(conn, "prod", 1, [], handler, 1_000)
+
+      assert {:ok, %{rows: [[24]]}} =
+               NIF.query(conn, "SELECT prod(x) FROM t WHERE grp = 'a'", [])
+    end
+
+    test "the :weighted_median built-in works as aggregate and window", %{conn: conn} do
+      :ok = Xqlite.register_builtin_aggregate(conn, "wmedian", :weighted_median)
+
+      # a: 2 (w1), 3 (w1), 4 (w2) — half of 4 is reached at 3.
+      # b: the NULL-valued row is skipped.
+      grouped = "SELECT grp, wmedian(x, w) FROM t GROUP BY grp ORDER BY grp"
+      assert {:ok, %{rows: [["a", 3.0], ["b", 5.0]]}} = NIF.query(conn, grouped, [])
+
+      assert {:ok, %{rows: [[nil]]}} = NIF.query(conn, "SELECT wmedian(x, 1) FROM empty", [])
+
+      sql = """
+      SELECT wmedian(x, w) OVER (ORDER BY x ROWS BETWEEN 1 PRECEDING AND CURRENT ROW)
+      FROM t WHERE grp = 'a' ORDER BY x
+      """
+
+      assert {:ok, %{rows: [[2.0], [2.0], [4.0]]}} = NIF.query(conn, sql, [])
+    end
+
+    test "unknown built-ins are rejected", %{conn: conn} do
+      assert {:error, {:invalid_builtin_aggregate, :hyperloglog}} =
+               Xqlite.register_builtin_aggregate(conn, "hll", :hyperloglog)
+    end
+
+    test "unregister_function/3 removes an aggregate", %{conn: conn} do
+      :ok = Xqlite.register_aggregate_function(conn, "product", 1, [], Product)
+      :ok = Xqlite.unregister_function(conn, "product", 1)
+
+      assert {:error, _} = NIF.query(conn, "SELECT product(x) FROM t", [])
+    end
+  end
+end
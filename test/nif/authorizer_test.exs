@@ -2,6 +2,7 @@ defmodule Xqlite.NIF.AuthorizerTest do
   use ExUnit.Case, async: true
 
   import Xqlite.ConnCase
+  import Xqlite.TestUtil, only: [tmp_db_path: 1]
 
   alias XqliteNIF, as: NIF
 
@@ -94,6 +95,161 @@ defmodule Xqlite.NIF.AuthorizerTest do
       assert :ok = Xqlite.remove_authorizer(conn)
       assert :ok = Xqlite.remove_authorizer(conn)
     end
+
+    test "a table matcher denies UPDATE on one table only", %{conn: conn} do
+      :ok = NIF.execute_batch(conn, "CREATE TABLE other(id INTEGER PRIMARY KEY, name TEXT);")
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO other(id, name) VALUES (1, 'a')", [])
+
+      :ok = Xqlite.set_authorizer_rules(conn, [{:deny, :update, table: "T"}])
+
+      assert {:error, {:authorization_denied, _, _}} =
+               NIF.execute(conn, "UPDATE t SET name = 'b'", [])
+
+      assert {:ok, 1} = NIF.execute(conn, "UPDATE other SET name = 'b'", [])
+    end
+
+    test ":ignore on a column read returns NULL for it", %{conn: conn} do
+      :ok = Xqlite.set_authorizer_rules(conn, [{:ignore, :read, table: "t", column: "name"}])
+
+      assert {:ok, %{rows: [[1, nil]]}} = NIF.query(conn, "SELECT id, name FROM t", [])
+    end
+
+    test "the first matching rule wins", %{conn: conn} do
+      path = tmp_db_path("authorizer_attach")
+
+      :ok =
+        Xqlite.set_authorizer_rules(conn, [
+          {:allow, :attach, file: path},
+          {:deny, [:attach, :detach]}
+        ])
+
+      assert {:ok, _} = NIF.execute(conn, "ATTACH DATABASE '#{path}' AS allowed", [])
+
+      assert {:error, {:authorization_denied, _, _}} =
+               NIF.execute(conn, "ATTACH DATABASE ':memory:' AS other", [])
+
+      assert {:error, {:authorization_denied, _, _}} =
+               NIF.execute(conn, "DETACH DATABASE allowed", [])
+    end
+
+    test "the default applies when no rule matches", %{conn: conn} do
+      :ok =
+        Xqlite.set_authorizer_rules(conn, [{:allow, :select}, {:allow, :read, table: "t"}],
+          default: :deny
+        )
+
+      assert {:ok, %{rows: [[1]]}} = NIF.query(conn, "SELECT id FROM t", [])
+
+      assert {:error, {:authorization_denied, _, _}} =
+               NIF.execute(conn, "DELETE FROM t", [])
+    end
+
+    test "malformed rules are rejected and install nothing", %{conn: conn} do
+      assert {:error, {:invalid_authorizer_rule, "{maybe,delete}"}} =
+               Xqlite.set_authorizer_rules(conn, [{:deny, :insert}, {:maybe, :delete}])
+
+      assert {:error, {:invalid_authorizer_rule, _}} =
+               Xqlite.set_authorizer_rules(conn, [{:deny, :delete, owner: "x"}])
+
+      assert {:error, {:invalid_authorizer_action, :bogus}} =
+               Xqlite.set_authorizer_rules(conn, [{:deny, [:delete, :bogus]}])
+
+      assert {:error, {:invalid_authorizer_rule, _}} =
+               Xqlite.set_authorizer_rules(conn, [], default: :maybe)
+
+      assert {:ok, 1} = NIF.execute(conn, "INSERT INTO t(id, name) VALUES (2, 'b')", [])
+    end
+
+    test "an observer sees each request with its arguments and decision", %{conn: conn} do
+      {:ok, handle} = Xqlite.register_authorizer_observer(conn, self())
+      :ok = Xqlite.set_authorizer_rules(conn, [{:ignore, :read, column: "name"}])
+
+      assert {:ok, %{rows: [[1, nil]]}} = NIF.query(conn, "SELECT id, name FROM t", [])
+
+      assert_receive {:xqlite_authorizer, :select, nil, nil, nil, nil, :allow}
+      assert_receive {:xqlite_authorizer, :read, "t", "id", "main", nil, :allow}
+      assert_receive {:xqlite_authorizer, :read, "t", "name", "main", nil, :ignore}
+
+      :ok = Xqlite.unregister_authorizer_observer(conn, handle)
+    end
+
+    test "an observer alone allows everything", %{conn: conn} do
+      {:ok, _handle} = Xqlite.register_authorizer_observer(conn, self())
+
+      assert {:ok, 1} = NIF.execute(conn, "DELETE FROM t WHERE id = 1", [])
+      assert_receive {:xqlite_authorizer, :delete, "t", nil, "main", nil, :allow}
+    end
+
+    test "raw arguments follow SQLite's layout", %{conn: conn} do
+      {:ok, _handle} = Xqlite.register_authorizer_observer(conn, self())
+
+      {:ok, _} = NIF.execute(conn, "PRAGMA user_version = 3", [])
+      assert_receive {:xqlite_authorizer, :pragma, "user_version", "3", _, nil, :allow}
+
+      :ok = NIF.execute_batch(conn, "BEGIN; COMMIT;")
+      assert_receive {:xqlite_authorizer, :transaction, "BEGIN", nil, nil, nil, :allow}
+      assert_receive {:xqlite_authorizer, :transaction, "COMMIT", nil, nil, nil, :allow}
+
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE VIEW v AS SELECT name FROM t;
+        """)
+
+      {:ok, _} = NIF.query(conn, "SELECT * FROM v", [])
+      assert_receive {:xqlite_authorizer, :read, "t", "name", "main", "v", :allow}
+    end
+
+    test "a dry-run policy reports decisions without enforcing them", %{conn: conn} do
+      {:ok, _handle} = Xqlite.register_authorizer_observer(conn, self())
+      :ok = Xqlite.set_authorizer_rules(conn, [{:deny, :delete, table: "t"}], dry_run: true)
+
+      assert {:ok, 1} = NIF.execute(conn, "DELETE FROM t WHERE id = 1", [])
+      assert_receive {:xqlite_authorizer, :delete, "t", nil, "main", nil, :deny}
+    end
+
+    test "observers survive policy changes and stop after unregistering", %{conn: conn} do
+      {:ok, handle} = Xqlite.register_authorizer_observer(conn, self())
+      :ok = Xqlite.set_authorizer(conn, [:insert])
+      :ok = Xqlite.remove_authorizer(conn)
+
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO t(id, name) VALUES (2, 'b')", [])
+      assert_receive {:xqlite_authorizer, :insert, "t", nil, "main", nil, :allow}
+
+      :ok = Xqlite.unregister_authorizer_observer(conn, handle)
+      assert :ok = Xqlite.unregister_authorizer_observer(conn, handle)
+
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO t(id, name) VALUES (3, 'c')", [])
+      refute_receive {:xqlite_authorizer, :insert, _, _, _, _, _}, 50
+    end
+
+    test "every observer receives each request", %{conn: conn} do
+      parent = self()
+
+      relay = fn tag ->
+        spawn_link(fn ->
+          receive do
+            {:xqlite_authorizer, :delete, _, _, _, _, _} -> send(parent, {tag, :delete})
+          end
+        end)
+      end
+
+      {:ok, _} = Xqlite.register_authorizer_observer(conn, relay.(:one))
+      {:ok, _} = Xqlite.register_authorizer_observer(conn, relay.(:two))
+
+      {:ok, 1} = NIF.execute(conn, "DELETE FROM t WHERE id = 1", [])
+      assert_receive {:one, :delete}
+      assert_receive {:two, :delete}
+    end
+
+    test "rules replace a deny-list in the same slot", %{conn: conn} do
+      :ok = Xqlite.set_authorizer(conn, [:delete])
+      :ok = Xqlite.set_authorizer_rules(conn, [{:deny, :insert}])
+
+      assert {:ok, 1} = NIF.execute(conn, "DELETE FROM t WHERE id = 1", [])
+
+      assert {:error, {:authorization_denied, _, _}} =
+               NIF.execute(conn, "INSERT INTO t(id, name) VALUES (2, 'b')", [])
+    end
   end
 
   # -------------------------------------------------------------------
@@ -106,5 +262,6 @@ defmodule Xqlite.NIF.AuthorizerTest do
 
     assert {:error, :connection_closed} = Xqlite.set_authorizer(conn, [:delete])
     assert {:error, :connection_closed} = Xqlite.remove_authorizer(conn)
+    assert {:error, :connection_closed} = Xqlite.register_authorizer_observer(conn, self())
   end
 end
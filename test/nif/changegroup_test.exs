@@ -0,0 +1,120 @@
+defmodule Xqlite.NIF.ChangegroupTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+  import Xqlite.TestUtil, only: [record_changeset: 2, tmp_db_path: 1]
+
+  alias Xqlite.Change
+  alias XqliteNIF, as: NIF
+
+  @schema "CREATE TABLE cg_t (id INTEGER PRIMARY KEY, val TEXT);"
+
+  defp changes(changeset), do: changeset |> Xqlite.changeset_stream() |> Enum.to_list()
+
+  for_each_opener "changegroup" do
+    setup %{conn: conn} do
+      :ok = NIF.execute_batch(conn, @schema)
+      :ok
+    end
+
+    test "folding many changesets matches pairwise concat", %{conn: conn} do
+      changesets =
+        [
+          "INSERT INTO cg_t VALUES (1, 'a'), (2, 'b');",
+          "UPDATE cg_t SET val = 'a2' WHERE id = 1;",
+          "DELETE FROM cg_t WHERE id = 2;",
+          "INSERT INTO cg_t VALUES (3, 'c');"
+        ]
+        |> Enum.map(&record_changeset(conn, &1))
+
+      {:ok, group} = NIF.changegroup_new()
+      Enum.each(changesets, &(:ok = NIF.changegroup_add(group, &1)))
+      {:ok, merged} = NIF.changegroup_output(group)
+
+      concatenated =
+        Enum.reduce(tl(changesets), hd(changesets), fn next, acc ->
+          {:ok, acc} = NIF.changeset_concat(acc, next)
+          acc
+        end)
+
+      assert changes(merged) == changes(concatenated)
+
+      assert [%Change{op: :insert, new: [1, "a2"]}, %Change{op: :insert, new: [3, "c"]}] =
+               Enum.sort_by(changes(merged), & &1.new)
+    end
+
+    test "output can be taken repeatedly while adding", %{conn: conn} do
+      {:ok, group} = NIF.changegroup_new()
+      first_insert = record_changeset(conn, "INSERT INTO cg_t VALUES (1, 'a');")
+      :ok = NIF.changegroup_add(group, first_insert)
+      {:ok, first} = NIF.changegroup_output(group)
+      second_insert = record_changeset(conn, "INSERT INTO cg_t VALUES (2, 'b');")
+      :ok = NIF.changegroup_add(group, second_insert)
+      {:ok, second} = NIF.changegroup_output(group)
+
+      assert length(changes(first)) == 1
+      assert length(changes(second)) == 2
+    end
+
+    test "files can be added and written", %{conn: conn} do
+      input = tmp_db_path("changegroup_in")
+      output = tmp_db_path("changegroup_out")
+      File.write!(input, record_changeset(conn, "INSERT INTO cg_t VALUES (1, 'a');"))
+
+      {:ok, group} = NIF.changegroup_new()
+      :ok = NIF.changegroup_add_file(group, input)
+      :ok = NIF.changegroup_add(group, record_changeset(conn, "UPDATE cg_t SET val = 'b';"))
+      :ok = NIF.changegroup_output_file(group, output)
+
+      {:ok, merged} = NIF.changegroup_output(group)
+      assert File.read!(output) == merged
+      assert [%Change{op: :insert, new: [1, "b"]}] = changes(merged)
+    end
+
+    test "a schema-bound group merges across ADD COLUMN", %{conn: conn} do
+      before = record_changeset(conn, "INSERT INTO cg_t VALUES (1, 'a');")
+      :ok = NIF.execute_batch(conn, "ALTER TABLE cg_t ADD COLUMN n INTEGER DEFAULT 7;")
+      later = record_changeset(conn, "INSERT INTO cg_t VALUES (2, 'b', 8);")
+
+      {:ok, group} = NIF.changegroup_new(conn, "main")
+      :ok = NIF.changegroup_add(group, before)
+      :ok = NIF.changegroup_add(group, later)
+      {:ok, merged} = NIF.changegroup_output(group)
+
+      assert [[1, "a", 7], [2, "b", 8]] =
+               merged |> changes() |> Enum.map(& &1.new) |> Enum.sort()
+
+      assert {:ok, merged} == Xqlite.changeset_merge([before, later], schema: conn)
+    end
+
+    test "without a schema, mismatched column counts are rejected", %{conn: conn} do
+      before = record_changeset(conn, "INSERT INTO cg_t VALUES (1, 'a');")
+      :ok = NIF.execute_batch(conn, "ALTER TABLE cg_t ADD COLUMN n INTEGER;")
+      later = record_changeset(conn, "INSERT INTO cg_t VALUES (2, 'b', 8);")
+
+      assert {:error, _} = Xqlite.changeset_merge([before, later])
+    end
+
+    test "delete is idempotent and later calls fail", %{conn: conn} do
+      {:ok, group} = NIF.changegroup_new()
+      assert :ok = NIF.changegroup_delete(group)
+      assert :ok = NIF.changegroup_delete(group)
+
+      changeset = record_changeset(conn, "INSERT INTO cg_t VALUES (1, 'a');")
+      assert {:error, _} = NIF.changegroup_add(group, changeset)
+
+      assert {:error, _} = NIF.changegroup_output(group)
+    end
+
+    test "changeset_merge writes to a file and reports missing inputs", %{conn: conn} do
+      changeset = record_changeset(conn, "INSERT INTO cg_t VALUES (1, 'a');")
+      path = tmp_db_path("changegroup_merge")
+
+      assert :ok = Xqlite.changeset_merge([changeset], into: {:file, path})
+      assert File.read!(path) == changeset
+
+      assert {:error, {:cannot_open_file, "/nonexistent/xqlite.changeset", _}} =
+               Xqlite.changeset_merge([changeset, {:file, "/nonexistent/xqlite.changeset"}])
+    end
+  end
+end
@@ -0,0 +1,204 @@
+defmodule Xqlite.NIF.ChangesetApplyTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+  import Xqlite.TestUtil, only: [record_changeset: 2]
+
+  alias Xqlite.Change
+  alias XqliteNIF, as: NIF
+
+  @schema """
+  CREATE TABLE ca_t (id INTEGER PRIMARY KEY, val TEXT);
+  CREATE TABLE ca_u (id INTEGER PRIMARY KEY, val TEXT);
+  """
+
+  defp replica(seed_sql) do
+    {:ok, replica} = NIF.open_in_memory(":memory:")
+    :ok = NIF.execute_batch(replica, @schema <> seed_sql)
+    on_exit(fn -> NIF.close(replica) end)
+    replica
+  end
+
+  defp rows(conn, table) do
+    {:ok, %{rows: rows}} = NIF.query(conn, "SELECT * FROM #{table} ORDER BY id", [])
+    rows
+  end
+
+  # Answers every conflict with `decision` and reports what it saw.
+  defp conflict_handler(decision) do
+    parent = self()
+
+    spawn_link(fn ->
+      Stream.repeatedly(fn ->
+        receive do
+          {:xqlite_changeset_conflict, call_id, type, change, conflicting} ->
+            send(parent, {:conflict, type, change, conflicting})
+
+            Xqlite.Conflict.handle_conflict(call_id, type, change, conflicting, fn _, _, _ ->
+              decision
+            end)
+        end
+      end)
+      |> Stream.run()
+    end)
+  end
+
+  for_each_opener "changeset apply" do
+    setup %{conn: conn} do
+      :ok = NIF.execute_batch(conn, @schema)
+      :ok
+    end
+
+    test "a pid handler sees :conflict with both rows and can replace", %{conn: conn} do
+      changeset = record_changeset(conn, "INSERT INTO ca_t VALUES (1, 'theirs');")
+      replica = replica("INSERT INTO ca_t VALUES (1, 'mine');")
+
+      assert :ok = NIF.changeset_apply(replica, changeset, conflict_handler(:replace))
+
+      assert_receive {:conflict, :conflict, %Change{op: :insert, new: [1, "theirs"]},
+                      [1, "mine"]}
+
+      assert rows(replica, "ca_t") == [[1, "theirs"]]
+    end
+
+    test "a :data conflict can be omitted", %{conn: conn} do
+      :ok = NIF.execute_batch(conn, "INSERT INTO ca_t VALUES (1, 'base');")
+      changeset = record_changeset(conn, "UPDATE ca_t SET val = 'theirs' WHERE id = 1;")
+      replica = replica("INSERT INTO ca_t VALUES (1, 'mine');")
+
+      assert :ok = NIF.changeset_apply(replica, changeset, conflict_handler(:omit))
+      assert_receive {:conflict, :data, %Change{op: :update}, [1, "mine"]}
+      assert rows(replica, "ca_t") == [[1, "mine"]]
+    end
+
+    test ":notfound carries no conflicting row", %{conn: conn} do
+      :ok = NIF.execute_batch(conn, "INSERT INTO ca_t VALUES (1, 'base');")
+      changeset = record_changeset(conn, "DELETE FROM ca_t WHERE id = 1;")
+      replica = replica("")
+
+      assert :ok = NIF.changeset_apply(replica, changeset, conflict_handler(:omit))
+      assert_receive {:conflict, :notfound, %Change{op: :delete, old: [1, "base"]}, nil}
+    end
+
+    test "the handler decides per conflict", %{conn: conn} do
+      changeset = record_changeset(conn, "INSERT INTO ca_t VALUES (1, 'a'), (2, 'b');")
+      replica = replica("INSERT INTO ca_t VALUES (1, 'x'), (2, 'y');")
+
+      decide = fn :conflict, %Change{new: [id, _]}, _row ->
+        if id == 1, do: :replace, else: :omit
+      end
+
+      assert :ok = Xqlite.changeset_apply(replica, changeset, decide)
+      assert rows(replica, "ca_t") == [[1, "a"], [2, "y"]]
+    end
+
+    test ":abort rolls back everything applied so far", %{conn: conn} do
+      changeset = record_changeset(conn, "INSERT INTO ca_t VALUES (1, 'a'), (2, 'b');")
+      replica = replica("INSERT INTO ca_t VALUES (2, 'y');")
+
+      assert {:error, _} = Xqlite.changeset_apply(replica, changeset, fn _, _, _ -> :abort end)
+      assert rows(replica, "ca_t") == [[2, "y"]]
+    end
+
+    test "replacing a :notfound conflict fails the apply", %{conn: conn} do
+      :ok = NIF.execute_batch(conn, "INSERT INTO ca_t VALUES (1, 'base');")
+      changeset = record_changeset(conn, "UPDATE ca_t SET val = 'new' WHERE id = 1;")
+      replica = replica("")
+
+      assert {:error, {:changeset_handler_failed, :invalid_result, message}} =
+               Xqlite.changeset_apply(replica, changeset, fn _, _, _ -> :replace end)
+
+      assert message =~ ":notfound"
+    end
+
+    test "handler errors, bad decisions and timeouts abort the apply", %{conn: conn} do
+      changeset = record_changeset(conn, "INSERT INTO ca_t VALUES (1, 'a'), (2, 'b');")
+      replica = replica("INSERT INTO ca_t VALUES (2, 'y');")
+
+      assert {:error, {:changeset_handler_failed, :error, "boom"}} =
+               Xqlite.changeset_apply(replica, changeset, fn _, _, _ -> raise "boom" end)
+
+      assert {:error, {:changeset_handler_failed, :error, message}} =
+               Xqlite.changeset_apply(replica, changeset, fn _, _, _ -> :maybe end)
+
+      assert message =~ ":maybe"
+
+      silent = spawn_link(fn -> Process.sleep(:infinity) end)
+
+      assert {:error, {:changeset_handler_failed, :timeout, _}} =
+               NIF.changeset_apply(replica, changeset, silent, timeout: 50)
+
+      assert rows(replica, "ca_t") == [[2, "y"]]
+    end
+
+    test ":tables and :exclude_tables select what is applied", %{conn: conn} do
+      changeset =
+        record_changeset(
+          conn,
+          "INSERT INTO ca_t VALUES (1, 'a'); INSERT INTO ca_u VALUES (1, 'b');"
+        )
+
+      only_t = replica("")
+      assert :ok = NIF.changeset_apply(only_t, changeset, :abort, tables: ["CA_T"])
+      assert {rows(only_t, "ca_t"), rows(only_t, "ca_u")} == {[[1, "a"]], []}
+
+      not_t = replica("")
+      assert :ok = NIF.changeset_apply(not_t, changeset, :abort, exclude_tables: ["ca_t"])
+      assert {rows(not_t, "ca_t"), rows(not_t, "ca_u")} == {[], [[1, "b"]]}
+    end
+
+    test "a table filter is asked once per table", %{conn: conn} do
+      changeset =
+        record_changeset(conn, """
+        INSERT INTO ca_t VALUES (1, 'a'), (2, 'b');
+        INSERT INTO ca_u VALUES (1, 'c');
+        """)
+
+      parent = self()
+
+      filter = fn table ->
+        send(parent, {:asked, table})
+        table == "ca_u"
+      end
+
+      replica = replica("")
+      assert :ok = Xqlite.changeset_apply(replica, changeset, :abort, table_filter: filter)
+
+      assert_received {:asked, "ca_t"}
+      assert_received {:asked, "ca_u"}
+      refute_received {:asked, _}
+      assert {rows(replica, "ca_t"), rows(replica, "ca_u")} == {[], [[1, "c"]]}
+    end
+
+    test "a failing table filter applies nothing", %{conn: conn} do
+      changeset =
+        record_changeset(
+          conn,
+          "INSERT INTO ca_t VALUES (1, 'a'); INSERT INTO ca_u VALUES (1, 'b');"
+        )
+
+      replica = replica("")
+
+      assert {:error, {:changeset_handler_failed, :error, message}} =
+               Xqlite.changeset_apply(replica, changeset, :omit,
+                 table_filter: fn table -> table == "ca_t" or :dunno end
+               )
+
+      assert message =~ ":dunno"
+      assert {rows(replica, "ca_t"), rows(replica, "ca_u")} == {[], []}
+    end
+
+    test "invalid arguments are rejected", %{conn: conn} do
+      changeset = record_changeset(conn, "INSERT INTO ca_t VALUES (1, 'a');")
+
+      assert {:error, :invalid_conflict_strategy} =
+               NIF.changeset_apply(conn, changeset, "omit")
+
+      assert {:error, {:invalid_changeset_apply_option, :bogus}} =
+               NIF.changeset_apply(conn, changeset, :omit, bogus: true)
+
+      assert {:error, {:invalid_changeset_apply_option, :timeout}} =
+               NIF.changeset_apply(conn, changeset, self(), timeout: 0)
+    end
+  end
+end
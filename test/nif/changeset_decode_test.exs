@@ -0,0 +1,148 @@
+defmodule Xqlite.NIF.ChangesetDecodeTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+  import Xqlite.TestUtil, only: [record_changeset: 2, record_changeset: 3, tmp_db_path: 1]
+
+  alias Xqlite.Change
+  alias XqliteNIF, as: NIF
+
+  for_each_opener "changeset decoding" do
+    setup %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE cs_t (id INTEGER PRIMARY KEY, name TEXT, score REAL, data BLOB);
+        INSERT INTO cs_t VALUES (1, 'alice', 1.5, x'00ff');
+        INSERT INTO cs_t VALUES (2, 'bob', NULL, NULL);
+        """)
+
+      :ok
+    end
+
+    test "INSERT decodes to a typed struct", %{conn: conn} do
+      changeset = record_changeset(conn, "INSERT INTO cs_t VALUES (3, 'carol', 2.5, x'01');")
+
+      assert {:ok, [change]} = NIF.changeset_decode(changeset)
+
+      assert change == %Change{
+               table: "cs_t",
+               op: :insert,
+               indirect: false,
+               pk_columns: [0],
+               old: nil,
+               new: [3, "carol", 2.5, <<1>>]
+             }
+    end
+
+    test "UPDATE marks unchanged columns :undefined", %{conn: conn} do
+      changeset = record_changeset(conn, "UPDATE cs_t SET name = 'alicia' WHERE id = 1;")
+
+      assert {:ok, [%Change{op: :update} = change]} = NIF.changeset_decode(changeset)
+      assert change.old == [1, "alice", :undefined, :undefined]
+      assert change.new == [:undefined, "alicia", :undefined, :undefined]
+    end
+
+    test "DELETE carries the full old row, NULLs included", %{conn: conn} do
+      changeset = record_changeset(conn, "DELETE FROM cs_t WHERE id = 2;")
+
+      assert {:ok, [%Change{op: :delete, old: [2, "bob", nil, nil], new: nil}]} =
+               NIF.changeset_decode(changeset)
+    end
+
+    test "patchsets decode with only the recorded columns", %{conn: conn} do
+      patchset =
+        record_changeset(
+          conn,
+          """
+          DELETE FROM cs_t WHERE id = 2;
+          UPDATE cs_t SET score = 9.0 WHERE id = 1;
+          """,
+          :patchset
+        )
+
+      assert {:ok, changes} = NIF.changeset_decode(patchset)
+      by_op = Map.new(changes, &{&1.op, &1})
+
+      assert by_op.delete.old == [2, :undefined, :undefined, :undefined]
+      assert by_op.update.old == [1, :undefined, :undefined, :undefined]
+      assert by_op.update.new == [:undefined, :undefined, 9.0, :undefined]
+    end
+
+    test "trigger-made changes are flagged indirect", %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE cs_log (id INTEGER PRIMARY KEY, msg TEXT);
+        CREATE TRIGGER cs_t_ai AFTER INSERT ON cs_t
+        BEGIN INSERT INTO cs_log (msg) VALUES (NEW.name); END;
+        """)
+
+      changeset = record_changeset(conn, "INSERT INTO cs_t (id, name) VALUES (3, 'dave');")
+
+      assert {:ok, changes} = NIF.changeset_decode(changeset)
+      assert %{"cs_t" => false, "cs_log" => true} = Map.new(changes, &{&1.table, &1.indirect})
+    end
+
+    test "an empty changeset decodes to []" do
+      assert {:ok, []} = NIF.changeset_decode(<<>>)
+    end
+
+    test "corrupt input returns an error", %{conn: conn} do
+      changeset = record_changeset(conn, "INSERT INTO cs_t VALUES (3, 'x', 0, NULL);")
+      truncated = binary_part(changeset, 0, byte_size(changeset) - 3)
+
+      assert {:error, _} = NIF.changeset_decode(truncated)
+    end
+
+    test "iterator hands out batches, then :done", %{conn: conn} do
+      changeset =
+        record_changeset(conn, """
+        INSERT INTO cs_t (id) VALUES (10);
+        INSERT INTO cs_t (id) VALUES (11);
+        INSERT INTO cs_t (id) VALUES (12);
+        """)
+
+      {:ok, iter} = NIF.changeset_iter_open(changeset)
+      assert {:ok, [_, _]} = NIF.changeset_iter_next(iter, 2)
+      assert {:ok, [_]} = NIF.changeset_iter_next(iter, 2)
+      assert :done = NIF.changeset_iter_next(iter, 2)
+      assert :done = NIF.changeset_iter_next(iter, 2)
+      assert :ok = NIF.changeset_iter_close(iter)
+      assert :ok = NIF.changeset_iter_close(iter)
+    end
+
+    test "iterator matches changeset_decode/1", %{conn: conn} do
+      changeset =
+        record_changeset(conn, """
+        UPDATE cs_t SET score = score + 1;
+        DELETE FROM cs_t WHERE id = 2;
+        """)
+
+      {:ok, decoded} = NIF.changeset_decode(changeset)
+      assert Enum.to_list(Xqlite.changeset_stream(changeset, batch_size: 1)) == decoded
+    end
+
+    test "streams a changeset from a file", %{conn: conn} do
+      changeset =
+        record_changeset(conn, """
+        WITH RECURSIVE n(i) AS (SELECT 100 UNION ALL SELECT i + 1 FROM n WHERE i < 2099)
+        INSERT INTO cs_t (id, name) SELECT i, 'row' || i FROM n;
+        """)
+
+      path = tmp_db_path("changeset")
+      File.write!(path, changeset)
+
+      changes = Enum.to_list(Xqlite.changeset_stream({:file, path}, batch_size: 128))
+      assert length(changes) == 2_000
+      assert Enum.all?(changes, &match?(%Change{op: :insert, table: "cs_t"}, &1))
+    end
+
+    test "missing file is reported" do
+      assert {:error, {:cannot_open_file, "/nonexistent/xqlite.changeset", _}} =
+               NIF.changeset_iter_open_file("/nonexistent/xqlite.changeset")
+
+      assert_raise Xqlite.StreamError, fn ->
+        Enum.to_list(Xqlite.changeset_stream({:file, "/nonexistent/xqlite.changeset"}))
+      end
+    end
+  end
+end
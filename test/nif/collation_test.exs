@@ -0,0 +1,220 @@
+defmodule Xqlite.NIF.CollationTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias XqliteNIF, as: NIF
+
+  # Orders by length; equal lengths compare equal unless `tiebreak?`.
+  def by_length(a, b, tiebreak?) do
+    cond do
+      String.length(a) < String.length(b) -> :lt
+      String.length(a) > String.length(b) -> :gt
+      tiebreak? and a < b -> :lt
+      tiebreak? and a > b -> :gt
+      true -> :eq
+    end
+  end
+
+  # The dispatcher `register` spawns, found by the link it adds to us.
+  defp spawned_dispatcher(register) do
+    {:links, before} = Process.info(self(), :links)
+    :ok = register.()
+    {:links, now} = Process.info(self(), :links)
+    [pid] = now -- before
+    pid
+  end
+
+  defp sorted(conn, collation, values) do
+    values_sql = Enum.map_join(values, ", ", &"('#{&1}')")
+    sql = "SELECT column1 FROM (VALUES #{values_sql}) ORDER BY column1 COLLATE #{collation}"
+    {:ok, %{rows: rows}} = NIF.query(conn, sql, [])
+    List.flatten(rows)
+  end
+
+  for_each_opener "collations" do
+    test ":unicode_nocase folds case beyond ASCII", %{conn: conn} do
+      :ok = Xqlite.register_collation(conn, "u_nocase", :unicode_nocase)
+
+      sql = "SELECT 'ÉCOLE' = 'école' COLLATE u_nocase, 'ÉCOLE' = 'école' COLLATE NOCASE"
+      assert {:ok, %{rows: [[1, 0]]}} = NIF.query(conn, sql, [])
+    end
+
+    test ":natural orders digit runs numerically", %{conn: conn} do
+      :ok = Xqlite.register_collation(conn, "natural", :natural)
+
+      assert sorted(conn, "natural", ["img12", "img2", "img1", "img10"]) ==
+               ["img1", "img2", "img10", "img12"]
+    end
+
+    test ":no_accents ignores diacritics but not case", %{conn: conn} do
+      :ok = Xqlite.register_collation(conn, "no_accents", :no_accents)
+
+      assert {:ok, %{rows: [[1, 1, 0]]}} =
+               NIF.query(
+                 conn,
+                 """
+                 SELECT 'crème brûlée' = 'creme brulee' COLLATE no_accents,
+                        'Straße' = 'Strasse' COLLATE no_accents,
+                        'É' = 'e' COLLATE no_accents
+                 """,
+                 []
+               )
+    end
+
+    test "native collations work in indexes and UNIQUE constraints", %{conn: conn} do
+      :ok = Xqlite.register_collation(conn, "u_nocase", :unicode_nocase)
+      :ok = NIF.execute_batch(conn, "CREATE TABLE u(name TEXT UNIQUE COLLATE u_nocase);")
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO u VALUES ('Ärger')", [])
+
+      assert {:error, {:constraint_violation, :constraint_unique, _}} =
+               NIF.execute(conn, "INSERT INTO u VALUES ('äRGER')", [])
+    end
+
+    test "unknown native collations are rejected", %{conn: conn} do
+      assert {:error, {:invalid_native_collation, :klingon}} =
+               Xqlite.register_collation(conn, "k", :klingon)
+    end
+
+    test "a fun comparator orders rows", %{conn: conn} do
+      :ok = Xqlite.register_collation(conn, "len", fn a, b -> by_length(a, b, true) end)
+
+      assert sorted(conn, "len", ["ccc", "a", "bb", "ab"]) == ["a", "ab", "bb", "ccc"]
+    end
+
+    test "an MFA comparator receives the extra arguments", %{conn: conn} do
+      :ok = Xqlite.register_collation(conn, "len_eq", {__MODULE__, :by_length, [false]})
+
+      assert {:ok, %{rows: [[1]]}} = NIF.query(conn, "SELECT 'ab' = 'zz' COLLATE len_eq", [])
+    end
+
+    test "a failing comparator falls back to byte order", %{conn: conn} do
+      :ok = Xqlite.register_collation(conn, "broken", fn _, _ -> raise "nope" end)
+
+      assert sorted(conn, "broken", ["b", "c", "a"]) == ["a", "b", "c"]
+    end
+
+    test "a self-managed pid answers via handle_compare/4", %{conn: conn} do
+      reversed = fn x, y -> by_length(y, x, true) end
+
+      handler =
+        spawn_link(fn ->
+          receive do
+            {:xqlite_collation_call, call_id, "rev", a, b} ->
+              Xqlite.Collation.handle_compare(call_id, a, b, reversed)
+          end
+        end)
+
+      :ok = NIF.register_collation(conn, "rev", handler, 1_000)
+      assert {:ok, %{rows: [[0]]}} = NIF.query(conn, "SELECT 'a' < 'bb' COLLATE rev", [])
+    end
+
+    test "unregister_collation/2 removes a collation", %{conn: conn} do
+      :ok = Xqlite.register_collation(conn, "natural", :natural)
+      :ok = Xqlite.unregister_collation(conn, "natural")
+
+      assert {:error, _} = NIF.query(conn, "SELECT 'a' = 'a' COLLATE natural", [])
+    end
+
+    test "a comparator's dispatcher stops when its collation goes away", %{conn: conn} do
+      first =
+        spawned_dispatcher(fn ->
+          Xqlite.register_collation(conn, "len", &by_length(&1, &2, true))
+        end)
+
+      ref = Process.monitor(first)
+
+      second =
+        spawned_dispatcher(fn ->
+          Xqlite.register_collation(conn, "len", &by_length(&2, &1, true))
+        end)
+
+      assert_receive {:DOWN, ^ref, :process, ^first, :normal}
+
+      ref = Process.monitor(second)
+      :ok = Xqlite.unregister_collation(conn, "len")
+      assert_receive {:DOWN, ^ref, :process, ^second, :normal}
+      assert {:links, links} = Process.info(self(), :links)
+      refute second in links
+    end
+  end
+
+  for_each_opener "collation_needed hook" do
+    test "unknown collations fail without a hook", %{conn: conn} do
+      assert {:error, _} = NIF.query(conn, "SELECT 'a' = 'A' COLLATE mystery", [])
+    end
+
+    test "the resolver can pick a native collation", %{conn: conn} do
+      test_pid = self()
+
+      :ok =
+        Xqlite.set_collation_needed_hook(conn, fn name ->
+          send(test_pid, {:needed, name})
+          if name == "natural_sort", do: :natural
+        end)
+
+      assert sorted(conn, "natural_sort", ["v10", "v9"]) == ["v9", "v10"]
+      assert_received {:needed, "natural_sort"}
+
+      # Resolved once, then registered on the connection.
+      assert sorted(conn, "natural_sort", ["v10", "v9"]) == ["v9", "v10"]
+      refute_received {:needed, _}
+
+      assert {:error, _} = NIF.query(conn, "SELECT 'a' = 'A' COLLATE other", [])
+      assert_received {:needed, "other"}
+    end
+
+    test "the resolver can return a comparator fun", %{conn: conn} do
+      longest_first = fn a, b -> by_length(b, a, true) end
+      :ok = Xqlite.set_collation_needed_hook(conn, fn "desc" -> longest_first end)
+
+      assert sorted(conn, "desc", ["a", "ccc", "bb"]) == ["ccc", "bb", "a"]
+    end
+
+    test "schemas referencing unknown collations become usable", %{conn: conn} do
+      :ok = Xqlite.register_collation(conn, "legacy", :unicode_nocase)
+      :ok = NIF.execute_batch(conn, "CREATE TABLE l(v TEXT UNIQUE COLLATE legacy);")
+      :ok = Xqlite.unregister_collation(conn, "legacy")
+
+      assert {:error, _} = NIF.execute(conn, "INSERT INTO l VALUES ('x')", [])
+
+      :ok = Xqlite.set_collation_needed_hook(conn, fn "legacy" -> :unicode_nocase end)
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO l VALUES ('Ñandú')", [])
+
+      assert {:ok, %{rows: [[1]]}} =
+               NIF.query(conn, "SELECT count(*) FROM l WHERE v = 'ñANDÚ'", [])
+    end
+
+    test "a raising resolver leaves the collation unknown", %{conn: conn} do
+      :ok = Xqlite.set_collation_needed_hook(conn, fn _ -> raise "no idea" end)
+
+      assert {:error, _} = NIF.query(conn, "SELECT 'a' = 'A' COLLATE mystery", [])
+    end
+
+    test "the resolver's dispatcher outlives the hook while it serves a collation",
+         %{conn: conn} do
+      resolver =
+        spawned_dispatcher(fn ->
+          Xqlite.set_collation_needed_hook(conn, fn "desc" -> &by_length(&2, &1, true) end)
+        end)
+
+      ref = Process.monitor(resolver)
+      assert sorted(conn, "desc", ["a", "bb"]) == ["bb", "a"]
+
+      :ok = Xqlite.remove_collation_needed_hook(conn)
+      assert sorted(conn, "desc", ["a", "bb"]) == ["bb", "a"]
+      refute_received {:DOWN, ^ref, _, _, _}
+
+      :ok = Xqlite.unregister_collation(conn, "desc")
+      assert_receive {:DOWN, ^ref, :process, ^resolver, :normal}
+    end
+
+    test "remove_collation_needed_hook/1 is idempotent", %{conn: conn} do
+      :ok = Xqlite.set_collation_needed_hook(conn, fn _ -> :natural end)
+      assert :ok = Xqlite.remove_collation_needed_hook(conn)
+      assert :ok = Xqlite.remove_collation_needed_hook(conn)
+
+      assert {:error, _} = NIF.query(conn, "SELECT 'a' = 'A' COLLATE mystery", [])
+    end
+  end
+end
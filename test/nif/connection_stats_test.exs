@@ -19,7 +19,10 @@ defmodule Xqlite.NIF.ConnectionStatsTest do
     :deferred_fks,
     :cache_used_shared,
     :cache_spill,
-    :tempbuf_spill
+    :tempbuf_spill,
+    :statement_cache_hit,
+    :statement_cache_miss,
+    :statement_cache_eviction
   ]
 
   for {type_tag, prefix, _opener_mfa_ignored_here} <- connection_openers() do
@@ -0,0 +1,111 @@
+defmodule Xqlite.NIF.ExecuteManyTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias XqliteNIF, as: NIF
+
+  @insert "INSERT INTO em(id, name) VALUES (?1, ?2)"
+
+  defp names(conn) do
+    {:ok, %{rows: rows}} = NIF.query(conn, "SELECT name FROM em ORDER BY id", [])
+    List.flatten(rows)
+  end
+
+  for_each_opener "execute_many" do
+    setup %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE em(id INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE);
+        """)
+
+      :ok
+    end
+
+    test "runs every parameter set and sums the changes", %{conn: conn} do
+      sets = for n <- 1..500, do: [n, "name#{n}"]
+
+      assert {:ok, 500} = NIF.execute_many(conn, @insert, sets, true, [])
+      assert {:ok, %{rows: [[500]]}} = NIF.query(conn, "SELECT count(*) FROM em", [])
+
+      ids = for n <- 1..250, do: [n]
+      assert {:ok, 250} = NIF.execute_many(conn, "DELETE FROM em WHERE id = ?1", ids, true, [])
+    end
+
+    test "keyword parameter sets bind by name", %{conn: conn} do
+      sql = "INSERT INTO em(id, name) VALUES (:id, :name)"
+
+      assert {:ok, 2} =
+               NIF.execute_many(conn, sql, [[id: 1, name: "a"], [name: "b", id: 2]], true, [])
+
+      assert names(conn) == ["a", "b"]
+    end
+
+    test "an empty list changes nothing", %{conn: conn} do
+      assert {:ok, 0} = NIF.execute_many(conn, @insert, [], true, [])
+    end
+
+    test "a failing row reports its index and rolls back the run", %{conn: conn} do
+      sets = [[1, "a"], [2, "b"], [3, "a"], [4, "d"]]
+
+      assert {:error, {:row_failed, 2, {:constraint_violation, :constraint_unique, details}}} =
+               NIF.execute_many(conn, @insert, sets, true, [])
+
+      assert details.table == "em"
+      assert names(conn) == []
+      assert {:ok, false} = NIF.transaction_status(conn)
+    end
+
+    test "without a transaction the rows before a failure stay", %{conn: conn} do
+      sets = [[1, "a"], [2, nil], [3, "c"]]
+
+      assert {:error, {:row_failed, 1, {:constraint_violation, :constraint_not_null, _}}} =
+               NIF.execute_many(conn, @insert, sets, false, [])
+
+      assert names(conn) == ["a"]
+    end
+
+    test "nests inside an open transaction", %{conn: conn} do
+      :ok = NIF.begin(conn)
+      {:ok, 1} = NIF.execute(conn, @insert, [1, "outer"])
+
+      assert {:error, {:row_failed, 1, _}} =
+               NIF.execute_many(conn, @insert, [[2, "b"], [3, "outer"]], true, [])
+
+      assert names(conn) == ["outer"]
+      assert {:ok, 1} = NIF.execute_many(conn, @insert, [[2, "b"]], true, [])
+      :ok = NIF.rollback(conn)
+
+      assert names(conn) == []
+    end
+
+    test "an unbindable value fails its row", %{conn: conn} do
+      assert {:error, {:row_failed, 1, {:unsupported_data_type, _}}} =
+               NIF.execute_many(conn, @insert, [[1, "a"], [2, self()]], true, [])
+
+      assert names(conn) == []
+    end
+
+    test "errors before any row are not wrapped", %{conn: conn} do
+      assert {:error, {:no_such_table, _}} =
+               NIF.execute_many(conn, "INSERT INTO nope VALUES (?1)", [[1]], true, [])
+
+      assert {:error, {:expected_list, _}} = NIF.execute_many(conn, @insert, :nope, true, [])
+    end
+
+    test "a statement returning rows fails its row", %{conn: conn} do
+      assert {:error, {:row_failed, 0, :execute_returned_results}} =
+               NIF.execute_many(conn, "SELECT ?1", [[1]], true, [])
+    end
+
+    test "a signalled token cancels the run", %{conn: conn} do
+      {:ok, token} = NIF.create_cancel_token()
+      :ok = NIF.cancel_operation(token)
+
+      assert {:error, :operation_cancelled} =
+               NIF.execute_many(conn, @insert, [[1, "a"], [2, "b"]], true, [token])
+
+      assert names(conn) == []
+    end
+  end
+end
@@ -0,0 +1,80 @@
+defmodule Xqlite.NIF.ExplainBytecodeTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias Xqlite.BytecodeOp
+  alias XqliteNIF, as: NIF
+
+  for_each_opener "explain bytecode" do
+    setup %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE t(id INTEGER PRIMARY KEY, name TEXT);
+        INSERT INTO t(name) VALUES ('a'), ('b'), ('c');
+        """)
+
+      :ok
+    end
+
+    test "lists the program as BytecodeOp structs in address order", %{conn: conn} do
+      assert {:ok, ops} = NIF.explain_bytecode(conn, "SELECT name FROM t", [])
+
+      assert [%BytecodeOp{addr: 0, opcode: "Init"} | _] = ops
+      assert Enum.map(ops, & &1.addr) == Enum.to_list(0..(length(ops) - 1))
+      assert Enum.any?(ops, &(&1.opcode == "Halt"))
+      assert Enum.all?(ops, &is_nil(&1.subprog))
+    end
+
+    test "instructions carry execution counts", %{conn: conn} do
+      assert {:ok, ops} = NIF.explain_bytecode(conn, "SELECT name FROM t WHERE id >= ?1", [2])
+
+      assert %BytecodeOp{nexec: 1} = Enum.find(ops, &(&1.opcode == "Init"))
+      assert %BytecodeOp{nexec: 2} = Enum.find(ops, &(&1.opcode == "ResultRow"))
+      assert Enum.all?(ops, &(is_integer(&1.nexec) and is_integer(&1.ncycle)))
+    end
+
+    test "Explain instructions carry the matching scan", %{conn: conn} do
+      assert {:ok, ops} = NIF.explain_bytecode(conn, "SELECT * FROM t ORDER BY name", [])
+
+      [scan_op, sort_op] = Enum.filter(ops, &(&1.opcode == "Explain"))
+      assert scan_op.p4 == "SCAN t"
+      assert %{name: "t", loops: 1, rows_visited: 3} = scan_op.scan
+      assert sort_op.p4 == "USE TEMP B-TREE FOR ORDER BY"
+      assert %{selectid: selectid} = sort_op.scan
+      assert selectid == sort_op.p1
+
+      assert Enum.all?(ops, &(&1.opcode == "Explain" or is_nil(&1.scan)))
+    end
+
+    test "trigger programs are listed with their subprog", %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE audit(id INTEGER);
+        CREATE TRIGGER t_audit AFTER INSERT ON t BEGIN
+          INSERT INTO audit VALUES (new.id);
+        END;
+        """)
+
+      assert {:ok, ops} = NIF.explain_bytecode(conn, "INSERT INTO t(name) VALUES ('d')", [])
+
+      assert Enum.any?(ops, &(&1.subprog == "TRIGGER t_audit"))
+      assert {:ok, %{rows: [[1]]}} = NIF.query(conn, "SELECT count(*) FROM audit", [])
+    end
+
+    test "named parameters are bound", %{conn: conn} do
+      assert {:ok, ops} =
+               NIF.explain_bytecode(conn, "SELECT name FROM t WHERE id = :id", id: 3)
+
+      assert %BytecodeOp{nexec: 1} = Enum.find(ops, &(&1.opcode == "ResultRow"))
+    end
+
+    test "comment-only SQL yields an empty listing", %{conn: conn} do
+      assert {:ok, []} = NIF.explain_bytecode(conn, "-- nothing here", [])
+    end
+
+    test "SQL errors surface as error tuples", %{conn: conn} do
+      assert {:error, _} = NIF.explain_bytecode(conn, "SELECT * FROM no_such_table", [])
+    end
+  end
+end
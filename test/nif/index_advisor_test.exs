@@ -0,0 +1,139 @@
+defmodule Xqlite.NIF.IndexAdvisorTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias XqliteNIF, as: NIF
+
+  for_each_opener "index advisor" do
+    setup %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE orgs(id INTEGER PRIMARY KEY, name TEXT);
+        CREATE TABLE users(
+          id INTEGER PRIMARY KEY,
+          email TEXT,
+          org_id INTEGER REFERENCES orgs(id),
+          created_at INTEGER
+        );
+        CREATE INDEX users_org ON users(org_id);
+        """)
+
+      :ok
+    end
+
+    test "suggests an index for an unindexed lookup", %{conn: conn} do
+      assert {:ok, advice} =
+               NIF.suggest_indexes(conn, ["SELECT * FROM users WHERE email = ?1"])
+
+      assert [index] = advice.indexes
+      assert index =~ ~r/^CREATE INDEX users_idx_\w+ ON users\(email\)$/
+      assert [statement] = advice.statements
+      assert statement.sql == "SELECT * FROM users WHERE email = ?1"
+      assert statement.indexes == advice.indexes
+      assert [%{parent: 0, detail: "SCAN users"}] = statement.plan_before
+
+      assert [%{parent: 0, detail: "SEARCH users USING INDEX users_idx_" <> _}] =
+               statement.plan_after
+    end
+
+    test "an existing index leaves nothing to suggest", %{conn: conn} do
+      assert {:ok, %{indexes: [], statements: [statement]}} =
+               NIF.suggest_indexes(conn, ["SELECT * FROM users WHERE org_id = ?1"])
+
+      assert statement.indexes == []
+      assert statement.plan_after == statement.plan_before
+    end
+
+    test "equality plus ORDER BY gets a two-column index", %{conn: conn} do
+      sql = "SELECT * FROM users WHERE email = ?1 ORDER BY created_at"
+      assert {:ok, %{statements: [statement]}} = NIF.suggest_indexes(conn, [sql])
+
+      assert [index] = statement.indexes
+      assert index =~ ~r/ON users\(email, created_at\)$/
+      assert Enum.any?(statement.plan_before, &(&1.detail =~ "TEMP B-TREE"))
+      refute Enum.any?(statement.plan_after, &(&1.detail =~ "TEMP B-TREE"))
+    end
+
+    test "joins get an index for the outer loop", %{conn: conn} do
+      sql = """
+      SELECT orgs.name FROM orgs JOIN users ON users.org_id = orgs.id
+      WHERE orgs.name = ?1
+      """
+
+      assert {:ok, %{statements: [statement]}} = NIF.suggest_indexes(conn, [sql])
+
+      assert [index] = statement.indexes
+      assert index =~ ~r/ON orgs\(name\)$/
+      assert Enum.any?(statement.plan_before, &(&1.detail == "SCAN orgs"))
+      assert Enum.all?(statement.plan_after, &String.starts_with?(&1.detail, "SEARCH"))
+    end
+
+    test "plans keep the EXPLAIN QUERY PLAN tree", %{conn: conn} do
+      sql = "SELECT * FROM orgs WHERE id IN (SELECT org_id FROM users WHERE email = ?1)"
+      assert {:ok, %{statements: [statement]}} = NIF.suggest_indexes(conn, [sql])
+
+      assert [_] = statement.indexes
+
+      for plan <- [statement.plan_before, statement.plan_after] do
+        assert %{id: subquery, parent: 0} = Enum.find(plan, &(&1.detail =~ "LIST SUBQUERY"))
+        assert %{detail: "S" <> _} = Enum.find(plan, &(&1.parent == subquery))
+      end
+
+      assert Enum.any?(statement.plan_after, &(&1.detail =~ "USING INDEX users_idx_"))
+    end
+
+    test "the workload list holds each suggestion once", %{conn: conn} do
+      sqls = [
+        "SELECT * FROM users WHERE email = ?1",
+        "DELETE FROM users WHERE email = ?1",
+        "SELECT 1"
+      ]
+
+      assert {:ok, advice} = NIF.suggest_indexes(conn, sqls)
+
+      assert [_] = advice.indexes
+      assert Enum.map(advice.statements, &length(&1.indexes)) == [1, 1, 0]
+    end
+
+    test "the connection's schema is left untouched", %{conn: conn} do
+      count_sql = "SELECT count(*) FROM sqlite_schema WHERE type = 'index'"
+      {:ok, %{rows: [[before]]}} = NIF.query(conn, count_sql, [])
+
+      {:ok, _} = NIF.suggest_indexes(conn, ["SELECT * FROM users WHERE email = ?1"])
+
+      assert {:ok, %{rows: [[^before]]}} = NIF.query(conn, count_sql, [])
+    end
+
+    test "connection-defined collations and functions are stood in for", %{conn: conn} do
+      :ok = NIF.register_native_collation(conn, "natsort", :natural)
+
+      :ok =
+        Xqlite.register_scalar_function(conn, "lowr", 1, [:deterministic], fn [s] ->
+          String.downcase(s)
+        end)
+
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE docs(id INTEGER PRIMARY KEY, title TEXT COLLATE natsort, slug TEXT);
+        CREATE INDEX docs_lowr_slug ON docs(lowr(slug));
+        CREATE VIRTUAL TABLE notes USING fts5(body);
+        """)
+
+      assert {:ok, %{indexes: [index], statements: [statement]}} =
+               NIF.suggest_indexes(conn, ["SELECT id FROM docs WHERE title = ?1"])
+
+      assert index =~ ~r/ON docs\(title\)$/
+      assert [%{detail: "SEARCH docs USING COVERING INDEX " <> _}] = statement.plan_after
+
+      assert {:ok, %{statements: [%{plan_before: [%{detail: detail}]}]}} =
+               NIF.suggest_indexes(conn, ["SELECT id FROM docs WHERE lowr(slug) = ?1"])
+
+      assert detail =~ "USING INDEX docs_lowr_slug"
+    end
+
+    test "a statement that does not prepare fails the call", %{conn: conn} do
+      assert {:error, _} = NIF.suggest_indexes(conn, ["SELECT * FROM no_such_table"])
+    end
+  end
+end
@@ -0,0 +1,122 @@
+defmodule Xqlite.NIF.PreupdateHookTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias XqliteNIF, as: NIF
+
+  for_each_opener "preupdate hook" do
+    setup %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE pre_t (id INTEGER PRIMARY KEY, name TEXT, score REAL, data BLOB);
+        INSERT INTO pre_t VALUES (1, 'alice', 1.5, x'00ff');
+        """)
+
+      :ok
+    end
+
+    test "INSERT delivers the new row", %{conn: conn} do
+      {:ok, _h} = NIF.register_preupdate_hook(conn, self())
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO pre_t VALUES (2, 'bob', NULL, NULL)", [])
+
+      assert_receive {:xqlite_preupdate, :insert, "main", "pre_t", details}, 2_000
+
+      assert details == %{
+               column_count: 4,
+               depth: 0,
+               old_rowid: nil,
+               new_rowid: 2,
+               old: nil,
+               new: [2, "bob", nil, nil]
+             }
+    end
+
+    test "UPDATE delivers old and new rows", %{conn: conn} do
+      {:ok, _h} = NIF.register_preupdate_hook(conn, self())
+      {:ok, 1} = NIF.execute(conn, "UPDATE pre_t SET name = 'carol', score = 2.0", [])
+
+      assert_receive {:xqlite_preupdate, :update, "main", "pre_t", details}, 2_000
+      assert details.old == [1, "alice", 1.5, <<0, 255>>]
+      assert details.new == [1, "carol", 2.0, <<0, 255>>]
+      assert {details.old_rowid, details.new_rowid} == {1, 1}
+    end
+
+    test "UPDATE of the rowid reports both rowids", %{conn: conn} do
+      {:ok, _h} = NIF.register_preupdate_hook(conn, self())
+      {:ok, 1} = NIF.execute(conn, "UPDATE pre_t SET id = 10 WHERE id = 1", [])
+
+      assert_receive {:xqlite_preupdate, :update, "main", "pre_t", details}, 2_000
+      assert {details.old_rowid, details.new_rowid} == {1, 10}
+    end
+
+    test "DELETE delivers the deleted row's values", %{conn: conn} do
+      {:ok, _h} = NIF.register_preupdate_hook(conn, self())
+      {:ok, 1} = NIF.execute(conn, "DELETE FROM pre_t WHERE id = 1", [])
+
+      assert_receive {:xqlite_preupdate, :delete, "main", "pre_t", details}, 2_000
+      assert details.old == [1, "alice", 1.5, <<0, 255>>]
+      assert details.new == nil
+      assert {details.old_rowid, details.new_rowid} == {1, nil}
+    end
+
+    test "changes made by a trigger report depth 1", %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE pre_log (msg TEXT);
+        CREATE TRIGGER pre_t_ai AFTER INSERT ON pre_t
+        BEGIN INSERT INTO pre_log VALUES (NEW.name); END;
+        """)
+
+      {:ok, _h} = NIF.register_preupdate_hook(conn, self())
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO pre_t (id, name) VALUES (2, 'dave')", [])
+
+      assert_receive {:xqlite_preupdate, :insert, "main", "pre_t", %{depth: 0}}, 2_000
+
+      assert_receive {:xqlite_preupdate, :insert, "main", "pre_log",
+                      %{depth: 1, new: ["dave"]}},
+                     2_000
+    end
+
+    test "every subscriber receives each event", %{conn: conn} do
+      parent = self()
+
+      other =
+        spawn_link(fn ->
+          receive do
+            msg -> send(parent, {:forwarded, msg})
+          end
+        end)
+
+      {:ok, _h1} = NIF.register_preupdate_hook(conn, self())
+      {:ok, _h2} = NIF.register_preupdate_hook(conn, other)
+      {:ok, 1} = NIF.execute(conn, "DELETE FROM pre_t", [])
+
+      assert_receive {:xqlite_preupdate, :delete, _, _, _}, 2_000
+      assert_receive {:forwarded, {:xqlite_preupdate, :delete, _, _, _}}, 2_000
+    end
+
+    test "unregister stops delivery and is idempotent", %{conn: conn} do
+      {:ok, h} = NIF.register_preupdate_hook(conn, self())
+      assert :ok = NIF.unregister_preupdate_hook(conn, h)
+      assert :ok = NIF.unregister_preupdate_hook(conn, h)
+      assert :ok = NIF.unregister_preupdate_hook(conn, 999_999)
+
+      {:ok, 1} = NIF.execute(conn, "DELETE FROM pre_t", [])
+      refute_receive {:xqlite_preupdate, _, _, _, _}, 200
+    end
+
+    test "is mutually exclusive with sessions", %{conn: conn} do
+      {:ok, session} = NIF.session_new(conn)
+      assert {:error, :preupdate_hook_conflict} = NIF.register_preupdate_hook(conn, self())
+      :ok = NIF.session_delete(session)
+
+      {:ok, h} = NIF.register_preupdate_hook(conn, self())
+      assert {:error, :preupdate_hook_conflict} = NIF.session_new(conn)
+      :ok = NIF.unregister_preupdate_hook(conn, h)
+
+      assert {:ok, session} = NIF.session_new(conn)
+      :ok = NIF.session_delete(session)
+    end
+  end
+end
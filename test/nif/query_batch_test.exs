@@ -0,0 +1,91 @@
+defmodule Xqlite.NIF.QueryBatchTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias XqliteNIF, as: NIF
+
+  for_each_opener "query batch" do
+    setup %{conn: conn} do
+      :ok = NIF.execute_batch(conn, "CREATE TABLE qb(id INTEGER PRIMARY KEY, name TEXT UNIQUE);")
+      :ok
+    end
+
+    test "returns one result per statement, in order", %{conn: conn} do
+      script = """
+      INSERT INTO qb(name) VALUES ('a'), ('b');
+      SELECT id, name FROM qb ORDER BY id;
+      UPDATE qb SET name = upper(name) WHERE id = 2 RETURNING name;
+      DELETE FROM qb;
+      """
+
+      assert {:ok, [inserted, selected, updated, deleted]} = NIF.query_batch(conn, script, false)
+
+      assert inserted == {:ok, %{columns: [], rows: [], num_rows: 0, changes: 2}}
+
+      assert selected ==
+               {:ok, %{columns: ["id", "name"], rows: [[1, "a"], [2, "b"]], num_rows: 2, changes: 0}}
+
+      assert updated == {:ok, %{columns: ["name"], rows: [["B"]], num_rows: 1, changes: 1}}
+      assert {:ok, %{changes: 2, num_rows: 0}} = deleted
+    end
+
+    test "DDL after DML does not repeat the sticky change count", %{conn: conn} do
+      assert {:ok, [{:ok, %{changes: 1}}, {:ok, %{changes: 0}}]} =
+               NIF.query_batch(conn, "INSERT INTO qb(name) VALUES ('a'); CREATE TABLE other(x);", false)
+    end
+
+    test "later statements see the schema of earlier ones", %{conn: conn} do
+      script = "CREATE TABLE t2(x); INSERT INTO t2 VALUES (1), (2); SELECT sum(x) FROM t2;"
+
+      assert {:ok, [_, _, {:ok, %{rows: [[3]]}}]} = NIF.query_batch(conn, script, false)
+    end
+
+    test "comments and blank statements produce no entry", %{conn: conn} do
+      assert {:ok, [{:ok, %{rows: [[1]]}}, {:ok, %{rows: [[2]]}}]} =
+               NIF.query_batch(conn, "-- header\nSELECT 1;;\n  ; /* x */ SELECT 2; -- done", false)
+
+      assert {:ok, []} = NIF.query_batch(conn, "  -- nothing\n", false)
+    end
+
+    test "stops at the first failing statement", %{conn: conn} do
+      script = "INSERT INTO qb(name) VALUES ('a'); INSERT INTO qb(name) VALUES ('a'); SELECT 1;"
+
+      assert {:ok, [{:ok, %{changes: 1}}, {:error, {:constraint_violation, :constraint_unique, _}}]} =
+               NIF.query_batch(conn, script, false)
+
+      assert {:ok, %{rows: [[1]]}} = NIF.query(conn, "SELECT count(*) FROM qb", [])
+    end
+
+    test "continues past runtime and prepare errors on request", %{conn: conn} do
+      script = """
+      INSERT INTO qb(name) VALUES ('a');
+      INSERT INTO qb(name) VALUES ('a');
+      SELEC 'broken; still broken';
+      SELECT * FROM missing;
+      CREATE TRIGGER qb_t AFTER INSERT ON qb BEGIN SELECT 1; SELECT 2; END;
+      SELECT count(*) FROM qb;
+      """
+
+      assert {:ok, outcomes} = NIF.query_batch(conn, script, true)
+
+      assert [
+               {:ok, %{changes: 1}},
+               {:error, {:constraint_violation, :constraint_unique, _}},
+               {:error, {:sql_input_error, _}},
+               {:error, {:no_such_table, _}},
+               {:ok, %{columns: []}},
+               {:ok, %{rows: [[1]]}}
+             ] = outcomes
+    end
+
+    test "a prepare error stops the script by default", %{conn: conn} do
+      assert {:ok, [{:ok, _}, {:error, {:no_such_table, _}}]} =
+               NIF.query_batch(conn, "SELECT 1; SELECT * FROM missing; SELECT 2;", false)
+    end
+
+    test "a NUL byte rejects the whole script", %{conn: conn} do
+      assert {:error, :null_byte_in_string} = NIF.query_batch(conn, "SELECT 1;\0 SELECT 2;", false)
+    end
+  end
+end
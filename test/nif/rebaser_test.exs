@@ -0,0 +1,100 @@
+defmodule Xqlite.NIF.RebaserTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+  import Xqlite.TestUtil, only: [record_changeset: 2]
+
+  alias Xqlite.Change
+  alias XqliteNIF, as: NIF
+
+  @schema "CREATE TABLE rb_t (id INTEGER PRIMARY KEY, val TEXT);"
+
+  defp server do
+    {:ok, server} = NIF.open_in_memory(":memory:")
+    :ok = NIF.execute_batch(server, @schema)
+    on_exit(fn -> NIF.close(server) end)
+    server
+  end
+
+  defp rows(conn) do
+    {:ok, %{rows: rows}} = NIF.query(conn, "SELECT * FROM rb_t ORDER BY id", [])
+    rows
+  end
+
+  for_each_opener "rebaser" do
+    setup %{conn: conn} do
+      :ok = NIF.execute_batch(conn, @schema)
+      :ok
+    end
+
+    test "rebase: true returns the rebase buffer", %{conn: conn} do
+      server = server()
+      remote = record_changeset(server, "INSERT INTO rb_t VALUES (1, 'server');")
+      :ok = NIF.execute_batch(conn, "INSERT INTO rb_t VALUES (1, 'client');")
+
+      assert {:ok, rebase} = NIF.changeset_apply(conn, remote, :omit, rebase: true)
+      assert byte_size(rebase) > 0
+      assert :ok = NIF.changeset_apply(conn, remote, :omit, rebase: false)
+    end
+
+    test "an omitted remote insert turns the local insert into an update", %{conn: conn} do
+      server = server()
+      remote = record_changeset(server, "INSERT INTO rb_t VALUES (1, 'server');")
+      local = record_changeset(conn, "INSERT INTO rb_t VALUES (1, 'client');")
+
+      {:ok, rebase} = Xqlite.changeset_apply(conn, remote, :omit, rebase: true)
+      {:ok, rebaser} = NIF.rebaser_new()
+      :ok = NIF.rebaser_configure(rebaser, rebase)
+      {:ok, rebased} = NIF.rebaser_rebase(rebaser, local)
+
+      assert [%Change{op: :update, old: [1, "server"], new: [:undefined, "client"]}] =
+               Xqlite.changeset_stream(rebased) |> Enum.to_list()
+
+      # The original local changeset would conflict upstream; the rebased one applies.
+      assert :ok = NIF.changeset_apply(server, rebased, :abort)
+      assert rows(server) == [[1, "client"]]
+    end
+
+    test "a replaced remote insert drops the local insert", %{conn: conn} do
+      server = server()
+      remote = record_changeset(server, "INSERT INTO rb_t VALUES (1, 'server');")
+      local = record_changeset(conn, "INSERT INTO rb_t VALUES (1, 'client');")
+
+      {:ok, rebase} = NIF.changeset_apply(conn, remote, :replace, rebase: true)
+      {:ok, rebaser} = NIF.rebaser_new()
+      :ok = NIF.rebaser_configure(rebaser, rebase)
+      {:ok, rebased} = NIF.rebaser_rebase(rebaser, local)
+
+      assert [] = Xqlite.changeset_stream(rebased) |> Enum.to_list()
+      assert rows(conn) == [[1, "server"]]
+    end
+
+    test "without conflicts the rebase buffer is empty and changes pass through",
+         %{conn: conn} do
+      server = server()
+      remote = record_changeset(server, "INSERT INTO rb_t VALUES (1, 'server');")
+      local = record_changeset(conn, "INSERT INTO rb_t VALUES (2, 'client');")
+
+      assert {:ok, ""} = NIF.changeset_apply(conn, remote, :abort, rebase: true)
+
+      {:ok, rebaser} = NIF.rebaser_new()
+      :ok = NIF.rebaser_configure(rebaser, "")
+      {:ok, rebased} = NIF.rebaser_rebase(rebaser, local)
+
+      assert [%Change{op: :insert, new: [2, "client"]}] =
+               Xqlite.changeset_stream(rebased) |> Enum.to_list()
+    end
+
+    test "delete is idempotent and later calls fail" do
+      {:ok, rebaser} = NIF.rebaser_new()
+      assert :ok = NIF.rebaser_delete(rebaser)
+      assert :ok = NIF.rebaser_delete(rebaser)
+      assert {:error, _} = NIF.rebaser_rebase(rebaser, "")
+    end
+
+    test "a non-boolean :rebase is rejected", %{conn: conn} do
+      assert {:error, {:invalid_changeset_apply_option, :rebase}} =
+               NIF.changeset_apply(conn, "", :omit, rebase: 1)
+    end
+  end
+end
@@ -0,0 +1,195 @@
+defmodule Xqlite.NIF.ScalarFunctionTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias XqliteNIF, as: NIF
+
+  def tagged(args, tag), do: "#{tag}:#{Enum.join(args, ",")}"
+
+  defp register_and_find_dispatcher(conn, fun) do
+    {:links, before} = Process.info(self(), :links)
+    :ok = Xqlite.register_scalar_function(conn, "ver", 0, [], fun)
+    {:links, now} = Process.info(self(), :links)
+    [dispatcher] = now -- before
+    dispatcher
+  end
+
+  for_each_opener "scalar functions" do
+    test "a fun handler answers calls from SQL", %{conn: conn} do
+      :ok =
+        Xqlite.register_scalar_function(conn, "slugify", 1, [:deterministic], fn [s] ->
+          s |> String.downcase() |> String.replace(" ", "-")
+        end)
+
+      assert {:ok, %{rows: [["hello-big-world"]]}} =
+               NIF.query(conn, "SELECT slugify(?1)", ["Hello Big World"])
+    end
+
+    test "an MFA handler receives args plus the extra arguments", %{conn: conn} do
+      :ok = Xqlite.register_scalar_function(conn, "tag", -1, [], {__MODULE__, :tagged, ["t"]})
+
+      assert {:ok, %{rows: [["t:1,2,x"]]}} = NIF.query(conn, "SELECT tag(1, 2, 'x')", [])
+    end
+
+    test "every SQLite value type round-trips", %{conn: conn} do
+      :ok = Xqlite.register_scalar_function(conn, "echo", 1, [], fn [v] -> v end)
+
+      assert {:ok, %{rows: [[42, 1.5, "txt", <<0, 1, 2>>, nil]]}} =
+               NIF.query(
+                 conn,
+                 "SELECT echo(42), echo(1.5), echo('txt'), echo(x'000102'), echo(NULL)",
+                 []
+               )
+    end
+
+    test "works inside WHERE clauses and triggers", %{conn: conn} do
+      :ok =
+        Xqlite.register_scalar_function(conn, "is_even", 1, [], fn [n] -> rem(n, 2) == 0 end)
+
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE n(v INTEGER);
+        CREATE TABLE evens(v INTEGER);
+        CREATE TRIGGER n_ai AFTER INSERT ON n WHEN is_even(NEW.v)
+        BEGIN INSERT INTO evens VALUES (NEW.v); END;
+        INSERT INTO n VALUES (1), (2), (3), (4);
+        """)
+
+      assert {:ok, %{rows: [[2], [4]]}} =
+               NIF.query(conn, "SELECT v FROM n WHERE is_even(v) ORDER BY v", [])
+
+      assert {:ok, %{rows: [[2], [4]]}} = NIF.query(conn, "SELECT v FROM evens ORDER BY v", [])
+    end
+
+    test "a self-managed pid handler answers via handle_call/3", %{conn: conn} do
+      handler =
+        spawn_link(fn ->
+          receive do
+            {:xqlite_function_call, call_id, "plus_one", [x]} ->
+              Xqlite.Function.handle_call(call_id, [x], fn [v] -> v + 1 end)
+          end
+        end)
+
+      :ok = NIF.register_scalar_function(conn, "plus_one", 1, [], handler, 1_000)
+      assert {:ok, %{rows: [[8]]}} = NIF.query(conn, "SELECT plus_one(7)", [])
+    end
+
+    test "{:error, reason} surfaces as :user_function_failed", %{conn: conn} do
+      :ok =
+        Xqlite.register_scalar_function(conn, "boom", 0, [], fn [] -> {:error, "kaboom"} end)
+
+      assert {:error, {:user_function_failed, "boom", :error, "kaboom"}} =
+               NIF.query(conn, "SELECT boom()", [])
+    end
+
+    test "a raising handler is reported, not crashed", %{conn: conn} do
+      :ok = Xqlite.register_scalar_function(conn, "raiser", 0, [], fn [] -> raise "nope" end)
+
+      assert {:error, {:user_function_failed, "raiser", :error, "nope"}} =
+               NIF.query(conn, "SELECT raiser()", [])
+
+      # The dispatcher survived the exception.
+      assert {:error, {:user_function_failed, "raiser", :error, "nope"}} =
+               NIF.query(conn, "SELECT raiser()", [])
+    end
+
+    test "a value with no SQLite mapping is :invalid_result", %{conn: conn} do
+      :ok = Xqlite.register_scalar_function(conn, "mapper", 0, [], fn [] -> %{a: 1} end)
+
+      assert {:error, {:user_function_failed, "mapper", :invalid_result, _}} =
+               NIF.query(conn, "SELECT mapper()", [])
+    end
+
+    test "a handler that never replies times out", %{conn: conn} do
+      silent = spawn_link(fn -> Process.sleep(:infinity) end)
+      :ok = NIF.register_scalar_function(conn, "silent", 0, [], silent, 50)
+
+      assert {:error, {:user_function_failed, "silent", :timeout, _}} =
+               NIF.query(conn, "SELECT silent()", [])
+
+      # The connection is still usable afterwards.
+      assert {:ok, %{rows: [[1]]}} = NIF.query(conn, "SELECT 1", [])
+    end
+
+    test "a dead handler pid is :noproc", %{conn: conn} do
+      dead = spawn(fn -> :ok end)
+      ref = Process.monitor(dead)
+      assert_receive {:DOWN, ^ref, :process, ^dead, _}
+
+      :ok = NIF.register_scalar_function(conn, "ghost", 0, [], dead, 1_000)
+
+      assert {:error, {:user_function_failed, "ghost", :noproc, _}} =
+               NIF.query(conn, "SELECT ghost()", [])
+    end
+
+    test "SQL raising a failure-like message is not a function failure", %{conn: conn} do
+      :ok =
+        Xqlite.register_scalar_function(conn, "boom", 0, [], fn [] -> {:error, "kaboom"} end)
+
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE t(v INTEGER);
+        CREATE TRIGGER t_bi BEFORE INSERT ON t
+        BEGIN SELECT RAISE(ABORT, 'xqlite user function ''boom'' failed (error): kaboom'); END;
+        """)
+
+      assert {:error, {:user_function_failed, "boom", :error, "kaboom"}} =
+               NIF.query(conn, "SELECT boom()", [])
+
+      assert {:error, {:constraint_violation, :constraint_trigger, _}} =
+               NIF.execute(conn, "INSERT INTO t VALUES (1)", [])
+    end
+
+    test "unknown flags are rejected and nothing is registered", %{conn: conn} do
+      assert {:error, {:invalid_function_flag, :bogus}} =
+               Xqlite.register_scalar_function(conn, "f", 0, [:bogus], fn [] -> 1 end)
+
+      assert {:error, _} = NIF.query(conn, "SELECT f()", [])
+    end
+
+    test ":direct_only forbids use from a view", %{conn: conn} do
+      :ok = Xqlite.register_scalar_function(conn, "direct", 0, [:direct_only], fn [] -> 1 end)
+
+      assert {:ok, %{rows: [[1]]}} = NIF.query(conn, "SELECT direct()", [])
+
+      :ok = NIF.execute_batch(conn, "CREATE VIEW v AS SELECT direct() AS d;")
+      assert {:error, _} = NIF.query(conn, "SELECT d FROM v", [])
+    end
+
+    test "re-registering replaces; unregister removes", %{conn: conn} do
+      :ok = Xqlite.register_scalar_function(conn, "ver", 0, [], fn [] -> 1 end)
+      :ok = Xqlite.register_scalar_function(conn, "ver", 0, [], fn [] -> 2 end)
+      assert {:ok, %{rows: [[2]]}} = NIF.query(conn, "SELECT ver()", [])
+
+      assert :ok = Xqlite.unregister_function(conn, "ver", 0)
+      assert {:error, _} = NIF.query(conn, "SELECT ver()", [])
+    end
+
+    test "a spawned dispatcher stops when its registration goes away", %{conn: conn} do
+      first = register_and_find_dispatcher(conn, fn [] -> 1 end)
+      ref = Process.monitor(first)
+
+      second = register_and_find_dispatcher(conn, fn [] -> 2 end)
+      assert_receive {:DOWN, ^ref, :process, ^first, :normal}
+      assert {:links, links} = Process.info(self(), :links)
+      refute first in links
+
+      ref = Process.monitor(second)
+
+      :ok = Xqlite.unregister_function(conn, "ver", 0)
+      assert_receive {:DOWN, ^ref, :process, ^second, :normal}
+    end
+
+    test "a caller-run pid handler is never told to stop", %{conn: conn} do
+      :ok = NIF.register_scalar_function(conn, "mine", 0, [], self(), 1_000)
+      :ok = Xqlite.unregister_function(conn, "mine", 0)
+
+      refute_receive :xqlite_dispatcher_stop, 100
+    end
+
+    test "function_reply/2 for an unknown call id is a no-op" do
+      assert :ok = NIF.function_reply(123_456_789_012, {:ok, 1})
+    end
+  end
+end
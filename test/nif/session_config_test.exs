@@ -0,0 +1,134 @@
+defmodule Xqlite.NIF.SessionConfigTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias XqliteNIF, as: NIF
+
+  defp changes(session) do
+    {:ok, changeset} = NIF.session_changeset(session)
+    changeset |> Xqlite.changeset_stream() |> Enum.to_list()
+  end
+
+  for_each_opener "session config" do
+    setup %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE sc_a (id INTEGER PRIMARY KEY, val TEXT);
+        CREATE TABLE sc_b (id INTEGER PRIMARY KEY, val TEXT);
+        """)
+
+      {:ok, session} = NIF.session_new(conn)
+      on_exit(fn -> NIF.session_delete(session) end)
+      {:ok, session: session}
+    end
+
+    test "a disabled session records nothing until re-enabled", %{
+      conn: conn,
+      session: session
+    } do
+      :ok = NIF.session_attach(session, nil)
+      assert NIF.session_is_enabled(session) == {:ok, true}
+
+      assert :ok = NIF.session_enable(session, false)
+      assert NIF.session_is_enabled(session) == {:ok, false}
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO sc_a VALUES (1, 'maintenance')", [])
+      assert NIF.session_is_empty(session) == {:ok, true}
+
+      assert :ok = NIF.session_enable(session, true)
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO sc_a VALUES (2, 'user')", [])
+
+      assert [%{op: :insert, new: [2, "user"]}] = changes(session)
+    end
+
+    test "changes made while indirect are flagged as such", %{conn: conn, session: session} do
+      :ok = NIF.session_attach(session, nil)
+      assert NIF.session_is_indirect(session) == {:ok, false}
+
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO sc_a VALUES (1, 'direct')", [])
+      assert :ok = NIF.session_indirect(session, true)
+      assert NIF.session_is_indirect(session) == {:ok, true}
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO sc_b VALUES (1, 'indirect')", [])
+
+      assert [%{table: "sc_a", indirect: false}, %{table: "sc_b", indirect: true}] =
+               session |> changes() |> Enum.sort_by(& &1.table)
+    end
+
+    test "memory_used grows with recorded changes", %{conn: conn, session: session} do
+      :ok = NIF.session_attach(session, nil)
+      {:ok, before} = NIF.session_memory_used(session)
+      assert is_integer(before) and before >= 0
+
+      for i <- 1..50 do
+        {:ok, 1} = NIF.execute(conn, "INSERT INTO sc_a VALUES (?1, 'x')", [i])
+      end
+
+      {:ok, later} = NIF.session_memory_used(session)
+      assert later > before
+    end
+
+    test "rowid mode records tables without a primary key", %{conn: conn, session: session} do
+      :ok = NIF.execute_batch(conn, "CREATE TABLE sc_nopk (val TEXT);")
+      assert :ok = NIF.session_rowid(session, true)
+      :ok = NIF.session_attach(session, "sc_nopk")
+
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO sc_nopk VALUES ('kept')", [])
+
+      assert [%{table: "sc_nopk", op: :insert, pk_columns: [0], new: [1, "kept"]}] =
+               changes(session)
+    end
+
+    test "tables without a primary key are ignored by default", %{
+      conn: conn,
+      session: session
+    } do
+      :ok = NIF.execute_batch(conn, "CREATE TABLE sc_nopk (val TEXT);")
+      :ok = NIF.session_attach(session, "sc_nopk")
+
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO sc_nopk VALUES ('lost')", [])
+
+      assert NIF.session_is_empty(session) == {:ok, true}
+    end
+
+    test "rowid mode cannot change after a table is attached", %{session: session} do
+      :ok = NIF.session_attach(session, "sc_a")
+      assert {:error, {:sqlite_failure, 21, 21, _}} = NIF.session_rowid(session, true)
+    end
+
+    test "exclude_tables tracks everything but the listed tables", %{
+      conn: conn,
+      session: session
+    } do
+      assert :ok = NIF.session_exclude_tables(session, ["SC_B"])
+
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO sc_a VALUES (1, 'in')", [])
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO sc_b VALUES (1, 'out')", [])
+
+      assert [%{table: "sc_a"}] = changes(session)
+    end
+
+    test "a new exclusion list replaces the previous one", %{conn: conn, session: session} do
+      :ok = NIF.session_exclude_tables(session, ["sc_a"])
+      :ok = NIF.session_exclude_tables(session, ["sc_b"])
+
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO sc_a VALUES (1, 'in')", [])
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO sc_b VALUES (1, 'out')", [])
+
+      assert [%{table: "sc_a"}] = changes(session)
+    end
+  end
+
+  test "config ops on a deleted session fail cleanly" do
+    {:ok, conn} = NIF.open_in_memory(":memory:")
+    {:ok, session} = NIF.session_new(conn)
+    :ok = NIF.session_delete(session)
+
+    assert {:error, _} = NIF.session_enable(session, false)
+    assert {:error, _} = NIF.session_indirect(session, true)
+    assert {:error, _} = NIF.session_rowid(session, true)
+    assert {:error, _} = NIF.session_exclude_tables(session, [])
+    assert {:error, _} = NIF.session_memory_used(session)
+
+    NIF.close(conn)
+  end
+end
@@ -0,0 +1,116 @@
+defmodule Xqlite.NIF.SessionDiffTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+  import Xqlite.TestUtil, only: [tmp_db_path: 1]
+
+  alias Xqlite.Change
+  alias XqliteNIF, as: NIF
+
+  @schema """
+  CREATE TABLE sd_t (id INTEGER PRIMARY KEY, val TEXT);
+  CREATE TABLE sd_u (id INTEGER PRIMARY KEY, n INTEGER);
+  """
+
+  # Creates a database file holding `@schema` plus `seed_sql`.
+  defp snapshot(seed_sql) do
+    path = tmp_db_path("session_diff")
+    {:ok, conn} = NIF.open(path)
+    :ok = NIF.execute_batch(conn, @schema <> seed_sql)
+    :ok = NIF.close(conn)
+    path
+  end
+
+  defp rows(conn, table) do
+    {:ok, %{rows: rows}} = NIF.query(conn, "SELECT * FROM #{table} ORDER BY id", [])
+    rows
+  end
+
+  for_each_opener "session diff" do
+    setup %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, @schema <> "INSERT INTO sd_t VALUES (1, 'a2'), (2, 'b');")
+
+      :ok
+    end
+
+    test "session_diff records the changes from an attached copy", %{conn: conn} do
+      path = snapshot("INSERT INTO sd_t VALUES (1, 'a'), (3, 'c');")
+      {:ok, _} = NIF.execute(conn, "ATTACH DATABASE ?1 AS snap", [path])
+
+      {:ok, session} = NIF.session_new(conn)
+      :ok = NIF.session_attach(session, nil)
+      assert :ok = NIF.session_diff(session, "snap", "sd_t")
+      assert NIF.session_is_empty(session) == {:ok, false}
+      {:ok, changeset} = NIF.session_changeset(session)
+      :ok = NIF.session_delete(session)
+
+      changes = changeset |> Xqlite.changeset_stream() |> Enum.sort_by(& &1.op)
+
+      assert [
+               %Change{op: :delete, old: [3, "c"]},
+               %Change{op: :insert, new: [2, "b"]},
+               %Change{op: :update, old: [1, "a"], new: [:undefined, "a2"]}
+             ] = changes
+    end
+
+    test "session_diff rejects mismatched or missing tables", %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        ATTACH DATABASE ':memory:' AS other;
+        CREATE TABLE other.sd_t (id INTEGER PRIMARY KEY, val TEXT, extra TEXT);
+        """)
+
+      {:ok, session} = NIF.session_new(conn)
+      :ok = NIF.session_attach(session, nil)
+
+      assert {:error, {:schema_changed, _, "table schemas do not match"}} =
+               NIF.session_diff(session, "other", "sd_t")
+
+      assert {:error, {:schema_changed, _, "no such table: nope.sd_t"}} =
+               NIF.session_diff(session, "nope", "sd_t")
+    end
+
+    test "changeset_diff brings the file in line with the connection", %{conn: conn} do
+      :ok = NIF.execute_batch(conn, "INSERT INTO sd_u VALUES (1, 10);")
+      path = snapshot("INSERT INTO sd_t VALUES (1, 'a'), (3, 'c');")
+
+      assert {:ok, changeset} = Xqlite.changeset_diff(conn, path)
+
+      {:ok, snap} = NIF.open(path)
+      on_exit(fn -> NIF.close(snap) end)
+      assert :ok = NIF.changeset_apply(snap, changeset, :abort)
+      assert rows(snap, "sd_t") == rows(conn, "sd_t")
+      assert rows(snap, "sd_u") == [[1, 10]]
+
+      # The file is detached again.
+      {:ok, %{rows: databases}} = NIF.query(conn, "SELECT name FROM pragma_database_list", [])
+      refute ["xqlite_diff"] in databases
+    end
+
+    test "changeset_diff honours :tables and :patchset", %{conn: conn} do
+      :ok = NIF.execute_batch(conn, "INSERT INTO sd_u VALUES (1, 10);")
+      path = snapshot("")
+
+      assert {:ok, changeset} = Xqlite.changeset_diff(conn, path, tables: ["sd_u"], as: "s")
+
+      assert [%Change{table: "sd_u", op: :insert}] =
+               changeset |> Xqlite.changeset_stream() |> Enum.to_list()
+
+      assert {:ok, patchset} = Xqlite.changeset_diff(conn, path, patchset: true)
+      assert {:ok, full} = Xqlite.changeset_diff(conn, path)
+      assert byte_size(patchset) <= byte_size(full)
+      assert length(Enum.to_list(Xqlite.changeset_stream(patchset))) == 3
+    end
+
+    test "changeset_diff reports schema mismatches and detaches", %{conn: conn} do
+      path = tmp_db_path("session_diff_mismatch")
+      {:ok, other} = NIF.open(path)
+      :ok = NIF.execute_batch(other, "CREATE TABLE sd_t (id INTEGER PRIMARY KEY);")
+      :ok = NIF.close(other)
+
+      assert {:error, {:schema_changed, _, _}} = Xqlite.changeset_diff(conn, path)
+      assert {:ok, _} = Xqlite.changeset_diff(conn, snapshot(""))
+    end
+  end
+end
@@ -0,0 +1,178 @@
+defmodule Xqlite.NIF.SessionStreamTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+  import Xqlite.TestUtil, only: [tmp_db_path: 1]
+
+  alias XqliteNIF, as: NIF
+
+  @schema "CREATE TABLE ss_t (id INTEGER PRIMARY KEY, val TEXT);"
+
+  for_each_opener "session stream" do
+    setup %{conn: conn} do
+      :ok = NIF.execute_batch(conn, @schema)
+      {:ok, session} = NIF.session_new(conn)
+      :ok = NIF.session_attach(session, nil)
+
+      for i <- 1..200 do
+        {:ok, 1} = NIF.execute(conn, "INSERT INTO ss_t VALUES (?1, ?2)", [i, "row #{i}"])
+      end
+
+      on_exit(fn -> NIF.session_delete(session) end)
+      {:ok, session: session}
+    end
+
+    test "session_changeset_file writes the same bytes as session_changeset", %{
+      session: session
+    } do
+      path = tmp_db_path("session_changeset_file")
+      {:ok, changeset} = NIF.session_changeset(session)
+
+      assert :ok = NIF.session_changeset_file(session, path)
+      assert File.read!(path) == changeset
+    end
+
+    test "session_patchset_file writes a patchset", %{session: session} do
+      path = tmp_db_path("session_patchset_file")
+      {:ok, patchset} = NIF.session_patchset(session)
+
+      assert :ok = NIF.session_patchset_file(session, path)
+      assert File.read!(path) == patchset
+    end
+
+    test "an uncreatable output file fails cleanly", %{session: session} do
+      path = Path.join([System.tmp_dir!(), "xqlite_no_such_dir", "out.changeset"])
+
+      assert {:error, {:cannot_open_file, ^path, _}} =
+               NIF.session_changeset_file(session, path)
+    end
+
+    test "session_changeset_into a fun delivers the changeset in bounded chunks", %{
+      session: session
+    } do
+      {:ok, changeset} = NIF.session_changeset(session)
+      parent = self()
+
+      assert :ok =
+               Xqlite.session_changeset_into(session, &send(parent, {:chunk, &1}),
+                 chunk_size: 256
+               )
+
+      chunks =
+        for _ <- 1..div(byte_size(changeset) + 255, 256) do
+          assert_receive {:chunk, chunk}
+          chunk
+        end
+
+      refute_received {:chunk, _}
+
+      assert Enum.all?(chunks, &(byte_size(&1) <= 256))
+      assert IO.iodata_to_binary(chunks) == changeset
+    end
+
+    test "session_changeset_into a file writes a patchset when asked", %{session: session} do
+      path = tmp_db_path("session_into_file")
+      {:ok, patchset} = NIF.session_patchset(session)
+
+      assert :ok = Xqlite.session_changeset_into(session, {:file, path}, patchset: true)
+      assert File.read!(path) == patchset
+    end
+
+    test "a raising fun stops the output", %{session: session} do
+      assert {:error, {:changeset_handler_failed, :error, "boom"}} =
+               Xqlite.session_changeset_into(session, fn _chunk -> raise "boom" end,
+                 chunk_size: 64
+               )
+    end
+
+    test "session_changeset_send waits for each chunk to be acknowledged", %{
+      session: session
+    } do
+      {:ok, changeset} = NIF.session_changeset(session)
+      parent = self()
+
+      task =
+        Task.async(fn -> NIF.session_changeset_send(session, parent, 1024, 5_000) end)
+
+      # Only one chunk is in flight until it is acknowledged.
+      assert_receive {:xqlite_changeset_chunk, call_id, first}
+      refute_receive {:xqlite_changeset_chunk, _, _}, 50
+      :ok = NIF.function_reply(call_id, {:ok, true})
+
+      rest = collect_until_done(task)
+
+      assert IO.iodata_to_binary([first | rest]) == changeset
+    end
+
+    test "an unanswered chunk times out", %{session: session} do
+      assert {:error, {:changeset_handler_failed, :timeout, _}} =
+               NIF.session_patchset_send(session, self(), 64, 20)
+    end
+
+    test "a zero chunk size is rejected", %{session: session} do
+      assert {:error, {:cannot_execute, _}} =
+               NIF.session_changeset_send(session, self(), 0, 1_000)
+    end
+
+    test "changeset_apply_file applies a changeset read from disk", %{session: session} do
+      path = tmp_db_path("session_apply_file")
+      :ok = NIF.session_changeset_file(session, path)
+
+      {:ok, replica} = NIF.open_in_memory(":memory:")
+      :ok = NIF.execute_batch(replica, @schema)
+
+      assert :ok = Xqlite.changeset_apply(replica, {:file, path}, :abort)
+
+      assert {:ok, %{rows: [[200]]}} = NIF.query(replica, "SELECT count(*) FROM ss_t", [])
+      NIF.close(replica)
+    end
+
+    test "changeset_apply_file honours table filters", %{session: session} do
+      path = tmp_db_path("session_apply_file_filter")
+      :ok = NIF.session_changeset_file(session, path)
+
+      {:ok, replica} = NIF.open_in_memory(":memory:")
+      :ok = NIF.execute_batch(replica, @schema)
+
+      assert :ok =
+               Xqlite.changeset_apply(replica, {:file, path}, :abort,
+                 table_filter: &(&1 != "ss_t")
+               )
+
+      assert {:ok, %{rows: [[0]]}} = NIF.query(replica, "SELECT count(*) FROM ss_t", [])
+      NIF.close(replica)
+    end
+
+    test "changeset_invert_file matches changeset_invert", %{session: session} do
+      input = tmp_db_path("session_invert_in")
+      output = tmp_db_path("session_invert_out")
+      {:ok, changeset} = NIF.session_changeset(session)
+      File.write!(input, changeset)
+
+      assert :ok = NIF.changeset_invert_file(input, output)
+      assert {:ok, File.read!(output)} == NIF.changeset_invert(changeset)
+    end
+  end
+
+  test "changeset_apply_file on a missing file fails cleanly" do
+    {:ok, conn} = NIF.open_in_memory(":memory:")
+    path = Path.join(System.tmp_dir!(), "xqlite_missing_#{System.unique_integer()}.changeset")
+
+    assert {:error, {:cannot_open_file, ^path, _}} = NIF.changeset_apply_file(conn, path, :omit)
+    NIF.close(conn)
+  end
+
+  # Acknowledges chunks until the sending task finishes.
+  defp collect_until_done(task, acc \\ []) do
+    receive do
+      {:xqlite_changeset_chunk, call_id, chunk} ->
+        :ok = NIF.function_reply(call_id, {:ok, true})
+        collect_until_done(task, [chunk | acc])
+
+      {ref, result} when ref == task.ref ->
+        Process.demonitor(ref, [:flush])
+        assert result == :ok
+        Enum.reverse(acc)
+    end
+  end
+end
@@ -0,0 +1,145 @@
+defmodule Xqlite.NIF.SlowQueryTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias XqliteNIF, as: NIF
+
+  for_each_opener "slow query log" do
+    setup %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE t(id INTEGER PRIMARY KEY, v TEXT);
+        INSERT INTO t VALUES (1, 'a'), (2, 'b'), (3, 'c');
+        """)
+
+      :ok
+    end
+
+    test "a zero threshold records every statement with its row count", %{conn: conn} do
+      :ok = NIF.set_slow_query_log(conn, 0, 0, 100, nil)
+
+      {:ok, %{num_rows: 2}} = NIF.query(conn, "SELECT v FROM t WHERE id > ?1", [1])
+
+      assert {:ok, [record]} = NIF.slow_queries(conn)
+
+      assert %{
+               sql: "SELECT v FROM t WHERE id > ?1",
+               param_count: 1,
+               rows: 2,
+               query_plan: nil
+             } = record
+
+      assert is_integer(record.duration_ns) and record.duration_ns >= 0
+      assert {:ok, []} = NIF.slow_queries(conn)
+    end
+
+    test "statements under the threshold are not recorded", %{conn: conn} do
+      :ok = NIF.set_slow_query_log(conn, 60_000, 0, 100, nil)
+
+      {:ok, _} = NIF.query(conn, "SELECT * FROM t", [])
+
+      assert {:ok, []} = NIF.slow_queries(conn)
+    end
+
+    test "each statement of a batch is recorded", %{conn: conn} do
+      :ok = NIF.set_slow_query_log(conn, 0, 0, 100, nil)
+
+      :ok = NIF.execute_batch(conn, "UPDATE t SET v = 'x'; DELETE FROM t WHERE id = 3;")
+
+      assert {:ok, [update, delete]} = NIF.slow_queries(conn)
+      assert update.sql =~ "UPDATE t SET v = 'x'"
+      assert delete.sql =~ "DELETE FROM t WHERE id = 3"
+    end
+
+    test "the ring buffer keeps the newest records", %{conn: conn} do
+      :ok = NIF.set_slow_query_log(conn, 0, 0, 2, nil)
+
+      for id <- 1..3, do: {:ok, _} = NIF.query(conn, "SELECT #{id}", [])
+
+      assert {:ok, [%{sql: "SELECT 2"}, %{sql: "SELECT 3"}]} = NIF.slow_queries(conn)
+    end
+
+    test "query plans are sampled every Nth slow statement", %{conn: conn} do
+      :ok = NIF.set_slow_query_log(conn, 0, 2, 100, nil)
+
+      for _ <- 1..3, do: {:ok, _} = NIF.query(conn, "SELECT v FROM t WHERE id = 1", [])
+
+      assert {:ok, [first, second, third]} = NIF.slow_queries(conn)
+      assert [%{detail: detail}] = first.query_plan
+      assert detail =~ "USING INTEGER PRIMARY KEY"
+      assert second.query_plan == nil
+      assert is_list(third.query_plan)
+    end
+
+    test "plan capture is invisible to trace subscribers", %{conn: conn} do
+      {:ok, _h} = NIF.register_trace_hook(conn, self(), [:stmt])
+      :ok = NIF.set_slow_query_log(conn, 0, 1, 100, nil)
+
+      {:ok, _} = NIF.query(conn, "SELECT * FROM t", [])
+
+      assert_receive {:xqlite_trace, :stmt, "SELECT * FROM t"}
+      refute_receive {:xqlite_trace, :stmt, "EXPLAIN" <> _}, 50
+      assert {:ok, [%{query_plan: [_ | _]}]} = NIF.slow_queries(conn)
+    end
+
+    test "records go to a subscriber pid instead of the buffer", %{conn: conn} do
+      :ok = NIF.set_slow_query_log(conn, 0, 0, 100, self())
+
+      {:ok, _} = NIF.query(conn, "SELECT * FROM t", [])
+
+      assert_receive {:xqlite_slow_query, %{sql: "SELECT * FROM t", rows: 3}}
+      assert {:ok, []} = NIF.slow_queries(conn)
+    end
+
+    test "clearing the log stops recording and is idempotent", %{conn: conn} do
+      :ok = NIF.set_slow_query_log(conn, 0, 0, 100, nil)
+      assert :ok = NIF.clear_slow_query_log(conn)
+      assert :ok = NIF.clear_slow_query_log(conn)
+
+      {:ok, _} = NIF.query(conn, "SELECT * FROM t", [])
+
+      assert {:ok, []} = NIF.slow_queries(conn)
+    end
+  end
+
+  test "the open option installs the log" do
+    {:ok, conn} = Xqlite.open_in_memory(slow_query_log: [threshold_ms: 0])
+
+    {:ok, _} = NIF.query(conn, "SELECT 1", [])
+
+    assert {:ok, [%{sql: "SELECT 1", rows: 1}]} = Xqlite.slow_queries(conn)
+    NIF.close(conn)
+  end
+
+  test "set_slow_query_log/2 rejects bad options and keeps the current log" do
+    {:ok, conn} = Xqlite.open_in_memory(slow_query_log: [threshold_ms: 0])
+
+    for {opts, key} <- [
+          {[threshold_ms: 0, capacity: 0], :capacity},
+          {[threshold_ms: 0, query_plan: 0], :query_plan},
+          {[threshold_ms: 0, query_plan: :yes], :query_plan},
+          {[threshold_ms: -1], :threshold_ms},
+          {[capacity: 10], :threshold_ms}
+        ] do
+      assert {:error, {:invalid_slow_query_log_option, %{key: ^key, message: message}}} =
+               Xqlite.set_slow_query_log(conn, opts)
+
+      assert is_binary(message)
+    end
+
+    assert {:error, {:invalid_slow_query_log_option, _}} =
+             Xqlite.set_slow_query_log(conn, threshold_ms: 0, bogus: 1)
+
+    {:ok, _} = NIF.query(conn, "SELECT 1", [])
+    assert {:ok, [%{sql: "SELECT 1"}]} = Xqlite.slow_queries(conn)
+    NIF.close(conn)
+  end
+
+  test "slow_queries on a closed connection fails" do
+    {:ok, conn} = NIF.open_in_memory(":memory:")
+    NIF.close(conn)
+
+    assert {:error, :connection_closed} = NIF.slow_queries(conn)
+  end
+end
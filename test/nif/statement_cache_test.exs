@@ -0,0 +1,141 @@
+defmodule Xqlite.NIF.StatementCacheTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias XqliteNIF, as: NIF
+
+  defp cache_counts(conn) do
+    {:ok, stats} = NIF.connection_stats(conn)
+    {stats.statement_cache_hit, stats.statement_cache_miss, stats.statement_cache_eviction}
+  end
+
+  for_each_opener "statement cache" do
+    setup %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE sc(id INTEGER PRIMARY KEY, v TEXT);
+        INSERT INTO sc(v) VALUES ('a'), ('b');
+        """)
+
+      :ok
+    end
+
+    test "repeated statements are served from the cache", %{conn: conn} do
+      {hits, misses, _} = cache_counts(conn)
+
+      for id <- [1, 2, 1] do
+        {:ok, %{num_rows: 1}} = NIF.query(conn, "SELECT v FROM sc WHERE id = ?1", [id])
+      end
+
+      {:ok, 1} = NIF.execute(conn, "UPDATE sc SET v = ?1 WHERE id = ?2", ["c", 1])
+      {:ok, 1} = NIF.execute(conn, "UPDATE sc SET v = ?1 WHERE id = ?2", ["d", 2])
+
+      assert {new_hits, new_misses, 0} = cache_counts(conn)
+      assert new_hits - hits == 3
+      assert new_misses - misses == 2
+    end
+
+    test "leading and trailing whitespace share an entry", %{conn: conn} do
+      {:ok, _} = NIF.query(conn, "SELECT v FROM sc", [])
+      {hits, _, _} = cache_counts(conn)
+
+      {:ok, _} = NIF.query(conn, "  SELECT v FROM sc\n", [])
+
+      assert {new_hits, _, _} = cache_counts(conn)
+      assert new_hits == hits + 1
+    end
+
+    test "the least recently used statement is evicted", %{conn: conn} do
+      :ok = NIF.set_statement_cache_size(conn, 2)
+
+      for sql <- ["SELECT 1", "SELECT 2", "SELECT 1", "SELECT 3"] do
+        {:ok, _} = NIF.query(conn, sql, [])
+      end
+
+      {hits, misses, evictions} = cache_counts(conn)
+      assert evictions == 1
+
+      # "SELECT 2" was the least recent; "SELECT 1" survived.
+      {:ok, _} = NIF.query(conn, "SELECT 1", [])
+      assert cache_counts(conn) == {hits + 1, misses, evictions}
+      {:ok, _} = NIF.query(conn, "SELECT 2", [])
+      assert {_, new_misses, _} = cache_counts(conn)
+      assert new_misses == misses + 1
+    end
+
+    test "shrinking the cache evicts", %{conn: conn} do
+      for n <- 1..3, do: {:ok, _} = NIF.query(conn, "SELECT #{n}", [])
+      {_, _, evictions} = cache_counts(conn)
+
+      :ok = NIF.set_statement_cache_size(conn, 1)
+
+      assert {_, _, new_evictions} = cache_counts(conn)
+      assert new_evictions == evictions + 2
+    end
+
+    test "size 0 disables the cache", %{conn: conn} do
+      :ok = NIF.set_statement_cache_size(conn, 0)
+      before = cache_counts(conn)
+
+      for _ <- 1..3, do: {:ok, _} = NIF.query(conn, "SELECT v FROM sc", [])
+
+      assert cache_counts(conn) == before
+    end
+
+    test "a schema change recompiles cached statements", %{conn: conn} do
+      {:ok, %{columns: ["id", "v"]}} = NIF.query(conn, "SELECT * FROM sc", [])
+      :ok = NIF.execute_batch(conn, "ALTER TABLE sc ADD COLUMN w INTEGER DEFAULT 7")
+      {hits, misses, _} = cache_counts(conn)
+
+      assert {:ok, %{columns: ["id", "v", "w"], rows: [[1, "a", 7], [2, "b", 7]]}} =
+               NIF.query(conn, "SELECT * FROM sc ORDER BY id", [])
+
+      assert {:ok, %{columns: ["id", "v", "w"], rows: [[1, "a", 7] | _]}} =
+               NIF.query(conn, "SELECT * FROM sc", [])
+
+      assert {^hits, new_misses, _} = cache_counts(conn)
+      assert new_misses == misses + 2
+    end
+
+    test "comment-only SQL takes no cache slot", %{conn: conn} do
+      :ok = NIF.set_statement_cache_size(conn, 1)
+      {:ok, _} = NIF.query(conn, "SELECT 1", [])
+
+      for _ <- 1..2, do: _ = NIF.query(conn, "  -- nothing to run\n", [])
+      {hits, _, evictions} = cache_counts(conn)
+
+      {:ok, _} = NIF.query(conn, "SELECT 1", [])
+      assert {new_hits, _, ^evictions} = cache_counts(conn)
+      assert new_hits == hits + 1
+    end
+
+    test "syntax error offsets count the caller's leading whitespace", %{conn: conn} do
+      sql = "  \n  SELEC 1"
+
+      assert {:error, {:sql_input_error, %{sql: ^sql, offset: 5}}} = NIF.query(conn, sql, [])
+
+      :ok = NIF.set_statement_cache_size(conn, 0)
+      assert {:error, {:sql_input_error, %{sql: ^sql, offset: 5}}} = NIF.query(conn, sql, [])
+    end
+
+    test "a failing statement leaves the cache usable", %{conn: conn} do
+      assert {:error, _} = NIF.query(conn, "SELECT * FROM no_such_table", [])
+      assert {:error, _} = NIF.execute(conn, "INSERT INTO sc(id) VALUES (?1)", [1])
+      assert {:ok, 1} = NIF.execute(conn, "INSERT INTO sc(id) VALUES (?1)", [3])
+    end
+  end
+
+  test "open/2 applies :statement_cache_size" do
+    {:ok, conn} = Xqlite.open_in_memory(statement_cache_size: 0)
+    for _ <- 1..2, do: {:ok, _} = NIF.query(conn, "SELECT 1", [])
+
+    assert {0, 0, 0} = cache_counts(conn)
+    NIF.close(conn)
+  end
+
+  test "open/2 rejects a negative :statement_cache_size" do
+    assert {:error, {:invalid_open_option, %{key: :statement_cache_size}}} =
+             Xqlite.open_in_memory(statement_cache_size: -1)
+  end
+end
@@ -0,0 +1,91 @@
+defmodule Xqlite.NIF.StatementDependenciesTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias XqliteNIF, as: NIF
+
+  for_each_opener "statement dependencies" do
+    setup %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        PRAGMA foreign_keys = ON;
+        CREATE TABLE orgs(id INTEGER PRIMARY KEY, name TEXT);
+        CREATE TABLE users(
+          id INTEGER PRIMARY KEY,
+          org_id INTEGER REFERENCES orgs(id),
+          email TEXT
+        );
+        CREATE INDEX users_email ON users(email);
+        CREATE TABLE audit(user_id INTEGER);
+        CREATE VIEW org_users AS
+          SELECT users.email, orgs.name FROM users JOIN orgs ON orgs.id = users.org_id;
+        CREATE TRIGGER users_audit AFTER INSERT ON users BEGIN
+          INSERT INTO audit VALUES (new.id);
+        END;
+        """)
+
+      :ok
+    end
+
+    test "a plain query reads its table", %{conn: conn} do
+      assert {:ok, %{"main" => deps}} = NIF.statement_dependencies(conn, "SELECT * FROM orgs")
+      assert deps == %{reads: ["orgs"], writes: [], index_reads: [], index_writes: []}
+    end
+
+    test "views resolve to their base tables", %{conn: conn} do
+      assert {:ok, %{"main" => %{reads: ["orgs", "users"], writes: []}}} =
+               NIF.statement_dependencies(conn, "SELECT * FROM org_users")
+    end
+
+    test "a covering index counts as a read of its table", %{conn: conn} do
+      assert {:ok, %{"main" => %{reads: ["users"], index_reads: ["users_email"]}}} =
+               NIF.statement_dependencies(conn, "SELECT id FROM users WHERE email = ?1")
+    end
+
+    test "writes include trigger targets, maintained indexes and FK parents", %{conn: conn} do
+      sql = "INSERT INTO users(org_id, email) VALUES (?1, ?2)"
+      assert {:ok, %{"main" => deps}} = NIF.statement_dependencies(conn, sql)
+
+      assert deps.writes == ["audit", "users"]
+      assert deps.index_writes == ["users_email"]
+      assert deps.reads == ["orgs"]
+    end
+
+    test "the statement is not executed", %{conn: conn} do
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO orgs(name) VALUES ('acme')", [])
+
+      assert {:ok, %{"main" => %{writes: ["orgs"]}}} =
+               NIF.statement_dependencies(conn, "DELETE FROM orgs")
+
+      assert {:ok, %{rows: [[1]]}} = NIF.query(conn, "SELECT count(*) FROM orgs", [])
+    end
+
+    test "temp and attached schemas are keyed by name", %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TEMP TABLE scratch(x);
+        ATTACH DATABASE ':memory:' AS aux;
+        CREATE TABLE aux.other(y);
+        """)
+
+      assert {:ok, deps} =
+               NIF.statement_dependencies(conn, "SELECT * FROM scratch, aux.other, orgs")
+
+      assert Map.keys(deps) |> Enum.sort() == ["aux", "main", "temp"]
+      assert deps["temp"].reads == ["scratch"]
+      assert deps["aux"].reads == ["other"]
+    end
+
+    test "SQL without tables or statements depends on nothing", %{conn: conn} do
+      assert {:ok, deps} = NIF.statement_dependencies(conn, "SELECT 1")
+      assert deps == %{}
+      assert {:ok, deps} = NIF.statement_dependencies(conn, "-- nothing")
+      assert deps == %{}
+    end
+
+    test "SQL errors surface as error tuples", %{conn: conn} do
+      assert {:error, _} = NIF.statement_dependencies(conn, "SELECT * FROM no_such_table")
+    end
+  end
+end
@@ -0,0 +1,100 @@
+defmodule Xqlite.NIF.StatementInfoTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias XqliteNIF, as: NIF
+
+  for_each_opener "statement info" do
+    setup %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE items(id INTEGER PRIMARY KEY, label VARCHAR(20), extra);
+        INSERT INTO items VALUES (1, 'one', NULL), (2, 'two', NULL);
+        """)
+
+      :ok
+    end
+
+    test "reports a SELECT as readonly with declared column types", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT id, label, extra, id + 1 FROM items")
+
+      assert {:ok, info} = NIF.stmt_info(stmt)
+      assert info.readonly
+      assert info.explain == :none
+      refute info.busy
+      assert info.sql == "SELECT id, label, extra, id + 1 FROM items"
+      assert info.declared_types == ["INTEGER", "VARCHAR(20)", nil, nil]
+    end
+
+    test "writes are not readonly", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "DELETE FROM items")
+
+      assert {:ok, %{readonly: false, declared_types: []}} = NIF.stmt_info(stmt)
+    end
+
+    test "expanded SQL inlines the current bindings", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT * FROM items WHERE label = :l AND id > :min")
+      :ok = NIF.stmt_bind(stmt, l: "it's", min: 1)
+
+      assert {:ok, %{expanded_sql: "SELECT * FROM items WHERE label = 'it''s' AND id > 1"}} =
+               NIF.stmt_info(stmt)
+    end
+
+    test "busy between the first step and reset", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT id FROM items")
+
+      assert {:row, [1]} = NIF.stmt_step(stmt)
+      assert {:ok, %{busy: true}} = NIF.stmt_info(stmt)
+      :ok = NIF.stmt_reset(stmt)
+      assert {:ok, %{busy: false}} = NIF.stmt_info(stmt)
+    end
+
+    test "an EXPLAIN prefix is reported", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "EXPLAIN QUERY PLAN SELECT * FROM items")
+
+      assert {:ok, %{explain: :query_plan}} = NIF.stmt_info(stmt)
+    end
+
+    test "switching to query plan mode and back", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT label FROM items WHERE id = ?1")
+      :ok = NIF.stmt_bind(stmt, [2])
+
+      assert :ok = NIF.stmt_explain(stmt, :query_plan)
+      assert {:ok, %{explain: :query_plan}} = NIF.stmt_info(stmt)
+      assert {:row, [_id, _parent, _notused, detail]} = NIF.stmt_step(stmt)
+      assert detail =~ "SEARCH items"
+      :ok = NIF.stmt_reset(stmt)
+
+      assert :ok = NIF.stmt_explain(stmt, :explain)
+      assert {:ok, %{explain: :explain}} = NIF.stmt_info(stmt)
+      assert {:ok, ["addr", "opcode" | _]} = NIF.stmt_column_names(stmt)
+
+      assert :ok = NIF.stmt_explain(stmt, :none)
+      assert {:row, ["two"]} = NIF.stmt_step(stmt)
+    end
+
+    test "a running statement cannot switch modes", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT id FROM items")
+      {:row, _} = NIF.stmt_step(stmt)
+
+      assert {:error, {:cannot_execute, _}} = NIF.stmt_explain(stmt, :query_plan)
+      :ok = NIF.stmt_reset(stmt)
+      assert :ok = NIF.stmt_explain(stmt, :query_plan)
+    end
+
+    test "unknown modes are rejected", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT 1")
+
+      assert {:error, {:invalid_explain_mode, :analyze}} = NIF.stmt_explain(stmt, :analyze)
+    end
+
+    test "a finalized statement is an error", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT 1")
+      :ok = NIF.stmt_finalize(stmt)
+
+      assert {:error, :statement_finalized} = NIF.stmt_info(stmt)
+      assert {:error, :statement_finalized} = NIF.stmt_explain(stmt, :none)
+    end
+  end
+end
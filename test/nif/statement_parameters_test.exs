@@ -0,0 +1,90 @@
+defmodule Xqlite.NIF.StatementParametersTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias XqliteNIF, as: NIF
+
+  for_each_opener "statement parameters" do
+    setup %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, "CREATE TABLE items (id INTEGER PRIMARY KEY, label TEXT);")
+
+      :ok
+    end
+
+    test "lists names by index with their prefixes", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT :a, @b, $c, ?, :a")
+
+      assert {:ok, %{count: 4, names: [":a", "@b", "$c", nil]}} = NIF.stmt_parameters(stmt)
+    end
+
+    test "numbered parameters set the count", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT ?3")
+
+      assert {:ok, %{count: 3, names: [nil, nil, "?3"]}} = NIF.stmt_parameters(stmt)
+    end
+
+    test "a statement without parameters", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT 1")
+
+      assert {:ok, %{count: 0, names: []}} = NIF.stmt_parameters(stmt)
+    end
+
+    test "a finalized statement is an error", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT :a")
+      :ok = NIF.stmt_finalize(stmt)
+
+      assert {:error, :statement_finalized} = NIF.stmt_parameters(stmt)
+    end
+
+    test "bind accepts a map with bare or prefixed string keys", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "INSERT INTO items VALUES (:id, @label)")
+
+      assert :ok = NIF.stmt_bind(stmt, %{"id" => 1, "@label" => "one"})
+      assert :done = NIF.stmt_step(stmt)
+
+      assert {:ok, %{rows: [[1, "one"]]}} = NIF.query(conn, "SELECT * FROM items", [])
+    end
+
+    test "map keys must name parameters", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT :id")
+
+      assert {:error, {:invalid_parameter_name, "nope"}} =
+               NIF.stmt_bind(stmt, %{"nope" => 1})
+
+      assert {:error, {:invalid_parameter_name, _}} = NIF.stmt_bind(stmt, %{id: 1})
+    end
+
+    test "bind_one rebinds a single value between runs", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "INSERT INTO items VALUES (?1, :label)")
+      :ok = NIF.stmt_bind_one(stmt, :label, "same")
+
+      for id <- 1..3 do
+        assert :ok = NIF.stmt_bind_one(stmt, 1, id)
+        assert :done = NIF.stmt_step(stmt)
+        :ok = NIF.stmt_reset(stmt)
+      end
+
+      assert {:ok, %{rows: [[1, "same"], [2, "same"], [3, "same"]]}} =
+               NIF.query(conn, "SELECT * FROM items ORDER BY id", [])
+    end
+
+    test "bind_one resolves string names like map keys", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT $x, :y")
+
+      assert :ok = NIF.stmt_bind_one(stmt, "x", 1)
+      assert :ok = NIF.stmt_bind_one(stmt, ":y", 2)
+      assert {:row, [1, 2]} = NIF.stmt_step(stmt)
+    end
+
+    test "bind_one rejects unknown keys", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT ?1, :name")
+
+      assert {:error, {:invalid_parameter_index, 0}} = NIF.stmt_bind_one(stmt, 0, 1)
+      assert {:error, {:invalid_parameter_index, 3}} = NIF.stmt_bind_one(stmt, 3, 1)
+      assert {:error, {:invalid_parameter_name, ":other"}} = NIF.stmt_bind_one(stmt, :other, 1)
+      assert {:error, {:invalid_parameter_name, "other"}} = NIF.stmt_bind_one(stmt, "other", 1)
+    end
+  end
+end
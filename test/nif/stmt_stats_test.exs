@@ -0,0 +1,98 @@
+defmodule Xqlite.NIF.StmtStatsTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias XqliteNIF, as: NIF
+
+  for_each_opener "stmt stats" do
+    setup %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE ss(id INTEGER PRIMARY KEY, grp INTEGER, v TEXT);
+        INSERT INTO ss VALUES (1, 1, 'a'), (2, 1, 'b'), (3, 2, 'c'), (4, 2, 'd');
+        """)
+
+      :ok
+    end
+
+    test "counters accumulate across executions of a prepared statement", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT v FROM ss WHERE grp = ?1 ORDER BY v")
+
+      for grp <- [1, 2, 1] do
+        :ok = NIF.stmt_bind(stmt, [grp])
+        {:ok, %{done: true}} = NIF.stmt_multi_step(stmt, 10)
+        :ok = NIF.stmt_reset(stmt)
+      end
+
+      assert {:ok, counters} = NIF.stmt_stats(stmt)
+      assert counters.run == 3
+      assert counters.sort == 3
+      assert counters.fullscan_step == 9
+      assert counters.vm_step > 0
+      NIF.stmt_finalize(stmt)
+    end
+
+    test "reset zeroes the counters after reading them", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT v FROM ss ORDER BY v")
+      {:ok, %{done: true}} = NIF.stmt_multi_step(stmt, 10)
+
+      assert {:ok, %{run: 1, sort: 1}} = NIF.stmt_stats(stmt, true)
+      assert {:ok, %{run: 0, sort: 0, vm_step: 0}} = NIF.stmt_stats(stmt)
+      NIF.stmt_finalize(stmt)
+    end
+
+    test "scanstatus reports loops and rows visited across runs", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT v FROM ss WHERE grp = 2")
+
+      for _ <- 1..2 do
+        {:ok, %{done: true}} = NIF.stmt_multi_step(stmt, 10)
+        :ok = NIF.stmt_reset(stmt)
+      end
+
+      assert {:ok, [scan]} = NIF.stmt_scanstatus(stmt, true)
+      assert scan.name == "ss"
+      assert scan.loops == 2
+      assert scan.rows_visited == 8
+
+      assert {:ok, [%{loops: 0, rows_visited: 0}]} = NIF.stmt_scanstatus(stmt)
+      NIF.stmt_finalize(stmt)
+    end
+
+    test "a live stream reports its counters", %{conn: conn} do
+      {:ok, stream} = NIF.stream_open(conn, "SELECT v FROM ss ORDER BY v DESC", [], [])
+      {:ok, %{rows: [["d"], ["c"]]}} = NIF.stream_fetch(stream, 2)
+
+      assert {:ok, %{run: 1, sort: 1}} = NIF.stmt_stats(stream)
+      assert {:ok, [%{name: "ss"}]} = NIF.stmt_scanstatus(stream)
+      NIF.stream_close(stream)
+    end
+
+    test "an exhausted stream still reports its final counters", %{conn: conn} do
+      {:ok, stream} = NIF.stream_open(conn, "SELECT v FROM ss", [], [])
+      {:ok, %{rows: rows}} = NIF.stream_fetch(stream, 10)
+      assert length(rows) == 4
+      assert :done = NIF.stream_fetch(stream, 10)
+
+      assert {:ok, %{run: 1, fullscan_step: 3}} = NIF.stmt_stats(stream)
+      assert {:ok, [%{loops: 1, rows_visited: 4}]} = NIF.stmt_scanstatus(stream)
+    end
+
+    test "a finalized statement or closed stream has no counters", %{conn: conn} do
+      {:ok, stmt} = NIF.stmt_prepare(conn, "SELECT 1")
+      :ok = NIF.stmt_finalize(stmt)
+      assert {:error, :statement_finalized} = NIF.stmt_stats(stmt)
+
+      {:ok, stream} = NIF.stream_open(conn, "SELECT v FROM ss", [], [])
+      :ok = NIF.stream_close(stream)
+      assert {:error, :statement_finalized} = NIF.stmt_scanstatus(stream)
+    end
+  end
+
+  test "anything but a statement or stream handle is a badarg" do
+    {:ok, conn} = NIF.open_in_memory(":memory:")
+
+    assert_raise ArgumentError, fn -> NIF.stmt_stats(conn) end
+    NIF.close(conn)
+  end
+end
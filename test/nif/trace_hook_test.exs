@@ -0,0 +1,118 @@
+defmodule Xqlite.NIF.TraceHookTest do
+  use ExUnit.Case, async: true
+
+  import Xqlite.ConnCase
+
+  alias XqliteNIF, as: NIF
+
+  for_each_opener "trace hook" do
+    setup %{conn: conn} do
+      :ok = NIF.execute_batch(conn, "CREATE TABLE t(id INTEGER PRIMARY KEY, v TEXT);")
+      :ok
+    end
+
+    test "register / unregister returns handle and is idempotent", %{conn: conn} do
+      assert {:ok, h} = NIF.register_trace_hook(conn, self())
+      assert is_integer(h) and h > 0
+      assert :ok = NIF.unregister_trace_hook(conn, h)
+      assert :ok = NIF.unregister_trace_hook(conn, h)
+      assert :ok = NIF.unregister_trace_hook(conn, 999_999)
+    end
+
+    test ":stmt carries the SQL with bindings expanded", %{conn: conn} do
+      {:ok, _h} = NIF.register_trace_hook(conn, self(), [:stmt])
+
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO t VALUES (?1, ?2)", [1, "a"])
+
+      assert_receive {:xqlite_trace, :stmt, "INSERT INTO t VALUES (1, 'a')"}
+    end
+
+    test ":profile carries the prepared SQL and a duration", %{conn: conn} do
+      {:ok, _h} = NIF.register_trace_hook(conn, self(), [:profile])
+
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO t VALUES (?1, ?2)", [1, "a"])
+
+      assert_receive {:xqlite_trace, :profile, "INSERT INTO t VALUES (?1, ?2)", nanos}
+      assert is_integer(nanos) and nanos >= 0
+      refute_received {:xqlite_trace, :stmt, _}
+    end
+
+    test ":row fires once per result row", %{conn: conn} do
+      :ok = NIF.execute_batch(conn, "INSERT INTO t VALUES (1, 'a'), (2, 'b'), (3, 'c');")
+      {:ok, _h} = NIF.register_trace_hook(conn, self(), [:row])
+
+      {:ok, %{num_rows: 3}} = NIF.query(conn, "SELECT id FROM t", [])
+
+      for _ <- 1..3, do: assert_receive({:xqlite_trace, :row, "SELECT id FROM t"})
+      refute_receive {:xqlite_trace, :row, _}, 50
+    end
+
+    test "trigger subprograms are reported as SQL comments", %{conn: conn} do
+      :ok =
+        NIF.execute_batch(conn, """
+        CREATE TABLE audit(id INTEGER);
+        CREATE TRIGGER t_audit AFTER INSERT ON t BEGIN
+          INSERT INTO audit VALUES (new.id);
+        END;
+        """)
+
+      {:ok, _h} = NIF.register_trace_hook(conn, self(), [:stmt])
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO t VALUES (1, 'a')", [])
+
+      assert_receive {:xqlite_trace, :stmt, "INSERT INTO t VALUES (1, 'a')"}
+      assert_receive {:xqlite_trace, :stmt, "-- TRIGGER t_audit"}
+    end
+
+    test "each subscriber only receives its own events", %{conn: conn} do
+      parent = self()
+
+      relay = fn tag ->
+        spawn_link(fn ->
+          receive do
+            {:xqlite_trace, event, _sql} -> send(parent, {tag, event})
+            {:xqlite_trace, event, _sql, _nanos} -> send(parent, {tag, event})
+          end
+        end)
+      end
+
+      {:ok, _} = NIF.register_trace_hook(conn, relay.(:stmts), [:stmt])
+      {:ok, _} = NIF.register_trace_hook(conn, relay.(:profiles), [:profile])
+
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO t VALUES (1, 'a')", [])
+
+      assert_receive {:stmts, :stmt}
+      assert_receive {:profiles, :profile}
+    end
+
+    test "unregistered subscribers stop receiving events", %{conn: conn} do
+      {:ok, h} = NIF.register_trace_hook(conn, self(), [:stmt, :profile])
+      :ok = NIF.unregister_trace_hook(conn, h)
+
+      {:ok, 1} = NIF.execute(conn, "INSERT INTO t VALUES (1, 'a')", [])
+
+      refute_receive {:xqlite_trace, _, _}, 50
+      refute_receive {:xqlite_trace, _, _, _}, 0
+    end
+
+    test "an unknown event is rejected", %{conn: conn} do
+      assert {:error, {:invalid_trace_event, :nope}} =
+               NIF.register_trace_hook(conn, self(), [:stmt, :nope])
+    end
+  end
+
+  test ":close fires when the connection closes" do
+    {:ok, conn} = NIF.open_in_memory(":memory:")
+    {:ok, _h} = NIF.register_trace_hook(conn, self(), [:close])
+
+    :ok = NIF.close(conn)
+
+    assert_receive {:xqlite_trace, :close}
+  end
+
+  test "register on a closed connection returns the closed-connection error" do
+    {:ok, conn} = NIF.open_in_memory(":memory:")
+    :ok = NIF.close(conn)
+
+    assert {:error, :connection_closed} = NIF.register_trace_hook(conn, self())
+  end
+end
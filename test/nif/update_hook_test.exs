@@ -484,6 +484,206 @@ defmodule Xqlite.NIF.UpdateHookTest do
       end
     end
 
+    describe "#{prefix}: batch mode" do
+      @describetag type_tag
+
+      setup context do
+        {mod, fun, args} = find_opener_mfa!(context)
+        {:ok, conn} = apply(mod, fun, args)
+        on_exit(fn -> NIF.close(conn) end)
+
+        :ok =
+          NIF.execute_batch(conn, """
+          CREATE TABLE hook_test (id INTEGER PRIMARY KEY, val TEXT);
+          CREATE TABLE other (id INTEGER PRIMARY KEY);
+          """)
+
+        {:ok, conn: conn}
+      end
+
+      test "delivers one message per committed transaction", %{conn: conn} do
+        {:ok, _h} = NIF.register_update_hook(conn, self(), batch: true)
+
+        :ok = NIF.begin(conn, :immediate)
+        {:ok, 1} = NIF.execute(conn, "INSERT INTO hook_test VALUES (1, 'a')", [])
+        {:ok, 1} = NIF.execute(conn, "UPDATE hook_test SET val = 'b' WHERE id = 1", [])
+        {:ok, 1} = NIF.execute(conn, "DELETE FROM hook_test WHERE id = 1", [])
+
+        refute_receive {:xqlite_update_batch, _}, 100
+        :ok = NIF.commit(conn)
+
+        assert_receive {:xqlite_update_batch, events}, 2_000
+
+        assert events == [
+                 {:insert, "main", "hook_test", 1},
+                 {:update, "main", "hook_test", 1},
+                 {:delete, "main", "hook_test", 1}
+               ]
+
+        refute_receive {:xqlite_update, _, _, _, _}, 100
+      end
+
+      test "autocommit statements each deliver a batch", %{conn: conn} do
+        {:ok, _h} = NIF.register_update_hook(conn, self(), batch: true)
+
+        {:ok, 1} = NIF.execute(conn, "INSERT INTO hook_test VALUES (1, 'a')", [])
+        assert_receive {:xqlite_update_batch, [{:insert, "main", "hook_test", 1}]}, 2_000
+      end
+
+      test "rollback discards buffered events", %{conn: conn} do
+        {:ok, _h} = NIF.register_update_hook(conn, self(), batch: true)
+
+        :ok = NIF.begin(conn, :immediate)
+        {:ok, 1} = NIF.execute(conn, "INSERT INTO hook_test VALUES (1, 'doomed')", [])
+        :ok = NIF.rollback(conn)
+
+        :ok = NIF.begin(conn, :immediate)
+        {:ok, 1} = NIF.execute(conn, "INSERT INTO hook_test VALUES (2, 'kept')", [])
+        :ok = NIF.commit(conn)
+
+        assert_receive {:xqlite_update_batch, events}, 2_000
+        assert events == [{:insert, "main", "hook_test", 2}]
+      end
+
+      test "rows an execute_many rolls back to its savepoint are not delivered",
+           %{conn: conn} do
+        {:ok, _h} = NIF.register_update_hook(conn, self(), batch: true)
+        insert = "INSERT INTO hook_test VALUES (?1, 'v')"
+
+        :ok = NIF.begin(conn, :immediate)
+        {:ok, 1} = NIF.execute(conn, "INSERT INTO hook_test VALUES (1, 'kept')", [])
+
+        assert {:error, {:row_failed, 2, _}} =
+                 NIF.execute_many(conn, insert, [[2], [3], [1]], true, [])
+
+        {:ok, 1} = NIF.execute(conn, "INSERT INTO hook_test VALUES (4, 'kept')", [])
+        :ok = NIF.commit(conn)
+
+        assert_receive {:xqlite_update_batch, events}, 2_000
+        assert events == [{:insert, "main", "hook_test", 1}, {:insert, "main", "hook_test", 4}]
+      end
+
+      test "a statement that aborts inside a transaction is not delivered", %{conn: conn} do
+        {:ok, _h} = NIF.register_update_hook(conn, self(), batch: true)
+
+        :ok = NIF.begin(conn, :immediate)
+        {:ok, 1} = NIF.execute(conn, "INSERT INTO hook_test VALUES (1, 'kept')", [])
+
+        assert {:error, {:constraint_violation, :constraint_primary_key, _}} =
+                 NIF.execute(conn, "INSERT INTO hook_test VALUES (2, 'v'), (1, 'dup')", [])
+
+        :ok = NIF.commit(conn)
+
+        assert_receive {:xqlite_update_batch, events}, 2_000
+        assert events == [{:insert, "main", "hook_test", 1}]
+      end
+
+      test "a large import arrives as a single message", %{conn: conn} do
+        {:ok, _h} = NIF.register_update_hook(conn, self(), batch: true)
+
+        :ok =
+          NIF.execute_batch(conn, """
+          WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5000)
+          INSERT INTO hook_test SELECT i, 'v' FROM n;
+          """)
+
+        assert_receive {:xqlite_update_batch, events}, 2_000
+        assert length(events) == 5_000
+        refute_receive {:xqlite_update_batch, _}, 100
+      end
+
+      test "transactions with no matching events send nothing", %{conn: conn} do
+        {:ok, _h} =
+          NIF.register_update_hook(conn, self(), batch: true, tables: ["hook_test"])
+
+        {:ok, 1} = NIF.execute(conn, "INSERT INTO other VALUES (1)", [])
+        refute_receive {:xqlite_update_batch, _}, 200
+      end
+
+      test "batch and per-row subscribers coexist", %{conn: conn} do
+        {:ok, _h} = NIF.register_update_hook(conn, self(), batch: true)
+        {:ok, _h} = NIF.register_update_hook(conn, self())
+
+        {:ok, 1} = NIF.execute(conn, "INSERT INTO hook_test VALUES (1, 'a')", [])
+
+        assert_receive {:xqlite_update, :insert, "main", "hook_test", 1}, 2_000
+        assert_receive {:xqlite_update_batch, [{:insert, "main", "hook_test", 1}]}, 2_000
+      end
+    end
+
+    describe "#{prefix}: filters" do
+      @describetag type_tag
+
+      setup context do
+        {mod, fun, args} = find_opener_mfa!(context)
+        {:ok, conn} = apply(mod, fun, args)
+        on_exit(fn -> NIF.close(conn) end)
+
+        :ok =
+          NIF.execute_batch(conn, """
+          CREATE TABLE hook_test (id INTEGER PRIMARY KEY, val TEXT);
+          CREATE TABLE other (id INTEGER PRIMARY KEY);
+          """)
+
+        {:ok, conn: conn}
+      end
+
+      test ":tables limits events to the listed tables", %{conn: conn} do
+        {:ok, _h} = NIF.register_update_hook(conn, self(), tables: ["HOOK_TEST"])
+
+        {:ok, 1} = NIF.execute(conn, "INSERT INTO other VALUES (1)", [])
+        {:ok, 1} = NIF.execute(conn, "INSERT INTO hook_test VALUES (1, 'a')", [])
+
+        assert_receive {:xqlite_update, :insert, "main", "hook_test", 1}, 2_000
+        refute_receive {:xqlite_update, _, _, "other", _}, 100
+      end
+
+      test ":actions limits events to the listed actions", %{conn: conn} do
+        {:ok, _h} = NIF.register_update_hook(conn, self(), actions: [:delete])
+
+        {:ok, 1} = NIF.execute(conn, "INSERT INTO hook_test VALUES (1, 'a')", [])
+        {:ok, 1} = NIF.execute(conn, "DELETE FROM hook_test WHERE id = 1", [])
+
+        assert_receive {:xqlite_update, :delete, "main", "hook_test", 1}, 2_000
+        refute_receive {:xqlite_update, :insert, _, _, _}, 100
+      end
+
+      test "filters apply to batch mode", %{conn: conn} do
+        {:ok, _h} =
+          NIF.register_update_hook(conn, self(),
+            batch: true,
+            tables: ["hook_test"],
+            actions: [:update]
+          )
+
+        :ok =
+          NIF.execute_batch(conn, """
+          BEGIN;
+          INSERT INTO hook_test VALUES (1, 'a');
+          INSERT INTO other VALUES (1);
+          UPDATE hook_test SET val = 'b';
+          UPDATE other SET id = 2;
+          COMMIT;
+          """)
+
+        assert_receive {:xqlite_update_batch, [{:update, "main", "hook_test", 1}]}, 2_000
+      end
+
+      test "invalid options are rejected", %{conn: conn} do
+        assert {:error, {:invalid_update_hook_option, :actions}} =
+                 NIF.register_update_hook(conn, self(), actions: [:truncate])
+
+        assert {:error, {:invalid_update_hook_option, :batch}} =
+                 NIF.register_update_hook(conn, self(), batch: :yes)
+
+        assert {:error, {:invalid_update_hook_option, :tables}} =
+                 NIF.register_update_hook(conn, self(), tables: "hook_test")
+
+        assert {:error, {:invalid_update_hook_option, :bogus}} =
+                 NIF.register_update_hook(conn, self(), bogus: 1)
+      end
+    end
+
     describe "#{prefix}: closed connection" do
       @describetag type_tag
 
@@ -28,6 +28,23 @@ defmodule Xqlite.TestUtil do
     path
   end
 
+  # Runs `sql` on `conn` under a fresh session attached to every table and
+  # returns what it recorded: the changeset, or with `:patchset` the patchset.
+  def record_changeset(conn, sql, kind \\ :changeset) do
+    {:ok, session} = NIF.session_new(conn)
+    :ok = NIF.session_attach(session, nil)
+    :ok = NIF.execute_batch(conn, sql)
+
+    {:ok, recorded} =
+      case kind do
+        :changeset -> NIF.session_changeset(session)
+        :patchset -> NIF.session_patchset(session)
+      end
+
+    :ok = NIF.session_delete(session)
+    recorded
+  end
+
   defp open_and_configure({mod, fun, args}) do
     with {:ok, conn} <- apply(mod, fun, args),
          {:ok, _} <- NIF.set_pragma(conn, "journal_mode", "WAL"),
@@ -185,10 +185,28 @@ defmodule Xqlite.XqliteTelemetryCancelBridgeTest do
       detach(handler_id)
     end
 
+    test "trace hook bridge re-emits profile events with sql + duration", %{conn: conn} do
+      :ok = XqliteNIF.execute_batch(conn, "CREATE TABLE t(id INTEGER PRIMARY KEY);")
+      {:ok, bridge} = Xqlite.Telemetry.bridge(conn, hooks: [:trace], tag: :traced)
+
+      handler_id = attach_capture([[:xqlite, :hook, :trace]])
+
+      {:ok, 1} = XqliteNIF.execute(conn, "INSERT INTO t VALUES (1)", [])
+
+      assert_receive {:telemetry_event, [:xqlite, :hook, :trace], measurements, metadata}
+      assert is_integer(measurements.duration)
+      assert metadata.event == :profile
+      assert metadata.sql == "INSERT INTO t VALUES (1)"
+      assert metadata.tag == :traced
+
+      :ok = Xqlite.Telemetry.unbridge(bridge)
+      detach(handler_id)
+    end
+
     test ":all expands to every per-conn hook", %{conn: conn} do
       {:ok, bridge} = Xqlite.Telemetry.bridge(conn, hooks: :all)
 
-      assert length(bridge.hook_handles) == 6
+      assert length(bridge.hook_handles) == 7
       assert Enum.all?(bridge.hook_handles, fn {h, _} -> is_atom(h) end)
 
       :ok = Xqlite.Telemetry.unbridge(bridge)